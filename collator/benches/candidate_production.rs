@@ -0,0 +1,171 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks of the candidate production path, run against the test runtime.
+//!
+//! Run them with `cargo bench -p cumulus-collator` and compare the results against the base
+//! branch of a change.
+
+use cumulus_collator::{CollatorService, ParachainCollatorService};
+use cumulus_primitives::{
+	inherents::DownwardMessagesType, relay_chain::Hash as PHash, InboundDownwardMessage,
+	ParachainInherentDataProvider, ValidationData,
+};
+use cumulus_runtime::ParachainBlockData;
+use cumulus_test_client::{
+	generate_block_inherents, generate_extrinsic_with_nonce,
+	runtime::{BalancesCall, Block, Call, UncheckedExtrinsic},
+	Client, ClientBlockImportExt, DefaultTestClientBuilderExt, TestClientBuilder,
+	TestClientBuilderExt,
+};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use sc_block_builder::BlockBuilderProvider;
+use sp_blockchain::HeaderBackend;
+use sp_consensus::BlockOrigin;
+use sp_inherents::InherentData;
+use sp_keyring::AccountKeyring;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+use sp_state_machine::StorageProof;
+
+use std::sync::Arc;
+
+/// The number of extrinsics the blocks are built with.
+const EXTRINSICS: &[u32] = &[10, 50, 200];
+
+/// Returns `count` transfers from Alice to Bob.
+fn transfers(client: &Client, count: u32) -> Vec<UncheckedExtrinsic> {
+	(0..count)
+		.map(|nonce| {
+			let function = Call::Balances(BalancesCall::transfer(
+				AccountKeyring::Bob.public().into(),
+				1_000,
+			));
+
+			generate_extrinsic_with_nonce(client, AccountKeyring::Alice, function, nonce)
+		})
+		.collect()
+}
+
+/// Build a block with the inherents and the given `extrinsics` on top of the best block.
+fn build_block(client: &Client, extrinsics: &[UncheckedExtrinsic], record_proof: bool) -> Block {
+	let mut builder = client
+		.new_block_at(
+			&BlockId::Hash(client.info().best_hash),
+			Default::default(),
+			record_proof,
+		)
+		.expect("Initializes new block");
+
+	generate_block_inherents(client, None)
+		.into_iter()
+		.chain(extrinsics.iter().cloned())
+		.for_each(|e| builder.push(e).expect("Pushes an extrinsic"));
+
+	builder.build().expect("Creates block").block
+}
+
+fn inherent_data(c: &mut Criterion) {
+	let mut group = c.benchmark_group("inherent_data");
+
+	for messages in &[0u32, 100, 1000] {
+		let downward_messages = (0..*messages)
+			.map(|sent_at| InboundDownwardMessage {
+				sent_at,
+				msg: vec![0; 256],
+			})
+			.collect::<DownwardMessagesType>();
+		let relay_chain = move |_: PHash| -> Result<DownwardMessagesType, String> {
+			Ok(downward_messages.clone())
+		};
+
+		group.bench_with_input(BenchmarkId::from_parameter(messages), messages, |b, _| {
+			b.iter(|| {
+				let mut inherent_data = InherentData::new();
+				ParachainInherentDataProvider::create_at(
+					PHash::default(),
+					ValidationData::default(),
+					&relay_chain,
+				)
+				.expect("Creates the provider")
+				.provide_inherent_data(&mut inherent_data)
+				.expect("Provides the inherent data");
+
+				inherent_data
+			})
+		});
+	}
+
+	group.finish();
+}
+
+/// Builds the block with the block builder directly, without the transaction pool and the
+/// deadline handling of the proposer.
+fn block_building(c: &mut Criterion) {
+	let client = TestClientBuilder::new().build();
+	let mut group = c.benchmark_group("block_building");
+
+	for count in EXTRINSICS {
+		let extrinsics = transfers(&client, *count);
+
+		group.bench_with_input(BenchmarkId::new("no_proof", count), count, |b, _| {
+			b.iter(|| build_block(&client, &extrinsics, false))
+		});
+		group.bench_with_input(BenchmarkId::new("record_proof", count), count, |b, _| {
+			b.iter(|| build_block(&client, &extrinsics, true))
+		});
+	}
+
+	group.finish();
+}
+
+fn build_collation(c: &mut Criterion) {
+	let mut group = c.benchmark_group("build_collation");
+
+	for count in EXTRINSICS {
+		let client_builder = TestClientBuilder::new();
+		let backend = client_builder.backend();
+		let mut client = client_builder.build();
+
+		let extrinsics = transfers(&client, *count);
+		let block = build_block(&client, &extrinsics, false);
+		let block_hash = block.hash();
+		client
+			.import(BlockOrigin::Own, block.clone())
+			.expect("Imports the block");
+
+		let service =
+			ParachainCollatorService::<Block, _, _>::new(Arc::new(client), backend, None);
+		let (header, extrinsics) = block.deconstruct();
+
+		group.bench_with_input(BenchmarkId::from_parameter(count), count, |b, _| {
+			b.iter_batched(
+				|| ParachainBlockData::new(header.clone(), extrinsics.clone(), StorageProof::empty()),
+				|block_data| {
+					service
+						.build_collation(block_data, block_hash, 1)
+						.expect("Builds the collation")
+				},
+				BatchSize::SmallInput,
+			)
+		});
+	}
+
+	group.finish();
+}
+
+criterion_group!(benches, inherent_data, block_building, build_collation);
+criterion_main!(benches);
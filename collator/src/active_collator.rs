@@ -0,0 +1,243 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Collate only while the key of the collator is designated on-chain, for redundant setups.
+//!
+//! Every node of a redundant setup runs with its own collator key, and the runtime, e.g. a
+//! collator selection pallet, designates one of these keys as the active collator. A node only
+//! produces a candidate if its key is designated on the parachain block it builds on. The
+//! designation is read again for every candidate, so a hot-standby node takes over as soon as
+//! its key is designated and stops again when the designation moves on.
+//!
+//! The designation is per key. Nodes that share the same key are not told apart and all
+//! collate while the key is designated.
+
+use sc_client_api::{Backend, StorageProvider};
+use sp_core::storage::StorageKey;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+
+use polkadot_primitives::v1::CollatorId;
+
+use codec::Decode;
+use log::{debug, info, warn};
+
+use std::{
+	marker::PhantomData,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+};
+
+/// Reads which collator is designated to collate.
+pub trait ActiveCollator<Block: BlockT>: Send + Sync {
+	/// Returns the collator that is designated to build on top of the block `at`.
+	///
+	/// Returns `None` if no collator is designated.
+	fn active_collator(&self, at: Block::Hash) -> Result<Option<CollatorId>, String>;
+}
+
+impl<Block, F> ActiveCollator<Block> for F
+where
+	Block: BlockT,
+	F: Fn(Block::Hash) -> Result<Option<CollatorId>, String> + Send + Sync,
+{
+	fn active_collator(&self, at: Block::Hash) -> Result<Option<CollatorId>, String> {
+		(self)(at)
+	}
+}
+
+/// Reads the designated collator from the state of the parachain.
+///
+/// The value at the storage `key` is the SCALE encoded [`CollatorId`] of the designated
+/// collator. No collator is designated while the key is not set.
+pub struct StorageActiveCollator<Client, Backend> {
+	client: Arc<Client>,
+	key: StorageKey,
+	_marker: PhantomData<fn() -> Backend>,
+}
+
+impl<Client, Backend> StorageActiveCollator<Client, Backend> {
+	/// Create a new instance that reads the storage `key` with `client`.
+	pub fn new(client: Arc<Client>, key: Vec<u8>) -> Self {
+		Self {
+			client,
+			key: StorageKey(key),
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<Block, Client, B> ActiveCollator<Block> for StorageActiveCollator<Client, B>
+where
+	Block: BlockT,
+	B: Backend<Block>,
+	Client: StorageProvider<Block, B> + Send + Sync,
+{
+	fn active_collator(&self, at: Block::Hash) -> Result<Option<CollatorId>, String> {
+		let active = self
+			.client
+			.storage(&BlockId::Hash(at), &self.key)
+			.map_err(|e| format!("Failed to read the active collator at {}: {:?}", at, e))?;
+
+		active
+			.map(|active| {
+				CollatorId::decode(&mut &active.0[..]).map_err(|e| {
+					format!("Failed to decode the active collator at {}: {:?}", at, e)
+				})
+			})
+			.transpose()
+	}
+}
+
+/// Checks that the key the collator runs with is designated.
+pub(crate) struct ActiveCollatorCheck<Block: BlockT> {
+	active_collator: Arc<dyn ActiveCollator<Block>>,
+	key: CollatorId,
+	designated: Arc<AtomicBool>,
+}
+
+impl<Block: BlockT> Clone for ActiveCollatorCheck<Block> {
+	fn clone(&self) -> Self {
+		Self {
+			active_collator: self.active_collator.clone(),
+			key: self.key.clone(),
+			designated: self.designated.clone(),
+		}
+	}
+}
+
+impl<Block: BlockT> ActiveCollatorCheck<Block> {
+	pub(crate) fn new(active_collator: Arc<dyn ActiveCollator<Block>>, key: CollatorId) -> Self {
+		Self {
+			active_collator,
+			key,
+			designated: Arc::new(AtomicBool::new(false)),
+		}
+	}
+
+	/// Returns `true` if the collator may build on top of `parent`.
+	///
+	/// A collator whose designation can not be read does not collate, as another node may be
+	/// designated.
+	pub(crate) fn may_collate(&self, parent: Block::Hash) -> bool {
+		let designated = match self.active_collator.active_collator(parent) {
+			Ok(Some(ref active)) if *active == self.key => true,
+			Ok(active) => {
+				debug!(
+					target: "cumulus-collator",
+					"Skipping candidate production, collator `{:?}` is designated on parent `{:?}`.",
+					active,
+					parent,
+				);
+				false
+			}
+			Err(e) => {
+				warn!(
+					target: "cumulus-collator",
+					"Skipping candidate production, failed to read the active collator of parent `{:?}`: {}",
+					parent,
+					e,
+				);
+				false
+			}
+		};
+
+		if self.designated.swap(designated, Ordering::Relaxed) != designated {
+			if designated {
+				info!(
+					target: "cumulus-collator",
+					"Collator `{:?}` is designated on parent `{:?}`, collating.",
+					self.key,
+					parent,
+				);
+			} else {
+				info!(
+					target: "cumulus-collator",
+					"Collator `{:?}` is not designated on parent `{:?}`, waiting in hot-standby.",
+					self.key,
+					parent,
+				);
+			}
+		}
+
+		designated
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use codec::Encode;
+	use cumulus_test_client::{
+		runtime::Block, DefaultTestClientBuilderExt, TestClientBuilder, TestClientBuilderExt,
+	};
+	use parking_lot::Mutex;
+	use polkadot_primitives::v1::CollatorPair;
+	use sp_blockchain::HeaderBackend;
+	use sp_core::{Pair, H256};
+
+	#[test]
+	fn collates_only_while_the_key_is_designated() {
+		let (first, second) = (CollatorPair::generate().0, CollatorPair::generate().0);
+		let active = Arc::new(Mutex::new(Some(second.public())));
+		let active_collator: Arc<dyn ActiveCollator<Block>> = {
+			let active = active.clone();
+			Arc::new(move |_: H256| -> Result<Option<CollatorId>, String> {
+				Ok(active.lock().clone())
+			})
+		};
+
+		let check = ActiveCollatorCheck::new(active_collator, second.public());
+		assert!(check.may_collate(H256::default()));
+
+		*active.lock() = Some(first.public());
+		assert!(!check.may_collate(H256::default()));
+
+		*active.lock() = None;
+		assert!(!check.may_collate(H256::default()));
+
+		*active.lock() = Some(second.public());
+		assert!(check.may_collate(H256::default()));
+	}
+
+	#[test]
+	fn reads_the_active_collator_from_storage() {
+		let key = CollatorPair::generate().0;
+		let client = Arc::new(
+			TestClientBuilder::new()
+				.set_genesis_storage(b"active_collator".to_vec(), key.public().encode())
+				.build(),
+		);
+		let genesis = client.info().genesis_hash;
+
+		let active_collator =
+			StorageActiveCollator::new(client.clone(), b"active_collator".to_vec());
+		assert_eq!(
+			Some(key.public()),
+			ActiveCollator::<Block>::active_collator(&active_collator, genesis)
+				.expect("Reads the active collator"),
+		);
+
+		let unset = StorageActiveCollator::new(client.clone(), b"unset".to_vec());
+		assert_eq!(
+			None,
+			ActiveCollator::<Block>::active_collator(&unset, genesis)
+				.expect("Reads the unset active collator"),
+		);
+	}
+}
@@ -0,0 +1,213 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Drive candidate production from relay chain blocks, without an overseer.
+//!
+//! Usually the collation generation subsystem of the overseer requests a candidate whenever the
+//! parachain is scheduled at a new relay block. [`drive_authoring`] instead tries to produce a
+//! candidate for every relay head of a stream, e.g. the
+//! [new best relay blocks](new_best_relay_heads) of a Polkadot client. The host decides when the
+//! heads are fed in, which makes the timing of candidate production controllable, and testable
+//! without a full overseer.
+
+use crate::{CollatorError, PHash};
+
+use cumulus_primitives::ValidationData;
+
+use sc_client_api::BlockchainEvents;
+use sp_api::ProvideRuntimeApi;
+use sp_runtime::generic::BlockId;
+
+use polkadot_node_primitives::Collation;
+use polkadot_primitives::v1::{
+	Block as PBlock, Id as ParaId, OccupiedCoreAssumption, ParachainHost,
+};
+
+use futures::{future, future::BoxFuture, Stream, StreamExt};
+use log::{debug, error};
+
+use std::sync::Arc;
+
+/// Provides the validation data of a candidate built on a relay parent.
+pub trait ValidationDataSource: Send + Sync {
+	/// Returns the validation data of the candidate built on `relay_parent`.
+	///
+	/// Returns `Ok(None)` if the parachain can not be built on `relay_parent`.
+	fn validation_data(&self, relay_parent: PHash) -> Result<Option<ValidationData>, String>;
+}
+
+impl<F> ValidationDataSource for F
+where
+	F: Fn(PHash) -> Result<Option<ValidationData>, String> + Send + Sync,
+{
+	fn validation_data(&self, relay_parent: PHash) -> Result<Option<ValidationData>, String> {
+		(self)(relay_parent)
+	}
+}
+
+/// Reads the validation data of `para_id` from a Polkadot client, assuming that the
+/// availability core of the parachain is free, like the collation generation subsystem.
+pub struct FullValidationData<PClient> {
+	polkadot_client: Arc<PClient>,
+	para_id: ParaId,
+}
+
+impl<PClient> FullValidationData<PClient> {
+	/// Create a new instance.
+	pub fn new(polkadot_client: Arc<PClient>, para_id: ParaId) -> Self {
+		Self {
+			polkadot_client,
+			para_id,
+		}
+	}
+}
+
+impl<PClient> ValidationDataSource for FullValidationData<PClient>
+where
+	PClient: ProvideRuntimeApi<PBlock> + Send + Sync,
+	PClient::Api: ParachainHost<PBlock>,
+{
+	fn validation_data(&self, relay_parent: PHash) -> Result<Option<ValidationData>, String> {
+		self.polkadot_client
+			.runtime_api()
+			.full_validation_data(
+				&BlockId::Hash(relay_parent),
+				self.para_id,
+				OccupiedCoreAssumption::Free,
+			)
+			.map_err(|e| {
+				format!(
+					"Failed to request the validation data for {}: {:?}",
+					relay_parent, e,
+				)
+			})
+	}
+}
+
+/// Returns the hashes of the new best blocks imported by `polkadot_client`.
+pub fn new_best_relay_heads<PClient>(polkadot_client: &PClient) -> impl Stream<Item = PHash> + Unpin
+where
+	PClient: BlockchainEvents<PBlock>,
+{
+	polkadot_client
+		.import_notification_stream()
+		.filter_map(|n| future::ready(if n.is_new_best { Some(n.hash) } else { None }))
+}
+
+/// Try to produce a candidate for every relay head of `relay_heads`.
+///
+/// `collate` produces the candidate, usually with
+/// [`Collator::produce_candidate`](crate::Collator::produce_candidate). Produced collations are
+/// passed to `on_collation`, together with their relay parent.
+pub async fn drive_authoring<S, C>(
+	mut relay_heads: S,
+	validation_data: Arc<dyn ValidationDataSource>,
+	collate: C,
+	on_collation: Arc<dyn Fn(PHash, Collation) + Send + Sync>,
+) where
+	S: Stream<Item = PHash> + Unpin,
+	C: Fn(PHash, ValidationData) -> BoxFuture<'static, Result<Option<Collation>, CollatorError>>,
+{
+	while let Some(relay_parent) = relay_heads.next().await {
+		let validation_data = match validation_data.validation_data(relay_parent) {
+			Ok(Some(validation_data)) => validation_data,
+			Ok(None) => {
+				debug!(
+					target: "cumulus-collator",
+					"No validation data at relay block `{}`, skipping candidate production.",
+					relay_parent,
+				);
+				continue;
+			}
+			Err(e) => {
+				error!(target: "cumulus-collator", "{}", e);
+				continue;
+			}
+		};
+
+		match collate(relay_parent, validation_data).await {
+			Ok(Some(collation)) => on_collation(relay_parent, collation),
+			Ok(None) => {}
+			Err(e) => error!(
+				target: "cumulus-collator",
+				"Failed to produce candidate for relay parent `{}` [{}]: {}",
+				relay_parent,
+				e.code(),
+				e,
+			),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::{executor::block_on, stream, FutureExt};
+	use parking_lot::Mutex;
+	use polkadot_primitives::v1::{BlockData, HeadData, PoV};
+
+	#[test]
+	fn collates_on_every_relay_head_with_validation_data() {
+		let relay_heads = stream::iter(vec![
+			PHash::repeat_byte(1),
+			PHash::repeat_byte(2),
+			PHash::repeat_byte(3),
+		]);
+
+		// The parachain is not scheduled at the second relay block.
+		let validation_data = Arc::new(|relay_parent: PHash| -> Result<_, String> {
+			Ok(if relay_parent == PHash::repeat_byte(2) {
+				None
+			} else {
+				Some(ValidationData::default())
+			})
+		});
+
+		let collate = |relay_parent: PHash, _| {
+			async move {
+				Ok::<_, CollatorError>(Some(Collation {
+					upward_messages: Vec::new(),
+					new_validation_code: None,
+					head_data: HeadData(relay_parent.as_bytes().to_vec()),
+					proof_of_validity: PoV {
+						block_data: BlockData(Vec::new()),
+					},
+					processed_downward_messages: 0,
+					horizontal_messages: Vec::new(),
+					hrmp_watermark: 0,
+				}))
+			}
+			.boxed()
+		};
+
+		let collations = Arc::new(Mutex::new(Vec::new()));
+		let on_collation = {
+			let collations = collations.clone();
+			Arc::new(move |relay_parent: PHash, collation: Collation| {
+				assert_eq!(relay_parent.as_bytes(), &collation.head_data.0[..]);
+				collations.lock().push(relay_parent);
+			})
+		};
+
+		block_on(drive_authoring(relay_heads, validation_data, collate, on_collation));
+
+		assert_eq!(
+			vec![PHash::repeat_byte(1), PHash::repeat_byte(3)],
+			*collations.lock(),
+		);
+	}
+}
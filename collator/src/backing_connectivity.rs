@@ -0,0 +1,139 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detect when the collator lost the connection to its backing group.
+//!
+//! Every produced candidate is sent to the validators of the backing group, which second it. When
+//! the connection to the backing group is lost, e.g. because the validator connections of the
+//! collator protocol dropped silently, candidates are still produced but never seconded, and the
+//! parachain misses its slots. The [`BackingConnectivity`] notices when candidates were produced
+//! for a given number of relay blocks without any being seconded, and reports it in the logs and
+//! the metrics of the collator.
+//!
+//! A candidate counts as seconded when its block is announced with the
+//! [`Statement::Seconded`](polkadot_node_primitives::Statement::Seconded) of a backing validator.
+
+use crate::PBlockNumber;
+
+use log::{info, warn};
+
+/// The default number of relay blocks without a seconded candidate before the backing group is
+/// considered lost.
+pub const DEFAULT_MAX_UNSECONDED_RELAY_BLOCKS: PBlockNumber = 10;
+
+/// Tracks whether the candidates of the collator are seconded by the backing group.
+pub struct BackingConnectivity {
+	max_unseconded_relay_blocks: PBlockNumber,
+	/// The relay parent of the first candidate produced since the last seconded one.
+	unseconded_since: Option<PBlockNumber>,
+	lost: bool,
+}
+
+impl Default for BackingConnectivity {
+	fn default() -> Self {
+		Self::new(DEFAULT_MAX_UNSECONDED_RELAY_BLOCKS)
+	}
+}
+
+impl BackingConnectivity {
+	/// Create a new instance.
+	///
+	/// The backing group is considered lost when no candidate was seconded for
+	/// `max_unseconded_relay_blocks` relay blocks while candidates were produced.
+	pub fn new(max_unseconded_relay_blocks: PBlockNumber) -> Self {
+		Self {
+			max_unseconded_relay_blocks,
+			unseconded_since: None,
+			lost: false,
+		}
+	}
+
+	/// Returns `true` if the backing group is considered lost.
+	pub fn is_lost(&self) -> bool {
+		self.lost
+	}
+
+	/// The number of relay blocks without a seconded candidate at `relay_block_number`.
+	pub fn unseconded_relay_blocks(&self, relay_block_number: PBlockNumber) -> PBlockNumber {
+		self.unseconded_since
+			.map_or(0, |since| relay_block_number.saturating_sub(since))
+	}
+
+	/// Note that a candidate was produced on the relay parent `relay_block_number`.
+	pub fn note_candidate(&mut self, relay_block_number: PBlockNumber) {
+		// The relay parents of the collation requests are not strictly increasing, e.g. on a
+		// relay chain reorg.
+		let since = self
+			.unseconded_since
+			.map_or(relay_block_number, |since| since.min(relay_block_number));
+		self.unseconded_since = Some(since);
+
+		if self.lost
+			|| relay_block_number.saturating_sub(since) < self.max_unseconded_relay_blocks
+		{
+			return;
+		}
+
+		warn!(
+			target: "cumulus-collator",
+			"No candidate was seconded since relay block #{}, the backing group may be unreachable.",
+			since,
+		);
+		self.lost = true;
+	}
+
+	/// Note that a produced candidate was seconded.
+	pub fn note_seconded(&mut self) {
+		if self.lost {
+			info!(
+				target: "cumulus-collator",
+				"A candidate was seconded again, the backing group is reachable.",
+			);
+		}
+
+		self.unseconded_since = None;
+		self.lost = false;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn is_lost_until_a_candidate_is_seconded() {
+		let mut connectivity = BackingConnectivity::new(2);
+
+		connectivity.note_candidate(10);
+		connectivity.note_candidate(11);
+		assert!(!connectivity.is_lost());
+		assert_eq!(1, connectivity.unseconded_relay_blocks(11));
+
+		connectivity.note_candidate(12);
+		assert!(connectivity.is_lost());
+		assert_eq!(2, connectivity.unseconded_relay_blocks(12));
+
+		connectivity.note_candidate(13);
+		assert!(connectivity.is_lost());
+
+		connectivity.note_seconded();
+		assert!(!connectivity.is_lost());
+		assert_eq!(0, connectivity.unseconded_relay_blocks(15));
+		connectivity.note_candidate(15);
+		connectivity.note_candidate(16);
+		assert!(!connectivity.is_lost());
+	}
+}
@@ -0,0 +1,309 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Repair parent blocks that are wrongly marked as known bad.
+//!
+//! The relay chain only includes candidates that were validated, so a parent head that was
+//! included can not be bad. If the local node still marks it as bad, e.g. because its import
+//! failed on a corrupted database, the collator can not build on the head of the parachain
+//! anymore. With a [`BadBlockRepair`], the collator clears the mark and fetches the block again
+//! through the [`ParentRecovery`](crate::parent_recovery::ParentRecovery), which imports it
+//! again.
+//!
+//! The client of the node does not remember blocks that failed to import. [`BadBlocks`] does:
+//! its [`block_import`](BadBlocks::block_import) marks every block whose import fails and
+//! refuses to import it again, until the collator clears the mark.
+
+use log::warn;
+use parking_lot::Mutex;
+use sp_consensus::{
+	import_queue::CacheKeyId, BlockCheckParams, BlockImport, BlockImportParams, ImportResult,
+};
+use sp_runtime::traits::Block as BlockT;
+
+use std::{
+	collections::{HashMap, VecDeque},
+	sync::Arc,
+};
+
+/// The number of repaired blocks that are remembered.
+///
+/// Every block is only repaired once, so a block that is really bad is not fetched over and over.
+const MAX_REPAIRED: usize = 16;
+
+/// The number of bad blocks that are remembered by [`BadBlocks`].
+const MAX_BAD_BLOCKS: usize = 1024;
+
+/// Clears the mark of a block as known bad.
+pub trait BadBlockRepair<Block: BlockT>: Send + Sync {
+	/// Remove the mark of the block `hash` as known bad, so it can be imported again.
+	fn clear_bad_mark(&self, hash: Block::Hash) -> Result<(), String>;
+
+	/// Returns `true` if the block `hash` is marked as bad, even if the client does not know it.
+	fn is_marked_bad(&self, _hash: Block::Hash) -> bool {
+		false
+	}
+}
+
+impl<Block, F> BadBlockRepair<Block> for F
+where
+	Block: BlockT,
+	F: Fn(Block::Hash) -> Result<(), String> + Send + Sync,
+{
+	fn clear_bad_mark(&self, hash: Block::Hash) -> Result<(), String> {
+		(self)(hash)
+	}
+}
+
+/// The blocks of the node that failed to import, see the [module docs](self).
+///
+/// Clones share the marks.
+pub struct BadBlocks<Block: BlockT>(Arc<Mutex<VecDeque<Block::Hash>>>);
+
+impl<Block: BlockT> Clone for BadBlocks<Block> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+impl<Block: BlockT> Default for BadBlocks<Block> {
+	fn default() -> Self {
+		Self(Default::default())
+	}
+}
+
+impl<Block: BlockT> BadBlocks<Block> {
+	/// Mark the block `hash` as bad.
+	pub fn mark_bad(&self, hash: Block::Hash) {
+		let mut bad_blocks = self.0.lock();
+		if bad_blocks.contains(&hash) {
+			return;
+		}
+
+		if bad_blocks.len() >= MAX_BAD_BLOCKS {
+			bad_blocks.pop_front();
+		}
+		bad_blocks.push_back(hash);
+	}
+
+	/// Returns `true` if the block `hash` is marked as bad.
+	pub fn is_bad(&self, hash: &Block::Hash) -> bool {
+		self.0.lock().contains(hash)
+	}
+
+	/// Wrap the `inner` block import, to mark the blocks that fail to import.
+	pub fn block_import<I>(&self, inner: I) -> MarkBadBlocks<Block, I> {
+		MarkBadBlocks {
+			inner,
+			bad_blocks: self.clone(),
+		}
+	}
+}
+
+impl<Block: BlockT> BadBlockRepair<Block> for BadBlocks<Block> {
+	fn clear_bad_mark(&self, hash: Block::Hash) -> Result<(), String> {
+		self.0.lock().retain(|bad| *bad != hash);
+		Ok(())
+	}
+
+	fn is_marked_bad(&self, hash: Block::Hash) -> bool {
+		self.is_bad(&hash)
+	}
+}
+
+/// A block import that marks the blocks that fail to import in [`BadBlocks`].
+///
+/// Blocks that are marked are not imported again, until the mark is cleared.
+pub struct MarkBadBlocks<Block: BlockT, I> {
+	inner: I,
+	bad_blocks: BadBlocks<Block>,
+}
+
+impl<Block, I> BlockImport<Block> for MarkBadBlocks<Block, I>
+where
+	Block: BlockT,
+	I: BlockImport<Block>,
+{
+	type Error = I::Error;
+	type Transaction = I::Transaction;
+
+	fn check_block(&mut self, block: BlockCheckParams<Block>) -> Result<ImportResult, Self::Error> {
+		if self.bad_blocks.is_bad(&block.hash) {
+			return Ok(ImportResult::KnownBad);
+		}
+
+		self.inner.check_block(block)
+	}
+
+	fn import_block(
+		&mut self,
+		block: BlockImportParams<Block, Self::Transaction>,
+		cache: HashMap<CacheKeyId, Vec<u8>>,
+	) -> Result<ImportResult, Self::Error> {
+		let hash = block.post_hash();
+		if self.bad_blocks.is_bad(&hash) {
+			return Ok(ImportResult::KnownBad);
+		}
+
+		let res = self.inner.import_block(block, cache);
+		if matches!(res, Ok(ImportResult::KnownBad) | Err(_)) {
+			self.bad_blocks.mark_bad(hash);
+		}
+
+		res
+	}
+}
+
+/// Repairs included parent blocks that are marked as known bad, at most once per block.
+pub(crate) struct KnownBadRepair<Block: BlockT> {
+	repair: Arc<dyn BadBlockRepair<Block>>,
+	repaired: Arc<Mutex<VecDeque<Block::Hash>>>,
+}
+
+impl<Block: BlockT> Clone for KnownBadRepair<Block> {
+	fn clone(&self) -> Self {
+		Self {
+			repair: self.repair.clone(),
+			repaired: self.repaired.clone(),
+		}
+	}
+}
+
+impl<Block: BlockT> KnownBadRepair<Block> {
+	pub(crate) fn new(repair: Arc<dyn BadBlockRepair<Block>>) -> Self {
+		Self {
+			repair,
+			repaired: Default::default(),
+		}
+	}
+
+	/// Returns `true` if the block `hash` is marked as bad by the repair.
+	pub(crate) fn is_marked_bad(&self, hash: Block::Hash) -> bool {
+		self.repair.is_marked_bad(hash)
+	}
+
+	/// Clear the mark of the included parent `hash` as known bad.
+	///
+	/// Returns `true` if the mark was cleared and the block should be fetched again.
+	pub(crate) fn try_repair(&self, hash: Block::Hash) -> bool {
+		let mut repaired = self.repaired.lock();
+		if repaired.contains(&hash) {
+			return false;
+		}
+
+		if repaired.len() >= MAX_REPAIRED {
+			repaired.pop_front();
+		}
+		repaired.push_back(hash);
+
+		warn!(
+			target: "cumulus-collator",
+			"Parent block `{:?}` was included by the relay chain, but is marked as known bad. \
+			Clearing the mark and fetching the block again.",
+			hash,
+		);
+
+		match self.repair.clear_bad_mark(hash) {
+			Ok(()) => true,
+			Err(e) => {
+				warn!(
+					target: "cumulus-collator",
+					"Failed to clear the known bad mark of block `{:?}`: {}",
+					hash,
+					e,
+				);
+				false
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_runtime::{Block, Header};
+	use sp_consensus::BlockOrigin;
+	use sp_core::H256;
+	use sp_runtime::traits::Header as HeaderT;
+
+	/// A block import of a corrupted database, every import fails.
+	struct CorruptedImport;
+
+	impl BlockImport<Block> for CorruptedImport {
+		type Error = sp_consensus::Error;
+		type Transaction = ();
+
+		fn check_block(&mut self, _: BlockCheckParams<Block>) -> Result<ImportResult, Self::Error> {
+			Ok(ImportResult::imported(false))
+		}
+
+		fn import_block(
+			&mut self,
+			_: BlockImportParams<Block, ()>,
+			_: HashMap<CacheKeyId, Vec<u8>>,
+		) -> Result<ImportResult, Self::Error> {
+			Err(sp_consensus::Error::ClientImport("Corrupted database".into()))
+		}
+	}
+
+	#[test]
+	fn repairs_every_block_once() {
+		let cleared = Arc::new(Mutex::new(Vec::new()));
+		let repair = KnownBadRepair::<Block>::new({
+			let cleared = cleared.clone();
+			Arc::new(move |hash: H256| -> Result<(), String> {
+				cleared.lock().push(hash);
+				Ok(())
+			})
+		});
+
+		assert!(repair.try_repair(H256::repeat_byte(1)));
+		assert!(!repair.try_repair(H256::repeat_byte(1)));
+		assert!(repair.try_repair(H256::repeat_byte(2)));
+		assert_eq!(vec![H256::repeat_byte(1), H256::repeat_byte(2)], *cleared.lock());
+	}
+
+	#[test]
+	fn marks_blocks_that_fail_to_import() {
+		let bad_blocks = BadBlocks::<Block>::default();
+		let mut block_import = bad_blocks.block_import(CorruptedImport);
+		let header = Header::new(
+			1,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		let hash = header.hash();
+		let import = |block_import: &mut MarkBadBlocks<Block, CorruptedImport>| {
+			block_import.import_block(
+				BlockImportParams::new(BlockOrigin::NetworkBroadcast, header.clone()),
+				HashMap::new(),
+			)
+		};
+
+		assert!(import(&mut block_import).is_err());
+		assert!(bad_blocks.is_marked_bad(hash));
+
+		// The block is not imported again while it is marked.
+		assert!(matches!(import(&mut block_import), Ok(ImportResult::KnownBad)));
+
+		bad_blocks.clear_bad_mark(hash).unwrap();
+		assert!(!bad_blocks.is_marked_bad(hash));
+		assert!(import(&mut block_import).is_err());
+	}
+}
@@ -0,0 +1,564 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Builder for a [`Collator`].
+//!
+//! [`start_collator`](crate::start_collator) builds a collator that is driven by the Polkadot
+//! overseer. Hosts with their own distribution layer, e.g. a testnet harness or a bridge, can
+//! build a collator without an overseer and call [`Collator::produce_candidate`] directly.
+
+use crate::{
+	active_collator::{ActiveCollator, ActiveCollatorCheck},
+	backing_connectivity::{BackingConnectivity, DEFAULT_MAX_UNSECONDED_RELAY_BLOCKS},
+	bad_block_repair::{BadBlockRepair, KnownBadRepair},
+	circuit_breaker::CircuitBreaker,
+	collator_service::RuntimeCollationInfo,
+	divergence_watchdog::{DivergenceWatchdog, DEFAULT_MAX_DIVERGENT_RELAY_BLOCKS},
+	downward_messages::{
+		DmqBudget, RetrieveDmqContents, WithDmqBudget, WithDmqOverride, WithMessageMetrics,
+	},
+	events::{CollatorEventHandler, DefaultEventHandler, PendingUpgrades},
+	execution_budget::ExecutionBudget,
+	inclusion_latency::InclusionTracker,
+	journal::CollationJournal,
+	parent_recovery::ParentRecovery,
+	parent_resolution::RelayChainValidationData,
+	pov_export::PoVExporter,
+	pov_size_limit::PoVSizeLimit,
+	pre_validation::PreValidator,
+	proof_recorder::{ProofRecorderProvider, ProposerProofRecorder},
+	proposal_stats::ReadyTransactions,
+	receipts::CollationReceipts,
+	reexecution::ExecuteOwnBlock,
+	runtime_divergence::RuntimeDivergenceDetector,
+	skip_reasons::SkipReasons,
+	storage_diff::StorageDiffs,
+	upgrade_only::UpgradeOnlyBuilder,
+	Collator, CollatorStatus, Metrics, PBlockNumber, ParachainCollatorService,
+};
+#[cfg(any(test, feature = "test-helpers"))]
+use crate::delayed_relay_chain::{DelayedRelayChainInterface, RelayChainDelay};
+
+use cumulus_consensus::{ParachainForkChoice, RelayChainForkChoice};
+use cumulus_network::{
+	announce_signing::AnnounceSigner,
+	announcement::{AnnounceBlock, CollationAnnouncement},
+	AnnouncePolicy, AnnounceTimeoutAction, WaitToAnnounce,
+};
+use cumulus_primitives::RelayChainInterface;
+
+use sp_core::traits::SpawnNamed;
+use sp_inherents::InherentDataProviders;
+use sp_runtime::traits::Block as BlockT;
+
+use polkadot_overseer::OverseerHandler;
+use polkadot_primitives::v1::{CollatorId, CollatorPair};
+
+use parking_lot::Mutex;
+
+use std::{marker::PhantomData, sync::Arc};
+
+/// Announce blocks after their candidate was seconded, which is observed through the overseer.
+struct Announcement<Block: BlockT> {
+	overseer_handler: OverseerHandler,
+	spawner: Arc<dyn SpawnNamed + Send + Sync>,
+	announce_block: Arc<dyn AnnounceBlock<Block>>,
+}
+
+/// Builder for a [`Collator`].
+pub struct CollatorBuilder<Block: BlockT, PF, BI, BS, Backend> {
+	proposer_factory: PF,
+	inherent_data_providers: InherentDataProviders,
+	block_import: BI,
+	block_status: Arc<BS>,
+	backend: Arc<Backend>,
+	relay_chain: Arc<dyn RelayChainInterface>,
+	relay_chain_validation_data: Arc<dyn RelayChainValidationData>,
+	announcement: Option<Announcement<Block>>,
+	announce_policy: AnnouncePolicy,
+	announce_signer: Option<Arc<dyn AnnounceSigner<Block>>>,
+	allow_multiple_collations: bool,
+	metrics: Metrics,
+	pov_exporter: Option<PoVExporter>,
+	status: CollatorStatus,
+	fork_choice: Arc<dyn ParachainForkChoice<Block>>,
+	pre_validate: bool,
+	blocking_spawner: Option<Arc<dyn SpawnNamed + Send + Sync>>,
+	ready_transactions: Option<Arc<dyn ReadyTransactions>>,
+	parent_recovery: Option<Arc<dyn ParentRecovery<Block>>>,
+	proof_recorder: Arc<dyn ProofRecorderProvider<Block>>,
+	execution_budget: ExecutionBudget,
+	inclusion_tracker: InclusionTracker,
+	pov_size_limit: PoVSizeLimit,
+	journal: Option<CollationJournal<Block::Hash>>,
+	upgrade_only_builder: Option<Arc<dyn UpgradeOnlyBuilder<Block>>>,
+	max_divergent_relay_blocks: PBlockNumber,
+	storage_diffs: Option<StorageDiffs<Block::Hash>>,
+	skip_reasons: Option<SkipReasons<Block::Hash>>,
+	runtime_collation_info: Option<Arc<dyn RuntimeCollationInfo<Block>>>,
+	bad_block_repair: Option<Arc<dyn BadBlockRepair<Block>>>,
+	receipts: Option<CollationReceipts<Block::Hash>>,
+	reexecute_own_blocks: Option<Arc<dyn ExecuteOwnBlock<Block>>>,
+	runtime_divergence: Option<RuntimeDivergenceDetector<Block>>,
+	event_handler: Arc<dyn CollatorEventHandler<Block>>,
+	pending_upgrades: PendingUpgrades<Block::Hash>,
+	active_collator: Option<ActiveCollatorCheck<Block>>,
+	dmq_budget: Option<DmqBudget>,
+	max_unseconded_relay_blocks: PBlockNumber,
+}
+
+impl<Block: BlockT, PF, BI, BS, Backend> CollatorBuilder<Block, PF, BI, BS, Backend> {
+	/// Create a new builder with the required components.
+	///
+	/// The `relay_chain` and `relay_chain_validation_data` give access to the relay chain state
+	/// at the relay parent of a candidate.
+	pub fn new(
+		proposer_factory: PF,
+		inherent_data_providers: InherentDataProviders,
+		block_import: BI,
+		block_status: Arc<BS>,
+		backend: Arc<Backend>,
+		relay_chain: Arc<dyn RelayChainInterface>,
+		relay_chain_validation_data: Arc<dyn RelayChainValidationData>,
+	) -> Self {
+		Self {
+			proposer_factory,
+			inherent_data_providers,
+			block_import,
+			block_status,
+			backend,
+			relay_chain,
+			relay_chain_validation_data,
+			announcement: None,
+			announce_policy: AnnouncePolicy::default(),
+			announce_signer: None,
+			allow_multiple_collations: false,
+			metrics: Metrics::default(),
+			pov_exporter: None,
+			status: CollatorStatus::default(),
+			fork_choice: Arc::new(RelayChainForkChoice),
+			pre_validate: false,
+			blocking_spawner: None,
+			ready_transactions: None,
+			parent_recovery: None,
+			proof_recorder: Arc::new(ProposerProofRecorder),
+			execution_budget: ExecutionBudget::default(),
+			inclusion_tracker: InclusionTracker::default(),
+			pov_size_limit: PoVSizeLimit::default(),
+			journal: None,
+			upgrade_only_builder: None,
+			max_divergent_relay_blocks: DEFAULT_MAX_DIVERGENT_RELAY_BLOCKS,
+			storage_diffs: None,
+			skip_reasons: None,
+			runtime_collation_info: None,
+			bad_block_repair: None,
+			receipts: None,
+			reexecute_own_blocks: None,
+			runtime_divergence: None,
+			event_handler: Arc::new(DefaultEventHandler::default()),
+			pending_upgrades: PendingUpgrades::default(),
+			active_collator: None,
+			dmq_budget: None,
+			max_unseconded_relay_blocks: DEFAULT_MAX_UNSECONDED_RELAY_BLOCKS,
+		}
+	}
+
+	/// Announce produced blocks with `announce_block` after their candidate was seconded.
+	///
+	/// Without this, the host is responsible for distributing the produced collations and
+	/// blocks. The circuit breaker is only active with announcements, as it relies on observing
+	/// the seconding of candidates.
+	pub fn announce_with_overseer(
+		mut self,
+		overseer_handler: OverseerHandler,
+		spawner: Arc<dyn SpawnNamed + Send + Sync>,
+		announce_block: Arc<dyn AnnounceBlock<Block>>,
+	) -> Self {
+		self.announcement = Some(Announcement {
+			overseer_handler,
+			spawner,
+			announce_block,
+		});
+		self
+	}
+
+	/// Use the given `policy` for announcing blocks.
+	///
+	/// Has no effect without [`announce_with_overseer`](Self::announce_with_overseer), which
+	/// can be called before or after this.
+	pub fn announce_policy(mut self, policy: AnnouncePolicy) -> Self {
+		self.announce_policy = policy;
+		self
+	}
+
+	/// Sign the announcements of blocks with the given `signer`.
+	///
+	/// Has no effect without [`announce_with_overseer`](Self::announce_with_overseer), which
+	/// can be called before or after this.
+	pub fn announce_signer(mut self, signer: Arc<dyn AnnounceSigner<Block>>) -> Self {
+		self.announce_signer = Some(signer);
+		self
+	}
+
+	/// Allow producing more than one candidate for the same relay parent.
+	pub fn allow_multiple_collations(mut self, allow: bool) -> Self {
+		self.allow_multiple_collations = allow;
+		self
+	}
+
+	/// Report metrics to the given `metrics`.
+	pub fn metrics(mut self, metrics: Metrics) -> Self {
+		self.metrics = metrics;
+		self
+	}
+
+	/// Export every produced PoV with the given `exporter`.
+	pub fn pov_exporter(mut self, exporter: PoVExporter) -> Self {
+		self.pov_exporter = Some(exporter);
+		self
+	}
+
+	/// Update the given `status` while collating.
+	pub fn status(mut self, status: CollatorStatus) -> Self {
+		self.status = status;
+		self
+	}
+
+	/// Use the given fork choice for produced blocks, defaults to [`RelayChainForkChoice`].
+	pub fn fork_choice(mut self, fork_choice: Arc<dyn ParachainForkChoice<Block>>) -> Self {
+		self.fork_choice = fork_choice;
+		self
+	}
+
+	/// Run every candidate through the local `validate_block`.
+	pub fn pre_validate(mut self, pre_validate: bool) -> Self {
+		self.pre_validate = pre_validate;
+		self
+	}
+
+	/// Run the blocking parts of candidate production, e.g. the execution of the local
+	/// `validate_block`, on a blocking task of `spawner`.
+	///
+	/// By default they run on the task that produces the candidate.
+	pub fn blocking_spawner(mut self, spawner: Arc<dyn SpawnNamed + Send + Sync>) -> Self {
+		self.blocking_spawner = Some(spawner);
+		self
+	}
+
+	/// Report how many of the `ready_transactions` were included in a candidate.
+	pub fn ready_transactions(mut self, ready_transactions: Arc<dyn ReadyTransactions>) -> Self {
+		self.ready_transactions = Some(ready_transactions);
+		self
+	}
+
+	/// Fetch unknown parent blocks with the given `parent_recovery`.
+	pub fn parent_recovery(mut self, parent_recovery: Arc<dyn ParentRecovery<Block>>) -> Self {
+		self.parent_recovery = Some(parent_recovery);
+		self
+	}
+
+	/// Provide the storage proof of the candidates with the given `proof_recorder`, defaults to
+	/// [`ProposerProofRecorder`].
+	pub fn proof_recorder(mut self, proof_recorder: Arc<dyn ProofRecorderProvider<Block>>) -> Self {
+		self.proof_recorder = proof_recorder;
+		self
+	}
+
+	/// Derive the maximum duration of a proposal with the given `execution_budget`.
+	pub fn execution_budget(mut self, execution_budget: ExecutionBudget) -> Self {
+		self.execution_budget = execution_budget;
+		self
+	}
+
+	/// Note the produced candidates in the given `inclusion_tracker`.
+	///
+	/// The inclusion of the candidates needs to be reported to it by the host, see
+	/// [`InclusionTracker::note_included`].
+	pub fn inclusion_tracker(mut self, inclusion_tracker: InclusionTracker) -> Self {
+		self.inclusion_tracker = inclusion_tracker;
+		self
+	}
+
+	/// Publish the maximum PoV size at the relay parent of every candidate in `pov_size_limit`.
+	pub fn pov_size_limit(mut self, pov_size_limit: PoVSizeLimit) -> Self {
+		self.pov_size_limit = pov_size_limit;
+		self
+	}
+
+	/// Append every collation request to the given `journal`.
+	pub fn journal(mut self, journal: CollationJournal<Block::Hash>) -> Self {
+		self.journal = Some(journal);
+		self
+	}
+
+	/// Replace blocks whose runtime upgrade does not fit into the PoV with a block that only
+	/// carries the upgrade, built by `builder`.
+	///
+	/// Without this, such a block is submitted as is and will not be backed.
+	pub fn upgrade_only_builder(mut self, builder: Arc<dyn UpgradeOnlyBuilder<Block>>) -> Self {
+		self.upgrade_only_builder = Some(builder);
+		self
+	}
+
+	/// Halt candidate production when the local chain diverged from the head of the parachain
+	/// on the relay chain for more than `max` relay blocks, defaults to
+	/// [`DEFAULT_MAX_DIVERGENT_RELAY_BLOCKS`].
+	pub fn max_divergent_relay_blocks(mut self, max: PBlockNumber) -> Self {
+		self.max_divergent_relay_blocks = max;
+		self
+	}
+
+	/// Send the storage changes of every produced block to the subscribers of `storage_diffs`.
+	pub fn storage_diffs(mut self, storage_diffs: StorageDiffs<Block::Hash>) -> Self {
+		self.storage_diffs = Some(storage_diffs);
+		self
+	}
+
+	/// Note why candidate production on a parent block was skipped in `skip_reasons`.
+	pub fn skip_reasons(mut self, skip_reasons: SkipReasons<Block::Hash>) -> Self {
+		self.skip_reasons = Some(skip_reasons);
+		self
+	}
+
+	/// Take the output of collated blocks from the runtime with `collation_info`, see
+	/// [`runtime_collation_info`](crate::runtime_collation_info).
+	///
+	/// By default it is read from the state of the block.
+	pub fn runtime_collation_info(
+		mut self,
+		collation_info: Arc<dyn RuntimeCollationInfo<Block>>,
+	) -> Self {
+		self.runtime_collation_info = Some(collation_info);
+		self
+	}
+
+	/// Clear the known bad mark of parent blocks that were included by the relay chain with
+	/// `repair` and fetch them again with the [`parent_recovery`](Self::parent_recovery).
+	pub fn bad_block_repair(mut self, repair: Arc<dyn BadBlockRepair<Block>>) -> Self {
+		self.bad_block_repair = Some(repair);
+		self
+	}
+
+	/// Sign the receipts of the produced candidates with the collator `key` and store them in
+	/// `receipts`.
+	pub fn collation_receipts(
+		mut self,
+		receipts: CollationReceipts<Block::Hash>,
+		key: CollatorPair,
+	) -> Self {
+		self.receipts = Some(receipts.with_key(key));
+		self
+	}
+
+	/// Execute every produced block once more with `executor` before importing it.
+	///
+	/// By default, produced blocks are imported with the storage changes computed while
+	/// building them. See [`reexecution`](crate::reexecution).
+	pub fn reexecute_own_blocks(mut self, executor: Arc<dyn ExecuteOwnBlock<Block>>) -> Self {
+		self.reexecute_own_blocks = Some(executor);
+		self
+	}
+
+	/// Check every produced block for a divergence of the native and the WASM runtime with
+	/// `detector`, see [`runtime_divergence`](crate::runtime_divergence).
+	pub fn detect_runtime_divergence(mut self, detector: RuntimeDivergenceDetector<Block>) -> Self {
+		self.runtime_divergence = Some(detector);
+		self
+	}
+
+	/// Report the events of the collator to `handler`, instead of only logging them.
+	pub fn event_handler(mut self, handler: Arc<dyn CollatorEventHandler<Block>>) -> Self {
+		self.event_handler = handler;
+		self
+	}
+
+	/// Note the produced blocks that schedule an upgrade in `pending_upgrades`.
+	///
+	/// The upgrade is reported as pending once the relay chain includes the block.
+	pub(crate) fn pending_upgrades(
+		mut self,
+		pending_upgrades: PendingUpgrades<Block::Hash>,
+	) -> Self {
+		self.pending_upgrades = pending_upgrades;
+		self
+	}
+
+	/// Only produce candidates while the collator `key` is designated by `active_collator`.
+	///
+	/// See [`active_collator`](crate::active_collator).
+	pub fn active_collator(
+		mut self,
+		active_collator: Arc<dyn ActiveCollator<Block>>,
+		key: CollatorId,
+	) -> Self {
+		self.active_collator = Some(ActiveCollatorCheck::new(active_collator, key));
+		self
+	}
+
+	/// Consider the backing group lost when produced candidates were not seconded for `max`
+	/// relay blocks, defaults to [`DEFAULT_MAX_UNSECONDED_RELAY_BLOCKS`].
+	pub fn max_unseconded_relay_blocks(mut self, max: PBlockNumber) -> Self {
+		self.max_unseconded_relay_blocks = max;
+		self
+	}
+
+	/// Retrieve the downward messages of the candidates with `retrieve`, instead of taking the
+	/// downward message queue of the `relay_chain` as is.
+	pub fn retrieve_dmq_contents(mut self, retrieve: Arc<dyn RetrieveDmqContents>) -> Self {
+		self.relay_chain = Arc::new(WithDmqOverride {
+			relay_chain: self.relay_chain,
+			retrieve,
+		});
+		self
+	}
+
+	/// Only provide the leading downward messages within `budget` to a candidate, the remaining
+	/// messages are provided to the next candidates.
+	///
+	/// By default all downward messages are provided.
+	pub fn dmq_budget(mut self, budget: DmqBudget) -> Self {
+		self.dmq_budget = Some(budget);
+		self
+	}
+
+	/// Add the given `delay` to every call to the relay chain, see [`delayed_relay_chain`].
+	///
+	/// [`delayed_relay_chain`]: crate::delayed_relay_chain
+	#[cfg(any(test, feature = "test-helpers"))]
+	pub fn relay_chain_delay(mut self, delay: RelayChainDelay) -> Self {
+		self.relay_chain = Arc::new(DelayedRelayChainInterface::new(self.relay_chain, delay));
+		self.relay_chain_validation_data = Arc::new(DelayedRelayChainInterface::new(
+			self.relay_chain_validation_data,
+			delay,
+		));
+		self
+	}
+
+	/// Build the [`Collator`].
+	pub fn build(self) -> Collator<Block, PF, BI, BS, Backend> {
+		let circuit_breaker = Arc::new(Mutex::new(CircuitBreaker::default()));
+		let backing_connectivity = Arc::new(Mutex::new(BackingConnectivity::new(
+			self.max_unseconded_relay_blocks,
+		)));
+
+		let metrics = self.metrics.clone();
+		let announce_policy = self.announce_policy;
+		let announce_signer = self.announce_signer;
+		let wait_to_announce = self.announcement.map(|announcement| {
+			// A block is announced with a justification only after its candidate was seconded,
+			// announcements without one are sent when the announce timeout is reached.
+			let announce_block: Arc<dyn AnnounceBlock<Block>> = {
+				let circuit_breaker = circuit_breaker.clone();
+				let backing_connectivity = backing_connectivity.clone();
+				let announce_block = announcement.announce_block;
+				Arc::new(move |hash: Block::Hash, announcement: CollationAnnouncement| {
+					if !matches!(announcement, CollationAnnouncement::Empty) {
+						circuit_breaker.lock().note_success();
+						backing_connectivity.lock().note_seconded();
+					}
+					announce_block.announce_block(hash, announcement)
+				})
+			};
+
+			let wait_to_announce = WaitToAnnounce::new(
+				announcement.spawner,
+				announce_block,
+				announcement.overseer_handler,
+			)
+			.with_policy(announce_policy)
+			.with_timeout_observer(Arc::new(move |action: AnnounceTimeoutAction| {
+				metrics.report_announce_timeout(action)
+			}));
+
+			match announce_signer {
+				Some(signer) => wait_to_announce.with_signer(signer),
+				None => wait_to_announce,
+			}
+		});
+
+		// The metrics report all pending messages, before they are truncated to the budget.
+		let mut relay_chain: Arc<dyn RelayChainInterface> = Arc::new(WithMessageMetrics {
+			relay_chain: self.relay_chain,
+			metrics: self.metrics.clone(),
+		});
+		if let Some(budget) = self.dmq_budget {
+			relay_chain = Arc::new(WithDmqBudget {
+				relay_chain,
+				budget,
+			});
+		}
+
+		let mut service = ParachainCollatorService::new(
+			self.block_status.clone(),
+			self.backend.clone(),
+			wait_to_announce,
+		);
+		if let Some(skip_reasons) = self.skip_reasons {
+			service = service.with_skip_reasons(skip_reasons);
+		}
+		if let Some(collation_info) = self.runtime_collation_info {
+			service = service.with_collation_info(collation_info);
+		}
+
+		Collator {
+			proposer_factory: Arc::new(Mutex::new(self.proposer_factory)),
+			inherent_data_providers: self.inherent_data_providers,
+			_phantom: PhantomData,
+			block_import: Arc::new(Mutex::new(self.block_import)),
+			service,
+			block_status: self.block_status,
+			backend: self.backend,
+			relay_chain,
+			relay_chain_validation_data: self.relay_chain_validation_data,
+			collated_relay_parents: Default::default(),
+			allow_multiple_collations: self.allow_multiple_collations,
+			circuit_breaker,
+			divergence_watchdog: Arc::new(Mutex::new(DivergenceWatchdog::new(
+				self.max_divergent_relay_blocks,
+			))),
+			metrics: self.metrics,
+			pov_exporter: self.pov_exporter,
+			status: self.status,
+			fork_choice: self.fork_choice,
+			pre_validator: if self.pre_validate {
+				let pre_validator = PreValidator::default();
+				Some(match self.blocking_spawner {
+					Some(spawner) => pre_validator.with_spawner(spawner),
+					None => pre_validator,
+				})
+			} else {
+				None
+			},
+			ready_transactions: self.ready_transactions,
+			parent_recovery: self.parent_recovery,
+			proof_recorder: self.proof_recorder,
+			execution_budget: self.execution_budget,
+			inclusion_tracker: self.inclusion_tracker,
+			pov_size_limit: self.pov_size_limit,
+			journal: self.journal,
+			upgrade_only_builder: self.upgrade_only_builder,
+			storage_diffs: self.storage_diffs,
+			known_bad_repair: self.bad_block_repair.map(KnownBadRepair::new),
+			receipts: self.receipts,
+			reexecute_own_blocks: self.reexecute_own_blocks,
+			runtime_divergence: self.runtime_divergence,
+			event_handler: self.event_handler,
+			pending_upgrades: self.pending_upgrades,
+			active_collator: self.active_collator,
+			backing_connectivity,
+		}
+	}
+}
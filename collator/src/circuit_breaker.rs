@@ -0,0 +1,172 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Circuit breaker for candidate production.
+//!
+//! When the relay chain keeps rejecting our candidates, for example because of a validation code
+//! mismatch, there is no point in building more candidates that are doomed to fail. The
+//! [`CircuitBreaker`] counts the candidates that were not seconded and, once a threshold is
+//! reached, stops candidate production for an exponentially growing amount of time.
+
+use log::warn;
+
+use std::time::{Duration, Instant};
+
+/// The default number of consecutive failed candidates before the circuit breaker opens.
+pub const DEFAULT_THRESHOLD: u32 = 10;
+
+/// The default backoff after the circuit breaker opened for the first time.
+pub const DEFAULT_BASE_BACKOFF: Duration = Duration::from_secs(6);
+
+/// The default maximum backoff.
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// The maximum exponent used for the exponential backoff.
+const MAX_BACKOFF_EXPONENT: u32 = 16;
+
+/// Stops candidate production when the last candidates were all not seconded.
+pub struct CircuitBreaker {
+	threshold: u32,
+	base_backoff: Duration,
+	max_backoff: Duration,
+	/// Is the last produced candidate still waiting to be seconded?
+	pending: bool,
+	consecutive_failures: u32,
+	open_until: Option<Instant>,
+}
+
+impl Default for CircuitBreaker {
+	fn default() -> Self {
+		Self::new(DEFAULT_THRESHOLD, DEFAULT_BASE_BACKOFF, DEFAULT_MAX_BACKOFF)
+	}
+}
+
+impl CircuitBreaker {
+	/// Create a new instance.
+	///
+	/// - `threshold`: The number of consecutive failed candidates before the breaker opens.
+	/// - `base_backoff`: The backoff after the breaker opened for the first time.
+	/// - `max_backoff`: The maximum backoff.
+	pub fn new(threshold: u32, base_backoff: Duration, max_backoff: Duration) -> Self {
+		Self {
+			threshold,
+			base_backoff,
+			max_backoff,
+			pending: false,
+			consecutive_failures: 0,
+			open_until: None,
+		}
+	}
+
+	/// Returns `true` if a new candidate may be produced at `now`.
+	pub fn allow_collation(&self, now: Instant) -> bool {
+		!self.is_open(now)
+	}
+
+	/// Returns `true` if the breaker is open at `now`.
+	pub fn is_open(&self, now: Instant) -> bool {
+		self.open_until.map_or(false, |until| now < until)
+	}
+
+	/// The number of consecutive candidates that were not seconded.
+	pub fn consecutive_failures(&self) -> u32 {
+		self.consecutive_failures
+	}
+
+	/// Note that a new candidate was produced at `now`.
+	///
+	/// If the previous candidate was not seconded in the meantime, it is counted as failed.
+	pub fn note_candidate(&mut self, now: Instant) {
+		if self.pending {
+			self.note_failure(now);
+		}
+
+		self.pending = true;
+	}
+
+	/// Note that the last produced candidate was seconded.
+	pub fn note_success(&mut self) {
+		self.pending = false;
+		self.consecutive_failures = 0;
+		self.open_until = None;
+	}
+
+	fn note_failure(&mut self, now: Instant) {
+		self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+
+		if self.consecutive_failures < self.threshold {
+			return;
+		}
+
+		let exponent = (self.consecutive_failures - self.threshold).min(MAX_BACKOFF_EXPONENT);
+		let backoff = self
+			.base_backoff
+			.checked_mul(1 << exponent)
+			.map_or(self.max_backoff, |b| b.min(self.max_backoff));
+
+		warn!(
+			target: "cumulus-collator",
+			"The last {} candidates were not seconded, pausing candidate production for {:?}.",
+			self.consecutive_failures,
+			backoff,
+		);
+
+		self.open_until = Some(now + backoff);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn opens_after_threshold_and_backs_off_exponentially() {
+		let mut breaker = CircuitBreaker::new(2, Duration::from_secs(1), Duration::from_secs(3));
+		let now = Instant::now();
+
+		// The first candidate can not fail, as there was no previous one.
+		breaker.note_candidate(now);
+		breaker.note_candidate(now);
+		assert!(breaker.allow_collation(now));
+
+		breaker.note_candidate(now);
+		assert_eq!(2, breaker.consecutive_failures());
+		assert!(!breaker.allow_collation(now));
+		assert!(breaker.allow_collation(now + Duration::from_secs(1)));
+
+		breaker.note_candidate(now);
+		assert!(!breaker.allow_collation(now + Duration::from_secs(1)));
+		assert!(breaker.allow_collation(now + Duration::from_secs(2)));
+
+		// The backoff is capped at the maximum.
+		breaker.note_candidate(now);
+		assert!(breaker.allow_collation(now + Duration::from_secs(3)));
+	}
+
+	#[test]
+	fn success_closes_the_breaker() {
+		let mut breaker = CircuitBreaker::new(1, Duration::from_secs(1), Duration::from_secs(1));
+		let now = Instant::now();
+
+		breaker.note_candidate(now);
+		breaker.note_candidate(now);
+		assert!(!breaker.allow_collation(now));
+
+		breaker.note_success();
+		assert!(breaker.allow_collation(now));
+		assert_eq!(0, breaker.consecutive_failures());
+	}
+}
@@ -0,0 +1,359 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The parts of the [`Collator`](crate::Collator) that are independent of how blocks are built.
+//!
+//! Other block authoring implementations, e.g. an Aura worker or manual seal, build and import
+//! blocks on their own. They can use a [`CollatorService`] to check the parent block, assemble
+//! the collation and announce the block, instead of reimplementing this.
+
+use crate::{
+	collation_from_info, collation_from_state,
+	skip_reasons::{SkipReason, SkipReasons},
+	CollatorError, PBlockNumber, PHash,
+};
+
+use cumulus_network::WaitToAnnounce;
+use cumulus_primitives::{CollationInfo, CollectCollationInfo};
+use cumulus_runtime::ParachainBlockData;
+
+use sc_client_api::BlockBackend;
+use sp_api::{ApiErrorFor, ApiExt, ProvideRuntimeApi};
+use sp_consensus::BlockStatus;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, Header as HeaderT},
+};
+
+use polkadot_node_primitives::Collation;
+
+use log::{debug, error};
+use parking_lot::Mutex;
+
+use std::sync::Arc;
+
+/// The services a collator needs besides building blocks.
+pub trait CollatorService<Block: BlockT>: Send + Sync {
+	/// Returns `true` if the block `hash` was imported with its state and can be built on.
+	fn check_block_status(&self, hash: Block::Hash) -> bool;
+
+	/// Build the collation for the imported block `block_hash`.
+	///
+	/// `relay_block_number` is the number of the relay parent the block was built on.
+	fn build_collation(
+		&self,
+		block: ParachainBlockData<Block>,
+		block_hash: Block::Hash,
+		relay_block_number: PBlockNumber,
+	) -> Result<Collation, CollatorError>;
+
+	/// Announce the block `block_hash` once its candidate with `pov_hash` was seconded.
+	///
+	/// Does nothing if the service was created without a [`WaitToAnnounce`].
+	fn announce_with_barrier(&self, block_hash: Block::Hash, pov_hash: PHash);
+}
+
+/// Provides the collation information the runtime computed for one of its blocks.
+///
+/// Usually this calls the [`CollectCollationInfo`] runtime api, see [`runtime_collation_info`].
+pub trait RuntimeCollationInfo<Block: BlockT>: Send + Sync {
+	/// Returns the collation information of the imported block with the given `header`.
+	///
+	/// Returns `None` if the runtime does not provide it, then it is read from the state.
+	fn collect_collation_info(
+		&self,
+		header: &Block::Header,
+	) -> Result<Option<CollationInfo>, String>;
+}
+
+impl<Block, F> RuntimeCollationInfo<Block> for F
+where
+	Block: BlockT,
+	F: Fn(&Block::Header) -> Result<Option<CollationInfo>, String> + Send + Sync,
+{
+	fn collect_collation_info(
+		&self,
+		header: &Block::Header,
+	) -> Result<Option<CollationInfo>, String> {
+		(self)(header)
+	}
+}
+
+/// Create a [`RuntimeCollationInfo`] that calls the [`CollectCollationInfo`] runtime api of
+/// `client`.
+///
+/// Runtimes that do not provide the api are not called.
+pub fn runtime_collation_info<Block, Client>(
+	client: Arc<Client>,
+) -> Arc<dyn RuntimeCollationInfo<Block>>
+where
+	Block: BlockT,
+	Client: ProvideRuntimeApi<Block> + Send + Sync + 'static,
+	Client::Api: CollectCollationInfo<Block>,
+{
+	Arc::new(move |header: &Block::Header| {
+		let runtime_api = client.runtime_api();
+		let at = BlockId::Hash(header.hash());
+
+		let supported = runtime_api
+			.has_api::<dyn CollectCollationInfo<Block, Error = ApiErrorFor<Client, Block>>>(&at)
+			.map_err(|e| format!("Failed to check for the collation info api: {:?}", e))?;
+		if !supported {
+			return Ok(None);
+		}
+
+		runtime_api
+			.collect_collation_info(&at, header)
+			.map(Some)
+			.map_err(|e| format!("Failed to collect the collation info: {:?}", e))
+	})
+}
+
+/// The [`CollatorService`] that is used by the [`Collator`](crate::Collator).
+pub struct ParachainCollatorService<Block: BlockT, BS, Backend> {
+	block_status: Arc<BS>,
+	backend: Arc<Backend>,
+	wait_to_announce: Option<Arc<Mutex<WaitToAnnounce<Block>>>>,
+	skip_reasons: Option<SkipReasons<Block::Hash>>,
+	collation_info: Option<Arc<dyn RuntimeCollationInfo<Block>>>,
+}
+
+impl<Block: BlockT, BS, Backend> Clone for ParachainCollatorService<Block, BS, Backend> {
+	fn clone(&self) -> Self {
+		Self {
+			block_status: self.block_status.clone(),
+			backend: self.backend.clone(),
+			wait_to_announce: self.wait_to_announce.clone(),
+			skip_reasons: self.skip_reasons.clone(),
+			collation_info: self.collation_info.clone(),
+		}
+	}
+}
+
+impl<Block: BlockT, BS, Backend> ParachainCollatorService<Block, BS, Backend> {
+	/// Create a new service.
+	///
+	/// Blocks are only announced if a `wait_to_announce` is given.
+	pub fn new(
+		block_status: Arc<BS>,
+		backend: Arc<Backend>,
+		wait_to_announce: Option<WaitToAnnounce<Block>>,
+	) -> Self {
+		Self {
+			block_status,
+			backend,
+			wait_to_announce: wait_to_announce.map(|w| Arc::new(Mutex::new(w))),
+			skip_reasons: None,
+			collation_info: None,
+		}
+	}
+
+	/// Note why candidate production on a parent block was skipped in `skip_reasons`.
+	pub fn with_skip_reasons(mut self, skip_reasons: SkipReasons<Block::Hash>) -> Self {
+		self.skip_reasons = Some(skip_reasons);
+		self
+	}
+
+	/// Take the output of collated blocks from `collation_info` instead of the state.
+	pub fn with_collation_info(
+		mut self,
+		collation_info: Arc<dyn RuntimeCollationInfo<Block>>,
+	) -> Self {
+		self.collation_info = Some(collation_info);
+		self
+	}
+
+	/// Returns `true` if produced blocks are announced by this service.
+	pub fn announces_blocks(&self) -> bool {
+		self.wait_to_announce.is_some()
+	}
+}
+
+impl<Block, BS, Backend> CollatorService<Block> for ParachainCollatorService<Block, BS, Backend>
+where
+	Block: BlockT,
+	BS: BlockBackend<Block> + Send + Sync,
+	Backend: sc_client_api::Backend<Block>,
+{
+	fn check_block_status(&self, hash: Block::Hash) -> bool {
+		let skip_reason = match self.block_status.block_status(&BlockId::Hash(hash)) {
+			Ok(BlockStatus::Queued) => {
+				debug!(
+					target: "cumulus-collator",
+					"Skipping candidate production, because block `{:?}` is still queued for import.", hash,
+				);
+				SkipReason::Queued
+			}
+			Ok(BlockStatus::InChainWithState) => return true,
+			Ok(BlockStatus::InChainPruned) => {
+				error!(
+					target: "cumulus-collator",
+					"Skipping candidate production, because the state of block `{:?}` is already pruned! \
+					The state of pruned blocks can not be recovered, consider increasing `--pruning`.",
+					hash,
+				);
+				SkipReason::Pruned
+			}
+			Ok(BlockStatus::KnownBad) => {
+				error!(
+					target: "cumulus-collator",
+					"Block `{}` is tagged as known bad and is included in the relay chain! Skipping candidate production!", hash,
+				);
+				SkipReason::KnownBad
+			}
+			Ok(BlockStatus::Unknown) => {
+				debug!(
+					target: "cumulus-collator",
+					"Skipping candidate production, because block `{:?}` is unknown.", hash,
+				);
+				SkipReason::Unknown
+			}
+			Err(e) => {
+				error!(target: "cumulus-collator", "Failed to get block status of `{:?}`: {:?}", hash, e);
+				SkipReason::Error(e.to_string())
+			}
+		};
+
+		if let Some(ref skip_reasons) = self.skip_reasons {
+			skip_reasons.note(hash, skip_reason);
+		}
+
+		false
+	}
+
+	fn build_collation(
+		&self,
+		block: ParachainBlockData<Block>,
+		block_hash: Block::Hash,
+		relay_block_number: PBlockNumber,
+	) -> Result<Collation, CollatorError> {
+		if let Some(ref collation_info) = self.collation_info {
+			if let Some(info) = collation_info
+				.collect_collation_info(block.header())
+				.map_err(CollatorError::State)?
+			{
+				return Ok(collation_from_info(block, info));
+			}
+		}
+
+		let state = self
+			.backend
+			.state_at(BlockId::Hash(block_hash))
+			.map_err(|e| CollatorError::State(format!("{:?}", e)))?;
+
+		collation_from_state(&state, block, relay_block_number)
+	}
+
+	fn announce_with_barrier(&self, block_hash: Block::Hash, pov_hash: PHash) {
+		if let Some(ref wait_to_announce) = self.wait_to_announce {
+			wait_to_announce.lock().wait_to_announce(block_hash, pov_hash);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_primitives::ValidationData;
+	use cumulus_test_client::{
+		generate_block_inherents, ClientBlockImportExt, DefaultTestClientBuilderExt,
+		TestClientBuilder, TestClientBuilderExt,
+	};
+	use cumulus_test_runtime::{Block, Header};
+	use sc_block_builder::BlockBuilderProvider;
+	use sp_blockchain::HeaderBackend;
+	use sp_consensus::BlockOrigin;
+	use sp_state_machine::StorageProof;
+
+	use polkadot_primitives::v1::HeadData;
+
+	use codec::Encode;
+
+	#[test]
+	fn only_blocks_with_state_can_be_built_on() {
+		let client_builder = TestClientBuilder::new();
+		let backend = client_builder.backend();
+		let client = Arc::new(client_builder.build());
+
+		let skip_reasons = SkipReasons::default();
+		let service = ParachainCollatorService::<Block, _, _>::new(client.clone(), backend, None)
+			.with_skip_reasons(skip_reasons.clone());
+
+		assert!(service.check_block_status(client.info().genesis_hash));
+		assert!(!service.check_block_status(Default::default()));
+		assert!(!service.announces_blocks());
+
+		let skipped = skip_reasons.recent();
+		assert_eq!(1, skipped.len());
+		assert_eq!(SkipReason::Unknown, skipped[0].reason);
+	}
+
+	#[test]
+	fn collation_info_is_taken_from_the_runtime_api() {
+		let client_builder = TestClientBuilder::new();
+		let backend = client_builder.backend();
+		let mut client = Arc::new(client_builder.build());
+
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.block_number = 10;
+		let mut builder = client
+			.new_block_at(&BlockId::Number(0), Default::default(), false)
+			.expect("Initializes new block");
+		generate_block_inherents(&*client, Some(validation_data))
+			.into_iter()
+			.for_each(|e| builder.push(e).expect("Pushes an inherent"));
+		let block = builder.build().expect("Creates block").block;
+		let (header, extrinsics) = block.clone().deconstruct();
+		client.import(BlockOrigin::Own, block).expect("Imports the block");
+		let block_data =
+			|| ParachainBlockData::new(header.clone(), extrinsics.clone(), StorageProof::empty());
+
+		// The runtime computed the watermark from the relay parent of the block.
+		let service =
+			ParachainCollatorService::<Block, _, _>::new(client.clone(), backend.clone(), None)
+				.with_collation_info(runtime_collation_info(client.clone()));
+		let collation = service.build_collation(block_data(), header.hash(), 5).unwrap();
+		assert_eq!(10, collation.hrmp_watermark);
+		assert_eq!(HeadData(header.encode()), collation.head_data);
+
+		let fixed_info = |header: &Header| -> Result<Option<CollationInfo>, String> {
+			Ok(Some(CollationInfo {
+				upward_messages: vec![vec![1]],
+				horizontal_messages: Vec::new(),
+				new_validation_code: None,
+				processed_downward_messages: 2,
+				hrmp_watermark: 3,
+				head_data: HeadData(header.encode()),
+			}))
+		};
+		let service =
+			ParachainCollatorService::<Block, _, _>::new(client.clone(), backend.clone(), None)
+				.with_collation_info(Arc::new(fixed_info));
+		let collation = service.build_collation(block_data(), header.hash(), 5).unwrap();
+		assert_eq!(vec![vec![1]], collation.upward_messages);
+		assert_eq!(2, collation.processed_downward_messages);
+		assert_eq!(3, collation.hrmp_watermark);
+
+		// Without the runtime api the output is read from the state.
+		let service = ParachainCollatorService::<Block, _, _>::new(client, backend, None)
+			.with_collation_info(Arc::new(
+				|_: &Header| -> Result<Option<CollationInfo>, String> { Ok(None) },
+			));
+		let collation = service.build_collation(block_data(), header.hash(), 5).unwrap();
+		assert_eq!(10, collation.hrmp_watermark);
+	}
+}
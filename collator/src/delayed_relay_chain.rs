@@ -0,0 +1,180 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Simulate a slow or unreliable relay chain.
+//!
+//! The collator reads the relay chain through the [`RelayChainInterface`] and the
+//! [`RelayChainValidationData`]. In a local network these calls are answered by the embedded
+//! relay chain node almost instantly, while a collator that talks to an external relay chain node
+//! waits for every call on the network. A [`DelayedRelayChainInterface`] adds a configurable
+//! [`RelayChainDelay`] to every call, so soak tests can check that the collator keeps up with the
+//! relay chain under such conditions.
+//!
+//! Only available with the `test-helpers` feature.
+
+use crate::{parent_resolution::RelayChainValidationData, PHash};
+
+use cumulus_primitives::{
+	inherents::DownwardMessagesType, ParaId, PersistedValidationData, RelayChainInterface,
+	RelaySessionInfo,
+};
+
+use polkadot_primitives::v1::OccupiedCoreAssumption;
+
+use futures::executor::block_on;
+use futures_timer::Delay;
+use rand::Rng;
+
+use std::{sync::Arc, time::Duration};
+
+/// The delay that is added to every call to the relay chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RelayChainDelay {
+	/// The latency of every call.
+	pub latency: Duration,
+	/// The maximum random delay that is added to the `latency`.
+	pub jitter: Duration,
+	/// The probability of a call to fail, between `0.0` and `1.0`.
+	pub error_rate: f64,
+}
+
+impl RelayChainDelay {
+	/// Delay every call by `latency`.
+	pub fn new(latency: Duration) -> Self {
+		Self {
+			latency,
+			..Default::default()
+		}
+	}
+
+	/// Add a random delay of up to `jitter` to every call.
+	pub fn jitter(mut self, jitter: Duration) -> Self {
+		self.jitter = jitter;
+		self
+	}
+
+	/// Let calls fail with the probability `error_rate`.
+	pub fn error_rate(mut self, error_rate: f64) -> Self {
+		self.error_rate = error_rate;
+		self
+	}
+
+	/// Wait for the delay of a call, then fail the call randomly.
+	///
+	/// The relay chain access is synchronous, so the calling thread waits for the timer.
+	fn delay(&self, call: &str) -> Result<(), String> {
+		let mut rng = rand::thread_rng();
+
+		let jitter = if self.jitter > Duration::default() {
+			rng.gen_range(Duration::default(), self.jitter)
+		} else {
+			Duration::default()
+		};
+		block_on(Delay::new(self.latency + jitter));
+
+		if rng.gen_bool(self.error_rate.max(0.0).min(1.0)) {
+			Err(format!("Simulated relay chain error in `{}`", call))
+		} else {
+			Ok(())
+		}
+	}
+}
+
+/// Adds a [`RelayChainDelay`] to the calls of the wrapped relay chain access.
+///
+/// Wraps either a [`RelayChainInterface`] or a [`RelayChainValidationData`].
+pub struct DelayedRelayChainInterface<T: ?Sized> {
+	inner: Arc<T>,
+	delay: RelayChainDelay,
+}
+
+impl<T: ?Sized> DelayedRelayChainInterface<T> {
+	/// Add the given `delay` to the calls to `inner`.
+	pub fn new(inner: Arc<T>, delay: RelayChainDelay) -> Self {
+		Self { inner, delay }
+	}
+}
+
+impl<T: RelayChainInterface + ?Sized> RelayChainInterface for DelayedRelayChainInterface<T> {
+	fn downward_messages(&self, relay_parent: PHash) -> Result<DownwardMessagesType, String> {
+		self.delay.delay("downward_messages")?;
+		self.inner.downward_messages(relay_parent)
+	}
+
+	fn session_info(&self, relay_parent: PHash) -> Result<Option<RelaySessionInfo>, String> {
+		self.delay.delay("session_info")?;
+		self.inner.session_info(relay_parent)
+	}
+
+	// The para id is known locally, it is not read from the relay chain.
+	fn para_id(&self) -> Option<ParaId> {
+		self.inner.para_id()
+	}
+
+	fn execution_timeout(&self, relay_parent: PHash) -> Result<Option<Duration>, String> {
+		self.delay.delay("execution_timeout")?;
+		self.inner.execution_timeout(relay_parent)
+	}
+
+	fn max_pov_size(&self, relay_parent: PHash) -> Result<Option<u32>, String> {
+		self.delay.delay("max_pov_size")?;
+		self.inner.max_pov_size(relay_parent)
+	}
+}
+
+impl<T: RelayChainValidationData + ?Sized> RelayChainValidationData
+	for DelayedRelayChainInterface<T>
+{
+	fn persisted_validation_data(
+		&self,
+		relay_parent: PHash,
+		assumption: OccupiedCoreAssumption,
+	) -> Result<Option<PersistedValidationData>, String> {
+		self.delay.delay("persisted_validation_data")?;
+		self.inner.persisted_validation_data(relay_parent, assumption)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::time::Instant;
+
+	fn relay_chain() -> Arc<dyn RelayChainInterface> {
+		Arc::new(|_: PHash| -> Result<DownwardMessagesType, String> { Ok(Vec::new()) })
+	}
+
+	#[test]
+	fn delays_and_fails_calls() {
+		let delayed = DelayedRelayChainInterface::new(
+			relay_chain(),
+			RelayChainDelay::new(Duration::from_millis(20)),
+		);
+
+		let start = Instant::now();
+		assert!(delayed.downward_messages(PHash::default()).is_ok());
+		assert!(start.elapsed() >= Duration::from_millis(20));
+
+		let failing = DelayedRelayChainInterface::new(
+			relay_chain(),
+			RelayChainDelay::default().error_rate(1.0),
+		);
+
+		assert!(failing.downward_messages(PHash::default()).is_err());
+		assert_eq!(None, failing.para_id());
+	}
+}
@@ -0,0 +1,530 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rebuild the parachain from the candidates included by the relay chain.
+//!
+//! If the parachain network lost its blocks, e.g. because all collators lost their databases,
+//! the relay chain still knows the head of every included candidate. [`included_candidates`]
+//! walks the relay chain back and collects the included candidates of the parachain, and
+//! [`recover_chain`] fetches their blocks from [`BlockSource`]s and imports them in order.
+//!
+//! The candidate events of a relay block are read from its state, so walking further back than
+//! the state pruning of the relay chain node requires an archive node.
+//!
+//! The recovered blocks are imported as described by a [`RecoveryImport`]. By default they are
+//! imported with [`BlockOrigin::ConsensusBroadcast`], so the client sends the usual import
+//! notifications for them. [`RecoveryImportHook`]s are called for every imported block, e.g. to
+//! let an indexer see the recovered blocks the same way as blocks received over the network.
+
+use crate::{
+	pov_export::{import_block, ExportedPoV},
+	PHash,
+};
+
+use cumulus_network::recent_blocks::RecentBlockFetcher;
+
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::{BlockStatus, HeaderBackend};
+use sp_consensus::{BlockImport, BlockOrigin, Error as ConsensusError};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, Header as HeaderT, NumberFor, Zero},
+};
+
+use polkadot_primitives::v1::{
+	Block as PBlock, BlockData, CandidateEvent, Id as ParaId, ParachainHost, PoV,
+};
+
+use codec::{Decode, Encode};
+use futures::{
+	future::{ready, BoxFuture, FutureExt},
+	stream::{self, StreamExt, TryStreamExt},
+};
+use log::{debug, info};
+
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+/// The maximum number of blocks that are fetched and imported as one batch.
+pub const MAX_BATCH_SIZE: usize = 64;
+
+/// The maximum number of blocks of a batch that are fetched at the same time.
+const MAX_PARALLEL_FETCHES: usize = 8;
+
+/// A candidate of the parachain that was included by the relay chain.
+#[derive(Debug)]
+pub struct IncludedCandidate<Block: BlockT> {
+	/// The relay block that included the candidate.
+	pub relay_block: PHash,
+	/// The hash of the PoV of the candidate.
+	pub pov_hash: PHash,
+	/// The header of the parachain block of the candidate.
+	pub header: Block::Header,
+}
+
+/// Called for every block that was imported by [`recover_chain`].
+pub trait RecoveryImportHook<Block: BlockT>: Send + Sync {
+	/// The recovered `block` was imported with the given `origin`.
+	fn on_import(&self, block: &Block, origin: BlockOrigin);
+}
+
+impl<Block, F> RecoveryImportHook<Block> for F
+where
+	Block: BlockT,
+	F: Fn(&Block, BlockOrigin) + Send + Sync,
+{
+	fn on_import(&self, block: &Block, origin: BlockOrigin) {
+		(self)(block, origin)
+	}
+}
+
+/// How the blocks recovered by [`recover_chain`] are imported.
+#[derive(Clone)]
+pub struct RecoveryImport<Block: BlockT> {
+	origin: BlockOrigin,
+	hooks: Vec<Arc<dyn RecoveryImportHook<Block>>>,
+}
+
+impl<Block: BlockT> Default for RecoveryImport<Block> {
+	fn default() -> Self {
+		Self {
+			origin: BlockOrigin::ConsensusBroadcast,
+			hooks: Vec::new(),
+		}
+	}
+}
+
+impl<Block: BlockT> RecoveryImport<Block> {
+	/// Import the recovered blocks with the given `origin`.
+	///
+	/// The client does not send import notifications for blocks imported with
+	/// [`BlockOrigin::NetworkInitialSync`].
+	pub fn origin(mut self, origin: BlockOrigin) -> Self {
+		self.origin = origin;
+		self
+	}
+
+	/// Call the given `hook` for every imported block.
+	pub fn register_hook(mut self, hook: Arc<dyn RecoveryImportHook<Block>>) -> Self {
+		self.hooks.push(hook);
+		self
+	}
+}
+
+/// Something that can fetch the block of an included candidate.
+pub trait BlockSource<Block: BlockT>: Send + Sync {
+	/// A short name of the source, used in logs.
+	fn name(&self) -> &'static str;
+
+	/// Fetch the block of the given `candidate`.
+	///
+	/// Returns `None` if the block is not available from this source.
+	fn fetch(&self, candidate: &IncludedCandidate<Block>) -> BoxFuture<'static, Option<Block>>;
+}
+
+/// Fetches blocks from the peers of the parachain network.
+///
+/// Peers that still have the blocks, e.g. archive nodes, serve them through the
+/// [`recent_blocks`](cumulus_network::recent_blocks) protocol.
+impl<Block: BlockT> BlockSource<Block> for RecentBlockFetcher<Block> {
+	fn name(&self) -> &'static str {
+		"network"
+	}
+
+	fn fetch(&self, candidate: &IncludedCandidate<Block>) -> BoxFuture<'static, Option<Block>> {
+		let fetcher = self.clone();
+		let hash = candidate.header.hash();
+
+		async move { fetcher.fetch(hash).await.and_then(|r| r.block) }.boxed()
+	}
+}
+
+/// Fetches blocks from a directory of PoVs written by the
+/// [`PoVExporter`](crate::pov_export::PoVExporter).
+pub struct ExportedPoVSource<Block> {
+	blocks: HashMap<PHash, Block>,
+}
+
+impl<Block: BlockT> ExportedPoVSource<Block> {
+	/// Read all exported PoVs in `dir`.
+	pub fn new(dir: &Path) -> Result<Self, String> {
+		let blocks = ExportedPoV::<Block>::read_dir(dir)?
+			.into_iter()
+			.map(|pov| {
+				let pov_hash = PoV {
+					block_data: BlockData(pov.block_data.encode()),
+				}
+				.hash();
+				let block = Block::new(
+					pov.block_data.header().clone(),
+					pov.block_data.extrinsics().to_vec(),
+				);
+
+				(pov_hash, block)
+			})
+			.collect();
+
+		Ok(Self { blocks })
+	}
+}
+
+impl<Block: BlockT> BlockSource<Block> for ExportedPoVSource<Block> {
+	fn name(&self) -> &'static str {
+		"exported PoVs"
+	}
+
+	fn fetch(&self, candidate: &IncludedCandidate<Block>) -> BoxFuture<'static, Option<Block>> {
+		ready(self.blocks.get(&candidate.pov_hash).cloned()).boxed()
+	}
+}
+
+/// Collect the candidates of `para_id` that were included in `relay_head` and its ancestors.
+///
+/// The relay chain is walked back until a candidate with a block number of at most `after` is
+/// found, or until the relay genesis. The candidates are returned in the order of inclusion.
+pub fn included_candidates<Block, PClient>(
+	polkadot_client: &PClient,
+	para_id: ParaId,
+	relay_head: PHash,
+	after: NumberFor<Block>,
+) -> Result<Vec<IncludedCandidate<Block>>, String>
+where
+	Block: BlockT,
+	PClient: ProvideRuntimeApi<PBlock> + HeaderBackend<PBlock>,
+	PClient::Api: ParachainHost<PBlock>,
+{
+	let mut candidates = Vec::new();
+	let mut relay_block = relay_head;
+
+	loop {
+		let relay_header = polkadot_client
+			.header(BlockId::Hash(relay_block))
+			.map_err(|e| format!("Failed to get relay block `{}`: {:?}", relay_block, e))?
+			.ok_or_else(|| format!("Relay block `{}` not found", relay_block))?;
+
+		let events = polkadot_client
+			.runtime_api()
+			.candidate_events(&BlockId::Hash(relay_block))
+			.map_err(|e| {
+				format!(
+					"Failed to get the candidate events of relay block `{}`, \
+					older relay blocks require an archive node: {:?}",
+					relay_block, e,
+				)
+			})?;
+
+		let mut reached_local_chain = false;
+
+		for event in events {
+			let (receipt, head) = match event {
+				CandidateEvent::CandidateIncluded(receipt, head) => (receipt, head),
+				_ => continue,
+			};
+
+			if receipt.descriptor.para_id != para_id {
+				continue;
+			}
+
+			let header = Block::Header::decode(&mut &head.0[..]).map_err(|e| {
+				format!(
+					"Failed to decode the head included in relay block `{}`: {:?}",
+					relay_block, e,
+				)
+			})?;
+
+			if *header.number() <= after {
+				reached_local_chain = true;
+				continue;
+			}
+
+			debug!(
+				target: "cumulus-collator",
+				"Found block #{} (`{}`) included in relay block `{}`.",
+				header.number(),
+				header.hash(),
+				relay_block,
+			);
+
+			candidates.push(IncludedCandidate {
+				relay_block,
+				pov_hash: receipt.descriptor.pov_hash,
+				header,
+			});
+		}
+
+		if reached_local_chain || relay_header.number.is_zero() {
+			break;
+		}
+
+		relay_block = relay_header.parent_hash;
+	}
+
+	candidates.reverse();
+	Ok(candidates)
+}
+
+/// Fetch the block of `candidate` from the first source that has it.
+async fn fetch_block<Block: BlockT>(
+	candidate: &IncludedCandidate<Block>,
+	sources: &[Arc<dyn BlockSource<Block>>],
+) -> Result<(Block, &'static str), String> {
+	let hash = candidate.header.hash();
+	let number = *candidate.header.number();
+
+	for source in sources {
+		match source.fetch(candidate).await {
+			Some(block) if block.header().hash() == hash => return Ok((block, source.name())),
+			Some(_) => debug!(
+				target: "cumulus-collator",
+				"Source `{}` returned the wrong block for #{} (`{}`).",
+				source.name(),
+				number,
+				hash,
+			),
+			None => {}
+		}
+	}
+
+	Err(format!(
+		"Block #{} (`{}`) could not be fetched from any source, its PoV included in relay \
+		block `{}` may only be recovered from the relay chain validators",
+		number, hash, candidate.relay_block,
+	))
+}
+
+/// Check that every block of the batch builds on the block before it, or on a known block.
+///
+/// This rejects a batch before any of its blocks is imported.
+fn verify_batch<Block, Client>(
+	client: &Client,
+	blocks: &[(Block, &'static str)],
+) -> Result<(), String>
+where
+	Block: BlockT,
+	Client: HeaderBackend<Block>,
+{
+	let mut previous = None;
+
+	for (block, _) in blocks {
+		let header = block.header();
+		let parent = *header.parent_hash();
+
+		if previous != Some(parent) {
+			let status = client
+				.status(BlockId::Hash(parent))
+				.map_err(|e| format!("Failed to get status of `{}`: {:?}", parent, e))?;
+			if status != BlockStatus::InChain {
+				return Err(format!(
+					"Parent `{}` of block #{} (`{}`) is neither part of the batch nor known locally",
+					parent,
+					header.number(),
+					header.hash(),
+				));
+			}
+		}
+
+		previous = Some(header.hash());
+	}
+
+	Ok(())
+}
+
+/// Fetch the blocks of the given `candidates` from the `sources` and import them in order.
+///
+/// The blocks are imported as described by `import`. The sources are asked in the given order.
+/// Blocks that are already known are skipped. The blocks are fetched and imported in batches of
+/// up to [`MAX_BATCH_SIZE`] blocks. A batch is only imported if all of its blocks could be fetched
+/// and build on each other, and only its last block becomes the new best block.
+///
+/// Returns the number of imported blocks, or an error if a block could not be fetched from any
+/// source or failed to import.
+pub async fn recover_chain<Block, Client>(
+	client: &Client,
+	candidates: Vec<IncludedCandidate<Block>>,
+	sources: &[Arc<dyn BlockSource<Block>>],
+	import: &RecoveryImport<Block>,
+) -> Result<usize, String>
+where
+	Block: BlockT,
+	Client: HeaderBackend<Block>,
+	for<'a> &'a Client: BlockImport<Block, Error = ConsensusError>,
+{
+	let mut missing = Vec::new();
+	for candidate in candidates {
+		let hash = candidate.header.hash();
+		let status = client
+			.status(BlockId::Hash(hash))
+			.map_err(|e| format!("Failed to get status of `{}`: {:?}", hash, e))?;
+
+		if status != BlockStatus::InChain {
+			missing.push(candidate);
+		}
+	}
+
+	let mut imported = 0;
+
+	for batch in missing.chunks(MAX_BATCH_SIZE) {
+		let blocks: Vec<_> = stream::iter(batch)
+			.map(|candidate| fetch_block(candidate, sources))
+			.buffered(MAX_PARALLEL_FETCHES)
+			.try_collect()
+			.await?;
+
+		verify_batch(client, &blocks)?;
+
+		let last = blocks.len() - 1;
+		for (i, (block, source)) in blocks.into_iter().enumerate() {
+			import_block(client, block.clone(), import.origin, source, i == last)?;
+			import.hooks.iter().for_each(|h| h.on_import(&block, import.origin));
+			imported += 1;
+		}
+	}
+
+	info!(target: "cumulus-collator", "Recovered {} blocks from the relay chain.", imported);
+
+	Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::pov_export::PoVExporter;
+
+	use cumulus_runtime::ParachainBlockData;
+	use cumulus_test_client::{
+		generate_block_inherents, DefaultTestClientBuilderExt, TestClientBuilder,
+		TestClientBuilderExt,
+	};
+	use cumulus_test_runtime::Block;
+	use futures::executor::block_on;
+	use parking_lot::Mutex;
+	use sc_block_builder::BlockBuilderProvider;
+
+	#[test]
+	fn recovers_blocks_from_exported_povs() {
+		let dir = tempfile::tempdir().expect("Creates a temp dir");
+		let exporter = PoVExporter::new(dir.path().to_path_buf()).expect("Creates the exporter");
+
+		let builder_client = TestClientBuilder::new().build();
+		let mut builder = builder_client
+			.new_block_at(&BlockId::Number(0), Default::default(), true)
+			.expect("Initializes new block");
+		generate_block_inherents(&builder_client, None)
+			.into_iter()
+			.for_each(|e| builder.push(e).expect("Pushes an inherent"));
+		let (block, _, proof) = builder.build().expect("Creates block").into_inner();
+		let (header, extrinsics) = block.deconstruct();
+
+		let block_data = ParachainBlockData::<Block>::new(
+			header.clone(),
+			extrinsics,
+			proof.expect("Proof is recorded"),
+		);
+		exporter
+			.export(PHash::default(), &block_data)
+			.expect("Exports the PoV");
+
+		let candidate = || IncludedCandidate::<Block> {
+			relay_block: PHash::default(),
+			pov_hash: PoV {
+				block_data: BlockData(block_data.encode()),
+			}
+			.hash(),
+			header: header.clone(),
+		};
+		let sources: Vec<Arc<dyn BlockSource<Block>>> =
+			vec![Arc::new(ExportedPoVSource::new(dir.path()).expect("Reads the PoVs"))];
+
+		let imported = Arc::new(Mutex::new(Vec::new()));
+		let import = RecoveryImport::default().register_hook({
+			let imported = imported.clone();
+			Arc::new(move |block: &Block, origin: BlockOrigin| {
+				imported.lock().push((block.header().hash(), origin))
+			})
+		});
+
+		let client = TestClientBuilder::new().build();
+		assert_eq!(
+			Ok(1),
+			block_on(recover_chain(&client, vec![candidate()], &sources, &import)),
+		);
+		assert_eq!(
+			Some(header.clone()),
+			client
+				.header(BlockId::Hash(header.hash()))
+				.expect("Reads the header"),
+		);
+
+		// Blocks that are already known are skipped.
+		assert_eq!(
+			Ok(0),
+			block_on(recover_chain(&client, vec![candidate()], &sources, &import)),
+		);
+
+		assert_eq!(
+			vec![(header.hash(), BlockOrigin::ConsensusBroadcast)],
+			*imported.lock(),
+		);
+	}
+
+	#[test]
+	fn rejects_batches_that_do_not_build_on_each_other() {
+		let client = TestClientBuilder::new().build();
+		let genesis = client.info().genesis_hash;
+
+		let header = |number, parent_hash| {
+			cumulus_test_runtime::Header::new(
+				number,
+				Default::default(),
+				Default::default(),
+				parent_hash,
+				Default::default(),
+			)
+		};
+		let first = Block::new(header(1, genesis), Vec::new());
+		let second = Block::new(header(2, first.header().hash()), Vec::new());
+		let unrelated = Block::new(header(2, PHash::repeat_byte(1)), Vec::new());
+
+		verify_batch(&client, &[(first.clone(), "test"), (second, "test")])
+			.expect("Blocks build on each other");
+		assert!(verify_batch(&client, &[(first, "test"), (unrelated, "test")]).is_err());
+	}
+
+	#[test]
+	fn fails_if_no_source_has_the_block() {
+		let header = cumulus_test_runtime::Header::new(
+			1,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		let candidate = IncludedCandidate::<Block> {
+			relay_block: PHash::default(),
+			pov_hash: PHash::repeat_byte(1),
+			header,
+		};
+
+		let client = TestClientBuilder::new().build();
+		assert!(block_on(recover_chain(
+			&client,
+			vec![candidate],
+			&[],
+			&Default::default(),
+		))
+		.is_err());
+	}
+}
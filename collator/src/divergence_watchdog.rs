@@ -0,0 +1,141 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Halt candidate production when the local chain diverges from the relay chain.
+//!
+//! The relay chain records the head of the parachain that was included last. Usually this head
+//! is known to the node, though it may be on another fork than the local best block when the
+//! relay chain forks. If the head is unknown to the node, or can not be imported, candidates
+//! can not be built on it. A short divergence is normal, e.g. while a block is still being
+//! imported. The [`DivergenceWatchdog`] halts candidate production when the divergence lasts
+//! for more than a given number of relay blocks, until the local chain caught up again.
+
+use crate::PBlockNumber;
+
+use log::{error, info};
+
+/// The default number of relay blocks the local chain may diverge from the relay chain before
+/// candidate production is halted.
+pub const DEFAULT_MAX_DIVERGENT_RELAY_BLOCKS: PBlockNumber = 10;
+
+/// Halts candidate production when the local chain diverges for too long.
+pub struct DivergenceWatchdog {
+	max_divergent_relay_blocks: PBlockNumber,
+	/// The relay block at which the divergence was first observed.
+	divergent_since: Option<PBlockNumber>,
+	halted: bool,
+}
+
+impl Default for DivergenceWatchdog {
+	fn default() -> Self {
+		Self::new(DEFAULT_MAX_DIVERGENT_RELAY_BLOCKS)
+	}
+}
+
+impl DivergenceWatchdog {
+	/// Create a new instance.
+	///
+	/// Candidate production is halted when the local chain diverged for more than
+	/// `max_divergent_relay_blocks` relay blocks.
+	pub fn new(max_divergent_relay_blocks: PBlockNumber) -> Self {
+		Self {
+			max_divergent_relay_blocks,
+			divergent_since: None,
+			halted: false,
+		}
+	}
+
+	/// Returns `true` if candidate production is halted.
+	pub fn is_halted(&self) -> bool {
+		self.halted
+	}
+
+	/// The number of relay blocks the local chain diverged for at `relay_block_number`.
+	pub fn divergent_relay_blocks(&self, relay_block_number: PBlockNumber) -> PBlockNumber {
+		self.divergent_since
+			.map_or(0, |since| relay_block_number.saturating_sub(since))
+	}
+
+	/// Note whether the local chain is `divergent` from the relay chain at `relay_block_number`.
+	///
+	/// Returns `true` if a new candidate may be produced.
+	pub fn note(&mut self, relay_block_number: PBlockNumber, divergent: bool) -> bool {
+		if !divergent {
+			if self.halted {
+				info!(
+					target: "cumulus-collator",
+					"The local chain caught up with the relay chain, resuming candidate production.",
+				);
+			}
+
+			self.divergent_since = None;
+			self.halted = false;
+			return true;
+		}
+
+		// The relay parents of the collation requests are not strictly increasing, e.g. on a
+		// relay chain reorg.
+		let since = self
+			.divergent_since
+			.map_or(relay_block_number, |since| since.min(relay_block_number));
+		self.divergent_since = Some(since);
+
+		if !self.halted && relay_block_number - since > self.max_divergent_relay_blocks {
+			error!(
+				target: "cumulus-collator",
+				"The head of the parachain on the relay chain diverged from the local chain since \
+				relay block #{}, halting candidate production!",
+				since,
+			);
+			self.halted = true;
+		}
+
+		!self.halted
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn halts_after_max_divergent_relay_blocks() {
+		let mut watchdog = DivergenceWatchdog::new(2);
+
+		assert!(watchdog.note(10, true));
+		assert!(watchdog.note(12, true));
+		assert_eq!(2, watchdog.divergent_relay_blocks(12));
+		assert!(!watchdog.note(13, true));
+		assert!(watchdog.is_halted());
+
+		// Stays halted until the divergence is resolved.
+		assert!(!watchdog.note(14, true));
+		assert!(watchdog.note(15, false));
+		assert!(!watchdog.is_halted());
+		assert_eq!(0, watchdog.divergent_relay_blocks(15));
+	}
+
+	#[test]
+	fn short_divergences_do_not_halt() {
+		let mut watchdog = DivergenceWatchdog::new(2);
+
+		assert!(watchdog.note(10, true));
+		assert!(watchdog.note(11, false));
+		assert!(watchdog.note(12, true));
+		assert!(watchdog.note(14, true));
+		assert!(!watchdog.is_halted());
+	}
+}
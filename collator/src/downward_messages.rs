@@ -0,0 +1,369 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Override how the downward messages of a candidate are retrieved.
+//!
+//! By default the downward messages are the contents of the downward message queue of the relay
+//! chain at the relay parent. A [`RetrieveDmqContents`] can filter these messages, or source them
+//! from somewhere else, e.g. from a bridge oracle in a private relay chain deployment.
+//!
+//! The number and the size of the retrieved messages are reported to the [`Metrics`], so a
+//! backlog of downward messages can be alerted on. A [`DmqBudget`] limits the messages that
+//! are provided to a single candidate.
+
+use crate::{Metrics, PHash};
+
+use cumulus_primitives::{
+	inherents::DownwardMessagesType, ParaId, RelayChainInterface, RelaySessionInfo,
+};
+
+use log::debug;
+
+use std::{sync::Arc, time::Duration};
+
+/// The share of the maximum PoV size that [`DmqBudget::for_max_pov_size`] gives to the
+/// downward messages, as divisor.
+const DMQ_POV_SHARE: usize = 4;
+
+/// Retrieves the downward messages for a candidate.
+pub trait RetrieveDmqContents: Send + Sync {
+	/// Returns the downward messages for the candidate built on `relay_parent`.
+	///
+	/// The messages of the relay chain can be read from `relay_chain`.
+	fn retrieve_dmq_contents(
+		&self,
+		relay_parent: PHash,
+		relay_chain: &dyn RelayChainInterface,
+	) -> Result<DownwardMessagesType, String>;
+}
+
+impl<F> RetrieveDmqContents for F
+where
+	F: Fn(PHash, &dyn RelayChainInterface) -> Result<DownwardMessagesType, String> + Send + Sync,
+{
+	fn retrieve_dmq_contents(
+		&self,
+		relay_parent: PHash,
+		relay_chain: &dyn RelayChainInterface,
+	) -> Result<DownwardMessagesType, String> {
+		(self)(relay_parent, relay_chain)
+	}
+}
+
+/// Only provides the downward messages of the relay chain before the first message that is
+/// bigger than the given number of bytes.
+///
+/// Like with the [`DmqBudget`], the runtime can only report a prefix of the queue as processed.
+/// Skipping a message in the middle would report it as processed without handling it, so the
+/// oversized message and all messages after it stay in the queue.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxMessageSize(pub usize);
+
+impl RetrieveDmqContents for MaxMessageSize {
+	fn retrieve_dmq_contents(
+		&self,
+		relay_parent: PHash,
+		relay_chain: &dyn RelayChainInterface,
+	) -> Result<DownwardMessagesType, String> {
+		Ok(relay_chain
+			.downward_messages(relay_parent)?
+			.into_iter()
+			.take_while(|m| m.msg.len() <= self.0)
+			.collect())
+	}
+}
+
+/// Limits the downward messages that are provided to a candidate.
+///
+/// A spike of downward messages can exceed the PoV or the weight of a block, which fails the
+/// candidate. Instead, only the longest prefix of the messages within the budget is provided.
+/// The runtime only reports the provided messages as processed, so the remaining messages stay
+/// in the downward message queue of the relay chain and are provided to the next candidates.
+///
+/// The first message is always provided, so the queue advances even if it exceeds the budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DmqBudget {
+	/// The maximum total size of the provided messages in bytes.
+	pub max_bytes: usize,
+	/// The maximum number of provided messages, unlimited if `None`.
+	pub max_messages: Option<usize>,
+}
+
+impl DmqBudget {
+	/// Provide downward messages up to a quarter of `max_pov_size`.
+	pub fn for_max_pov_size(max_pov_size: usize) -> Self {
+		Self {
+			max_bytes: max_pov_size / DMQ_POV_SHARE,
+			max_messages: None,
+		}
+	}
+
+	/// Returns the number of leading `messages` that fit into the budget.
+	fn fitting_messages(&self, messages: &DownwardMessagesType) -> usize {
+		let max_messages = self.max_messages.unwrap_or(usize::max_value());
+		let mut total_bytes = 0usize;
+
+		messages
+			.iter()
+			.take(max_messages.max(1))
+			.enumerate()
+			.take_while(|(index, m)| {
+				total_bytes = total_bytes.saturating_add(m.msg.len());
+				*index == 0 || total_bytes <= self.max_bytes
+			})
+			.count()
+	}
+}
+
+/// A [`RelayChainInterface`] that only provides the downward messages within a [`DmqBudget`].
+pub(crate) struct WithDmqBudget {
+	pub(crate) relay_chain: Arc<dyn RelayChainInterface>,
+	pub(crate) budget: DmqBudget,
+}
+
+impl RelayChainInterface for WithDmqBudget {
+	fn downward_messages(&self, relay_parent: PHash) -> Result<DownwardMessagesType, String> {
+		let mut messages = self.relay_chain.downward_messages(relay_parent)?;
+
+		let fitting = self.budget.fitting_messages(&messages);
+		if fitting < messages.len() {
+			debug!(
+				target: "cumulus-collator",
+				"Providing {} of {} downward messages at relay parent `{}`, the rest exceeds the budget.",
+				fitting,
+				messages.len(),
+				relay_parent,
+			);
+			messages.truncate(fitting);
+		}
+
+		Ok(messages)
+	}
+
+	fn session_info(&self, relay_parent: PHash) -> Result<Option<RelaySessionInfo>, String> {
+		self.relay_chain.session_info(relay_parent)
+	}
+
+	fn para_id(&self) -> Option<ParaId> {
+		self.relay_chain.para_id()
+	}
+
+	fn execution_timeout(&self, relay_parent: PHash) -> Result<Option<Duration>, String> {
+		self.relay_chain.execution_timeout(relay_parent)
+	}
+
+	fn max_pov_size(&self, relay_parent: PHash) -> Result<Option<u32>, String> {
+		self.relay_chain.max_pov_size(relay_parent)
+	}
+}
+
+/// A [`RelayChainInterface`] whose downward messages are retrieved by a [`RetrieveDmqContents`].
+pub(crate) struct WithDmqOverride {
+	pub(crate) relay_chain: Arc<dyn RelayChainInterface>,
+	pub(crate) retrieve: Arc<dyn RetrieveDmqContents>,
+}
+
+impl RelayChainInterface for WithDmqOverride {
+	fn downward_messages(&self, relay_parent: PHash) -> Result<DownwardMessagesType, String> {
+		self.retrieve.retrieve_dmq_contents(relay_parent, &*self.relay_chain)
+	}
+
+	fn session_info(&self, relay_parent: PHash) -> Result<Option<RelaySessionInfo>, String> {
+		self.relay_chain.session_info(relay_parent)
+	}
+
+	fn para_id(&self) -> Option<ParaId> {
+		self.relay_chain.para_id()
+	}
+
+	fn execution_timeout(&self, relay_parent: PHash) -> Result<Option<Duration>, String> {
+		self.relay_chain.execution_timeout(relay_parent)
+	}
+
+	fn max_pov_size(&self, relay_parent: PHash) -> Result<Option<u32>, String> {
+		self.relay_chain.max_pov_size(relay_parent)
+	}
+}
+
+/// A [`RelayChainInterface`] that reports the retrieved downward messages to the [`Metrics`].
+pub(crate) struct WithMessageMetrics {
+	pub(crate) relay_chain: Arc<dyn RelayChainInterface>,
+	pub(crate) metrics: Metrics,
+}
+
+impl RelayChainInterface for WithMessageMetrics {
+	fn downward_messages(&self, relay_parent: PHash) -> Result<DownwardMessagesType, String> {
+		let messages = self.relay_chain.downward_messages(relay_parent)?;
+		self.metrics.report_downward_messages(&messages);
+		Ok(messages)
+	}
+
+	fn session_info(&self, relay_parent: PHash) -> Result<Option<RelaySessionInfo>, String> {
+		self.relay_chain.session_info(relay_parent)
+	}
+
+	fn para_id(&self) -> Option<ParaId> {
+		self.relay_chain.para_id()
+	}
+
+	fn execution_timeout(&self, relay_parent: PHash) -> Result<Option<Duration>, String> {
+		self.relay_chain.execution_timeout(relay_parent)
+	}
+
+	fn max_pov_size(&self, relay_parent: PHash) -> Result<Option<u32>, String> {
+		self.relay_chain.max_pov_size(relay_parent)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_primitives::InboundDownwardMessage;
+
+	struct TestRelayChain;
+
+	impl RelayChainInterface for TestRelayChain {
+		fn downward_messages(&self, _: PHash) -> Result<DownwardMessagesType, String> {
+			Ok(vec![
+				InboundDownwardMessage {
+					sent_at: 1,
+					msg: vec![1; 4],
+				},
+				InboundDownwardMessage {
+					sent_at: 2,
+					msg: vec![2; 16],
+				},
+			])
+		}
+
+		fn execution_timeout(&self, _: PHash) -> Result<Option<Duration>, String> {
+			Ok(Some(Duration::from_secs(1)))
+		}
+
+		fn max_pov_size(&self, _: PHash) -> Result<Option<u32>, String> {
+			Ok(Some(1024))
+		}
+	}
+
+	#[test]
+	fn stops_at_the_first_message_above_the_max_size() {
+		let relay_chain = WithDmqOverride {
+			relay_chain: Arc::new(TestRelayChain),
+			retrieve: Arc::new(MaxMessageSize(8)),
+		};
+
+		let messages = relay_chain
+			.downward_messages(PHash::default())
+			.expect("Retrieves the messages");
+
+		assert_eq!(1, messages.len());
+		assert_eq!(1, messages[0].sent_at);
+
+		// The small message after the oversized one is not provided either.
+		let oversized_first = |_: PHash| -> Result<DownwardMessagesType, String> {
+			Ok(vec![
+				InboundDownwardMessage {
+					sent_at: 1,
+					msg: vec![1; 16],
+				},
+				InboundDownwardMessage {
+					sent_at: 2,
+					msg: vec![2; 4],
+				},
+			])
+		};
+		assert!(MaxMessageSize(8)
+			.retrieve_dmq_contents(PHash::default(), &oversized_first)
+			.expect("Retrieves the messages")
+			.is_empty());
+	}
+
+	#[test]
+	fn truncates_messages_to_the_budget() {
+		let budget = |max_bytes, max_messages| WithDmqBudget {
+			relay_chain: Arc::new(TestRelayChain),
+			budget: DmqBudget {
+				max_bytes,
+				max_messages,
+			},
+		};
+		let provided = |relay_chain: WithDmqBudget| {
+			relay_chain
+				.downward_messages(PHash::default())
+				.expect("Retrieves the messages")
+				.len()
+		};
+
+		assert_eq!(2, provided(budget(20, None)));
+		assert_eq!(1, provided(budget(19, None)));
+		assert_eq!(1, provided(budget(20, Some(1))));
+		// The first message is always provided.
+		assert_eq!(1, provided(budget(0, Some(0))));
+	}
+
+	#[test]
+	fn reports_the_retrieved_messages() {
+		let registry = substrate_prometheus_endpoint::Registry::new();
+		let relay_chain = WithMessageMetrics {
+			relay_chain: Arc::new(TestRelayChain),
+			metrics: Metrics::register(Some(&registry)).expect("Registers the metrics"),
+		};
+
+		relay_chain
+			.downward_messages(PHash::default())
+			.expect("Retrieves the messages");
+
+		let gauge = |name| {
+			registry
+				.gather()
+				.into_iter()
+				.find(|family| family.get_name() == name)
+				.map(|family| family.get_metric()[0].get_gauge().get_value())
+		};
+		assert_eq!(Some(2.0), gauge("cumulus_collator_downward_messages"));
+		assert_eq!(Some(20.0), gauge("cumulus_collator_downward_message_bytes"));
+	}
+
+	#[test]
+	fn only_overrides_downward_messages() {
+		let relay_chain = WithDmqOverride {
+			relay_chain: Arc::new(TestRelayChain),
+			retrieve: Arc::new(
+				|_: PHash, _: &dyn RelayChainInterface| -> Result<DownwardMessagesType, String> {
+					Ok(Vec::new())
+				},
+			),
+		};
+
+		assert!(relay_chain
+			.downward_messages(PHash::default())
+			.expect("Retrieves the messages")
+			.is_empty());
+		assert_eq!(
+			Some(Duration::from_secs(1)),
+			relay_chain
+				.execution_timeout(PHash::default())
+				.expect("Returns the timeout"),
+		);
+		assert_eq!(
+			Some(1024),
+			relay_chain
+				.max_pov_size(PHash::default())
+				.expect("Returns the max PoV size"),
+		);
+	}
+}
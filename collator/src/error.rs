@@ -0,0 +1,171 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Errors of the collator.
+
+use crate::{migration_check::StorageVersion, relay_api_version::RelayChainApiVersions};
+
+use polkadot_node_subsystem::SubsystemError;
+use sp_consensus::Error as ConsensusError;
+use substrate_prometheus_endpoint::PrometheusError;
+
+use std::fmt;
+
+/// Errors of the collator.
+///
+/// Every error has a stable [`code`](CollatorError::code) that is used in logs and metrics.
+#[derive(Debug)]
+pub enum CollatorError {
+	/// Registering the collator metrics failed.
+	Metrics(PrometheusError),
+	/// Creating the PoV export directory failed.
+	PoVExportDir(std::io::Error),
+	/// Following the relay chain could not be started.
+	FollowPolkadot(sp_blockchain::Error),
+	/// Sending the given message to the overseer failed, even after retrying.
+	Overseer(&'static str, SubsystemError),
+	/// The parent head data given by the relay chain could not be decoded.
+	InvalidHeadData(codec::Error),
+	/// Creating the inherent data failed.
+	InherentData(sp_inherents::Error),
+	/// A runtime api call on the relay chain failed.
+	RelayApi(String),
+	/// Initializing the proposer failed.
+	ProposerInit(String),
+	/// Proposing the block failed.
+	Proposing(String),
+	/// The proposer did not return the requested storage proof.
+	ProofMissing,
+	/// Creating the storage proof with a custom
+	/// [`ProofRecorderProvider`](crate::proof_recorder::ProofRecorderProvider) failed.
+	ProofRecording(String),
+	/// Importing the freshly built block failed.
+	Import(ConsensusError),
+	/// Reading the state of a parachain block failed.
+	State(String),
+	/// Reading a value from the parachain state failed.
+	InvalidState(&'static str, codec::Error),
+	/// The local `validate_block` rejected the freshly built candidate.
+	PreValidation(String),
+	/// Executing the freshly built block once more before importing it failed, see
+	/// [`reexecution`](crate::reexecution).
+	Reexecution(String),
+	/// The on-chain storage was migrated by a newer runtime than the native runtime, i.e. the
+	/// node is outdated.
+	StorageVersionMismatch {
+		on_chain: StorageVersion,
+		native: StorageVersion,
+	},
+	/// The relay chain runtime does not provide the runtime apis the collator requires.
+	IncompatibleRelayChain(RelayChainApiVersions),
+	/// Even the block that only carries the runtime upgrade needs the given number of bytes,
+	/// more than the maximum PoV size.
+	UpgradeTooLarge(usize),
+}
+
+impl CollatorError {
+	/// A short, stable identifier of the error.
+	pub fn code(&self) -> &'static str {
+		match self {
+			CollatorError::Metrics(_) => "metrics",
+			CollatorError::PoVExportDir(_) => "pov_export_dir",
+			CollatorError::FollowPolkadot(_) => "follow_polkadot",
+			CollatorError::Overseer(..) => "overseer",
+			CollatorError::InvalidHeadData(_) => "invalid_head_data",
+			CollatorError::InherentData(_) => "inherent_data",
+			CollatorError::RelayApi(_) => "relay_api",
+			CollatorError::ProposerInit(_) => "proposer_init",
+			CollatorError::Proposing(_) => "proposing",
+			CollatorError::ProofMissing => "proof_missing",
+			CollatorError::ProofRecording(_) => "proof_recording",
+			CollatorError::Import(_) => "import",
+			CollatorError::State(_) => "state",
+			CollatorError::InvalidState(..) => "invalid_state",
+			CollatorError::PreValidation(_) => "pre_validation",
+			CollatorError::Reexecution(_) => "reexecution",
+			CollatorError::StorageVersionMismatch { .. } => "storage_version_mismatch",
+			CollatorError::IncompatibleRelayChain(_) => "incompatible_relay_chain",
+			CollatorError::UpgradeTooLarge(_) => "upgrade_too_large",
+		}
+	}
+}
+
+impl fmt::Display for CollatorError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CollatorError::Metrics(e) => write!(f, "Failed to register collator metrics: {:?}", e),
+			CollatorError::PoVExportDir(e) => {
+				write!(f, "Failed to create the PoV export directory: {:?}", e)
+			}
+			CollatorError::FollowPolkadot(e) => {
+				write!(f, "Could not start following polkadot: {:?}", e)
+			}
+			CollatorError::Overseer(msg, e) => {
+				write!(f, "Failed to send `{}` message: {:?}", msg, e)
+			}
+			CollatorError::InvalidHeadData(e) => {
+				write!(f, "Could not decode the head data: {:?}", e)
+			}
+			CollatorError::InherentData(e) => write!(f, "Failed to create inherent data: {:?}", e),
+			CollatorError::RelayApi(e) => write!(f, "Relay chain runtime api call failed: {}", e),
+			CollatorError::ProposerInit(e) => write!(f, "Could not create proposer: {}", e),
+			CollatorError::Proposing(e) => write!(f, "Proposing failed: {}", e),
+			CollatorError::ProofMissing => write!(f, "Proposer did not return the requested proof"),
+			CollatorError::ProofRecording(e) => {
+				write!(f, "Failed to create the storage proof: {}", e)
+			}
+			CollatorError::Import(e) => write!(f, "Error importing build block: {:?}", e),
+			CollatorError::State(e) => write!(f, "Failed to get the state of a block: {}", e),
+			CollatorError::InvalidState(what, e) => write!(f, "Failed to decode {}: {:?}", what, e),
+			CollatorError::PreValidation(e) => {
+				write!(
+					f,
+					"Candidate was rejected by the local `validate_block`: {}",
+					e
+				)
+			}
+			CollatorError::Reexecution(e) => {
+				write!(
+					f,
+					"Re-executing the built block before its import failed: {}",
+					e
+				)
+			}
+			CollatorError::StorageVersionMismatch { on_chain, native } => write!(
+				f,
+				"Refusing to collate: the on-chain storage was last migrated for `{}`, but the \
+				native runtime is the older `{}`. Candidates built by the outdated runtime would \
+				fail validation",
+				on_chain, native,
+			),
+			CollatorError::IncompatibleRelayChain(found) => write!(
+				f,
+				"The relay chain runtime provides {}, but the collator requires `ParachainHost` \
+				version {} or newer",
+				found,
+				crate::relay_api_version::MIN_PARACHAIN_HOST_VERSION,
+			),
+			CollatorError::UpgradeTooLarge(size) => write!(
+				f,
+				"The runtime upgrade and the PoV of the block carrying it need {} bytes, more than \
+				the maximum PoV size",
+				size,
+			),
+		}
+	}
+}
+
+impl std::error::Error for CollatorError {}
@@ -0,0 +1,253 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hooks for alerting the operator of a collator.
+//!
+//! The collator reports noteworthy [`CollatorEvent`]s to a [`CollatorEventHandler`]. Nodes can
+//! implement the handler to push the events to a webhook or a pager. By default the events are
+//! logged and counted by the [`DefaultEventHandler`].
+
+use crate::{PBlockNumber, PHash};
+
+use sp_core::crypto::KeyTypeId;
+use sp_runtime::traits::Block as BlockT;
+
+use substrate_prometheus_endpoint::{register, CounterVec, Opts, PrometheusError, Registry, U64};
+
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+
+use std::{collections::VecDeque, fmt, sync::Arc};
+
+/// The maximum number of produced blocks with an upgrade that wait for their inclusion.
+const MAX_PENDING_UPGRADES: usize = 16;
+
+/// An event of the collator an operator may want to be alerted about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CollatorEvent<Hash> {
+	/// A candidate was produced for `relay_parent`.
+	CandidateProduced {
+		relay_parent: PHash,
+		block_hash: Hash,
+		pov_hash: PHash,
+	},
+	/// Producing a candidate for `relay_parent` failed with the error with the given
+	/// [`code`](crate::CollatorError::code).
+	CandidateFailed { relay_parent: PHash, error: String },
+	/// The local chain diverged from the head of the parachain on the relay chain for
+	/// `divergent_relay_blocks` and candidate production was halted.
+	FallingBehind { divergent_relay_blocks: PBlockNumber },
+	/// The local chain caught up with the relay chain again after [`FallingBehind`].
+	///
+	/// [`FallingBehind`]: CollatorEvent::FallingBehind
+	CaughtUp,
+	/// A key of the given type is missing from the keystore.
+	///
+	/// The collator is started with its key, so this is reported by the node, e.g. when there
+	/// is no key to sign block announcements with.
+	KeyMissing(KeyTypeId),
+	/// The relay chain included the produced block `block_hash`, which scheduled an upgrade to
+	/// validation code of `code_size` bytes. The upgrade is now pending on the relay chain.
+	UpgradePending { block_hash: Hash, code_size: usize },
+}
+
+impl<Hash> CollatorEvent<Hash> {
+	/// A short, stable name of the event that is used in metrics.
+	pub fn name(&self) -> &'static str {
+		match self {
+			CollatorEvent::CandidateProduced { .. } => "candidate_produced",
+			CollatorEvent::CandidateFailed { .. } => "candidate_failed",
+			CollatorEvent::FallingBehind { .. } => "falling_behind",
+			CollatorEvent::CaughtUp => "caught_up",
+			CollatorEvent::KeyMissing(_) => "key_missing",
+			CollatorEvent::UpgradePending { .. } => "upgrade_pending",
+		}
+	}
+}
+
+impl<Hash: fmt::Debug> fmt::Display for CollatorEvent<Hash> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CollatorEvent::CandidateProduced {
+				relay_parent,
+				block_hash,
+				pov_hash,
+			} => write!(
+				f,
+				"Produced candidate with PoV `{}` for block `{:?}` on relay parent `{}`",
+				pov_hash, block_hash, relay_parent,
+			),
+			CollatorEvent::CandidateFailed {
+				relay_parent,
+				error,
+			} => write!(
+				f,
+				"Failed to produce candidate for relay parent `{}` [{}]",
+				relay_parent, error,
+			),
+			CollatorEvent::FallingBehind {
+				divergent_relay_blocks,
+			} => write!(
+				f,
+				"Candidate production halted, the local chain diverged from the relay chain for \
+				{} relay blocks",
+				divergent_relay_blocks,
+			),
+			CollatorEvent::CaughtUp => write!(f, "The local chain caught up with the relay chain"),
+			CollatorEvent::KeyMissing(key_type) => {
+				write!(f, "No key of type `{:?}` in the keystore", key_type)
+			}
+			CollatorEvent::UpgradePending {
+				block_hash,
+				code_size,
+			} => write!(
+				f,
+				"Block `{:?}` was included, its upgrade to validation code of {} bytes is \
+				pending on the relay chain",
+				block_hash, code_size,
+			),
+		}
+	}
+}
+
+/// The produced blocks that scheduled an upgrade and were not included by the relay chain yet.
+///
+/// Clones share the same blocks.
+pub(crate) struct PendingUpgrades<Hash>(Arc<Mutex<VecDeque<(Hash, usize)>>>);
+
+impl<Hash> Clone for PendingUpgrades<Hash> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+impl<Hash> Default for PendingUpgrades<Hash> {
+	fn default() -> Self {
+		Self(Default::default())
+	}
+}
+
+impl<Hash: PartialEq> PendingUpgrades<Hash> {
+	/// Note that the produced block `block_hash` scheduled an upgrade to validation code of
+	/// `code_size` bytes.
+	pub(crate) fn note_produced(&self, block_hash: Hash, code_size: usize) {
+		let mut pending = self.0.lock();
+
+		if pending.len() >= MAX_PENDING_UPGRADES {
+			pending.pop_front();
+		}
+		pending.push_back((block_hash, code_size));
+	}
+
+	/// Note that the relay chain included the block `block_hash`.
+	///
+	/// Returns the size of the validation code, if the block was produced by us and scheduled an
+	/// upgrade.
+	pub(crate) fn note_included(&self, block_hash: &Hash) -> Option<usize> {
+		let mut pending = self.0.lock();
+
+		let index = pending.iter().position(|(hash, _)| hash == block_hash)?;
+		pending.remove(index).map(|(_, code_size)| code_size)
+	}
+}
+
+/// Handles the events of a collator.
+pub trait CollatorEventHandler<Block: BlockT>: Send + Sync {
+	/// Handle the given `event`.
+	///
+	/// Called from the task that produces candidates, so this should not block.
+	fn handle_event(&self, event: &CollatorEvent<Block::Hash>);
+}
+
+impl<Block, F> CollatorEventHandler<Block> for F
+where
+	Block: BlockT,
+	F: Fn(&CollatorEvent<Block::Hash>) + Send + Sync,
+{
+	fn handle_event(&self, event: &CollatorEvent<Block::Hash>) {
+		(self)(event)
+	}
+}
+
+/// Logs the events and counts them in the `cumulus_collator_events_total` metric.
+#[derive(Clone, Default)]
+pub struct DefaultEventHandler {
+	events: Option<CounterVec<U64>>,
+}
+
+impl DefaultEventHandler {
+	/// Create a new handler that registers its metric at the given `registry`.
+	pub fn new(registry: Option<&Registry>) -> Result<Self, PrometheusError> {
+		let events = match registry {
+			Some(registry) => Some(register(
+				CounterVec::new(
+					Opts::new(
+						"cumulus_collator_events_total",
+						"Number of collator events, by event",
+					),
+					&["event"],
+				)?,
+				registry,
+			)?),
+			None => None,
+		};
+
+		Ok(Self { events })
+	}
+}
+
+impl<Block: BlockT> CollatorEventHandler<Block> for DefaultEventHandler {
+	fn handle_event(&self, event: &CollatorEvent<Block::Hash>) {
+		match event {
+			CollatorEvent::KeyMissing(_) => warn!(target: "cumulus-collator", "{}.", event),
+			CollatorEvent::UpgradePending { .. } => info!(target: "cumulus-collator", "{}.", event),
+			// The other events are already logged where they occur.
+			_ => debug!(target: "cumulus-collator", "{}.", event),
+		}
+
+		if let Some(ref events) = self.events {
+			events.with_label_values(&[event.name()]).inc();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_runtime::Block;
+	use parking_lot::Mutex;
+	use sp_core::H256;
+
+	use std::sync::Arc;
+
+	#[test]
+	fn closures_handle_events() {
+		let handled = Arc::new(Mutex::new(Vec::new()));
+		let handler: Arc<dyn CollatorEventHandler<Block>> = {
+			let handled = handled.clone();
+			Arc::new(move |event: &CollatorEvent<H256>| handled.lock().push(event.name()))
+		};
+
+		handler.handle_event(&CollatorEvent::CaughtUp);
+		handler.handle_event(&CollatorEvent::UpgradePending {
+			block_hash: H256::repeat_byte(1),
+			code_size: 1024,
+		});
+
+		assert_eq!(vec!["caught_up", "upgrade_pending"], *handled.lock());
+	}
+}
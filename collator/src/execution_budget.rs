@@ -0,0 +1,108 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The time the proposer may spend on building a candidate.
+//!
+//! Validators have to execute the candidate within the PVF execution timeout of the relay
+//! chain. They may run on slower hardware than the collator and also need to check the storage
+//! proof, so the proposer only gets a share of this timeout.
+
+use sp_runtime::Percent;
+
+use std::time::Duration;
+
+/// The PVF execution timeout that is assumed if the relay chain doesn't provide one.
+///
+/// This is the timeout used by Polkadot validators in release builds.
+pub const DEFAULT_EXECUTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Derives the maximum duration of a proposal from the PVF execution timeout.
+#[derive(Clone, Copy, Debug)]
+pub struct ExecutionBudget {
+	proposal_share: Percent,
+	execution_timeout: Duration,
+}
+
+impl Default for ExecutionBudget {
+	/// Gives the proposer 10% of the execution timeout, i.e. 500ms of the
+	/// [`DEFAULT_EXECUTION_TIMEOUT`].
+	fn default() -> Self {
+		Self::new(Percent::from_percent(10))
+	}
+}
+
+impl ExecutionBudget {
+	/// Give the proposer the given share of the execution timeout.
+	pub fn new(proposal_share: Percent) -> Self {
+		Self {
+			proposal_share,
+			execution_timeout: DEFAULT_EXECUTION_TIMEOUT,
+		}
+	}
+
+	/// Assume the given `execution_timeout` if the relay chain doesn't provide one, instead of
+	/// the [`DEFAULT_EXECUTION_TIMEOUT`].
+	pub fn with_execution_timeout(mut self, execution_timeout: Duration) -> Self {
+		self.execution_timeout = execution_timeout;
+		self
+	}
+
+	/// The maximum duration of a proposal for the given `execution_timeout` of the relay chain.
+	///
+	/// Falls back to the configured timeout if the relay chain provides none, see
+	/// [`with_execution_timeout`](Self::with_execution_timeout).
+	pub fn max_duration(&self, execution_timeout: Option<Duration>) -> Duration {
+		let execution_timeout = execution_timeout.unwrap_or(self.execution_timeout);
+
+		Duration::from_millis(self.proposal_share * execution_timeout.as_millis() as u64)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn max_duration_is_share_of_execution_timeout() {
+		let budget = ExecutionBudget::default();
+
+		assert_eq!(Duration::from_millis(500), budget.max_duration(None));
+		assert_eq!(
+			Duration::from_millis(200),
+			budget.max_duration(Some(Duration::from_secs(2))),
+		);
+		assert_eq!(
+			Duration::from_secs(2),
+			ExecutionBudget::new(Percent::from_percent(100))
+				.max_duration(Some(Duration::from_secs(2))),
+		);
+		assert_eq!(
+			Duration::from_secs(0),
+			ExecutionBudget::new(Percent::from_percent(0)).max_duration(None),
+		);
+	}
+
+	#[test]
+	fn configured_timeout_is_only_used_without_relay_chain_timeout() {
+		let budget = ExecutionBudget::default().with_execution_timeout(Duration::from_secs(10));
+
+		assert_eq!(Duration::from_secs(1), budget.max_duration(None));
+		assert_eq!(
+			Duration::from_millis(200),
+			budget.max_duration(Some(Duration::from_secs(2))),
+		);
+	}
+}
@@ -0,0 +1,217 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Track how long it takes until produced candidates are included by the relay chain.
+//!
+//! The latency is measured in relay chain blocks, from the relay parent a candidate was produced
+//! on to the relay block that included it. Candidates are identified by the hash of their PoV.
+//!
+//! Candidates that were backed, but timed out during availability are never included. They are
+//! tracked separately, as their parachain parent needs to be built on again.
+
+use crate::{PBlockNumber, PHash};
+
+use parking_lot::Mutex;
+
+use std::{collections::VecDeque, sync::Arc};
+
+/// The number of produced candidates that are waiting for their inclusion.
+///
+/// Candidates that are never included are dropped when this limit is reached.
+const MAX_PENDING: usize = 64;
+
+/// The number of included candidates that are remembered.
+const MAX_RECENT: usize = 16;
+
+/// The inclusion of a produced candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Inclusion {
+	/// The hash of the PoV of the candidate.
+	pub pov_hash: PHash,
+	/// The number of the relay parent the candidate was produced on.
+	pub produced_at: PBlockNumber,
+	/// The number of the relay block that included the candidate.
+	pub included_at: PBlockNumber,
+}
+
+impl Inclusion {
+	/// The number of relay blocks between production and inclusion.
+	pub fn latency(&self) -> PBlockNumber {
+		self.included_at.saturating_sub(self.produced_at)
+	}
+}
+
+#[derive(Default)]
+struct Inner {
+	pending: VecDeque<(PHash, PBlockNumber)>,
+	recent: VecDeque<Inclusion>,
+	timed_out: u64,
+}
+
+/// Shared tracker of the inclusion of produced candidates.
+///
+/// The collator updates it while running, clones of it can be handed out to report the
+/// latencies.
+#[derive(Clone, Default)]
+pub struct InclusionTracker(Arc<Mutex<Inner>>);
+
+impl InclusionTracker {
+	/// Note that the candidate with the given `pov_hash` was produced on the relay parent with
+	/// the number `produced_at`.
+	pub(crate) fn note_produced(&self, pov_hash: PHash, produced_at: PBlockNumber) {
+		let mut inner = self.0.lock();
+
+		if inner.pending.len() >= MAX_PENDING {
+			inner.pending.pop_front();
+		}
+		inner.pending.push_back((pov_hash, produced_at));
+	}
+
+	/// Note that the candidate with the given `pov_hash` was included in the relay block with
+	/// the number `included_at`.
+	///
+	/// Returns the inclusion, if the candidate was produced by us.
+	pub fn note_included(
+		&self,
+		pov_hash: PHash,
+		included_at: PBlockNumber,
+	) -> Option<Inclusion> {
+		let mut inner = self.0.lock();
+
+		let index = inner.pending.iter().position(|(h, _)| *h == pov_hash)?;
+		let (_, produced_at) = inner.pending.remove(index)?;
+
+		let inclusion = Inclusion {
+			pov_hash,
+			produced_at,
+			included_at,
+		};
+
+		if inner.recent.len() >= MAX_RECENT {
+			inner.recent.pop_front();
+		}
+		inner.recent.push_back(inclusion);
+
+		Some(inclusion)
+	}
+
+	/// Note that the candidate with the given `pov_hash` timed out during availability.
+	///
+	/// Returns `true` if the candidate was produced by us.
+	pub fn note_timed_out(&self, pov_hash: PHash) -> bool {
+		let mut inner = self.0.lock();
+
+		match inner.pending.iter().position(|(h, _)| *h == pov_hash) {
+			Some(index) => {
+				inner.pending.remove(index);
+				inner.timed_out += 1;
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// The number of produced candidates that timed out during availability.
+	pub fn timed_out(&self) -> u64 {
+		self.0.lock().timed_out
+	}
+
+	/// The most recent inclusions, oldest first.
+	pub fn recent(&self) -> Vec<Inclusion> {
+		self.0.lock().recent.iter().cloned().collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tracks_latency_of_own_candidates() {
+		let tracker = InclusionTracker::default();
+		let reported = tracker.clone();
+
+		tracker.note_produced(PHash::repeat_byte(1), 10);
+		tracker.note_produced(PHash::repeat_byte(2), 11);
+
+		// Not produced by us.
+		assert!(tracker.note_included(PHash::repeat_byte(3), 12).is_none());
+
+		assert_eq!(
+			Some(2),
+			tracker
+				.note_included(PHash::repeat_byte(2), 13)
+				.map(|i| i.latency()),
+		);
+		assert_eq!(
+			Some(4),
+			tracker
+				.note_included(PHash::repeat_byte(1), 14)
+				.map(|i| i.latency()),
+		);
+
+		// Only included once.
+		assert!(tracker.note_included(PHash::repeat_byte(1), 15).is_none());
+
+		assert_eq!(
+			vec![PHash::repeat_byte(2), PHash::repeat_byte(1)],
+			reported
+				.recent()
+				.into_iter()
+				.map(|i| i.pov_hash)
+				.collect::<Vec<_>>(),
+		);
+	}
+
+	#[test]
+	fn timed_out_candidates_are_not_included() {
+		let tracker = InclusionTracker::default();
+
+		tracker.note_produced(PHash::repeat_byte(1), 10);
+
+		assert!(!tracker.note_timed_out(PHash::repeat_byte(2)));
+		assert!(tracker.note_timed_out(PHash::repeat_byte(1)));
+		assert!(!tracker.note_timed_out(PHash::repeat_byte(1)));
+		assert_eq!(1, tracker.timed_out());
+
+		assert!(tracker.note_included(PHash::repeat_byte(1), 12).is_none());
+		assert!(tracker.recent().is_empty());
+	}
+
+	#[test]
+	fn bounds_pending_and_recent_candidates() {
+		let tracker = InclusionTracker::default();
+
+		for i in 0..(MAX_PENDING + 1) {
+			tracker.note_produced(PHash::from_low_u64_be(i as u64), i as PBlockNumber);
+		}
+
+		// The oldest candidate was dropped.
+		assert!(tracker.note_included(PHash::from_low_u64_be(0), 100).is_none());
+
+		for i in 1..(MAX_PENDING + 1) {
+			assert!(tracker
+				.note_included(PHash::from_low_u64_be(i as u64), 100)
+				.is_some());
+		}
+
+		assert_eq!(MAX_RECENT, tracker.recent().len());
+		assert_eq!(
+			PHash::from_low_u64_be(MAX_PENDING as u64),
+			tracker.recent().last().unwrap().pov_hash,
+		);
+	}
+}
@@ -0,0 +1,206 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A journal of the last collations, persisted in the database of the node.
+//!
+//! Every collation request is appended to the journal with its outcome. The journal is stored in
+//! the auxiliary storage of the client, so it survives a crash of the node and can be read after
+//! a restart, e.g. over RPC, to find out what the collator did before an incident.
+
+use crate::{CollatorError, PHash};
+
+use sc_client_api::AuxStore;
+
+use codec::{Decode, Encode};
+use log::warn;
+use parking_lot::Mutex;
+
+use std::{
+	marker::PhantomData,
+	sync::Arc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The key of the journal in the auxiliary storage.
+pub const JOURNAL_KEY: &[u8] = b"cumulus_collation_journal";
+
+/// The default number of collations that are kept in the journal.
+pub const DEFAULT_JOURNAL_SIZE: usize = 256;
+
+/// The outcome of a collation request.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum CollationOutcome {
+	/// A candidate was produced.
+	Produced,
+	/// No candidate was produced, e.g. because the parent block is unknown.
+	Skipped,
+	/// Producing the candidate failed with the error with the given
+	/// [`code`](CollatorError::code).
+	Failed(String),
+}
+
+/// A collation request in the journal.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct JournalEntry<Hash> {
+	/// Milliseconds since the unix epoch when the request was finished.
+	pub timestamp: u64,
+	/// The relay parent of the request.
+	pub relay_parent: PHash,
+	/// The parachain block the candidate was built on, if known.
+	pub para_parent: Option<Hash>,
+	/// The hash of the produced block.
+	pub block_hash: Option<Hash>,
+	/// The hash of the PoV of the produced candidate.
+	pub pov_hash: Option<PHash>,
+	/// The outcome of the request.
+	pub outcome: CollationOutcome,
+}
+
+/// Read and write access to the stored journal.
+trait JournalStore: Send + Sync {
+	fn read(&self) -> Option<Vec<u8>>;
+	fn write(&self, journal: &[u8]) -> sp_blockchain::Result<()>;
+}
+
+struct AuxJournalStore<A>(Arc<A>);
+
+impl<A: AuxStore + Send + Sync> JournalStore for AuxJournalStore<A> {
+	fn read(&self) -> Option<Vec<u8>> {
+		self.0.get_aux(JOURNAL_KEY).ok().flatten()
+	}
+
+	fn write(&self, journal: &[u8]) -> sp_blockchain::Result<()> {
+		self.0.insert_aux(&[(JOURNAL_KEY, journal)], &[])
+	}
+}
+
+/// The journal of the last collations.
+///
+/// Clones share the same journal.
+pub struct CollationJournal<Hash> {
+	store: Arc<dyn JournalStore>,
+	size: usize,
+	lock: Arc<Mutex<()>>,
+	_marker: PhantomData<Hash>,
+}
+
+impl<Hash> Clone for CollationJournal<Hash> {
+	fn clone(&self) -> Self {
+		Self {
+			store: self.store.clone(),
+			size: self.size,
+			lock: self.lock.clone(),
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<Hash: Encode + Decode> CollationJournal<Hash> {
+	/// Keep the last `size` collations in the auxiliary storage of `aux`.
+	pub fn new<A: AuxStore + Send + Sync + 'static>(aux: Arc<A>, size: usize) -> Self {
+		Self {
+			store: Arc::new(AuxJournalStore(aux)),
+			size,
+			lock: Default::default(),
+			_marker: PhantomData,
+		}
+	}
+
+	/// Returns the journal, oldest entry first.
+	pub fn entries(&self) -> Vec<JournalEntry<Hash>> {
+		self.store
+			.read()
+			.and_then(|raw| Vec::decode(&mut &raw[..]).ok())
+			.unwrap_or_default()
+	}
+
+	/// Append `entry` to the journal, dropping the oldest entries above the size of the journal.
+	pub fn append(&self, entry: JournalEntry<Hash>) {
+		let _lock = self.lock.lock();
+
+		let mut entries = self.entries();
+		entries.push(entry);
+		if entries.len() > self.size {
+			entries.drain(..entries.len() - self.size);
+		}
+
+		if let Err(e) = self.store.write(&entries.encode()) {
+			warn!(target: "cumulus-collator", "Failed to write the collation journal: {:?}", e);
+		}
+	}
+
+	/// Append the result of a collation request on `relay_parent` to the journal.
+	pub(crate) fn note(
+		&self,
+		relay_parent: PHash,
+		para_parent: Option<Hash>,
+		produced: Result<Option<(Hash, PHash)>, &CollatorError>,
+	) {
+		let (block_hash, pov_hash, outcome) = match produced {
+			Ok(Some((block_hash, pov_hash))) => {
+				(Some(block_hash), Some(pov_hash), CollationOutcome::Produced)
+			}
+			Ok(None) => (None, None, CollationOutcome::Skipped),
+			Err(e) => (None, None, CollationOutcome::Failed(e.code().into())),
+		};
+
+		self.append(JournalEntry {
+			timestamp: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|d| d.as_millis() as u64)
+				.unwrap_or_default(),
+			relay_parent,
+			para_parent,
+			block_hash,
+			pov_hash,
+			outcome,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_client::{DefaultTestClientBuilderExt, TestClientBuilder, TestClientBuilderExt};
+	use sp_core::H256;
+
+	#[test]
+	fn keeps_the_last_entries_across_restarts() {
+		let client = Arc::new(TestClientBuilder::new().build());
+
+		let journal = CollationJournal::<H256>::new(client.clone(), 2);
+		journal.note(PHash::repeat_byte(1), None, Ok(None));
+		journal.note(
+			PHash::repeat_byte(2),
+			Some(H256::repeat_byte(1)),
+			Ok(Some((H256::repeat_byte(2), PHash::repeat_byte(3)))),
+		);
+		journal.note(PHash::repeat_byte(3), None, Err(&CollatorError::ProofMissing));
+
+		// Reading the journal from the database again.
+		let entries = CollationJournal::<H256>::new(client, 2).entries();
+
+		assert_eq!(2, entries.len());
+		assert_eq!(PHash::repeat_byte(2), entries[0].relay_parent);
+		assert_eq!(Some(H256::repeat_byte(2)), entries[0].block_hash);
+		assert_eq!(CollationOutcome::Produced, entries[0].outcome);
+		assert_eq!(
+			CollationOutcome::Failed("proof_missing".into()),
+			entries[1].outcome,
+		);
+	}
+}
@@ -16,49 +16,247 @@
 
 //! Cumulus Collator implementation for Substrate.
 
-use cumulus_network::WaitToAnnounce;
+use cumulus_consensus::{NewBestObserver, ParachainForkChoice, RelayChainCache};
+pub use cumulus_network::{
+	announce_signing::AnnounceSigner,
+	announcement::{AnnounceBlock, CollationAnnouncement, EncodedAnnouncement},
+	AnnouncePolicy, AnnounceTimeoutAction,
+};
 use cumulus_primitives::{
-	inherents::{DownwardMessagesType, DOWNWARD_MESSAGES_IDENTIFIER, VALIDATION_DATA_IDENTIFIER},
-	well_known_keys, ValidationData,
+	exceeds_max_pov_size, inherents::DownwardMessagesType, well_known_keys, CollationInfo,
+	CollectCollationInfo, ParachainInherentDataProvider, PersistedValidationData,
+	PolkadotRelayChain, RelayChainInterface, RelayChainTypes, RelaySessionInfo, TimestampAnchor,
+	ValidationData,
 };
 use cumulus_runtime::ParachainBlockData;
 
-use sc_client_api::{BlockBackend, Finalizer, StateBackend, UsageProvider};
+use sc_client_api::{
+	BlockBackend, BlockchainEvents, Finalizer, StateBackend, StorageProvider, UsageProvider,
+};
+use sc_telemetry::{telemetry, CONSENSUS_INFO};
+use sp_api::ProvideRuntimeApi;
 use sp_blockchain::HeaderBackend;
 use sp_consensus::{
 	BlockImport, BlockImportParams, BlockOrigin, BlockStatus, Environment, Error as ConsensusError,
-	ForkChoiceStrategy, Proposal, Proposer, RecordProof,
+	Proposal, Proposer,
 };
-use sp_core::traits::SpawnNamed;
+use sp_core::{traits::SpawnNamed, Pair};
 use sp_inherents::{InherentData, InherentDataProviders};
 use sp_runtime::{
 	generic::BlockId,
-	traits::{BlakeTwo256, Block as BlockT, Header as HeaderT},
+	traits::{BlakeTwo256, Block as BlockT, HashFor, Header as HeaderT},
 };
 use sp_state_machine::InspectState;
+use sp_version::RuntimeVersion;
 
 use polkadot_node_primitives::{Collation, CollationGenerationConfig};
-use polkadot_node_subsystem::messages::{CollationGenerationMessage, CollatorProtocolMessage};
+use polkadot_node_subsystem::messages::{
+	AllMessages, CollationGenerationMessage, CollatorProtocolMessage,
+};
 use polkadot_overseer::OverseerHandler;
 use polkadot_primitives::v1::{
-	Block as PBlock, BlockData, CollatorPair, Hash as PHash, HeadData, Id as ParaId, PoV,
-	UpwardMessage, BlockNumber as PBlockNumber,
+	Block as PBlock, BlockData, CandidateEvent, CollatorPair, CoreIndex, CoreState, HeadData,
+	Id as ParaId, ParachainHost, PoV,
 };
 use polkadot_service::RuntimeApiCollection;
 
 use codec::{Decode, Encode};
 
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 
 use futures::prelude::*;
-
-use std::{marker::PhantomData, sync::Arc, time::Duration};
+use futures_timer::Delay;
+
+use std::{
+	collections::VecDeque,
+	marker::PhantomData,
+	path::PathBuf,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
 use parking_lot::Mutex;
 
+pub mod active_collator;
+pub mod authoring_driver;
+pub mod backing_connectivity;
+pub mod bad_block_repair;
+mod builder;
+pub mod circuit_breaker;
+pub mod collator_service;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod delayed_relay_chain;
+pub mod disaster_recovery;
+pub mod divergence_watchdog;
+pub mod downward_messages;
+mod error;
+pub mod events;
+pub mod execution_budget;
+pub mod inclusion_latency;
+pub mod journal;
+mod metrics;
+pub mod migration_check;
+pub mod parent_recovery;
+pub mod parent_resolution;
+pub mod pov_calibration;
+pub mod pov_export;
+pub mod pov_size_limit;
+pub mod pre_validation;
+pub mod proof_recorder;
+pub mod proposal_stats;
+pub mod receipts;
+pub mod reexecution;
+pub mod relay_api_version;
+pub mod relay_prefetch;
+pub mod runtime_divergence;
+pub mod skip_reasons;
+mod status;
+pub mod storage_diff;
+pub mod task_group;
+pub mod upgrade_dry_run;
+pub mod upgrade_only;
+pub mod validation_code_check;
+
+use active_collator::{ActiveCollator, ActiveCollatorCheck};
+use backing_connectivity::BackingConnectivity;
+use bad_block_repair::{BadBlockRepair, KnownBadRepair};
+pub use builder::CollatorBuilder;
+use circuit_breaker::CircuitBreaker;
+pub use collator_service::{
+	runtime_collation_info, CollatorService, ParachainCollatorService, RuntimeCollationInfo,
+};
+#[cfg(any(test, feature = "test-helpers"))]
+pub use delayed_relay_chain::RelayChainDelay;
+use divergence_watchdog::DivergenceWatchdog;
+use downward_messages::{DmqBudget, RetrieveDmqContents};
+pub use error::CollatorError;
+use events::{CollatorEvent, CollatorEventHandler, DefaultEventHandler, PendingUpgrades};
+use execution_budget::ExecutionBudget;
+pub use inclusion_latency::InclusionTracker;
+pub use journal::CollationJournal;
+pub use metrics::Metrics;
+use parent_recovery::ParentRecovery;
+use parent_resolution::{RelayChainValidationData, ResolvedParent};
+use pov_export::PoVExporter;
+pub use pov_size_limit::PoVSizeLimit;
+use pre_validation::PreValidator;
+use proof_recorder::ProofRecorderProvider;
+use proposal_stats::{ProposalStats, ReadyTransactions};
+use receipts::CollationReceipt;
+pub use receipts::CollationReceipts;
+use reexecution::ExecuteOwnBlock;
+use relay_api_version::RelayChainApiVersions;
+pub use relay_prefetch::RelayApiLatency;
+use runtime_divergence::RuntimeDivergenceDetector;
+pub use skip_reasons::SkipReasons;
+pub use status::CollatorStatus;
+use storage_diff::StorageDiff;
+pub use storage_diff::StorageDiffs;
+pub use task_group::{TaskGroup, TaskMetrics};
+use task_group::{COLLATOR_TASKS, NETWORK_TASKS};
+use upgrade_only::UpgradeOnlyBuilder;
+
+/// The relay chain the collator is built for.
+type RelayChain = PolkadotRelayChain;
+/// The hash of a relay chain block.
+type PHash = <RelayChain as RelayChainTypes>::Hash;
+/// The number of a relay chain block.
+type PBlockNumber = <RelayChain as RelayChainTypes>::BlockNumber;
+
 type TransactionFor<E, Block> =
 	<<E as Environment<Block>>::Proposer as Proposer<Block>>::Transaction;
 
+/// How often sending a message to the overseer is tried before giving up.
+const MAX_OVERSEER_SEND_ATTEMPTS: u32 = 5;
+
+/// The delay before retrying to send a message to the overseer, doubled on every attempt.
+const OVERSEER_SEND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// If no collation was requested for this long, the initialization is sent again to the overseer.
+///
+/// The overseer may restart its subsystems, which loses the collation generation config. Sending
+/// the initialization to a subsystem that is still initialized is ignored by it.
+const REINITIALIZE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// The delay before retrying a failed reinitialization, doubled on every attempt up to
+/// [`REINITIALIZE_AFTER`].
+const REINITIALIZE_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The number of relay parents for that we remember that we already produced a candidate.
+const TRACKED_RELAY_PARENTS: usize = 256;
+
+/// The relay parents we already produced, or are producing, a candidate for.
+///
+/// Used to refuse building a second, conflicting candidate for the same relay parent. A relay
+/// parent is reserved before its candidate is built, so concurrent requests for it can not both
+/// build one. The reservation is released again if no candidate is produced, so a failed
+/// collation can be retried.
+#[derive(Default)]
+struct CollatedRelayParents(VecDeque<PHash>);
+
+impl CollatedRelayParents {
+	/// Reserve `relay_parent` for the candidate that is about to be built.
+	///
+	/// Returns `None` if a candidate was already produced, or is being built, for this relay
+	/// parent.
+	fn reserve(collated: &Arc<Mutex<Self>>, relay_parent: PHash) -> Option<RelayParentReservation> {
+		if !collated.lock().note_collation(relay_parent) {
+			return None;
+		}
+
+		Some(RelayParentReservation {
+			collated: collated.clone(),
+			relay_parent,
+			produced: false,
+		})
+	}
+
+	/// Note that we produce a candidate for `relay_parent`.
+	///
+	/// Returns `false` if we already produce a candidate for this relay parent.
+	fn note_collation(&mut self, relay_parent: PHash) -> bool {
+		if self.0.contains(&relay_parent) {
+			return false;
+		}
+
+		if self.0.len() >= TRACKED_RELAY_PARENTS {
+			self.0.pop_front();
+		}
+
+		self.0.push_back(relay_parent);
+		true
+	}
+
+	/// Forget `relay_parent`, no candidate was produced for it.
+	fn release(&mut self, relay_parent: &PHash) {
+		self.0.retain(|r| r != relay_parent);
+	}
+}
+
+/// A relay parent that is reserved in [`CollatedRelayParents`] while its candidate is built.
+///
+/// The reservation is released when it is dropped before the candidate was produced.
+struct RelayParentReservation {
+	collated: Arc<Mutex<CollatedRelayParents>>,
+	relay_parent: PHash,
+	produced: bool,
+}
+
+impl RelayParentReservation {
+	/// Keep the relay parent reserved, the candidate was produced.
+	fn note_produced(mut self) {
+		self.produced = true;
+	}
+}
+
+impl Drop for RelayParentReservation {
+	fn drop(&mut self) {
+		if !self.produced {
+			self.collated.lock().release(&self.relay_parent);
+		}
+	}
+}
+
 /// The implementation of the Cumulus `Collator`.
 pub struct Collator<Block: BlockT, PF, BI, BS, Backend> {
 	proposer_factory: Arc<Mutex<PF>>,
@@ -66,9 +264,36 @@ pub struct Collator<Block: BlockT, PF, BI, BS, Backend> {
 	inherent_data_providers: InherentDataProviders,
 	block_import: Arc<Mutex<BI>>,
 	block_status: Arc<BS>,
-	wait_to_announce: Arc<Mutex<WaitToAnnounce<Block>>>,
+	service: ParachainCollatorService<Block, BS, Backend>,
 	backend: Arc<Backend>,
-	retrieve_dmq_contents: Arc<dyn Fn(PHash) -> Option<DownwardMessagesType> + Send + Sync>,
+	relay_chain: Arc<dyn RelayChainInterface>,
+	relay_chain_validation_data: Arc<dyn RelayChainValidationData>,
+	collated_relay_parents: Arc<Mutex<CollatedRelayParents>>,
+	allow_multiple_collations: bool,
+	circuit_breaker: Arc<Mutex<CircuitBreaker>>,
+	divergence_watchdog: Arc<Mutex<DivergenceWatchdog>>,
+	metrics: Metrics,
+	pov_exporter: Option<PoVExporter>,
+	status: CollatorStatus,
+	fork_choice: Arc<dyn ParachainForkChoice<Block>>,
+	pre_validator: Option<PreValidator>,
+	ready_transactions: Option<Arc<dyn ReadyTransactions>>,
+	parent_recovery: Option<Arc<dyn ParentRecovery<Block>>>,
+	proof_recorder: Arc<dyn ProofRecorderProvider<Block>>,
+	execution_budget: ExecutionBudget,
+	inclusion_tracker: InclusionTracker,
+	pov_size_limit: PoVSizeLimit,
+	journal: Option<CollationJournal<Block::Hash>>,
+	upgrade_only_builder: Option<Arc<dyn UpgradeOnlyBuilder<Block>>>,
+	storage_diffs: Option<StorageDiffs<Block::Hash>>,
+	known_bad_repair: Option<KnownBadRepair<Block>>,
+	receipts: Option<CollationReceipts<Block::Hash>>,
+	reexecute_own_blocks: Option<Arc<dyn ExecuteOwnBlock<Block>>>,
+	runtime_divergence: Option<RuntimeDivergenceDetector<Block>>,
+	event_handler: Arc<dyn CollatorEventHandler<Block>>,
+	pending_upgrades: PendingUpgrades<Block::Hash>,
+	active_collator: Option<ActiveCollatorCheck<Block>>,
+	backing_connectivity: Arc<Mutex<BackingConnectivity>>,
 }
 
 impl<Block: BlockT, PF, BI, BS, Backend> Clone for Collator<Block, PF, BI, BS, Backend> {
@@ -79,9 +304,36 @@ impl<Block: BlockT, PF, BI, BS, Backend> Clone for Collator<Block, PF, BI, BS, B
 			_phantom: PhantomData,
 			block_import: self.block_import.clone(),
 			block_status: self.block_status.clone(),
-			wait_to_announce: self.wait_to_announce.clone(),
+			service: self.service.clone(),
 			backend: self.backend.clone(),
-			retrieve_dmq_contents: self.retrieve_dmq_contents.clone(),
+			relay_chain: self.relay_chain.clone(),
+			relay_chain_validation_data: self.relay_chain_validation_data.clone(),
+			collated_relay_parents: self.collated_relay_parents.clone(),
+			allow_multiple_collations: self.allow_multiple_collations,
+			circuit_breaker: self.circuit_breaker.clone(),
+			divergence_watchdog: self.divergence_watchdog.clone(),
+			metrics: self.metrics.clone(),
+			pov_exporter: self.pov_exporter.clone(),
+			status: self.status.clone(),
+			fork_choice: self.fork_choice.clone(),
+			pre_validator: self.pre_validator.clone(),
+			ready_transactions: self.ready_transactions.clone(),
+			parent_recovery: self.parent_recovery.clone(),
+			proof_recorder: self.proof_recorder.clone(),
+			execution_budget: self.execution_budget,
+			inclusion_tracker: self.inclusion_tracker.clone(),
+			pov_size_limit: self.pov_size_limit.clone(),
+			journal: self.journal.clone(),
+			upgrade_only_builder: self.upgrade_only_builder.clone(),
+			storage_diffs: self.storage_diffs.clone(),
+			known_bad_repair: self.known_bad_repair.clone(),
+			receipts: self.receipts.clone(),
+			reexecute_own_blocks: self.reexecute_own_blocks.clone(),
+			runtime_divergence: self.runtime_divergence.clone(),
+			event_handler: self.event_handler.clone(),
+			pending_upgrades: self.pending_upgrades.clone(),
+			active_collator: self.active_collator.clone(),
+			backing_connectivity: self.backing_connectivity.clone(),
 		}
 	}
 }
@@ -98,205 +350,503 @@ where
 		> + Send
 		+ Sync
 		+ 'static,
-	BS: BlockBackend<Block>,
+	BS: BlockBackend<Block> + Send + Sync,
 	Backend: sc_client_api::Backend<Block> + 'static,
 {
-	/// Create a new instance.
-	fn new(
-		proposer_factory: PF,
-		inherent_data_providers: InherentDataProviders,
-		overseer_handler: OverseerHandler,
-		block_import: BI,
-		block_status: Arc<BS>,
-		spawner: Arc<dyn SpawnNamed + Send + Sync>,
-		announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
-		backend: Arc<Backend>,
-		retrieve_dmq_contents: Arc<dyn Fn(PHash) -> Option<DownwardMessagesType> + Send + Sync>,
-	) -> Self {
-		let wait_to_announce = Arc::new(Mutex::new(WaitToAnnounce::new(
-			spawner,
-			announce_block,
-			overseer_handler,
-		)));
-
-		Self {
-			proposer_factory: Arc::new(Mutex::new(proposer_factory)),
-			inherent_data_providers,
-			_phantom: PhantomData,
-			block_import: Arc::new(Mutex::new(block_import)),
-			block_status,
-			wait_to_announce,
-			backend,
-			retrieve_dmq_contents,
-		}
-	}
-
 	/// Get the inherent data with validation function parameters injected
 	fn inherent_data(
 		&mut self,
 		validation_data: &ValidationData,
 		relay_parent: PHash,
-	) -> Option<InherentData> {
+	) -> Result<InherentData, CollatorError> {
 		let mut inherent_data = self
 			.inherent_data_providers
 			.create_inherent_data()
-			.map_err(|e| {
-				error!(
-					target: "cumulus-collator",
-					"Failed to create inherent data: {:?}",
-					e,
-				)
-			})
-			.ok()?;
+			.map_err(CollatorError::InherentData)?;
 
-		inherent_data
-			.put_data(VALIDATION_DATA_IDENTIFIER, validation_data)
-			.map_err(|e| {
-				error!(
+		let provider = ParachainInherentDataProvider::create_at(
+			relay_parent,
+			validation_data.clone(),
+			&*self.relay_chain,
+		)
+		.map_err(CollatorError::RelayApi)?;
+
+		provider
+			.provide_inherent_data(&mut inherent_data)
+			.map_err(CollatorError::InherentData)?;
+
+		Ok(inherent_data)
+	}
+
+	/// The maximum duration of the proposal for a candidate at the given `relay_parent`.
+	///
+	/// Derived from the execution timeout of the relay chain, see [`ExecutionBudget`].
+	fn max_proposal_duration(&self, relay_parent: PHash) -> Duration {
+		let execution_timeout = self
+			.relay_chain
+			.execution_timeout(relay_parent)
+			.unwrap_or_else(|e| {
+				warn!(
 					target: "cumulus-collator",
-					"Failed to put validation function params into inherent data: {:?}",
+					"Failed to get the execution timeout at relay parent `{}`, using the default: {}",
+					relay_parent,
 					e,
-				)
-			})
-			.ok()?;
+				);
+				None
+			});
 
-		let downward_messages = (self.retrieve_dmq_contents)(relay_parent)?;
-		inherent_data
-			.put_data(DOWNWARD_MESSAGES_IDENTIFIER, &downward_messages)
-			.map_err(|e| {
-				error!(
+		self.execution_budget.max_duration(execution_timeout)
+	}
+
+	/// The maximum PoV size of a candidate at the given `relay_parent`.
+	///
+	/// Falls back to [`DEFAULT_MAX_POV_SIZE`](pov_size_limit::DEFAULT_MAX_POV_SIZE) if the relay
+	/// chain does not provide it.
+	fn max_pov_size(&self, relay_parent: PHash) -> usize {
+		self.relay_chain
+			.max_pov_size(relay_parent)
+			.unwrap_or_else(|e| {
+				warn!(
 					target: "cumulus-collator",
-					"Failed to put downward messages into inherent data: {:?}",
+					"Failed to get the max PoV size at relay parent `{}`, using the default: {}",
+					relay_parent,
 					e,
-				)
+				);
+				None
 			})
-			.ok()?;
+			.map_or(pov_size_limit::DEFAULT_MAX_POV_SIZE, |size| size as usize)
+	}
+
+	/// Checks that the timestamp inherent is within the relay chain slot implied by the
+	/// `validation_data`.
+	///
+	/// This check is only done if the runtime of the `parent` block stored a [`TimestampAnchor`],
+	/// as otherwise the runtime doesn't enforce it either. Returns `false` if the block would be
+	/// rejected by the runtime.
+	fn check_timestamp(
+		&self,
+		parent: Block::Hash,
+		inherent_data: &InherentData,
+		validation_data: &ValidationData,
+	) -> Result<bool, CollatorError> {
+		let state = self
+			.backend
+			.state_at(BlockId::Hash(parent))
+			.map_err(|e| CollatorError::State(format!("{:?}", e)))?;
+
+		let anchor = match state
+			.storage(well_known_keys::TIMESTAMP_ANCHOR)
+			.map_err(|e| CollatorError::State(format!("{:?}", e)))?
+		{
+			Some(anchor) => TimestampAnchor::decode(&mut &anchor[..])
+				.map_err(|e| CollatorError::InvalidState("the timestamp anchor", e))?,
+			None => return Ok(true),
+		};
 
-		Some(inherent_data)
+		let timestamp = match inherent_data
+			.get_data::<u64>(&sp_timestamp::INHERENT_IDENTIFIER)
+			.map_err(CollatorError::InherentData)?
+		{
+			Some(timestamp) => timestamp,
+			None => return Ok(true),
+		};
+
+		if anchor.check(validation_data.persisted.block_number, timestamp) {
+			Ok(true)
+		} else {
+			error!(
+				target: "cumulus-collator",
+				"Skipping candidate production, because timestamp `{}` is not within the slot of relay chain block #{}.",
+				timestamp,
+				validation_data.persisted.block_number,
+			);
+			Ok(false)
+		}
 	}
 
-	/// Checks the status of the given block hash in the Parachain.
+	/// Resolve the validation data of the parent the candidate should be built on.
 	///
-	/// Returns `true` if the block could be found and is good to be build on.
-	fn check_block_status(&self, hash: Block::Hash) -> bool {
-		match self.block_status.block_status(&BlockId::Hash(hash)) {
-			Ok(BlockStatus::Queued) => {
+	/// Returns `Ok(None)` if no candidate should be produced.
+	fn resolve_parent(
+		&self,
+		relay_parent: PHash,
+		mut validation_data: ValidationData,
+	) -> Result<Option<ValidationData>, CollatorError> {
+		let block_status = &self.block_status;
+		let is_known = |head: &HeadData| {
+			Block::Header::decode(&mut &head.0[..]).map_or(false, |header| {
+				matches!(
+					block_status.block_status(&BlockId::Hash(header.hash())),
+					Ok(BlockStatus::InChainWithState)
+				)
+			})
+		};
+
+		let resolved = parent_resolution::resolve_parent(
+			&*self.relay_chain_validation_data,
+			relay_parent,
+			&validation_data.persisted,
+			is_known,
+		)
+		.map_err(CollatorError::RelayApi)?;
+
+		match resolved {
+			ResolvedParent::Given => Ok(Some(validation_data)),
+			ResolvedParent::PendingAvailability(persisted) => {
 				debug!(
 					target: "cumulus-collator",
-					"Skipping candidate production, because block `{:?}` is still queued for import.", hash,
-				);
-				false
-			}
-			Ok(BlockStatus::InChainWithState) => true,
-			Ok(BlockStatus::InChainPruned) => {
-				error!(
-					target: "cumulus-collator",
-					"Skipping candidate production, because block `{:?}` is already pruned!", hash,
+					"Building on the head of the candidate that is pending availability at relay parent `{}`.",
+					relay_parent,
 				);
-				false
-			}
-			Ok(BlockStatus::KnownBad) => {
-				error!(
-					target: "cumulus-collator",
-					"Block `{}` is tagged as known bad and is included in the relay chain! Skipping candidate production!", hash,
-				);
-				false
+
+				validation_data.persisted = persisted;
+				Ok(Some(validation_data))
 			}
-			Ok(BlockStatus::Unknown) => {
+			ResolvedParent::UnknownPendingAvailability(head) => {
 				debug!(
 					target: "cumulus-collator",
-					"Skipping candidate production, because block `{:?}` is unknown.", hash,
+					"Skipping candidate production, because the head `{:?}` of the candidate pending availability is not known.",
+					head,
 				);
-				false
+
+				Ok(None)
 			}
+		}
+	}
+
+	/// Checks the status of the given block hash in the Parachain.
+	///
+	/// Returns `true` if the block could be found and is good to be build on.
+	fn check_block_status(&self, hash: Block::Hash) -> bool {
+		self.service.check_block_status(hash)
+	}
+
+	/// Returns `true` if the head of the parachain on the relay chain, `relay_head`, is unknown
+	/// or can not be imported.
+	///
+	/// A known head on another fork than the local best block is no divergence, the relay chain
+	/// forks as well and candidates are built on the head of the respective relay parent.
+	fn is_divergent(&self, relay_head: &Block::Header) -> bool {
+		let hash = relay_head.hash();
+		match self.block_status.block_status(&BlockId::Hash(hash)) {
+			Ok(BlockStatus::Unknown) | Ok(BlockStatus::KnownBad) => true,
+			Ok(_) => false,
 			Err(e) => {
-				error!(target: "cumulus-collator", "Failed to get block status of `{:?}`: {:?}", hash, e);
+				warn!(target: "cumulus-collator", "Failed to get block status of `{:?}`: {:?}", hash, e);
 				false
 			}
 		}
 	}
 
-	fn build_collation(
-		&mut self,
-		block: ParachainBlockData<Block>,
-		block_hash: Block::Hash,
+	/// Note in the divergence watchdog whether the local chain diverged from `relay_head`.
+	///
+	/// Returns `true` if a new candidate may be produced.
+	fn check_divergence(
+		&self,
+		relay_head: &Block::Header,
 		relay_block_number: PBlockNumber,
-	) -> Option<Collation> {
-		let block_data = BlockData(block.encode());
-		let header = block.into_header();
-		let head_data = HeadData(header.encode());
+	) -> bool {
+		let divergent = self.is_divergent(relay_head);
+
+		let mut watchdog = self.divergence_watchdog.lock();
+		let was_halted = watchdog.is_halted();
+		let allowed = watchdog.note(relay_block_number, divergent);
+		let divergent_relay_blocks = watchdog.divergent_relay_blocks(relay_block_number);
+		self.metrics
+			.report_divergence(divergent_relay_blocks, watchdog.is_halted());
+
+		match (was_halted, watchdog.is_halted()) {
+			(false, true) => self
+				.event_handler
+				.handle_event(&CollatorEvent::FallingBehind {
+					divergent_relay_blocks,
+				}),
+			(true, false) => self.event_handler.handle_event(&CollatorEvent::CaughtUp),
+			_ => {}
+		}
 
-		let state = match self.backend.state_at(BlockId::Hash(block_hash)) {
-			Ok(state) => state,
-			Err(e) => {
-				error!(target: "cumulus-collator", "Failed to get state of the freshly built block: {:?}", e);
-				return None;
+		allowed
+	}
+
+	/// Note that a candidate was produced on the relay parent `relay_block_number`, and report
+	/// whether the backing group still seconds the candidates.
+	fn note_backing_candidate(&self, relay_block_number: PBlockNumber) {
+		let mut connectivity = self.backing_connectivity.lock();
+		connectivity.note_candidate(relay_block_number);
+		self.metrics.report_backing_connectivity(
+			connectivity.unseconded_relay_blocks(relay_block_number),
+			connectivity.is_lost(),
+		);
+	}
+
+	/// Request the parent block with the given `header` from the network, if it is unknown.
+	///
+	/// A parent that is known bad, or unknown but marked as bad by the [`BadBlockRepair`], is
+	/// only requested after its mark was cleared. Returns `true` if the block was requested.
+	fn request_parent(&self, header: &Block::Header) -> bool {
+		let recovery = match self.parent_recovery {
+			Some(ref recovery) => recovery,
+			None => return false,
+		};
+
+		let hash = header.hash();
+		let marked_bad = self
+			.known_bad_repair
+			.as_ref()
+			.map_or(false, |repair| repair.is_marked_bad(hash));
+		match self.block_status.block_status(&BlockId::Hash(hash)) {
+			Ok(BlockStatus::Unknown) if !marked_bad => {}
+			Ok(BlockStatus::Unknown) | Ok(BlockStatus::KnownBad) => match self.known_bad_repair {
+				Some(ref repair) if repair.try_repair(hash) => {}
+				_ => return false,
+			},
+			_ => return false,
+		}
+
+		info!(
+			target: "cumulus-collator",
+			"Requesting parent block `{:?}` (#{}) from the network.",
+			hash,
+			header.number(),
+		);
+		recovery.recover(hash, *header.number());
+
+		true
+	}
+
+	/// Replace `b` with a block that only carries the runtime upgrade, if the new validation code
+	/// set by its storage `changes` does not fit into a candidate together with its PoV.
+	///
+	/// `max_pov_size` is the maximum PoV size at the relay parent. Returns `Ok(None)` if `b` should
+	/// be kept.
+	fn upgrade_only_block(
+		&self,
+		parent: Block::Hash,
+		b: &ParachainBlockData<Block>,
+		changes: &[(Vec<u8>, Option<Vec<u8>>)],
+		max_pov_size: usize,
+	) -> Result<Option<ParachainBlockData<Block>>, CollatorError> {
+		let code = match upgrade_only::new_validation_code(changes) {
+			Some(code) => code,
+			_ => return Ok(None),
+		};
+
+		if !exceeds_max_pov_size(b.encoded_size(), code.len(), max_pov_size) {
+			return Ok(None);
+		}
+
+		let builder = match self.upgrade_only_builder {
+			Some(ref builder) => builder,
+			None => {
+				warn!(
+					target: "cumulus-collator",
+					"The runtime upgrade and the PoV of block `{:?}` exceed the maximum PoV size of {} bytes.",
+					b.header().hash(),
+					max_pov_size,
+				);
+				return Ok(None);
 			}
 		};
 
-		state.inspect_state(|| {
-			let upward_messages = sp_io::storage::get(well_known_keys::UPWARD_MESSAGES);
-			let upward_messages = match upward_messages.map(|v| Vec::<UpwardMessage>::decode(&mut &v[..])) {
-				Some(Ok(msgs)) => msgs,
-				Some(Err(e)) => {
-					error!(target: "cumulus-collator", "Failed to decode upward messages from the build block: {:?}", e);
-					return None
-				},
-				None => Vec::new(),
-			};
+		let extrinsics = upgrade_only::upgrade_only_extrinsics(b.extrinsics(), code);
+		let (block, proof) = builder
+			.build(parent, extrinsics)
+			.map_err(CollatorError::Proposing)?;
+		let (header, extrinsics) = block.deconstruct();
+		let upgrade_only = ParachainBlockData::<Block>::new(header, extrinsics, proof);
 
-			let new_validation_code = sp_io::storage::get(well_known_keys::NEW_VALIDATION_CODE);
+		let size = upgrade_only.encoded_size().saturating_add(code.len());
+		if size > max_pov_size {
+			return Err(CollatorError::UpgradeTooLarge(size));
+		}
 
-			let processed_downward_messages = sp_io::storage::get(well_known_keys::PROCESSED_DOWNWARD_MESSAGES);
-			let processed_downward_messages = match processed_downward_messages
-				.map(|v| u32::decode(&mut &v[..]))
-			{
-				Some(Ok(processed_cnt)) => processed_cnt,
-				Some(Err(e)) => {
-					error!(
-						target: "cumulus-collator",
-						"Failed to decode the count of processed downward messages: {:?}",
-						e
-					);
-					return None
-				}
-				None => 0,
-			};
+		info!(
+			target: "cumulus-collator",
+			"Replaced block `{:?}` by block `{:?}` that only carries the runtime upgrade.",
+			b.header().hash(),
+			upgrade_only.header().hash(),
+		);
 
-			Some(Collation {
-				upward_messages,
-				new_validation_code: new_validation_code.map(Into::into),
-				head_data,
-				proof_of_validity: PoV { block_data },
-				processed_downward_messages,
-				// TODO!
-				horizontal_messages: Vec::new(),
-				hrmp_watermark: relay_block_number,
+		Ok(Some(upgrade_only))
+	}
+
+	/// Returns the runtime code that is stored in the state of `parent`.
+	fn validation_code(&self, parent: Block::Hash) -> Result<Vec<u8>, CollatorError> {
+		self.backend
+			.state_at(BlockId::Hash(parent))
+			.map_err(|e| CollatorError::State(format!("{:?}", e)))?
+			.storage(sp_core::storage::well_known_keys::CODE)
+			.map_err(|e| CollatorError::State(format!("{:?}", e)))?
+			.ok_or_else(|| {
+				CollatorError::State(format!(
+					"Runtime code not found in the state of `{}`",
+					parent
+				))
 			})
-		})
 	}
 
-	async fn produce_candidate(
-		mut self,
+	/// Produce a candidate for the given `relay_parent` on top of the parent head given by
+	/// `validation_data`.
+	///
+	/// This is the entry point for hosts that drive the collator without an overseer, see
+	/// [`CollatorBuilder`]. Returns `Ok(None)` if no candidate should be produced.
+	pub async fn produce_candidate(
+		self,
+		relay_parent: PHash,
+		validation_data: ValidationData,
+	) -> Result<Option<Collation>, CollatorError> {
+		let metrics = self.metrics.clone();
+		let journal = self.journal.clone();
+		let receipts = self.receipts.clone();
+		let event_handler = self.event_handler.clone();
+		let para_parent = Block::Header::decode(&mut &validation_data.persisted.parent_head.0[..])
+			.ok()
+			.map(|h| h.hash());
+
+		let res = self
+			.try_produce_candidate(relay_parent, validation_data)
+			.await
+			.map_err(|e| {
+				metrics.report_error(&e);
+				e
+			});
+
+		let produced = res.as_ref().map(|collation| {
+			collation.as_ref().and_then(|c| {
+				Block::Header::decode(&mut &c.head_data.0[..])
+					.ok()
+					.map(|h| (h.hash(), c.proof_of_validity.hash()))
+			})
+		});
+
+		if let Some(journal) = journal {
+			journal.note(relay_parent, para_parent, produced);
+		}
+
+		match produced {
+			Ok(Some((block_hash, pov_hash))) => {
+				event_handler.handle_event(&CollatorEvent::CandidateProduced {
+					relay_parent,
+					block_hash,
+					pov_hash,
+				})
+			}
+			Ok(None) => {}
+			Err(e) => event_handler.handle_event(&CollatorEvent::CandidateFailed {
+				relay_parent,
+				error: e.code().into(),
+			}),
+		}
+
+		if let (Some(receipts), Some(para_parent), Ok(Some((block_hash, pov_hash)))) =
+			(receipts, para_parent, produced)
+		{
+			receipts.issue(CollationReceipt {
+				para_parent,
+				block_hash,
+				pov_hash,
+				relay_parent,
+			});
+		}
+
+		res
+	}
+
+	/// Produce a candidate for the overseer, errors are only logged.
+	async fn collate(
+		self,
 		relay_parent: PHash,
 		validation_data: ValidationData,
 	) -> Option<Collation> {
+		match self.produce_candidate(relay_parent, validation_data).await {
+			Ok(collation) => collation,
+			Err(e) => {
+				error!(
+					target: "cumulus-collator",
+					"Failed to produce candidate for relay parent `{}` [{}]: {}",
+					relay_parent,
+					e.code(),
+					e,
+				);
+
+				None
+			}
+		}
+	}
+
+	/// Produce a candidate for the given `relay_parent`.
+	///
+	/// Returns `Ok(None)` if no candidate should be produced.
+	async fn try_produce_candidate(
+		mut self,
+		relay_parent: PHash,
+		validation_data: ValidationData,
+	) -> Result<Option<Collation>, CollatorError> {
 		trace!(target: "cumulus-collator", "Producing candidate");
 
-		let last_head =
-			match Block::Header::decode(&mut &validation_data.persisted.parent_head.0[..]) {
-				Ok(x) => x,
-				Err(e) => {
-					error!(target: "cumulus-collator", "Could not decode the head data: {:?}", e);
-					return None;
-				}
-			};
+		let validation_data = match self.resolve_parent(relay_parent, validation_data)? {
+			Some(validation_data) => validation_data,
+			None => return Ok(None),
+		};
+
+		let last_head = Block::Header::decode(&mut &validation_data.persisted.parent_head.0[..])
+			.map_err(CollatorError::InvalidHeadData)?;
 
 		let last_head_hash = last_head.hash();
+		if !self.check_divergence(&last_head, validation_data.persisted.block_number) {
+			// Try to recover, the watchdog resumes candidate production once the head is imported.
+			self.request_parent(&last_head);
+			return Ok(None);
+		}
+
 		if !self.check_block_status(last_head_hash) {
-			return None;
+			if !self.request_parent(&last_head) {
+				return Ok(None);
+			}
+
+			let block_status = self.block_status.clone();
+			if !parent_recovery::wait_for_import(block_status, last_head_hash).await {
+				debug!(
+					target: "cumulus-collator",
+					"Parent block `{:?}` was not imported in time, retrying on the next relay parent.",
+					last_head_hash,
+				);
+				return Ok(None);
+			}
+
+			debug!(target: "cumulus-collator", "Recovered parent block `{:?}`.", last_head_hash);
+		}
+
+		if let Some(ref active_collator) = self.active_collator {
+			if !active_collator.may_collate(last_head_hash) {
+				return Ok(None);
+			}
+		}
+
+		if !self.circuit_breaker.lock().allow_collation(Instant::now()) {
+			debug!(
+				target: "cumulus-collator",
+				"Skipping candidate production, because the circuit breaker is open.",
+			);
+			return Ok(None);
 		}
 
+		let reservation = if self.allow_multiple_collations {
+			None
+		} else {
+			match CollatedRelayParents::reserve(&self.collated_relay_parents, relay_parent) {
+				Some(reservation) => Some(reservation),
+				None => {
+					error!(
+						target: "cumulus-collator",
+						"Skipping candidate production, because we already produce a candidate for relay parent `{}`.",
+						relay_parent,
+					);
+					return Ok(None);
+				}
+			}
+		};
+
 		info!(
 			target: "cumulus-collator",
 			"Starting collation for relay parent `{}` on parent `{}`.",
@@ -308,17 +858,23 @@ where
 
 		let proposer = proposer_future
 			.await
-			.map_err(|e| {
-				error!(
-					target: "cumulus-collator",
-					"Could not create proposer: {:?}",
-					e,
-				)
-			})
-			.ok()?;
+			.map_err(|e| CollatorError::ProposerInit(format!("{:?}", e)))?;
 
 		let inherent_data = self.inherent_data(&validation_data, relay_parent)?;
 
+		if !self.check_timestamp(last_head_hash, &inherent_data, &validation_data)? {
+			return Ok(None);
+		}
+
+		let ready = self
+			.ready_transactions
+			.as_ref()
+			.map(|r| r.ready_transactions());
+
+		let max_duration = self.max_proposal_duration(relay_parent);
+		let max_pov_size = self.max_pov_size(relay_parent);
+		self.pov_size_limit.set(max_pov_size);
+
 		let Proposal {
 			block,
 			storage_changes,
@@ -327,69 +883,216 @@ where
 			.propose(
 				inherent_data,
 				Default::default(),
-				//TODO: Fix this.
-				Duration::from_millis(500),
-				RecordProof::Yes,
+				max_duration,
+				self.proof_recorder.record_proof(),
 			)
 			.await
-			.map_err(|e| {
-				error!(
-					target: "cumulus-collator",
-					"Proposing failed: {:?}",
-					e,
-				)
-			})
-			.ok()?;
+			.map_err(|e| CollatorError::Proposing(format!("{:?}", e)))?;
 
-		let proof = match proof {
-			Some(proof) => proof,
-			None => {
-				error!(
-					target: "cumulus-collator",
-					"Proposer did not return the requested proof.",
-				);
+		let proof = self
+			.proof_recorder
+			.storage_proof(last_head_hash, &block, proof)?;
 
-				return None;
-			}
+		let (header, extrinsics) = block.deconstruct();
+		let upgrade_code_size =
+			upgrade_only::new_validation_code(&storage_changes.main_storage_changes)
+				.map(|code| code.len());
+
+		// Create the parachain block data for the validators.
+		let b = ParachainBlockData::<Block>::new(header, extrinsics, proof);
+
+		let upgrade_only = self.upgrade_only_block(
+			last_head_hash,
+			&b,
+			&storage_changes.main_storage_changes,
+			max_pov_size,
+		)?;
+		let (b, storage_changes) = match upgrade_only {
+			// The storage changes of the replacement are computed on import.
+			Some(upgrade_only) => (upgrade_only, None),
+			None => (b, Some(storage_changes)),
 		};
 
-		let (header, extrinsics) = block.deconstruct();
+		let header = b.header().clone();
 		let block_hash = header.hash();
+		let stats = ready.map(|ready| ProposalStats::new(ready, b.extrinsics()));
+
+		if let Some(pre_validator) = self.pre_validator.clone() {
+			// Run the candidate through the `validate_block` of the runtime of the parent block.
+			let validation_code = self.validation_code(last_head_hash)?;
+			pre_validator
+				.pre_validate_blocking(
+					validation_code,
+					b.clone(),
+					validation_data.persisted.clone(),
+				)
+				.await
+				.map_err(CollatorError::PreValidation)?;
 
-		// Create the parachain block data for the validators.
-		let b = ParachainBlockData::<Block>::new(header.clone(), extrinsics, proof);
+			debug!(
+				target: "cumulus-collator",
+				"Candidate for block `{:?}` passed the local `validate_block`.",
+				block_hash,
+			);
+		}
+
+		if let Some(ref executor) = self.reexecute_own_blocks {
+			executor
+				.execute_block(
+					last_head_hash,
+					&Block::new(header.clone(), b.extrinsics().to_vec()),
+				)
+				.map_err(CollatorError::Reexecution)?;
+		}
 
+		let fork_choice = self.fork_choice.fork_choice(&header);
 		let mut block_import_params = BlockImportParams::new(BlockOrigin::Own, header);
 		block_import_params.body = Some(b.extrinsics().to_vec());
-		// Best block is determined by the relay chain.
-		block_import_params.fork_choice = Some(ForkChoiceStrategy::Custom(false));
-		block_import_params.storage_changes = Some(storage_changes);
+		block_import_params.fork_choice = Some(fork_choice);
+		let storage_diff = match (&self.storage_diffs, &storage_changes) {
+			(Some(diffs), Some(changes)) if diffs.has_subscribers() => Some(StorageDiff {
+				block_hash,
+				changes: changes.main_storage_changes.clone(),
+				child_changes: changes.child_storage_changes.clone(),
+			}),
+			_ => None,
+		};
+		block_import_params.storage_changes = storage_changes;
 
-		if let Err(err) = self
-			.block_import
+		self.block_import
 			.lock()
 			.import_block(block_import_params, Default::default())
-		{
-			error!(
-				target: "cumulus-collator",
-				"Error importing build block (at {:?}): {:?}",
-				b.header().parent_hash(),
-				err,
-			);
+			.map_err(CollatorError::Import)?;
 
-			return None;
+		if let (Some(diffs), Some(diff)) = (&self.storage_diffs, storage_diff) {
+			diffs.notify(diff);
 		}
 
-		let collation = self.build_collation(b, block_hash, validation_data.persisted.block_number)?;
+		if let Some(code_size) = upgrade_code_size {
+			self.pending_upgrades.note_produced(block_hash, code_size);
+		}
+
+		if let Some(ref detector) = self.runtime_divergence {
+			match self.validation_code(last_head_hash) {
+				Ok(code) => detector.check(b.clone(), code, validation_data.persisted.clone()),
+				Err(e) => warn!(
+					target: "cumulus-collator",
+					"Not checking block `{:?}` for a runtime divergence: {}",
+					block_hash,
+					e,
+				),
+			}
+		}
+
+		if let Some(ref exporter) = self.pov_exporter {
+			match exporter.export(relay_parent, &b) {
+				Ok(path) => debug!(
+					target: "cumulus-collator",
+					"Exported PoV of block `{:?}` to `{}`.",
+					block_hash,
+					path.display(),
+				),
+				Err(e) => warn!(
+					target: "cumulus-collator",
+					"Failed to export PoV of block `{:?}`: {:?}",
+					block_hash,
+					e,
+				),
+			}
+		}
+
+		let collation =
+			self.service
+				.build_collation(b, block_hash, validation_data.persisted.block_number)?;
 		let pov_hash = collation.proof_of_validity.hash();
 
-		self.wait_to_announce
-			.lock()
-			.wait_to_announce(block_hash, pov_hash);
+		let now = Instant::now();
+		self.status.note_candidate(now);
+		self.inclusion_tracker
+			.note_produced(pov_hash, validation_data.persisted.block_number);
+
+		if self.service.announces_blocks() {
+			let mut circuit_breaker = self.circuit_breaker.lock();
+			circuit_breaker.note_candidate(now);
+			self.metrics.report_circuit_breaker(
+				circuit_breaker.consecutive_failures(),
+				circuit_breaker.is_open(now),
+			);
 
-		info!(target: "cumulus-collator", "Produced proof-of-validity candidate `{:?}` from block `{:?}`.", pov_hash, block_hash);
+			self.note_backing_candidate(validation_data.persisted.block_number);
+		}
+		self.service.announce_with_barrier(block_hash, pov_hash);
+
+		match stats {
+			Some(stats) => {
+				info!(
+					target: "cumulus-collator",
+					"Produced proof-of-validity candidate `{:?}` from block `{:?}` ({}).",
+					pov_hash,
+					block_hash,
+					stats,
+				);
 
-		Some(collation)
+				self.metrics.report_proposal(&stats);
+				telemetry!(
+					CONSENSUS_INFO; "cumulus.collator.candidate_produced";
+					"block" => ?block_hash,
+					"ready_transactions" => stats.ready,
+					"included_transactions" => stats.included,
+					"dropped_transactions" => stats.dropped(),
+				);
+			}
+			None => info!(
+				target: "cumulus-collator",
+				"Produced proof-of-validity candidate `{:?}` from block `{:?}`.",
+				pov_hash,
+				block_hash,
+			),
+		}
+
+		if let Some(reservation) = reservation {
+			reservation.note_produced();
+		}
+
+		Ok(Some(collation))
+	}
+}
+
+/// Build the [`Collation`] for `block` from the information its runtime stored in `state`.
+///
+/// `state` needs to be the state after `block` was executed. The number of the relay parent
+/// `relay_block_number` is only used as HRMP watermark if the runtime did not compute one.
+fn collation_from_state<Block: BlockT, State: StateBackend<HashFor<Block>>>(
+	state: &State,
+	block: ParachainBlockData<Block>,
+	relay_block_number: PBlockNumber,
+) -> Result<Collation, CollatorError> {
+	let head_data = HeadData(block.header().encode());
+
+	let info = state
+		.inspect_state(|| {
+			CollationInfo::from_storage(sp_io::storage::get, head_data, relay_block_number)
+		})
+		.map_err(|e| CollatorError::InvalidState(e.what, e.error))?;
+
+	Ok(collation_from_info(block, info))
+}
+
+/// Build the collation for `block`, with the output `info` of the block.
+fn collation_from_info<Block: BlockT>(
+	block: ParachainBlockData<Block>,
+	info: CollationInfo,
+) -> Collation {
+	Collation {
+		upward_messages: info.upward_messages,
+		new_validation_code: info.new_validation_code,
+		head_data: info.head_data,
+		proof_of_validity: PoV {
+			block_data: BlockData(block.encode()),
+		},
+		processed_downward_messages: info.processed_downward_messages,
+		horizontal_messages: info.horizontal_messages,
+		hrmp_watermark: info.hrmp_watermark,
 	}
 }
 
@@ -401,12 +1104,237 @@ pub struct StartCollatorParams<Block: BlockT, PF, BI, Backend, Client, BS, Spawn
 	pub block_import: BI,
 	pub block_status: Arc<BS>,
 	pub client: Arc<Client>,
-	pub announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	pub announce_block: Arc<dyn AnnounceBlock<Block>>,
 	pub overseer_handler: OverseerHandler,
 	pub spawner: Spawner,
 	pub para_id: ParaId,
 	pub key: CollatorPair,
 	pub polkadot_client: Arc<PClient>,
+	/// Allow producing more than one candidate for the same relay parent.
+	///
+	/// This should only be enabled if it is known that no other node collates with the same key.
+	pub allow_multiple_collations: bool,
+	pub prometheus_registry: Option<substrate_prometheus_endpoint::Registry>,
+	/// If set, every produced PoV is written into this directory.
+	///
+	/// See [`pov_export`] for reading them back.
+	pub pov_export_dir: Option<PathBuf>,
+	/// Updated with the status of the collator while it is running.
+	pub status: CollatorStatus,
+	/// The fork choice for blocks built by this collator.
+	pub fork_choice: Arc<dyn ParachainForkChoice<Block>>,
+	/// Run every candidate through the local `validate_block` before it is submitted.
+	///
+	/// Candidates that are rejected locally are dropped instead of being sent to the validators.
+	pub pre_validate: bool,
+	/// Used to report how many of the ready transactions were included in a candidate.
+	pub ready_transactions: Option<Arc<dyn ReadyTransactions>>,
+	/// Used to fetch unknown parent blocks instead of skipping candidate production.
+	pub parent_recovery: Option<Arc<dyn ParentRecovery<Block>>>,
+	/// When and how often produced blocks are announced.
+	pub announce_policy: AnnouncePolicy,
+	/// Provides the storage proof of the candidates, defaults to the proof recorded by the
+	/// proposer.
+	pub proof_recorder: Option<Arc<dyn ProofRecorderProvider<Block>>>,
+	/// Updated with the inclusion of the produced candidates while the collator is running.
+	pub inclusion_tracker: InclusionTracker,
+	/// Measures the relay chain runtime api calls, the relay chain data is prefetched while they
+	/// are slow, see [`relay_prefetch`].
+	pub relay_api_latency: RelayApiLatency,
+	/// Updated with the maximum PoV size at the relay parent of every candidate.
+	pub pov_size_limit: PoVSizeLimit,
+	/// Signs the announcements of produced blocks.
+	pub announce_signer: Option<Arc<dyn AnnounceSigner<Block>>>,
+	/// If set, the collator refuses to start when the on-chain storage was migrated by a newer
+	/// runtime than this native runtime.
+	///
+	/// See [`migration_check`] for details.
+	pub native_version: Option<RuntimeVersion>,
+	/// Shared with the other users of the relay chain runtime apis, e.g. the block announce
+	/// validator.
+	pub relay_chain_cache: RelayChainCache,
+	/// Measures the tasks spawned by the collator, see [`task_group`].
+	pub task_metrics: TaskMetrics,
+	/// Retrieves the downward messages of the candidates, defaults to the downward message
+	/// queue of the relay chain.
+	pub retrieve_dmq_contents: Option<Arc<dyn RetrieveDmqContents>>,
+	/// The share of the execution timeout of the relay chain the proposer may spend on a
+	/// candidate.
+	pub execution_budget: ExecutionBudget,
+	/// Every collation request is appended to this journal, see [`journal`].
+	pub journal: Option<CollationJournal<Block::Hash>>,
+	/// Builds the replacement of blocks whose runtime upgrade does not fit into the PoV, see
+	/// [`upgrade_only`].
+	pub upgrade_only_builder: Option<Arc<dyn UpgradeOnlyBuilder<Block>>>,
+	/// Notified when the relay chain made an already imported block the best block.
+	pub new_best_observer: Option<Arc<dyn NewBestObserver<Block>>>,
+	/// The number of relay blocks the local chain may diverge from the head of the parachain on
+	/// the relay chain before candidate production is halted, see [`divergence_watchdog`].
+	pub max_divergent_relay_blocks: PBlockNumber,
+	/// The number of relay blocks without a seconded candidate before the backing group is
+	/// reported as lost, see [`backing_connectivity`].
+	pub max_unseconded_relay_blocks: PBlockNumber,
+	/// Receives the storage changes of every produced block, see [`storage_diff`].
+	pub storage_diffs: Option<StorageDiffs<Block::Hash>>,
+	/// Added to every call to the relay chain, only used for testing, see
+	/// [`delayed_relay_chain`].
+	#[cfg(feature = "test-helpers")]
+	pub relay_chain_delay: Option<RelayChainDelay>,
+	/// Notes why candidate production on a parent block was skipped, see [`skip_reasons`].
+	pub skip_reasons: Option<SkipReasons<Block::Hash>>,
+	/// Clears the known bad mark of included parent blocks, see [`bad_block_repair`].
+	pub bad_block_repair: Option<Arc<dyn BadBlockRepair<Block>>>,
+	/// Stores the signed receipts of the produced candidates, see [`receipts`].
+	pub receipts: Option<CollationReceipts<Block::Hash>>,
+	/// Executes every produced block once more before it is imported, see [`reexecution`].
+	pub reexecute_own_blocks: Option<Arc<dyn ExecuteOwnBlock<Block>>>,
+	/// Check every produced block for a divergence of the native and the WASM runtime in the
+	/// background, see [`runtime_divergence`].
+	pub detect_runtime_divergence: bool,
+	/// Handles the events of the collator, see [`events`].
+	///
+	/// Defaults to a [`DefaultEventHandler`] that reports to the `prometheus_registry`.
+	pub event_handler: Option<Arc<dyn CollatorEventHandler<Block>>>,
+	/// Only collate while the `key` is designated, see [`active_collator`].
+	pub active_collator: Option<Arc<dyn ActiveCollator<Block>>>,
+	/// Limits the downward messages of a candidate, see [`DmqBudget`].
+	pub dmq_budget: Option<DmqBudget>,
+}
+
+/// The relay chain data of the parachain, read from the Polkadot client.
+///
+/// The downward messages that are not in the `cache` yet are measured by the `latency`.
+struct RelayChainClient<PClient, PBackend> {
+	polkadot_client: Arc<PClient>,
+	para_id: ParaId,
+	cache: RelayChainCache,
+	latency: RelayApiLatency,
+	metrics: Metrics,
+	_backend: PhantomData<fn() -> PBackend>,
+}
+
+impl<PClient, PBackend> RelayChainClient<PClient, PBackend> {
+	fn new(
+		polkadot_client: Arc<PClient>,
+		para_id: ParaId,
+		cache: RelayChainCache,
+		latency: RelayApiLatency,
+		metrics: Metrics,
+	) -> Self {
+		Self {
+			polkadot_client,
+			para_id,
+			cache,
+			latency,
+			metrics,
+			_backend: PhantomData,
+		}
+	}
+}
+
+impl<PClient, PBackend> RelayChainInterface for RelayChainClient<PClient, PBackend>
+where
+	PClient: ProvideRuntimeApi<PBlock>
+		+ HeaderBackend<PBlock>
+		+ StorageProvider<PBlock, PBackend>
+		+ Send
+		+ Sync,
+	PClient::Api: ParachainHost<PBlock>,
+	PBackend: sc_client_api::Backend<PBlock>,
+{
+	fn downward_messages(&self, relay_parent: PHash) -> Result<DownwardMessagesType, String> {
+		let cached = self.cache.contains_dmq_contents(relay_parent, self.para_id);
+
+		relay_prefetch::measure(cached, &self.latency, &self.metrics, || {
+			self.cache
+				.dmq_contents(&*self.polkadot_client, relay_parent, self.para_id)
+		})
+		.map_err(|e| {
+			// Report a relay chain upgrade that removed the api instead of the call error.
+			match RelayChainApiVersions::detect(&*self.polkadot_client, relay_parent)
+				.and_then(RelayChainApiVersions::check)
+			{
+				Err(incompatible @ CollatorError::IncompatibleRelayChain(_)) => format!(
+					"Failed to request the downward messages for {}: {}",
+					relay_parent, incompatible,
+				),
+				_ => format!(
+					"Failed to request the downward messages for {}: {:?}",
+					relay_parent, e,
+				),
+			}
+		})
+	}
+
+	fn para_id(&self) -> Option<ParaId> {
+		Some(self.para_id)
+	}
+
+	fn max_pov_size(&self, relay_parent: PHash) -> Result<Option<u32>, String> {
+		pov_size_limit::relay_max_pov_size(&*self.polkadot_client, relay_parent)
+	}
+
+	fn session_info(&self, relay_parent: PHash) -> Result<Option<RelaySessionInfo>, String> {
+		let runtime_api = self.polkadot_client.runtime_api();
+		let block_id = BlockId::hash(relay_parent);
+		let map_err = |e| {
+			format!(
+				"Failed to request the session info for {}: {:?}",
+				relay_parent, e
+			)
+		};
+
+		let session_index = runtime_api
+			.session_index_for_child(&block_id)
+			.map_err(map_err)?;
+		let cores = runtime_api.availability_cores(&block_id).map_err(map_err)?;
+		let core = cores.iter().position(|core| match core {
+			CoreState::Scheduled(scheduled) => scheduled.para_id == self.para_id,
+			_ => false,
+		});
+
+		let (core, group) = match core {
+			Some(core) => {
+				let core = CoreIndex(core as u32);
+				let (_, group_rotation_info) =
+					runtime_api.validator_groups(&block_id).map_err(map_err)?;
+
+				(
+					Some(core),
+					Some(group_rotation_info.group_for_core(core, cores.len())),
+				)
+			}
+			None => (None, None),
+		};
+
+		Ok(Some(RelaySessionInfo {
+			session_index,
+			core,
+			group,
+		}))
+	}
+}
+
+/// Returns `true` if the parachain is scheduled on a core at the best block of the relay chain,
+/// so the overseer is expected to request collations.
+///
+/// Errors count as scheduled.
+fn is_scheduled<PClient, PBackend>(relay_chain: &RelayChainClient<PClient, PBackend>) -> bool
+where
+	PClient: ProvideRuntimeApi<PBlock>
+		+ HeaderBackend<PBlock>
+		+ StorageProvider<PBlock, PBackend>
+		+ Send
+		+ Sync,
+	PClient::Api: ParachainHost<PBlock>,
+	PBackend: sc_client_api::Backend<PBlock>,
+{
+	let best_hash = relay_chain.polkadot_client.info().best_hash;
+
+	match relay_chain.session_info(best_hash) {
+		Ok(Some(session_info)) => session_info.core.is_some(),
+		Ok(None) | Err(_) => true,
+	}
 }
 
 pub async fn start_collator<
@@ -434,8 +1362,43 @@ pub async fn start_collator<
 		para_id,
 		key,
 		polkadot_client,
+		allow_multiple_collations,
+		prometheus_registry,
+		pov_export_dir,
+		status,
+		fork_choice,
+		pre_validate,
+		ready_transactions,
+		parent_recovery,
+		announce_policy,
+		proof_recorder,
+		inclusion_tracker,
+		relay_api_latency,
+		pov_size_limit,
+		announce_signer,
+		native_version,
+		relay_chain_cache,
+		task_metrics,
+		retrieve_dmq_contents,
+		execution_budget,
+		journal,
+		upgrade_only_builder,
+		new_best_observer,
+		max_divergent_relay_blocks,
+		max_unseconded_relay_blocks,
+		storage_diffs,
+		#[cfg(feature = "test-helpers")]
+		relay_chain_delay,
+		skip_reasons,
+		bad_block_repair,
+		receipts,
+		reexecute_own_blocks,
+		detect_runtime_divergence,
+		event_handler,
+		active_collator,
+		dmq_budget,
 	}: StartCollatorParams<Block, PF, BI, Backend, Client, BS, Spawner, PClient>,
-) -> Result<(), String>
+) -> Result<(), CollatorError>
 where
 	PF: Environment<Block> + Send + 'static,
 	BI: BlockImport<Block, Error = sp_consensus::Error, Transaction = TransactionFor<PF, Block>>
@@ -446,10 +1409,12 @@ where
 	Client: Finalizer<Block, Backend>
 		+ UsageProvider<Block>
 		+ HeaderBackend<Block>
+		+ ProvideRuntimeApi<Block>
 		+ Send
 		+ Sync
 		+ BlockBackend<Block>
 		+ 'static,
+	Client::Api: CollectCollationInfo<Block>,
 	for<'a> &'a Client: BlockImport<Block>,
 	BS: BlockBackend<Block> + Send + Sync + 'static,
 	Spawner: SpawnNamed + Clone + Send + Sync + 'static,
@@ -458,84 +1423,482 @@ where
 	PApi: RuntimeApiCollection<StateBackend = PBackend::State>,
 	PClient: polkadot_service::AbstractClient<PBlock, PBackend, Api = PApi> + 'static,
 {
-	let retrieve_dmq_contents = {
+	if let Some(native_version) = native_version {
+		migration_check::check_storage_version(
+			&*backend,
+			client.usage_info().chain.best_hash,
+			&native_version,
+		)?;
+	}
+
+	// The relay chain may still be syncing, so its best block says nothing about the relay
+	// parents the collator will build on. Incompatible relay parents are reported when reading
+	// their downward messages fails.
+	let relay_best_hash = polkadot_client.info().best_hash;
+	if let Err(e) = RelayChainApiVersions::detect(&*polkadot_client, relay_best_hash)
+		.and_then(RelayChainApiVersions::check)
+	{
+		warn!(
+			target: "cumulus-collator",
+			"Relay chain block `{:?}` is not supported by the collator: {}",
+			relay_best_hash,
+			e,
+		);
+	}
+
+	let metrics =
+		Metrics::register(prometheus_registry.as_ref()).map_err(CollatorError::Metrics)?;
+
+	let pov_exporter = pov_export_dir
+		.map(PoVExporter::new)
+		.transpose()
+		.map_err(CollatorError::PoVExportDir)?;
+
+	let relay_chain = RelayChainClient::<_, PBackend>::new(
+		polkadot_client.clone(),
+		para_id,
+		relay_chain_cache.clone(),
+		relay_api_latency.clone(),
+		metrics.clone(),
+	);
+	let scheduled_on = RelayChainClient::<_, PBackend>::new(
+		polkadot_client.clone(),
+		para_id,
+		relay_chain_cache.clone(),
+		relay_api_latency.clone(),
+		metrics.clone(),
+	);
+
+	let relay_chain_validation_data = {
 		let polkadot_client = polkadot_client.clone();
-		move |relay_parent: PHash| {
-			polkadot_client.runtime_api()
-				.dmq_contents_with_context(
-					&BlockId::hash(relay_parent),
-					sp_core::ExecutionContext::Importing,
-					para_id,
-				)
+		let relay_chain_cache = relay_chain_cache.clone();
+		move |relay_parent: PHash, assumption| {
+			relay_chain_cache
+				.persisted_validation_data(&*polkadot_client, relay_parent, para_id, assumption)
 				.map_err(|e| {
-					error!(
-						target: "cumulus-collator",
-						"An error occured during requesting the downward messages for {}: {:?}",
+					format!(
+						"Failed to request the persisted validation data for {}: {:?}",
 						relay_parent, e,
-					);
+					)
+				})
+		}
+	};
+
+	let collator_tasks = TaskGroup::new(spawner.clone(), COLLATOR_TASKS, task_metrics.clone());
+	let network_tasks = TaskGroup::new(spawner, NETWORK_TASKS, task_metrics);
+
+	let event_handler = match event_handler {
+		Some(event_handler) => event_handler,
+		None => Arc::new(
+			DefaultEventHandler::new(prometheus_registry.as_ref())
+				.map_err(CollatorError::Metrics)?,
+		),
+	};
+	let pending_upgrades = PendingUpgrades::default();
+
+	collator_tasks.spawn(
+		"cumulus-inclusion-latency",
+		track_inclusions(
+			polkadot_client.clone(),
+			relay_chain_cache.clone(),
+			para_id,
+			inclusion_tracker.clone(),
+			metrics.clone(),
+			pending_upgrades.clone(),
+			event_handler.clone(),
+		)
+		.boxed(),
+	);
+
+	collator_tasks.spawn(
+		"cumulus-relay-prefetch",
+		relay_prefetch::prefetch_relay_data(
+			polkadot_client.clone(),
+			relay_chain_cache.clone(),
+			para_id,
+			relay_api_latency,
+			metrics.clone(),
+		)
+		.boxed(),
+	);
+
+	let collation_info = runtime_collation_info(client.clone());
+
+	let follow = cumulus_consensus::follow_polkadot(
+		para_id,
+		client,
+		relay_chain_cache.with_client(polkadot_client),
+		{
+			let announce_block = announce_block.clone();
+			Arc::new(move |hash: Block::Hash| {
+				announce_block.announce_block(hash, CollationAnnouncement::Empty)
+			})
+		},
+		new_best_observer,
+	)
+	.map_err(CollatorError::FollowPolkadot)?;
+
+	collator_tasks.spawn("cumulus-follow-polkadot", follow.map(|_| ()).boxed());
+
+	let mut builder = CollatorBuilder::new(
+		proposer_factory,
+		inherent_data_providers,
+		block_import,
+		block_status,
+		backend,
+		Arc::new(relay_chain),
+		Arc::new(relay_chain_validation_data),
+	)
+	.announce_with_overseer(
+		overseer_handler.clone(),
+		Arc::new(network_tasks),
+		announce_block,
+	)
+	.allow_multiple_collations(allow_multiple_collations)
+	.metrics(metrics.clone())
+	.status(status.clone())
+	.inclusion_tracker(inclusion_tracker.clone())
+	.pov_size_limit(pov_size_limit)
+	.fork_choice(fork_choice)
+	.runtime_collation_info(collation_info)
+	.pre_validate(pre_validate)
+	.blocking_spawner(Arc::new(collator_tasks.clone()))
+	.announce_policy(announce_policy)
+	.execution_budget(execution_budget)
+	.max_divergent_relay_blocks(max_divergent_relay_blocks)
+	.max_unseconded_relay_blocks(max_unseconded_relay_blocks);
+
+	if let Some(pov_exporter) = pov_exporter {
+		builder = builder.pov_exporter(pov_exporter);
+	}
+	if let Some(ready_transactions) = ready_transactions {
+		builder = builder.ready_transactions(ready_transactions);
+	}
+	if let Some(parent_recovery) = parent_recovery {
+		builder = builder.parent_recovery(parent_recovery);
+	}
+	if let Some(proof_recorder) = proof_recorder {
+		builder = builder.proof_recorder(proof_recorder);
+	}
+	if let Some(announce_signer) = announce_signer {
+		builder = builder.announce_signer(announce_signer);
+	}
+	if let Some(retrieve_dmq_contents) = retrieve_dmq_contents {
+		builder = builder.retrieve_dmq_contents(retrieve_dmq_contents);
+	}
+	if let Some(dmq_budget) = dmq_budget {
+		builder = builder.dmq_budget(dmq_budget);
+	}
+	if let Some(journal) = journal {
+		builder = builder.journal(journal);
+	}
+	if let Some(upgrade_only_builder) = upgrade_only_builder {
+		builder = builder.upgrade_only_builder(upgrade_only_builder);
+	}
+	if let Some(storage_diffs) = storage_diffs {
+		builder = builder.storage_diffs(storage_diffs);
+	}
+	#[cfg(feature = "test-helpers")]
+	if let Some(relay_chain_delay) = relay_chain_delay {
+		builder = builder.relay_chain_delay(relay_chain_delay);
+	}
+	if let Some(skip_reasons) = skip_reasons {
+		builder = builder.skip_reasons(skip_reasons);
+	}
+	if let Some(bad_block_repair) = bad_block_repair {
+		builder = builder.bad_block_repair(bad_block_repair);
+	}
+	if let Some(receipts) = receipts {
+		builder = builder.collation_receipts(receipts, key.clone());
+	}
+	if let Some(reexecute_own_blocks) = reexecute_own_blocks {
+		builder = builder.reexecute_own_blocks(reexecute_own_blocks);
+	}
+	if let Some(active_collator) = active_collator {
+		builder = builder.active_collator(active_collator, key.public());
+	}
+	builder = builder
+		.event_handler(event_handler)
+		.pending_upgrades(pending_upgrades);
+	if detect_runtime_divergence {
+		let (detector, task) = RuntimeDivergenceDetector::new(metrics.clone());
+		collator_tasks.spawn_blocking("cumulus-runtime-divergence", task);
+		builder = builder.detect_runtime_divergence(detector);
+	}
+
+	let collator = builder.build();
+
+	let last_request = Arc::new(Mutex::new(Instant::now()));
+
+	let build_config = {
+		let last_request = last_request.clone();
+		move || CollationGenerationConfig {
+			key: key.clone(),
+			para_id,
+			collator: {
+				let collator = collator.clone();
+				let last_request = last_request.clone();
+				Box::new(move |relay_parent, validation_data| {
+					*last_request.lock() = Instant::now();
+					let collator = collator.clone();
+					collator
+						.collate(relay_parent, validation_data.clone())
+						.boxed()
 				})
-				.ok()
+			},
+		}
+	};
+
+	initialize(&mut overseer_handler, para_id, &build_config).await?;
+	status.note_registered();
+
+	collator_tasks.spawn(
+		"cumulus-collator-reinitialize",
+		reinitialize_on_silence(
+			overseer_handler,
+			para_id,
+			last_request,
+			move || is_scheduled(&scheduled_on),
+			build_config,
+		)
+		.boxed(),
+	);
+
+	Ok(())
+}
+
+/// Report the inclusion of the candidates produced by us to the `inclusion_tracker` and the
+/// `metrics`.
+///
+/// Every imported relay chain block is checked, so the inclusion on the first fork that is
+/// imported counts. Candidates that time out during availability are reported as well. Their
+/// availability core is free again at the relay block that timed them out, so the overseer
+/// requests the next candidate, on the same parachain parent, right at this block.
+///
+/// Once a block that scheduled an upgrade is included, the upgrade is reported as
+/// [`CollatorEvent::UpgradePending`] to the `event_handler`.
+async fn track_inclusions<Block, PClient>(
+	polkadot_client: Arc<PClient>,
+	relay_chain_cache: RelayChainCache,
+	para_id: ParaId,
+	inclusion_tracker: InclusionTracker,
+	metrics: Metrics,
+	pending_upgrades: PendingUpgrades<Block::Hash>,
+	event_handler: Arc<dyn CollatorEventHandler<Block>>,
+) where
+	Block: BlockT,
+	PClient:
+		ProvideRuntimeApi<PBlock> + HeaderBackend<PBlock> + BlockchainEvents<PBlock> + Send + Sync,
+	PClient::Api: ParachainHost<PBlock>,
+{
+	let mut imported_blocks = polkadot_client.import_notification_stream();
+
+	while let Some(notification) = imported_blocks.next().await {
+		let events = match relay_chain_cache.candidate_events(&*polkadot_client, notification.hash)
+		{
+			Ok(events) => events,
+			Err(e) => {
+				debug!(
+					target: "cumulus-collator",
+					"Failed to get the candidate events of relay block `{}`: {:?}",
+					notification.hash,
+					e,
+				);
+				continue;
+			}
+		};
+
+		note_candidate_events(
+			events,
+			&notification.header,
+			para_id,
+			&inclusion_tracker,
+			&metrics,
+			&pending_upgrades,
+			&*event_handler,
+		);
+	}
+}
+
+/// Report the candidate `events` of the relay block with the given `relay_header`, see
+/// [`track_inclusions`].
+fn note_candidate_events<Block: BlockT>(
+	events: Vec<CandidateEvent>,
+	relay_header: &<PBlock as BlockT>::Header,
+	para_id: ParaId,
+	inclusion_tracker: &InclusionTracker,
+	metrics: &Metrics,
+	pending_upgrades: &PendingUpgrades<Block::Hash>,
+	event_handler: &dyn CollatorEventHandler<Block>,
+) {
+	for event in events {
+		let (receipt, head, included) = match event {
+			CandidateEvent::CandidateIncluded(receipt, head) => (receipt, head, true),
+			CandidateEvent::CandidateTimedOut(receipt, head) => (receipt, head, false),
+			_ => continue,
+		};
+
+		if receipt.descriptor.para_id != para_id {
+			continue;
+		}
+
+		if !included {
+			if inclusion_tracker.note_timed_out(receipt.descriptor.pov_hash) {
+				warn!(
+					target: "cumulus-collator",
+					"Candidate `{}` timed out during availability at relay block `{}`, \
+					the next candidate is built on its parent.",
+					receipt.descriptor.pov_hash,
+					relay_header.hash(),
+				);
+
+				metrics.report_availability_timeout();
+			}
+
+			continue;
+		}
+
+		if let Some(inclusion) =
+			inclusion_tracker.note_included(receipt.descriptor.pov_hash, *relay_header.number())
+		{
+			debug!(
+				target: "cumulus-collator",
+				"Candidate `{}` was included after {} relay blocks.",
+				inclusion.pov_hash,
+				inclusion.latency(),
+			);
+
+			metrics.report_inclusion(&inclusion);
+		}
+
+		let block_hash = match Block::Header::decode(&mut &head.0[..]) {
+			Ok(header) => header.hash(),
+			Err(_) => continue,
+		};
+		if let Some(code_size) = pending_upgrades.note_included(&block_hash) {
+			event_handler.handle_event(&CollatorEvent::UpgradePending {
+				block_hash,
+				code_size,
+			});
+		}
+	}
+}
+
+/// Send the message created by `msg` to the overseer.
+///
+/// Failed sends are retried up to [`MAX_OVERSEER_SEND_ATTEMPTS`] times with an exponential
+/// backoff.
+async fn send_with_retry<M: Into<AllMessages>>(
+	overseer_handler: &mut OverseerHandler,
+	name: &'static str,
+	msg: impl Fn() -> M,
+) -> Result<(), CollatorError> {
+	let mut delay = OVERSEER_SEND_RETRY_DELAY;
+	let mut attempt = 1;
+
+	loop {
+		match overseer_handler.send_msg(msg()).await {
+			Ok(()) => return Ok(()),
+			Err(e) if attempt >= MAX_OVERSEER_SEND_ATTEMPTS => {
+				return Err(CollatorError::Overseer(name, e))
+			}
+			Err(e) => {
+				warn!(
+					target: "cumulus-collator",
+					"Failed to send `{}` message (attempt {}/{}), retrying in {:?}: {:?}",
+					name,
+					attempt,
+					MAX_OVERSEER_SEND_ATTEMPTS,
+					delay,
+					e,
+				);
+
+				Delay::new(delay).await;
+				delay *= 2;
+				attempt += 1;
+			}
 		}
-	};
+	}
+}
 
-	let follow = match cumulus_consensus::follow_polkadot(
-		para_id,
-		client,
-		polkadot_client,
-		announce_block.clone(),
-	) {
-		Ok(follow) => follow,
-		Err(e) => return Err(format!("Could not start following polkadot: {:?}", e)),
-	};
+/// Register the collator at the overseer.
+async fn initialize(
+	overseer_handler: &mut OverseerHandler,
+	para_id: ParaId,
+	build_config: &impl Fn() -> CollationGenerationConfig,
+) -> Result<(), CollatorError> {
+	send_with_retry(overseer_handler, "Initialize", || {
+		CollationGenerationMessage::Initialize(build_config())
+	})
+	.await?;
+
+	send_with_retry(overseer_handler, "CollateOn", || {
+		CollatorProtocolMessage::CollateOn(para_id)
+	})
+	.await
+}
 
-	spawner.spawn("cumulus-follow-polkadot", follow.map(|_| ()).boxed());
+/// Send the initialization again to the overseer when no collation was requested for
+/// [`REINITIALIZE_AFTER`].
+///
+/// Silence is only unexpected while `collations_expected` returns `true`, an idle collator is
+/// not reinitialized. A failed reinitialization is retried with an exponential backoff.
+async fn reinitialize_on_silence(
+	mut overseer_handler: OverseerHandler,
+	para_id: ParaId,
+	last_request: Arc<Mutex<Instant>>,
+	collations_expected: impl Fn() -> bool,
+	build_config: impl Fn() -> CollationGenerationConfig,
+) {
+	loop {
+		Delay::new(REINITIALIZE_AFTER).await;
+
+		let elapsed = last_request.lock().elapsed();
+		if elapsed < REINITIALIZE_AFTER || !collations_expected() {
+			continue;
+		}
 
-	let collator = Collator::new(
-		proposer_factory,
-		inherent_data_providers,
-		overseer_handler.clone(),
-		block_import,
-		block_status,
-		Arc::new(spawner),
-		announce_block,
-		backend,
-		Arc::new(retrieve_dmq_contents),
-	);
+		debug!(
+			target: "cumulus-collator",
+			"No collation was requested for {:?}, sending the initialization again.",
+			elapsed,
+		);
 
-	let config = CollationGenerationConfig {
-		key,
-		para_id,
-		collator: Box::new(move |relay_parent, validation_data| {
-			let collator = collator.clone();
-			collator
-				.produce_candidate(relay_parent, validation_data.clone())
-				.boxed()
-		}),
-	};
+		let mut retry_delay = REINITIALIZE_RETRY_DELAY;
+		while let Err(e) = initialize(&mut overseer_handler, para_id, &build_config).await {
+			error!(
+				target: "cumulus-collator",
+				"Failed to reinitialize the collator, retrying in {:?}: {}",
+				retry_delay,
+				e,
+			);
 
-	overseer_handler
-		.send_msg(CollationGenerationMessage::Initialize(config))
-		.await
-		.map_err(|e| format!("Failed to send `Initialize` message: {:?}", e))?;
+			Delay::new(retry_delay).await;
+			retry_delay = (retry_delay * 2).min(REINITIALIZE_AFTER);
+		}
 
-	overseer_handler
-		.send_msg(CollatorProtocolMessage::CollateOn(para_id))
-		.await
-		.map_err(|e| format!("Failed to send `CollateOn` message: {:?}", e))
+		*last_request.lock() = Instant::now();
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use std::{pin::Pin, time::Duration};
+	use std::{
+		pin::Pin,
+		sync::atomic::{AtomicBool, Ordering},
+		task::Poll,
+		time::Duration,
+	};
 
 	use sc_block_builder::BlockBuilderProvider;
+	use sp_consensus::RecordProof;
 	use sp_core::{testing::TaskExecutor, Pair};
 	use sp_inherents::InherentData;
 	use sp_runtime::traits::DigestFor;
 
 	use cumulus_test_client::{
-		generate_block_inherents, Client, DefaultTestClientBuilderExt,
+		generate_block_inherents, Client, ClientBlockImportExt, DefaultTestClientBuilderExt,
 		TestClientBuilder, TestClientBuilderExt,
 	};
 	use cumulus_test_runtime::{Block, Header};
@@ -543,8 +1906,15 @@ mod tests {
 	use polkadot_node_subsystem::messages::CollationGenerationMessage;
 	use polkadot_node_subsystem_test_helpers::ForwardSubsystem;
 	use polkadot_overseer::{AllSubsystems, Overseer};
+	use polkadot_primitives::v1::{
+		CandidateDescriptor, CandidateReceipt, CollatorId, Header as PHeader,
+		OccupiedCoreAssumption, UpwardMessage,
+	};
 
 	use futures::{channel::mpsc, executor::block_on, future};
+	use proptest::prelude::*;
+	use sp_core::storage::Storage;
+	use sp_state_machine::{InMemoryBackend, StorageProof};
 
 	#[derive(Debug)]
 	struct Error;
@@ -565,10 +1935,24 @@ mod tests {
 		>;
 
 		fn init(&mut self, header: &Header) -> Self::CreateProposer {
-			Box::pin(future::ready(Ok(DummyProposer {
+			let proposer: Result<_, Error> = Ok(DummyProposer {
 				client: self.0.clone(),
 				header: header.clone(),
-			})))
+			});
+
+			// Yield once, like a real proposer factory, so concurrent collations interleave.
+			let mut yielded = false;
+			let yield_once = future::poll_fn(move |cx| {
+				if yielded {
+					return Poll::Ready(());
+				}
+
+				yielded = true;
+				cx.waker().wake_by_ref();
+				Poll::Pending
+			});
+
+			Box::pin(yield_once.map(move |_| proposer))
 		}
 	}
 
@@ -610,13 +1994,50 @@ mod tests {
 		}
 	}
 
+	/// A [`CollatorBuilder`] for a new test client that reads the relay chain data from
+	/// `relay_chain` and finds no relay chain validation data.
+	fn test_collator_builder(
+		relay_chain: impl RelayChainInterface + 'static,
+	) -> (
+		CollatorBuilder<Block, DummyFactory, Arc<Client>, Client, cumulus_test_client::Backend>,
+		Arc<Client>,
+	) {
+		fn relay_chain_validation_data(
+			_: PHash,
+			_: OccupiedCoreAssumption,
+		) -> Result<Option<PersistedValidationData>, String> {
+			Ok(None)
+		}
+
+		let client_builder = TestClientBuilder::new();
+		let backend = client_builder.backend();
+		let client = Arc::new(client_builder.build());
+
+		let builder = CollatorBuilder::new(
+			DummyFactory(client.clone()),
+			Default::default(),
+			client.clone(),
+			client.clone(),
+			backend,
+			Arc::new(relay_chain),
+			Arc::new(relay_chain_validation_data),
+		);
+
+		(builder, client)
+	}
+
+	/// The downward messages of a relay chain without messages.
+	fn no_downward_messages(_: PHash) -> Result<DownwardMessagesType, String> {
+		Ok(Vec::new())
+	}
+
 	#[test]
 	fn collates_produces_a_block() {
 		let _ = env_logger::try_init();
 
 		let spawner = TaskExecutor::new();
 		let para_id = ParaId::from(100);
-		let announce_block = |_, _| ();
+		let announce_block = |_: <Block as BlockT>::Hash, _: CollationAnnouncement| ();
 		let client_builder = TestClientBuilder::new();
 		let backend = client_builder.backend();
 		let client = Arc::new(client_builder.build());
@@ -660,6 +2081,43 @@ mod tests {
 					para_id,
 					key: CollatorPair::generate().0,
 					polkadot_client: Arc::new(polkadot_client,),
+					allow_multiple_collations: false,
+					prometheus_registry: None,
+					pov_export_dir: None,
+					status: Default::default(),
+					fork_choice: Arc::new(cumulus_consensus::RelayChainForkChoice),
+					pre_validate: false,
+					ready_transactions: None,
+					parent_recovery: None,
+					announce_policy: Default::default(),
+					proof_recorder: None,
+					inclusion_tracker: Default::default(),
+					relay_api_latency: Default::default(),
+					pov_size_limit: Default::default(),
+					announce_signer: None,
+					native_version: None,
+					relay_chain_cache: Default::default(),
+					task_metrics: Default::default(),
+					retrieve_dmq_contents: None,
+					execution_budget: Default::default(),
+					journal: None,
+					upgrade_only_builder: None,
+					new_best_observer: None,
+					max_divergent_relay_blocks:
+						divergence_watchdog::DEFAULT_MAX_DIVERGENT_RELAY_BLOCKS,
+					max_unseconded_relay_blocks:
+						backing_connectivity::DEFAULT_MAX_UNSECONDED_RELAY_BLOCKS,
+					storage_diffs: None,
+					#[cfg(feature = "test-helpers")]
+					relay_chain_delay: None,
+					skip_reasons: None,
+					bad_block_repair: None,
+					receipts: None,
+					reexecute_own_blocks: None,
+					detect_runtime_divergence: false,
+					event_handler: None,
+					active_collator: None,
+					dmq_budget: None,
 				},
 			);
 		block_on(collator_start).expect("Should start collator");
@@ -684,4 +2142,638 @@ mod tests {
 
 		assert_eq!(1, *block.header().number());
 	}
+
+	#[test]
+	fn produces_candidate_without_overseer() {
+		let _ = env_logger::try_init();
+
+		let (builder, client) = test_collator_builder(no_downward_messages);
+		let collator = builder.build();
+		let header = client.header(&BlockId::Number(0)).unwrap().unwrap();
+
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.parent_head = header.encode().into();
+
+		let collation =
+			block_on(collator.produce_candidate(PHash::repeat_byte(1), validation_data))
+				.expect("Produces a candidate")
+				.expect("Collation is build");
+
+		let block_data = collation.proof_of_validity.block_data;
+
+		let block = Block::decode(&mut &block_data.0[..]).expect("Is a valid block");
+
+		assert_eq!(1, *block.header().number());
+	}
+
+	#[test]
+	fn produces_candidates_only_while_the_key_is_designated() {
+		let _ = env_logger::try_init();
+
+		let key = CollatorPair::generate().0;
+		let active = Arc::new(Mutex::new(Ok(Some(key.public()))));
+		let active_collator: Arc<dyn ActiveCollator<Block>> = {
+			let active = active.clone();
+			Arc::new(
+				move |_: <Block as BlockT>::Hash| -> Result<Option<CollatorId>, String> {
+					active.lock().clone()
+				},
+			)
+		};
+
+		let (builder, client) = test_collator_builder(no_downward_messages);
+		let collator = builder
+			.active_collator(active_collator, key.public())
+			.build();
+		let parent = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		let produce = |relay_parent: u8| {
+			let mut validation_data = ValidationData::default();
+			validation_data.persisted.parent_head = parent.encode().into();
+			block_on(
+				collator
+					.clone()
+					.produce_candidate(PHash::repeat_byte(relay_parent), validation_data),
+			)
+			.expect("Candidate production does not fail")
+		};
+
+		assert!(produce(1).is_some());
+
+		*active.lock() = Ok(Some(CollatorPair::generate().0.public()));
+		assert!(produce(2).is_none());
+
+		*active.lock() = Ok(None);
+		assert!(produce(3).is_none());
+
+		*active.lock() = Err("Unknown block".into());
+		assert!(produce(4).is_none());
+
+		// The designation is read again for every candidate.
+		*active.lock() = Ok(Some(key.public()));
+		assert!(produce(5).is_some());
+	}
+
+	#[test]
+	fn reports_pending_upgrades_when_the_relay_chain_includes_the_block() {
+		let events = Arc::new(Mutex::new(Vec::new()));
+		let event_handler: Arc<dyn CollatorEventHandler<Block>> = {
+			let events = events.clone();
+			Arc::new(move |event: &CollatorEvent<_>| events.lock().push(event.clone()))
+		};
+		let pending_upgrades = PendingUpgrades::default();
+
+		let (builder, client) = test_collator_builder(no_downward_messages);
+		let collator = builder
+			.event_handler(event_handler.clone())
+			.pending_upgrades(pending_upgrades.clone())
+			.build();
+		let parent = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.parent_head = parent.encode().into();
+
+		let collation =
+			block_on(collator.produce_candidate(PHash::repeat_byte(1), validation_data))
+				.expect("Produces a candidate")
+				.expect("Collation is build");
+		let block_hash = Header::decode(&mut &collation.head_data.0[..])
+			.unwrap()
+			.hash();
+		assert!(matches!(
+			events.lock().pop(),
+			Some(CollatorEvent::CandidateProduced { block_hash: hash, .. }) if hash == block_hash
+		));
+		events.lock().clear();
+
+		// The block scheduled an upgrade.
+		pending_upgrades.note_produced(block_hash, 100);
+
+		let para_id = ParaId::from(100);
+		let included = |para_id| {
+			let receipt = CandidateReceipt {
+				descriptor: CandidateDescriptor {
+					para_id,
+					..Default::default()
+				},
+				commitments_hash: Default::default(),
+			};
+			CandidateEvent::CandidateIncluded(receipt, collation.head_data.clone())
+		};
+		let relay_header = PHeader::new(
+			2,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		let note = |candidate_events| {
+			note_candidate_events(
+				candidate_events,
+				&relay_header,
+				para_id,
+				&InclusionTracker::default(),
+				&Metrics::default(),
+				&pending_upgrades,
+				&*event_handler,
+			)
+		};
+
+		// Candidates of other parachains are ignored.
+		note(vec![included(ParaId::from(200))]);
+		assert!(events.lock().is_empty());
+
+		note(vec![included(para_id)]);
+		assert_eq!(
+			vec![CollatorEvent::UpgradePending {
+				block_hash,
+				code_size: 100,
+			}],
+			*events.lock(),
+		);
+
+		// The upgrade is only reported once.
+		note(vec![included(para_id)]);
+		assert_eq!(1, events.lock().len());
+	}
+
+	#[test]
+	fn publishes_the_max_pov_size_of_the_relay_parent() {
+		struct SmallPoV;
+
+		impl RelayChainInterface for SmallPoV {
+			fn downward_messages(&self, _: PHash) -> Result<DownwardMessagesType, String> {
+				Ok(Vec::new())
+			}
+
+			fn max_pov_size(&self, _: PHash) -> Result<Option<u32>, String> {
+				Ok(Some(1024 * 1024))
+			}
+		}
+
+		let pov_size_limit = PoVSizeLimit::default();
+		let (builder, client) = test_collator_builder(SmallPoV);
+		let collator = builder.pov_size_limit(pov_size_limit.clone()).build();
+		let header = client.header(&BlockId::Number(0)).unwrap().unwrap();
+
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.parent_head = header.encode().into();
+
+		assert_eq!(pov_size_limit::DEFAULT_MAX_POV_SIZE, pov_size_limit.get());
+		block_on(collator.produce_candidate(PHash::repeat_byte(1), validation_data))
+			.expect("Produces a candidate")
+			.expect("Collation is build");
+		assert_eq!(1024 * 1024, pov_size_limit.get());
+	}
+
+	#[test]
+	fn retries_relay_parent_after_failed_collation() {
+		let relay_chain_down = Arc::new(AtomicBool::new(true));
+		let relay_chain = {
+			let relay_chain_down = relay_chain_down.clone();
+			move |_: PHash| -> Result<DownwardMessagesType, String> {
+				if relay_chain_down.load(Ordering::SeqCst) {
+					Err("Relay chain is down".into())
+				} else {
+					Ok(Vec::new())
+				}
+			}
+		};
+
+		let (builder, client) = test_collator_builder(relay_chain);
+		let collator = builder.build();
+		let header = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		let relay_parent = PHash::repeat_byte(1);
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.parent_head = header.encode().into();
+
+		// Reading the downward messages fails, so no candidate is produced.
+		assert!(block_on(
+			collator
+				.clone()
+				.produce_candidate(relay_parent, validation_data.clone())
+		)
+		.is_err());
+
+		relay_chain_down.store(false, Ordering::SeqCst);
+		assert!(block_on(
+			collator
+				.clone()
+				.produce_candidate(relay_parent, validation_data.clone())
+		)
+		.expect("Produces a candidate")
+		.is_some());
+
+		// A second candidate on the same relay parent is refused.
+		assert!(
+			block_on(collator.produce_candidate(relay_parent, validation_data))
+				.expect("Does not fail")
+				.is_none()
+		);
+	}
+
+	#[test]
+	fn uses_the_proof_of_the_proof_recorder() {
+		let _ = env_logger::try_init();
+
+		/// Doesn't let the proposer record a proof and returns a fixed proof instead.
+		struct FixedProof(Mutex<Option<<Block as BlockT>::Hash>>);
+
+		impl ProofRecorderProvider<Block> for FixedProof {
+			fn record_proof(&self) -> RecordProof {
+				RecordProof::No
+			}
+
+			fn storage_proof(
+				&self,
+				parent: <Block as BlockT>::Hash,
+				_: &Block,
+				recorded: Option<StorageProof>,
+			) -> Result<StorageProof, CollatorError> {
+				assert!(recorded.is_none());
+				*self.0.lock() = Some(parent);
+
+				Ok(StorageProof::new(vec![vec![1, 2, 3]]))
+			}
+		}
+
+		let proof_recorder = Arc::new(FixedProof(Mutex::new(None)));
+
+		let (builder, client) = test_collator_builder(no_downward_messages);
+		let collator = builder.proof_recorder(proof_recorder.clone()).build();
+		let header = client.header(&BlockId::Number(0)).unwrap().unwrap();
+
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.parent_head = header.encode().into();
+
+		let collation =
+			block_on(collator.produce_candidate(PHash::repeat_byte(1), validation_data))
+				.expect("Produces a candidate")
+				.expect("Collation is build");
+
+		let block_data = collation.proof_of_validity.block_data;
+		let block = Block::decode(&mut &block_data.0[..]).expect("Is a valid block");
+		let (block_header, extrinsics) = block.deconstruct();
+
+		assert_eq!(Some(header.hash()), *proof_recorder.0.lock());
+		assert_eq!(
+			ParachainBlockData::<Block>::new(
+				block_header,
+				extrinsics,
+				StorageProof::new(vec![vec![1, 2, 3]]),
+			)
+			.encode(),
+			block_data.0,
+		);
+	}
+
+	/// Builds the upgrade-only block with the given extrinsics as block `2`, without a proof.
+	struct TestUpgradeOnlyBuilder;
+
+	impl UpgradeOnlyBuilder<Block> for TestUpgradeOnlyBuilder {
+		fn build(
+			&self,
+			parent: <Block as BlockT>::Hash,
+			extrinsics: Vec<<Block as BlockT>::Extrinsic>,
+		) -> Result<(Block, StorageProof), String> {
+			let header = Header::new(
+				2,
+				Default::default(),
+				Default::default(),
+				parent,
+				Default::default(),
+			);
+
+			Ok((Block::new(header, extrinsics), StorageProof::empty()))
+		}
+	}
+
+	#[test]
+	fn replaces_blocks_that_do_not_fit_with_the_upgrade() {
+		let (builder, _) = test_collator_builder(no_downward_messages);
+		let collator = builder
+			.upgrade_only_builder(Arc::new(TestUpgradeOnlyBuilder))
+			.build();
+
+		let header = parachain_block().into_header();
+		let b = ParachainBlockData::<Block>::new(
+			header,
+			Vec::new(),
+			StorageProof::new(vec![vec![0; 1000]]),
+		);
+		let changes = vec![(
+			well_known_keys::NEW_VALIDATION_CODE.to_vec(),
+			Some(vec![1; 100]),
+		)];
+		let upgrade = |max_pov_size| {
+			collator.upgrade_only_block(Default::default(), &b, &changes, max_pov_size)
+		};
+
+		// The block fits together with the upgrade.
+		assert!(upgrade(2000).expect("Checks the block").is_none());
+		// Without the proof of the proposed block, the upgrade fits.
+		let replacement = upgrade(1000)
+			.expect("Builds the upgrade-only block")
+			.expect("Replaces the block");
+		assert_eq!(2, *replacement.header().number());
+		// Even the upgrade-only block does not fit.
+		assert!(matches!(upgrade(150), Err(CollatorError::UpgradeTooLarge(_))));
+		// Blocks without an upgrade are kept.
+		assert!(collator
+			.upgrade_only_block(Default::default(), &b, &[], 150)
+			.expect("Checks the block")
+			.is_none());
+	}
+
+	#[test]
+	fn provides_downward_messages_as_inherent_data() {
+		fn relay_chain(relay_parent: PHash) -> Result<DownwardMessagesType, String> {
+			assert_eq!(PHash::repeat_byte(1), relay_parent);
+
+			Ok(vec![cumulus_primitives::InboundDownwardMessage {
+				sent_at: 1,
+				msg: vec![1, 2, 3],
+			}])
+		}
+
+		let (builder, _) = test_collator_builder(relay_chain);
+		let mut collator = builder.build();
+
+		let inherent_data = collator
+			.inherent_data(&ValidationData::default(), PHash::repeat_byte(1))
+			.expect("Creates the inherent data");
+
+		let downward_messages = inherent_data
+			.get_data::<DownwardMessagesType>(
+				&cumulus_primitives::inherents::DOWNWARD_MESSAGES_IDENTIFIER,
+			)
+			.unwrap()
+			.expect("Downward messages are provided");
+
+		assert_eq!(1, downward_messages.len());
+		assert_eq!(1, downward_messages[0].sent_at);
+		assert_eq!(vec![1, 2, 3], downward_messages[0].msg);
+	}
+
+	#[test]
+	fn relay_chain_forks_are_no_divergence() {
+		let (builder, mut client) = test_collator_builder(no_downward_messages);
+		let collator = builder.build();
+
+		// Build one parachain block per relay chain fork, the second one becomes the best block.
+		let mut fork_block = |fork: u8| {
+			let digest = sp_runtime::generic::Digest {
+				logs: vec![sp_runtime::generic::DigestItem::Other(vec![fork])],
+			};
+			let mut builder = client
+				.new_block_at(&BlockId::Number(0), digest, false)
+				.expect("Initializes new block");
+			generate_block_inherents(&*client, None)
+				.into_iter()
+				.for_each(|e| builder.push(e).expect("Pushes an inherent"));
+			let block = builder.build().expect("Creates block").block;
+			let header = block.header().clone();
+			client
+				.import_as_best(BlockOrigin::Own, block)
+				.expect("Imports the block");
+			header
+		};
+		let first_fork = fork_block(1);
+		let second_fork = fork_block(2);
+
+		assert_eq!(second_fork.hash(), client.info().best_hash);
+		assert!(!collator.is_divergent(&first_fork));
+		assert!(!collator.is_divergent(&second_fork));
+
+		let mut unknown = second_fork;
+		unknown.set_state_root(Default::default());
+		assert!(collator.is_divergent(&unknown));
+	}
+
+	#[test]
+	fn repairs_parents_that_are_marked_bad() {
+		let (builder, client) = test_collator_builder(no_downward_messages);
+		let recovered = Arc::new(Mutex::new(Vec::new()));
+		let bad_blocks = bad_block_repair::BadBlocks::<Block>::default();
+		let collator = builder
+			.parent_recovery(Arc::new({
+				let recovered = recovered.clone();
+				move |hash: <Block as BlockT>::Hash, _: <Header as HeaderT>::Number| {
+					recovered.lock().push(hash)
+				}
+			}))
+			.bad_block_repair(Arc::new(bad_blocks.clone()))
+			.build();
+
+		// An included parent whose import failed is unknown to the client, but marked as bad.
+		let mut parent = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		parent.set_state_root(Default::default());
+		bad_blocks.mark_bad(parent.hash());
+
+		assert!(collator.request_parent(&parent));
+		assert!(!bad_blocks.is_bad(&parent.hash()));
+		assert_eq!(vec![parent.hash()], *recovered.lock());
+
+		// Every block is only repaired once, a block that fails to import again stays bad.
+		bad_blocks.mark_bad(parent.hash());
+		assert!(!collator.request_parent(&parent));
+		assert_eq!(1, recovered.lock().len());
+	}
+
+	#[test]
+	fn refuses_second_collation_on_same_relay_parent() {
+		let collated = Arc::new(Mutex::new(CollatedRelayParents::default()));
+
+		let reservation = CollatedRelayParents::reserve(&collated, PHash::repeat_byte(1))
+			.expect("Relay parent is not reserved yet");
+		assert!(CollatedRelayParents::reserve(&collated, PHash::repeat_byte(1)).is_none());
+		assert!(CollatedRelayParents::reserve(&collated, PHash::repeat_byte(2)).is_some());
+
+		// A failed collation releases the relay parent again.
+		drop(reservation);
+		let reservation = CollatedRelayParents::reserve(&collated, PHash::repeat_byte(1))
+			.expect("Reservation was released");
+
+		reservation.note_produced();
+		assert!(CollatedRelayParents::reserve(&collated, PHash::repeat_byte(1)).is_none());
+	}
+
+	#[test]
+	fn concurrent_collations_on_the_same_relay_parent_produce_one_candidate() {
+		let (builder, client) = test_collator_builder(no_downward_messages);
+		let collator = builder.build();
+		let parent = client.header(&BlockId::Number(0)).unwrap().unwrap();
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.parent_head = parent.encode().into();
+
+		// Both requests are suspended while the proposer is created, so they interleave.
+		let (first, second) = block_on(future::join(
+			collator
+				.clone()
+				.produce_candidate(PHash::repeat_byte(1), validation_data.clone()),
+			collator.produce_candidate(PHash::repeat_byte(1), validation_data),
+		));
+
+		let produced = vec![first, second]
+			.into_iter()
+			.map(|res| res.expect("Candidate production does not fail"))
+			.filter(Option::is_some)
+			.count();
+		assert_eq!(1, produced);
+	}
+
+	/// The state a parachain runtime leaves behind for the collator.
+	fn parachain_state(
+		upward_messages: Option<Vec<u8>>,
+		new_validation_code: Option<Vec<u8>>,
+		processed_downward_messages: Option<Vec<u8>>,
+	) -> InMemoryBackend<BlakeTwo256> {
+		let top = vec![
+			(well_known_keys::UPWARD_MESSAGES, upward_messages),
+			(well_known_keys::NEW_VALIDATION_CODE, new_validation_code),
+			(
+				well_known_keys::PROCESSED_DOWNWARD_MESSAGES,
+				processed_downward_messages,
+			),
+		]
+		.into_iter()
+		.filter_map(|(k, v)| v.map(|v| (k.to_vec(), v)))
+		.collect();
+
+		Storage {
+			top,
+			children_default: Default::default(),
+		}
+		.into()
+	}
+
+	fn parachain_block() -> ParachainBlockData<Block> {
+		let header = Header::new(
+			1,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+
+		ParachainBlockData::new(header, Vec::new(), StorageProof::empty())
+	}
+
+	/// Check that `collation` contains exactly what was put into the state.
+	fn assert_collation(
+		collation: &Collation,
+		upward_messages: Option<Vec<UpwardMessage>>,
+		new_validation_code: Option<Vec<u8>>,
+		processed_downward_messages: Option<u32>,
+		relay_block_number: PBlockNumber,
+	) {
+		let block = parachain_block();
+
+		assert_eq!(
+			upward_messages.unwrap_or_default(),
+			collation.upward_messages
+		);
+		assert_eq!(
+			new_validation_code,
+			collation.new_validation_code.clone().map(|c| c.0),
+		);
+		assert_eq!(
+			processed_downward_messages.unwrap_or_default(),
+			collation.processed_downward_messages,
+		);
+		assert!(collation.horizontal_messages.is_empty());
+		assert_eq!(relay_block_number, collation.hrmp_watermark);
+		assert_eq!(block.header().encode(), collation.head_data.0);
+		assert_eq!(block.encode(), collation.proof_of_validity.block_data.0);
+	}
+
+	proptest! {
+		#[test]
+		fn collation_contains_the_state_written_by_the_runtime(
+			upward_messages in prop::option::of(
+				prop::collection::vec(prop::collection::vec(any::<u8>(), 0..1024), 0..16),
+			),
+			new_validation_code in prop::option::of(prop::collection::vec(any::<u8>(), 0..4096)),
+			processed_downward_messages in prop::option::of(any::<u32>()),
+			relay_block_number in any::<PBlockNumber>(),
+		) {
+			let state = parachain_state(
+				upward_messages.as_ref().map(Encode::encode),
+				new_validation_code.clone(),
+				processed_downward_messages.as_ref().map(Encode::encode),
+			);
+
+			let collation = collation_from_state(&state, parachain_block(), relay_block_number)
+				.expect("Builds the collation");
+
+			assert_collation(
+				&collation,
+				upward_messages,
+				new_validation_code,
+				processed_downward_messages,
+				relay_block_number,
+			);
+		}
+	}
+
+	#[test]
+	fn collation_contains_empty_and_maximum_sized_state() {
+		let cases = vec![
+			(Some(Vec::new()), Some(Vec::new()), Some(0)),
+			(
+				Some(vec![vec![0xff; 1024 * 1024]]),
+				Some(vec![0xff; 3 * 1024 * 1024]),
+				Some(u32::max_value()),
+			),
+			(Some(vec![Vec::new(); 1024]), None, Some(u32::max_value())),
+		];
+
+		for (upward_messages, new_validation_code, processed_downward_messages) in cases {
+			let state = parachain_state(
+				upward_messages.as_ref().map(Encode::encode),
+				new_validation_code.clone(),
+				processed_downward_messages.as_ref().map(Encode::encode),
+			);
+
+			let collation =
+				collation_from_state(&state, parachain_block(), PBlockNumber::max_value())
+					.expect("Builds the collation");
+
+			assert_collation(
+				&collation,
+				upward_messages,
+				new_validation_code,
+				processed_downward_messages,
+				PBlockNumber::max_value(),
+			);
+		}
+	}
+
+	#[test]
+	fn collation_uses_the_hrmp_watermark_of_the_runtime() {
+		let state: InMemoryBackend<BlakeTwo256> = Storage {
+			top: vec![(well_known_keys::HRMP_WATERMARK.to_vec(), 5u32.encode())]
+				.into_iter()
+				.collect(),
+			children_default: Default::default(),
+		}
+		.into();
+
+		let collation =
+			collation_from_state(&state, parachain_block(), 10).expect("Builds the collation");
+		assert_eq!(5, collation.hrmp_watermark);
+	}
+
+	#[test]
+	fn collation_fails_on_undecodable_state() {
+		let state = parachain_state(Some(vec![4, 1]), None, None);
+		assert!(matches!(
+			collation_from_state(&state, parachain_block(), 1),
+			Err(CollatorError::InvalidState("the upward messages", _)),
+		));
+
+		let state = parachain_state(None, None, Some(vec![1, 2]));
+		assert!(matches!(
+			collation_from_state(&state, parachain_block(), 1),
+			Err(CollatorError::InvalidState("the count of processed downward messages", _)),
+		));
+	}
 }
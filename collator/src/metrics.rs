@@ -0,0 +1,288 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Prometheus metrics of the collator.
+
+use cumulus_network::AnnounceTimeoutAction;
+use cumulus_primitives::InboundDownwardMessage;
+
+use substrate_prometheus_endpoint::{
+	register, Counter, CounterVec, Gauge, Histogram, HistogramOpts, Opts, PrometheusError,
+	Registry, U64,
+};
+
+use crate::{inclusion_latency::Inclusion, proposal_stats::ProposalStats, CollatorError};
+
+use std::time::Duration;
+
+/// Collator metrics.
+///
+/// Does nothing if no prometheus registry was given.
+#[derive(Clone, Default)]
+pub struct Metrics(Option<MetricsInner>);
+
+#[derive(Clone)]
+struct MetricsInner {
+	consecutive_failed_candidates: Gauge<U64>,
+	circuit_breaker_open: Gauge<U64>,
+	divergent_relay_blocks: Gauge<U64>,
+	divergence_halted: Gauge<U64>,
+	candidate_errors: CounterVec<U64>,
+	included_transactions: Counter<U64>,
+	dropped_transactions: Counter<U64>,
+	announce_timeouts: CounterVec<U64>,
+	inclusion_latency: Histogram,
+	availability_timeouts: Counter<U64>,
+	relay_api_latency: Histogram,
+	runtime_divergences: Counter<U64>,
+	downward_messages: Gauge<U64>,
+	downward_message_bytes: Gauge<U64>,
+	unseconded_relay_blocks: Gauge<U64>,
+	backing_group_lost: Gauge<U64>,
+}
+
+impl Metrics {
+	/// Register the metrics at the given `registry`.
+	pub fn register(registry: Option<&Registry>) -> Result<Self, PrometheusError> {
+		let registry = match registry {
+			Some(registry) => registry,
+			None => return Ok(Self(None)),
+		};
+
+		Ok(Self(Some(MetricsInner {
+			consecutive_failed_candidates: register(
+				Gauge::new(
+					"cumulus_collator_consecutive_failed_candidates",
+					"Number of consecutive candidates that were not seconded",
+				)?,
+				registry,
+			)?,
+			circuit_breaker_open: register(
+				Gauge::new(
+					"cumulus_collator_circuit_breaker_open",
+					"Is candidate production paused by the circuit breaker (0 or 1)",
+				)?,
+				registry,
+			)?,
+			divergent_relay_blocks: register(
+				Gauge::new(
+					"cumulus_collator_divergent_relay_blocks",
+					"Number of relay blocks the local chain diverged from the head on the relay chain",
+				)?,
+				registry,
+			)?,
+			divergence_halted: register(
+				Gauge::new(
+					"cumulus_collator_divergence_halted",
+					"Is candidate production halted, because the local chain diverged (0 or 1)",
+				)?,
+				registry,
+			)?,
+			candidate_errors: register(
+				CounterVec::new(
+					Opts::new(
+						"cumulus_collator_candidate_errors_total",
+						"Number of failed candidate productions, by error code",
+					),
+					&["error"],
+				)?,
+				registry,
+			)?,
+			included_transactions: register(
+				Counter::new(
+					"cumulus_collator_included_transactions_total",
+					"Number of ready transactions that were included in candidates",
+				)?,
+				registry,
+			)?,
+			dropped_transactions: register(
+				Counter::new(
+					"cumulus_collator_dropped_transactions_total",
+					"Number of ready transactions that did not fit into candidates",
+				)?,
+				registry,
+			)?,
+			announce_timeouts: register(
+				CounterVec::new(
+					Opts::new(
+						"cumulus_collator_announce_timeouts_total",
+						"Number of blocks that were not seconded before the announce timeout, by action",
+					),
+					&["action"],
+				)?,
+				registry,
+			)?,
+			inclusion_latency: register(
+				Histogram::with_opts(
+					HistogramOpts::new(
+						"cumulus_collator_inclusion_latency",
+						"Number of relay blocks from the relay parent of a candidate to its inclusion",
+					)
+					.buckets(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 15.0, 20.0]),
+				)?,
+				registry,
+			)?,
+			availability_timeouts: register(
+				Counter::new(
+					"cumulus_collator_availability_timeouts_total",
+					"Number of produced candidates that were backed, but timed out during availability",
+				)?,
+				registry,
+			)?,
+			relay_api_latency: register(
+				Histogram::with_opts(
+					HistogramOpts::new(
+						"cumulus_collator_relay_api_latency",
+						"Time in seconds to read the relay chain data of a candidate",
+					)
+					.buckets(vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+				)?,
+				registry,
+			)?,
+			runtime_divergences: register(
+				Counter::new(
+					"cumulus_collator_runtime_divergences_total",
+					"Number of produced blocks for which the native and the WASM runtime diverge",
+				)?,
+				registry,
+			)?,
+			downward_messages: register(
+				Gauge::new(
+					"cumulus_collator_downward_messages",
+					"Number of pending downward messages at the relay parent of the last candidate",
+				)?,
+				registry,
+			)?,
+			downward_message_bytes: register(
+				Gauge::new(
+					"cumulus_collator_downward_message_bytes",
+					"Total size in bytes of the pending downward messages of the last candidate",
+				)?,
+				registry,
+			)?,
+			unseconded_relay_blocks: register(
+				Gauge::new(
+					"cumulus_collator_unseconded_relay_blocks",
+					"Number of relay blocks since which produced candidates were not seconded",
+				)?,
+				registry,
+			)?,
+			backing_group_lost: register(
+				Gauge::new(
+					"cumulus_collator_backing_group_lost",
+					"Is the connection to the backing group considered lost (0 or 1)",
+				)?,
+				registry,
+			)?,
+		})))
+	}
+
+	/// Report the state of the circuit breaker.
+	pub fn report_circuit_breaker(&self, consecutive_failures: u32, is_open: bool) {
+		if let Some(metrics) = &self.0 {
+			metrics
+				.consecutive_failed_candidates
+				.set(consecutive_failures as u64);
+			metrics.circuit_breaker_open.set(is_open as u64);
+		}
+	}
+
+	/// Report the state of the divergence watchdog.
+	pub fn report_divergence(&self, divergent_relay_blocks: u32, is_halted: bool) {
+		if let Some(metrics) = &self.0 {
+			metrics
+				.divergent_relay_blocks
+				.set(divergent_relay_blocks as u64);
+			metrics.divergence_halted.set(is_halted as u64);
+		}
+	}
+
+	/// Report that producing a candidate failed with the given `error`.
+	pub fn report_error(&self, error: &CollatorError) {
+		if let Some(metrics) = &self.0 {
+			metrics
+				.candidate_errors
+				.with_label_values(&[error.code()])
+				.inc();
+		}
+	}
+
+	/// Report the transactions the proposer put into a candidate.
+	pub fn report_proposal(&self, stats: &ProposalStats) {
+		if let Some(metrics) = &self.0 {
+			metrics.included_transactions.inc_by(stats.included as u64);
+			metrics.dropped_transactions.inc_by(stats.dropped() as u64);
+		}
+	}
+
+	/// Report that a block was not seconded before the announce timeout.
+	pub fn report_announce_timeout(&self, action: AnnounceTimeoutAction) {
+		if let Some(metrics) = &self.0 {
+			metrics
+				.announce_timeouts
+				.with_label_values(&[action.name()])
+				.inc();
+		}
+	}
+
+	/// Report the state of the connection to the backing group.
+	pub fn report_backing_connectivity(&self, unseconded_relay_blocks: u32, is_lost: bool) {
+		if let Some(metrics) = &self.0 {
+			metrics
+				.unseconded_relay_blocks
+				.set(unseconded_relay_blocks as u64);
+			metrics.backing_group_lost.set(is_lost as u64);
+		}
+	}
+
+	/// Report the inclusion of a produced candidate.
+	pub fn report_inclusion(&self, inclusion: &Inclusion) {
+		if let Some(metrics) = &self.0 {
+			metrics.inclusion_latency.observe(inclusion.latency() as f64);
+		}
+	}
+
+	/// Report that a produced candidate timed out during availability.
+	pub fn report_availability_timeout(&self) {
+		if let Some(metrics) = &self.0 {
+			metrics.availability_timeouts.inc();
+		}
+	}
+
+	/// Report that reading the relay chain data of a candidate took `latency`.
+	pub fn report_relay_api_latency(&self, latency: Duration) {
+		if let Some(metrics) = &self.0 {
+			metrics.relay_api_latency.observe(latency.as_secs_f64());
+		}
+	}
+
+	/// Report that the native and the WASM runtime diverge for a produced block.
+	pub fn report_runtime_divergence(&self) {
+		if let Some(metrics) = &self.0 {
+			metrics.runtime_divergences.inc();
+		}
+	}
+
+	/// Report the pending downward `messages` that were retrieved for a candidate.
+	pub fn report_downward_messages(&self, messages: &[InboundDownwardMessage]) {
+		if let Some(metrics) = &self.0 {
+			metrics.downward_messages.set(messages.len() as u64);
+			metrics
+				.downward_message_bytes
+				.set(messages.iter().map(|m| m.msg.len() as u64).sum());
+		}
+	}
+}
@@ -0,0 +1,196 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Check that the on-chain storage matches the native runtime before collating.
+//!
+//! `frame_system` records the runtime version whose migrations were applied last in
+//! `LastRuntimeUpgrade`. If the on-chain version is ahead of the native runtime, the node is
+//! outdated. Blocks built against storage in a layout the runtime doesn't expect fail the
+//! `validate_block` of the validators, so the collator refuses to start instead.
+//!
+//! Any other difference only results in a warning. An on-chain version behind the native runtime
+//! is expected right after a runtime upgrade was enacted, the pending migration runs in the next
+//! block.
+
+use crate::CollatorError;
+
+use sc_client_api::{Backend as BackendT, StateBackend};
+use sp_core::hashing::twox_128;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT, RuntimeString};
+use sp_version::RuntimeVersion;
+
+use codec::{Compact, Decode};
+use log::warn;
+
+use std::fmt;
+
+/// The storage key of `frame_system::LastRuntimeUpgrade`.
+pub fn last_runtime_upgrade_key() -> Vec<u8> {
+	[twox_128(b"System"), twox_128(b"LastRuntimeUpgrade")].concat()
+}
+
+/// Mirrors `frame_system::LastRuntimeUpgradeInfo`.
+#[derive(Decode)]
+struct LastRuntimeUpgradeInfo {
+	spec_version: Compact<u32>,
+	spec_name: RuntimeString,
+}
+
+/// The runtime version a storage layout belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageVersion {
+	pub spec_name: String,
+	pub spec_version: u32,
+}
+
+impl From<&RuntimeVersion> for StorageVersion {
+	fn from(version: &RuntimeVersion) -> Self {
+		Self {
+			spec_name: version.spec_name.to_string(),
+			spec_version: version.spec_version,
+		}
+	}
+}
+
+impl fmt::Display for StorageVersion {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}-{}", self.spec_name, self.spec_version)
+	}
+}
+
+/// Returns the storage version recorded in the state of the block `at`.
+///
+/// Returns `None` if the runtime does not record its last upgrade.
+pub fn on_chain_storage_version<Block, Backend>(
+	backend: &Backend,
+	at: Block::Hash,
+) -> Result<Option<StorageVersion>, CollatorError>
+where
+	Block: BlockT,
+	Backend: BackendT<Block>,
+{
+	let state = backend
+		.state_at(BlockId::Hash(at))
+		.map_err(|e| CollatorError::State(format!("{:?}", e)))?;
+
+	let raw = match state
+		.storage(&last_runtime_upgrade_key())
+		.map_err(|e| CollatorError::State(format!("{:?}", e)))?
+	{
+		Some(raw) => raw,
+		None => return Ok(None),
+	};
+
+	let info = LastRuntimeUpgradeInfo::decode(&mut &raw[..])
+		.map_err(|e| CollatorError::InvalidState("the last runtime upgrade", e))?;
+
+	Ok(Some(StorageVersion {
+		spec_name: info.spec_name.to_string(),
+		spec_version: info.spec_version.0,
+	}))
+}
+
+/// Check that the storage at the block `at` was not migrated by a newer runtime than the
+/// `native` runtime.
+///
+/// Passes with a warning if the versions differ otherwise, and without one if the runtime does
+/// not record its last upgrade.
+pub fn check_storage_version<Block, Backend>(
+	backend: &Backend,
+	at: Block::Hash,
+	native: &RuntimeVersion,
+) -> Result<(), CollatorError>
+where
+	Block: BlockT,
+	Backend: BackendT<Block>,
+{
+	let native = StorageVersion::from(native);
+
+	match on_chain_storage_version(backend, at)? {
+		Some(on_chain)
+			if on_chain.spec_name == native.spec_name
+				&& on_chain.spec_version > native.spec_version =>
+		{
+			Err(CollatorError::StorageVersionMismatch { on_chain, native })
+		}
+		Some(on_chain) if on_chain != native => {
+			warn!(
+				target: "cumulus-collator",
+				"The on-chain storage was last migrated for `{}`, but the native runtime is `{}`.",
+				on_chain,
+				native,
+			);
+			Ok(())
+		}
+		_ => Ok(()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use codec::Encode;
+	use cumulus_test_client::{DefaultTestClientBuilderExt, TestClientBuilder, TestClientBuilderExt};
+	use sc_client_api::UsageProvider;
+
+	#[test]
+	fn passes_for_the_native_runtime() {
+		let builder = TestClientBuilder::new();
+		let backend = builder.backend();
+		let client = builder.build();
+
+		check_storage_version(
+			&*backend,
+			client.usage_info().chain.best_hash,
+			&cumulus_test_runtime::VERSION,
+		)
+		.expect("Storage matches the native runtime");
+	}
+
+	/// Check the storage version against the native runtime, with the given `spec_version`
+	/// recorded on chain.
+	fn check_recorded_version(spec_version: u32) -> Result<(), CollatorError> {
+		let native = cumulus_test_runtime::VERSION;
+		let recorded = (Compact(spec_version), native.spec_name.to_string());
+
+		let builder =
+			TestClientBuilder::new().add_extra_storage(last_runtime_upgrade_key(), recorded.encode());
+		let backend = builder.backend();
+		let client = builder.build();
+
+		check_storage_version(&*backend, client.usage_info().chain.best_hash, &native)
+	}
+
+	#[test]
+	fn passes_with_pending_migration() {
+		check_recorded_version(cumulus_test_runtime::VERSION.spec_version - 1)
+			.expect("The native runtime applies the migration");
+	}
+
+	#[test]
+	fn detects_outdated_native_runtime() {
+		let native = cumulus_test_runtime::VERSION;
+
+		match check_recorded_version(native.spec_version + 1) {
+			Err(CollatorError::StorageVersionMismatch { on_chain, native: expected }) => {
+				assert_eq!(native.spec_version + 1, on_chain.spec_version);
+				assert_eq!(native.spec_version, expected.spec_version);
+			}
+			r => panic!("Unexpected result: {:?}", r),
+		}
+	}
+}
@@ -0,0 +1,81 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Recover parent blocks that are not available locally.
+//!
+//! A collator that fell behind may be asked to build on a parent it doesn't know. Instead of
+//! skipping candidate production until the block arrives by chance, the collator requests the
+//! block through a [`ParentRecovery`] and waits a short time for it to be imported.
+
+use sc_client_api::BlockBackend;
+use sp_consensus::BlockStatus;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor},
+};
+
+use futures_timer::Delay;
+
+use std::{
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+/// How long we wait for a recovered parent to be imported before giving up.
+///
+/// If the parent is imported later, the candidate is produced on the next relay parent.
+pub const RECOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the status of a recovered parent is checked.
+const RECOVERY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Something that can fetch a parachain block that is not available locally.
+pub trait ParentRecovery<Block: BlockT>: Send + Sync {
+	/// Start fetching the block with the given `hash` and `number`.
+	///
+	/// The fetched block is expected to be imported into the client.
+	fn recover(&self, hash: Block::Hash, number: NumberFor<Block>);
+}
+
+impl<Block, F> ParentRecovery<Block> for F
+where
+	Block: BlockT,
+	F: Fn(Block::Hash, NumberFor<Block>) + Send + Sync,
+{
+	fn recover(&self, hash: Block::Hash, number: NumberFor<Block>) {
+		(self)(hash, number)
+	}
+}
+
+/// Wait up to [`RECOVERY_TIMEOUT`] for the block with the given `hash` to be imported with state.
+///
+/// Returns `true` if the block was imported in time.
+pub async fn wait_for_import<Block: BlockT, BS: BlockBackend<Block>>(
+	block_status: Arc<BS>,
+	hash: Block::Hash,
+) -> bool {
+	let start = Instant::now();
+
+	while start.elapsed() < RECOVERY_TIMEOUT {
+		Delay::new(RECOVERY_POLL_INTERVAL).await;
+
+		if let Ok(BlockStatus::InChainWithState) = block_status.block_status(&BlockId::Hash(hash)) {
+			return true;
+		}
+	}
+
+	false
+}
@@ -0,0 +1,137 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Resolve the parachain parent a candidate should be built on.
+//!
+//! The validation data given to the collator assumes that the availability core of the parachain
+//! is free. While the previous candidate is still pending availability, its head will be the
+//! parent of the next included candidate. [`resolve_parent`] cross-checks the given validation
+//! data against the relay chain, assuming that the pending candidate gets included.
+
+use cumulus_primitives::PersistedValidationData;
+
+use polkadot_primitives::v1::{HeadData, OccupiedCoreAssumption};
+
+use crate::PHash;
+
+/// Access to the `persisted_validation_data` runtime api of the relay chain.
+pub trait RelayChainValidationData: Send + Sync {
+	/// Returns the persisted validation data of the parachain at `relay_parent`, with the
+	/// availability core of the parachain treated according to `assumption`.
+	fn persisted_validation_data(
+		&self,
+		relay_parent: PHash,
+		assumption: OccupiedCoreAssumption,
+	) -> Result<Option<PersistedValidationData>, String>;
+}
+
+impl<F> RelayChainValidationData for F
+where
+	F: Fn(PHash, OccupiedCoreAssumption) -> Result<Option<PersistedValidationData>, String>
+		+ Send
+		+ Sync,
+{
+	fn persisted_validation_data(
+		&self,
+		relay_parent: PHash,
+		assumption: OccupiedCoreAssumption,
+	) -> Result<Option<PersistedValidationData>, String> {
+		(self)(relay_parent, assumption)
+	}
+}
+
+/// The outcome of [`resolve_parent`].
+#[derive(Debug, PartialEq)]
+pub enum ResolvedParent {
+	/// Build on the parent head of the given validation data.
+	Given,
+	/// A candidate is pending availability, build on its head using this validation data.
+	PendingAvailability(PersistedValidationData),
+	/// A candidate with the given head is pending availability, but its block is not known
+	/// locally.
+	UnknownPendingAvailability(HeadData),
+}
+
+/// Resolve the parent for a candidate that is built on `relay_parent`.
+///
+/// `is_known` is used to check that the head of a candidate that is pending availability can be
+/// built on.
+pub fn resolve_parent(
+	relay_chain: &dyn RelayChainValidationData,
+	relay_parent: PHash,
+	validation_data: &PersistedValidationData,
+	is_known: impl Fn(&HeadData) -> bool,
+) -> Result<ResolvedParent, String> {
+	let included =
+		match relay_chain.persisted_validation_data(relay_parent, OccupiedCoreAssumption::Included)? {
+			Some(data) => data,
+			None => return Ok(ResolvedParent::Given),
+		};
+
+	if included.parent_head == validation_data.parent_head {
+		Ok(ResolvedParent::Given)
+	} else if is_known(&included.parent_head) {
+		Ok(ResolvedParent::PendingAvailability(included))
+	} else {
+		Ok(ResolvedParent::UnknownPendingAvailability(included.parent_head))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn validation_data(head: u8) -> PersistedValidationData {
+		PersistedValidationData {
+			parent_head: HeadData(vec![head]),
+			..Default::default()
+		}
+	}
+
+	/// A relay chain that has a candidate with the given head pending availability.
+	fn relay_chain(pending: Option<u8>) -> impl RelayChainValidationData {
+		move |_: PHash,
+		      assumption: OccupiedCoreAssumption|
+		      -> Result<Option<PersistedValidationData>, String> {
+			assert_eq!(OccupiedCoreAssumption::Included, assumption);
+			Ok(pending.map(validation_data))
+		}
+	}
+
+	#[test]
+	fn keeps_given_parent_without_pending_candidate() {
+		assert_eq!(
+			Ok(ResolvedParent::Given),
+			resolve_parent(&relay_chain(Some(1)), PHash::default(), &validation_data(1), |_| true),
+		);
+		assert_eq!(
+			Ok(ResolvedParent::Given),
+			resolve_parent(&relay_chain(None), PHash::default(), &validation_data(1), |_| true),
+		);
+	}
+
+	#[test]
+	fn builds_on_pending_candidate() {
+		assert_eq!(
+			Ok(ResolvedParent::PendingAvailability(validation_data(2))),
+			resolve_parent(&relay_chain(Some(2)), PHash::default(), &validation_data(1), |_| true),
+		);
+		assert_eq!(
+			Ok(ResolvedParent::UnknownPendingAvailability(HeadData(vec![2]))),
+			resolve_parent(&relay_chain(Some(2)), PHash::default(), &validation_data(1), |_| false),
+		);
+	}
+}
@@ -0,0 +1,280 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Measure how many bytes storage accesses add to the proof-of-validity.
+//!
+//! Every storage value a parachain block reads or writes has to be proven in its PoV, together
+//! with the trie nodes on the path to it. How big these proofs are depends on the shape of the
+//! trie of the chain, e.g. the number of keys and the size of the values. [`calibrate_pov_size`]
+//! samples keys of the local state and measures their proofs, the resulting [`PoVCalibration`]
+//! can be written as Rust constants and included into the weight configuration of the runtime.
+
+use sc_client_api::{Backend as BackendT, ProofProvider, StorageProvider};
+use sp_core::storage::{well_known_keys, ChildInfo, StorageKey};
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+
+use codec::Encode;
+
+use std::fmt::Write;
+
+/// The default number of keys that are sampled.
+pub const DEFAULT_SAMPLES: usize = 100;
+
+/// The proof sizes measured on the state of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoVCalibration {
+	/// The number of keys in the state, including the keys of the child tries.
+	pub keys: usize,
+	/// The number of keys that were sampled.
+	pub samples: usize,
+	/// The average size of the sampled values.
+	pub average_value_size: u64,
+	/// The average proof size of reading or overwriting a single value.
+	///
+	/// Overwriting a value needs the same proof as reading it, as the node holding the old value
+	/// is required to compute the new storage root.
+	pub proof_size_per_read: u64,
+	/// The proof size of reading all sampled values at once, divided by the number of samples.
+	///
+	/// Trie nodes shared between the values are only proven once, so this is the cost of a read
+	/// in a block that reads many values.
+	pub proof_size_per_read_amortized: u64,
+	/// The average proof size of inserting a new value.
+	pub proof_size_per_insert: u64,
+}
+
+impl PoVCalibration {
+	/// Returns the calibration as Rust constants that can be included into a runtime.
+	pub fn to_rust(&self) -> String {
+		let mut out = String::new();
+		let _ = writeln!(
+			out,
+			"// Measured on a state with {} keys, {} sampled, {} bytes per value on average.",
+			self.keys, self.samples, self.average_value_size,
+		);
+		let mut constant = |doc: &str, name: &str, value: u64| {
+			let _ = writeln!(out, "\n/// {}\npub const {}: u64 = {};", doc, name, value);
+		};
+		constant(
+			"Proof bytes of reading or overwriting a single storage value.",
+			"PROOF_SIZE_PER_READ",
+			self.proof_size_per_read,
+		);
+		constant(
+			"Proof bytes of reading a storage value in a block that reads many values.",
+			"PROOF_SIZE_PER_READ_AMORTIZED",
+			self.proof_size_per_read_amortized,
+		);
+		constant(
+			"Proof bytes of inserting a new storage value.",
+			"PROOF_SIZE_PER_INSERT",
+			self.proof_size_per_insert,
+		);
+		out
+	}
+}
+
+/// A sampled key, with the child trie it is stored in.
+struct SampledKey {
+	child_info: Option<ChildInfo>,
+	key: Vec<u8>,
+}
+
+/// Measure the proof sizes of up to `samples` keys of the state of the block `at`.
+///
+/// The keys are sampled evenly over the whole state, including the default child tries. Inserts
+/// are measured with keys next to the sampled keys, which is where new entries of a storage map
+/// end up.
+///
+/// The keys of the main trie are iterated twice, once to count and once to sample them, instead
+/// of being collected. Child tries can only be read as a whole.
+pub fn calibrate_pov_size<Block, Client, Backend>(
+	client: &Client,
+	at: BlockId<Block>,
+	samples: usize,
+) -> Result<PoVCalibration, String>
+where
+	Block: BlockT,
+	Backend: BackendT<Block>,
+	Client: ProofProvider<Block> + StorageProvider<Block, Backend>,
+{
+	let mut tries = vec![None];
+	tries.extend(child_tries(client, &at)?.into_iter().map(Some));
+
+	let mut keys = 0;
+	for child_info in &tries {
+		keys += trie_keys(client, &at, child_info.as_ref())?.count();
+	}
+
+	let step = (keys / samples.max(1)).max(1);
+	let mut sampled = Vec::new();
+	let mut index = 0;
+	for child_info in &tries {
+		for key in trie_keys(client, &at, child_info.as_ref())? {
+			if index % step == 0 && sampled.len() < samples {
+				sampled.push(SampledKey {
+					child_info: child_info.clone(),
+					key,
+				});
+			}
+			index += 1;
+		}
+	}
+	if sampled.is_empty() {
+		return Err("The state is empty".into());
+	}
+
+	let proof_size = |child_info: Option<&ChildInfo>, keys: &[&[u8]]| {
+		match child_info {
+			Some(child_info) => client.read_child_proof(&at, child_info, &mut keys.iter().copied()),
+			None => client.read_proof(&at, &mut keys.iter().copied()),
+		}
+		.map(|proof| proof.encoded_size() as u64)
+		.map_err(|e| format!("Failed to prove the storage: {:?}", e))
+	};
+
+	let mut value_size = 0;
+	let mut read_size = 0;
+	let mut insert_size = 0;
+	for sample in &sampled {
+		let key = StorageKey(sample.key.clone());
+		value_size += match sample.child_info {
+			Some(ref child_info) => client.child_storage(&at, child_info, &key),
+			None => client.storage(&at, &key),
+		}
+		.map_err(|e| format!("Failed to read the storage: {:?}", e))?
+		.map_or(0, |v| v.0.len() as u64);
+		read_size += proof_size(sample.child_info.as_ref(), &[&sample.key[..]])?;
+
+		let mut new_key = sample.key.clone();
+		new_key.push(0);
+		insert_size += proof_size(sample.child_info.as_ref(), &[&new_key[..]])?;
+	}
+
+	// Nodes are only shared within a trie, so all samples of a trie are proven at once.
+	let mut amortized_size = 0;
+	for child_info in &tries {
+		let keys = sampled
+			.iter()
+			.filter(|s| s.child_info == *child_info)
+			.map(|s| &s.key[..])
+			.collect::<Vec<_>>();
+		if !keys.is_empty() {
+			amortized_size += proof_size(child_info.as_ref(), &keys)?;
+		}
+	}
+
+	let count = sampled.len() as u64;
+
+	Ok(PoVCalibration {
+		keys,
+		samples: sampled.len(),
+		average_value_size: value_size / count,
+		proof_size_per_read: read_size / count,
+		proof_size_per_read_amortized: amortized_size / count,
+		proof_size_per_insert: insert_size / count,
+	})
+}
+
+/// Returns the default child tries in the state of the block `at`.
+fn child_tries<Block, Client, Backend>(
+	client: &Client,
+	at: &BlockId<Block>,
+) -> Result<Vec<ChildInfo>, String>
+where
+	Block: BlockT,
+	Backend: BackendT<Block>,
+	Client: StorageProvider<Block, Backend>,
+{
+	let prefix = StorageKey(well_known_keys::DEFAULT_CHILD_STORAGE_KEY_PREFIX.to_vec());
+
+	Ok(client
+		.storage_keys_iter(at, Some(&prefix), None)
+		.map_err(|e| format!("Failed to read the storage keys: {:?}", e))?
+		.map(|key| ChildInfo::new_default(&key.0[prefix.0.len()..]))
+		.collect())
+}
+
+/// Returns the keys of the main trie, or of the child trie `child_info`, of the block `at`.
+fn trie_keys<'a, Block, Client, Backend>(
+	client: &'a Client,
+	at: &BlockId<Block>,
+	child_info: Option<&ChildInfo>,
+) -> Result<Box<dyn Iterator<Item = Vec<u8>> + 'a>, String>
+where
+	Block: BlockT,
+	Backend: BackendT<Block> + 'a,
+	Client: StorageProvider<Block, Backend>,
+{
+	match child_info {
+		Some(child_info) => Ok(Box::new(
+			client
+				.child_storage_keys(at, child_info, &StorageKey(Vec::new()))
+				.map_err(|e| format!("Failed to read the child storage keys: {:?}", e))?
+				.into_iter()
+				.map(|key| key.0),
+		)),
+		None => Ok(Box::new(
+			client
+				.storage_keys_iter(at, None, None)
+				.map_err(|e| format!("Failed to read the storage keys: {:?}", e))?
+				.map(|key| key.0),
+		)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_client::{DefaultTestClientBuilderExt, TestClientBuilder, TestClientBuilderExt};
+	use sp_blockchain::HeaderBackend;
+
+	#[test]
+	fn shared_nodes_make_reads_cheaper() {
+		let client = TestClientBuilder::new().build();
+		let at = BlockId::Hash(client.info().genesis_hash);
+
+		let calibration = calibrate_pov_size(&client, at, 10).expect("Calibrates the genesis state");
+
+		assert!(calibration.samples > 1);
+		assert!(calibration.proof_size_per_read > calibration.average_value_size);
+		assert!(calibration.proof_size_per_read_amortized < calibration.proof_size_per_read);
+		assert!(calibration.to_rust().contains(&format!(
+			"pub const PROOF_SIZE_PER_READ: u64 = {};",
+			calibration.proof_size_per_read,
+		)));
+	}
+
+	#[test]
+	fn samples_the_child_tries() {
+		let child_info = ChildInfo::new_default(b"child");
+		let builder = (0u32..50).fold(TestClientBuilder::new(), |builder, i| {
+			builder.add_extra_child_storage(&child_info, i.encode(), vec![1; 32])
+		});
+		let client = builder.build();
+		let plain = TestClientBuilder::new().build();
+
+		let calibration = calibrate_pov_size(&client, BlockId::Number(0), 10_000)
+			.expect("Calibrates the genesis state");
+		let plain_calibration = calibrate_pov_size(&plain, BlockId::Number(0), 10_000)
+			.expect("Calibrates the genesis state");
+
+		// The child trie adds its keys and its root in the main trie.
+		assert_eq!(plain_calibration.keys + 51, calibration.keys);
+		assert_eq!(calibration.keys, calibration.samples);
+	}
+}
@@ -0,0 +1,276 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Export produced PoVs to disk and import them again.
+//!
+//! Reproducing a rejected candidate requires the exact bytes of the PoV. The [`PoVExporter`]
+//! writes the SCALE encoded [`ParachainBlockData`] of every produced candidate into a directory,
+//! [`ExportedPoV`] reads them back. [`import_pov`] imports a saved PoV into the local chain, e.g.
+//! to recover blocks that got lost on all collators.
+
+use cumulus_runtime::ParachainBlockData;
+
+use sp_blockchain::{BlockStatus, HeaderBackend};
+use sp_consensus::{
+	BlockImport, BlockImportParams, BlockOrigin, Error as ConsensusError, ForkChoiceStrategy,
+	ImportResult,
+};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, Header as HeaderT, One},
+};
+
+use crate::PHash;
+
+use codec::{Decode, Encode};
+
+use log::info;
+
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+
+/// The file extension of exported PoVs.
+const POV_FILE_EXTENSION: &str = "pov";
+
+/// Writes produced PoVs into a directory.
+///
+/// Every PoV is written to `<relay_parent>_<block_hash>.pov`.
+#[derive(Clone)]
+pub struct PoVExporter {
+	dir: PathBuf,
+}
+
+impl PoVExporter {
+	/// Create a new instance that writes to `dir`.
+	///
+	/// The directory is created if it does not exist.
+	pub fn new(dir: PathBuf) -> std::io::Result<Self> {
+		fs::create_dir_all(&dir)?;
+		Ok(Self { dir })
+	}
+
+	/// Write the given `block_data` that was built on `relay_parent`.
+	///
+	/// Returns the path of the written file.
+	pub fn export<Block: BlockT>(
+		&self,
+		relay_parent: PHash,
+		block_data: &ParachainBlockData<Block>,
+	) -> std::io::Result<PathBuf> {
+		let path = self.dir.join(format!(
+			"{:?}_{:?}.{}",
+			relay_parent,
+			block_data.header().hash(),
+			POV_FILE_EXTENSION,
+		));
+
+		fs::write(&path, block_data.encode())?;
+
+		Ok(path)
+	}
+}
+
+/// A PoV that was written by the [`PoVExporter`].
+pub struct ExportedPoV<Block: BlockT> {
+	/// The relay parent the PoV was built on, if it could be parsed from the file name.
+	pub relay_parent: Option<PHash>,
+	/// The actual PoV.
+	pub block_data: ParachainBlockData<Block>,
+}
+
+impl<Block: BlockT> ExportedPoV<Block> {
+	/// Read the exported PoV at `path`.
+	pub fn read(path: &Path) -> Result<Self, String> {
+		let encoded = fs::read(path).map_err(|e| format!("Failed to read `{}`: {:?}", path.display(), e))?;
+		let block_data = ParachainBlockData::<Block>::decode(&mut &encoded[..])
+			.map_err(|e| format!("Failed to decode `{}`: {:?}", path.display(), e))?;
+
+		let relay_parent = path
+			.file_stem()
+			.and_then(|s| s.to_str())
+			.and_then(|s| s.split('_').next())
+			.and_then(|s| PHash::from_str(s.trim_start_matches("0x")).ok());
+
+		Ok(Self {
+			relay_parent,
+			block_data,
+		})
+	}
+
+	/// Read all exported PoVs in `dir`, ordered by file name.
+	pub fn read_dir(dir: &Path) -> Result<Vec<Self>, String> {
+		let mut paths = fs::read_dir(dir)
+			.map_err(|e| format!("Failed to read `{}`: {:?}", dir.display(), e))?
+			.filter_map(|entry| entry.ok().map(|e| e.path()))
+			.filter(|path| path.extension().map_or(false, |e| e == POV_FILE_EXTENSION))
+			.collect::<Vec<_>>();
+		paths.sort();
+
+		paths.iter().map(|path| Self::read(path)).collect()
+	}
+}
+
+/// Import the PoV saved at `path` into the local chain.
+///
+/// The parent of the block needs to be known locally. The block is re-executed on import, so a
+/// PoV that does not match the local chain is rejected. Returns the hash of the imported block.
+pub fn import_pov<Block, Client>(client: &Client, path: &Path) -> Result<Block::Hash, String>
+where
+	Block: BlockT,
+	Client: HeaderBackend<Block>,
+	for<'a> &'a Client: BlockImport<Block, Error = ConsensusError>,
+{
+	let block_data = ExportedPoV::<Block>::read(path)?.block_data;
+	let block = Block::new(block_data.header().clone(), block_data.extrinsics().to_vec());
+
+	import_block(client, block, BlockOrigin::File, &path.display().to_string(), false)
+}
+
+/// Import the given `block`, that was loaded from `source`, into the local chain.
+///
+/// The parent of the block needs to be known locally. The block is re-executed on import and
+/// only becomes the new best block if `is_new_best` is set. Returns the hash of the imported
+/// block.
+pub(crate) fn import_block<Block, Client>(
+	client: &Client,
+	block: Block,
+	origin: BlockOrigin,
+	source: &str,
+	is_new_best: bool,
+) -> Result<Block::Hash, String>
+where
+	Block: BlockT,
+	Client: HeaderBackend<Block>,
+	for<'a> &'a Client: BlockImport<Block, Error = ConsensusError>,
+{
+	let (header, extrinsics) = block.deconstruct();
+	let hash = header.hash();
+	let parent_hash = *header.parent_hash();
+
+	let status = client
+		.status(BlockId::Hash(hash))
+		.map_err(|e| format!("Failed to get status of `{}`: {:?}", hash, e))?;
+	if status == BlockStatus::InChain {
+		info!(target: "cumulus-collator", "Block `{}` is already part of the local chain.", hash);
+		return Ok(hash);
+	}
+
+	let parent = client
+		.header(BlockId::Hash(parent_hash))
+		.map_err(|e| format!("Failed to get header of `{}`: {:?}", parent_hash, e))?
+		.ok_or_else(|| format!("Parent `{}` of block `{}` is not known locally", parent_hash, hash))?;
+
+	if *parent.number() + One::one() != *header.number() {
+		return Err(format!(
+			"Block `{}` has number {}, but its parent has number {}",
+			hash,
+			header.number(),
+			parent.number(),
+		));
+	}
+
+	let mut block_import_params = BlockImportParams::new(origin, header);
+	block_import_params.body = Some(extrinsics);
+	// The caller decides about the best block, e.g. disaster recovery makes the last block of a
+	// batch the new best block. Otherwise it is determined by the relay chain.
+	block_import_params.fork_choice = Some(ForkChoiceStrategy::Custom(is_new_best));
+
+	let mut block_import = client;
+	match block_import.import_block(block_import_params, Default::default()) {
+		Ok(ImportResult::Imported(_)) | Ok(ImportResult::AlreadyInChain) => {
+			info!(target: "cumulus-collator", "Imported block `{}` from `{}`.", hash, source);
+			Ok(hash)
+		}
+		Ok(res) => Err(format!("Failed to import block `{}`: {:?}", hash, res)),
+		Err(e) => Err(format!("Failed to import block `{}`: {:?}", hash, e)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_client::{
+		generate_block_inherents, DefaultTestClientBuilderExt, TestClientBuilder,
+		TestClientBuilderExt,
+	};
+	use cumulus_test_runtime::{Block, Header};
+	use sc_block_builder::BlockBuilderProvider;
+	use sp_state_machine::StorageProof;
+
+	#[test]
+	fn exported_pov_can_be_read_back() {
+		let dir = tempfile::tempdir().expect("Creates a temp dir");
+		let exporter = PoVExporter::new(dir.path().join("povs")).expect("Creates the exporter");
+
+		let relay_parent = PHash::repeat_byte(1);
+		let header = Header::new(
+			1,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		let block_data =
+			ParachainBlockData::<Block>::new(header.clone(), Vec::new(), StorageProof::empty());
+
+		let path = exporter.export(relay_parent, &block_data).expect("Exports the PoV");
+		let exported = ExportedPoV::<Block>::read(&path).expect("Reads the PoV");
+
+		assert_eq!(Some(relay_parent), exported.relay_parent);
+		assert_eq!(&header, exported.block_data.header());
+
+		let all = ExportedPoV::<Block>::read_dir(&dir.path().join("povs")).expect("Reads all PoVs");
+		assert_eq!(1, all.len());
+	}
+
+	#[test]
+	fn import_pov_imports_block() {
+		let dir = tempfile::tempdir().expect("Creates a temp dir");
+		let exporter = PoVExporter::new(dir.path().to_path_buf()).expect("Creates the exporter");
+
+		let builder_client = TestClientBuilder::new().build();
+		let mut builder = builder_client
+			.new_block_at(&BlockId::Number(0), Default::default(), true)
+			.expect("Initializes new block");
+		generate_block_inherents(&builder_client, None)
+			.into_iter()
+			.for_each(|e| builder.push(e).expect("Pushes an inherent"));
+		let (block, _, proof) = builder.build().expect("Creates block").into_inner();
+		let (header, extrinsics) = block.deconstruct();
+
+		let block_data = ParachainBlockData::<Block>::new(
+			header.clone(),
+			extrinsics,
+			proof.expect("Proof is recorded"),
+		);
+		let path = exporter
+			.export(PHash::default(), &block_data)
+			.expect("Exports the PoV");
+
+		let client = TestClientBuilder::new().build();
+		let hash = import_pov(&client, &path).expect("Imports the PoV");
+
+		assert_eq!(header.hash(), hash);
+		assert_eq!(
+			Some(header),
+			client.header(BlockId::Hash(hash)).expect("Reads the header"),
+		);
+	}
+}
@@ -0,0 +1,108 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The maximum PoV size of the relay chain.
+//!
+//! The relay chain configures how big the PoV of a candidate may be. The collator reads the
+//! limit at the relay parent of every candidate, see [`relay_max_pov_size`], and publishes it in
+//! a [`PoVSizeLimit`]. Other parts of the node, e.g. the transaction pool of the proposer, can
+//! read it from there while the candidate is built.
+
+use crate::{PBlockNumber, PHash};
+
+use sc_client_api::{Backend, StorageProvider};
+use sp_core::{
+	storage::{StorageData, StorageKey},
+	twox_128,
+};
+use sp_runtime::generic::BlockId;
+
+use polkadot_primitives::v1::Block as PBlock;
+use polkadot_runtime_parachains::configuration::HostConfiguration;
+
+use codec::Decode;
+
+use std::sync::{
+	atomic::{AtomicUsize, Ordering},
+	Arc,
+};
+
+/// The maximum size of a PoV the relay chain accepts by default, in bytes.
+///
+/// Used if the relay chain does not provide its configuration.
+pub const DEFAULT_MAX_POV_SIZE: usize = 5 * 1024 * 1024;
+
+/// The storage key of the active host configuration of the relay chain.
+fn host_configuration_key() -> Vec<u8> {
+	[twox_128(b"Configuration"), twox_128(b"ActiveConfig")].concat()
+}
+
+/// Returns the maximum PoV size of the relay chain at `relay_parent`, in bytes.
+///
+/// Returns `None` if the relay chain has no host configuration.
+pub fn relay_max_pov_size<PClient, PBackend>(
+	polkadot_client: &PClient,
+	relay_parent: PHash,
+) -> Result<Option<u32>, String>
+where
+	PClient: StorageProvider<PBlock, PBackend>,
+	PBackend: Backend<PBlock>,
+{
+	let config = polkadot_client
+		.storage(&BlockId::Hash(relay_parent), &StorageKey(host_configuration_key()))
+		.map_err(|e| {
+			format!("Failed to read the host configuration at {}: {:?}", relay_parent, e)
+		})?;
+
+	let decode = |config: StorageData| {
+		HostConfiguration::<PBlockNumber>::decode(&mut &config.0[..])
+			.map(|config| config.max_pov_size)
+			.map_err(|e| {
+				format!("Failed to decode the host configuration at {}: {:?}", relay_parent, e)
+			})
+	};
+
+	config.map(decode).transpose()
+}
+
+/// The maximum PoV size at the relay parent of the latest candidate, in bytes.
+///
+/// Starts with [`DEFAULT_MAX_POV_SIZE`] until the first candidate is built.
+#[derive(Clone, Debug)]
+pub struct PoVSizeLimit(Arc<AtomicUsize>);
+
+impl Default for PoVSizeLimit {
+	fn default() -> Self {
+		Self::new(DEFAULT_MAX_POV_SIZE)
+	}
+}
+
+impl PoVSizeLimit {
+	/// Start with the given `max_pov_size`, until the first candidate is built.
+	pub fn new(max_pov_size: usize) -> Self {
+		Self(Arc::new(AtomicUsize::new(max_pov_size)))
+	}
+
+	/// Returns the current maximum PoV size.
+	pub fn get(&self) -> usize {
+		self.0.load(Ordering::Relaxed)
+	}
+
+	/// Note the maximum PoV size at the relay parent of the candidate that is built next.
+	pub(crate) fn set(&self, max_pov_size: usize) {
+		self.0.store(max_pov_size, Ordering::Relaxed);
+	}
+}
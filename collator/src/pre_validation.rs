@@ -0,0 +1,151 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validate a freshly built PoV locally before it is handed to the relay chain.
+//!
+//! A candidate that is rejected by its own `validate_block` wastes the backing attempts of the
+//! relay chain validators. The [`PreValidator`] executes the `validate_block` of the runtime that
+//! is stored in the state of the parent block, in the same way the validators will do it.
+//!
+//! The execution is synchronous. Given a spawner, the [`PreValidator`] runs it on a blocking
+//! task, so it does not stall the executor thread of the collator.
+
+use cumulus_primitives::PersistedValidationData;
+use cumulus_runtime::ParachainBlockData;
+
+use sc_executor::WasmExecutor;
+use sp_core::traits::{CallInWasm, MissingHostFunctions, SpawnNamed};
+use sp_runtime::traits::Block as BlockT;
+
+use polkadot_parachain::primitives::{BlockData, ValidationParams, ValidationResult};
+
+use codec::{Decode, Encode};
+use futures::{channel::oneshot, FutureExt};
+
+use std::sync::Arc;
+
+use crate::validation_code_check::ValidationCodeChecker;
+
+/// Execute the `validate_block` of `validation_code` for the given `block_data`.
+pub fn execute_validate_block<Block: BlockT>(
+	executor: &WasmExecutor,
+	validation_code: &[u8],
+	block_data: &ParachainBlockData<Block>,
+	validation_data: &PersistedValidationData,
+) -> Result<ValidationResult, String> {
+	let params = ValidationParams {
+		block_data: BlockData(block_data.encode()),
+		parent_head: validation_data.parent_head.clone(),
+		relay_chain_height: validation_data.block_number,
+		hrmp_mqc_heads: validation_data.hrmp_mqc_heads.clone(),
+		dmq_mqc_head: validation_data.dmq_mqc_head,
+	};
+
+	let mut ext = sp_io::TestExternalities::default();
+	let mut ext_ext = ext.ext();
+
+	let result = executor
+		.call_in_wasm(
+			validation_code,
+			None,
+			"validate_block",
+			&params.encode(),
+			&mut ext_ext,
+			MissingHostFunctions::Disallow,
+		)
+		.map_err(|e| format!("`validate_block` failed: {}", e))?;
+
+	ValidationResult::decode(&mut &result[..])
+		.map_err(|e| format!("Failed to decode the `ValidationResult`: {:?}", e))
+}
+
+/// Runs the `validate_block` of the parachain runtime for every candidate before it is submitted.
+#[derive(Clone)]
+pub struct PreValidator {
+	executor: Arc<WasmExecutor>,
+	spawner: Option<Arc<dyn SpawnNamed + Send + Sync>>,
+}
+
+impl Default for PreValidator {
+	fn default() -> Self {
+		Self {
+			executor: Arc::new(ValidationCodeChecker::default().executor()),
+			spawner: None,
+		}
+	}
+}
+
+impl PreValidator {
+	/// Run [`pre_validate_blocking`](Self::pre_validate_blocking) on a blocking task of `spawner`.
+	pub fn with_spawner(mut self, spawner: Arc<dyn SpawnNamed + Send + Sync>) -> Self {
+		self.spawner = Some(spawner);
+		self
+	}
+
+	/// Like [`pre_validate`](Self::pre_validate), but runs on a blocking task of the spawner
+	/// given to [`with_spawner`](Self::with_spawner).
+	///
+	/// Without a spawner, the validation runs on the calling task.
+	pub async fn pre_validate_blocking<Block: BlockT>(
+		&self,
+		validation_code: Vec<u8>,
+		block_data: ParachainBlockData<Block>,
+		validation_data: PersistedValidationData,
+	) -> Result<(), String> {
+		let spawner = match self.spawner {
+			Some(ref spawner) => spawner,
+			None => return self.pre_validate(&validation_code, &block_data, &validation_data),
+		};
+
+		let (sender, receiver) = oneshot::channel();
+		let pre_validator = self.clone();
+		spawner.spawn_blocking(
+			"cumulus-pre-validation",
+			async move {
+				let _ = sender.send(pre_validator.pre_validate(
+					&validation_code,
+					&block_data,
+					&validation_data,
+				));
+			}
+			.boxed(),
+		);
+
+		receiver
+			.await
+			.map_err(|_| String::from("The pre-validation task was canceled"))?
+	}
+
+	/// Validate `block_data` with the given `validation_code`.
+	///
+	/// Returns an error if `validate_block` rejects the block or if it returns a different head
+	/// data than the header of the block.
+	pub fn pre_validate<Block: BlockT>(
+		&self,
+		validation_code: &[u8],
+		block_data: &ParachainBlockData<Block>,
+		validation_data: &PersistedValidationData,
+	) -> Result<(), String> {
+		let result =
+			execute_validate_block(&self.executor, validation_code, block_data, validation_data)?;
+
+		if result.head_data.0 != block_data.header().encode() {
+			return Err("Head data returned by `validate_block` does not match the header".into());
+		}
+
+		Ok(())
+	}
+}
@@ -0,0 +1,65 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Control how the storage proof of a candidate is recorded.
+//!
+//! By default the proposer records every storage read while building the block and this proof
+//! is put into the PoV. A [`ProofRecorderProvider`] can replace this, e.g. to build the proof
+//! with a backend that also records child trie reads or to track the size of the proof.
+
+use crate::CollatorError;
+
+use sp_consensus::RecordProof;
+use sp_runtime::traits::Block as BlockT;
+use sp_state_machine::StorageProof;
+
+/// Provides the storage proof that is put into the PoV of a candidate.
+pub trait ProofRecorderProvider<Block: BlockT>: Send + Sync {
+	/// Whether the proposer should record a proof while building the block.
+	fn record_proof(&self) -> RecordProof;
+
+	/// Returns the storage proof for `block` that was built on top of `parent`.
+	///
+	/// `recorded` is the proof that was recorded by the proposer, if one was requested by
+	/// [`record_proof`](Self::record_proof).
+	fn storage_proof(
+		&self,
+		parent: Block::Hash,
+		block: &Block,
+		recorded: Option<StorageProof>,
+	) -> Result<StorageProof, CollatorError>;
+}
+
+/// Uses the proof recorded by the proposer.
+///
+/// This is the default [`ProofRecorderProvider`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProposerProofRecorder;
+
+impl<Block: BlockT> ProofRecorderProvider<Block> for ProposerProofRecorder {
+	fn record_proof(&self) -> RecordProof {
+		RecordProof::Yes
+	}
+
+	fn storage_proof(
+		&self,
+		_: Block::Hash,
+		_: &Block,
+		recorded: Option<StorageProof>,
+	) -> Result<StorageProof, CollatorError> {
+		recorded.ok_or(CollatorError::ProofMissing)
+	}
+}
@@ -0,0 +1,105 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Statistics about the transactions the proposer put into a candidate.
+
+use sp_runtime::traits::Extrinsic as ExtrinsicT;
+
+use std::fmt;
+
+/// Something that knows how many transactions are ready for inclusion.
+///
+/// Usually this is the transaction pool that is also used by the proposer.
+pub trait ReadyTransactions: Send + Sync {
+	/// The number of transactions that are ready to be included in the next block.
+	fn ready_transactions(&self) -> usize;
+}
+
+impl<T> ReadyTransactions for T
+where
+	T: Fn() -> usize + Send + Sync,
+{
+	fn ready_transactions(&self) -> usize {
+		(self)()
+	}
+}
+
+/// The number of transactions the proposer included in a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProposalStats {
+	/// The number of ready transactions before proposing.
+	pub ready: usize,
+	/// The number of transactions that were included in the block.
+	pub included: usize,
+}
+
+impl ProposalStats {
+	/// Create the statistics for a block with the given `extrinsics`.
+	///
+	/// Only signed extrinsics are counted as transactions, as unsigned extrinsics are mostly
+	/// inherents that are not coming from the transaction pool.
+	pub fn new<E: ExtrinsicT>(ready: usize, extrinsics: &[E]) -> Self {
+		Self {
+			ready,
+			included: extrinsics
+				.iter()
+				.filter(|e| e.is_signed().unwrap_or(false))
+				.count(),
+		}
+	}
+
+	/// The number of ready transactions that did not fit into the block.
+	///
+	/// These were dropped by the proposer, because the block reached its weight or size limit or
+	/// the proposing deadline.
+	pub fn dropped(&self) -> usize {
+		self.ready.saturating_sub(self.included)
+	}
+}
+
+impl fmt::Display for ProposalStats {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{} of {} ready transactions included, {} dropped",
+			self.included,
+			self.ready,
+			self.dropped(),
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_runtime::testing::TestXt;
+
+	#[test]
+	fn counts_only_signed_extrinsics() {
+		let extrinsics = vec![
+			TestXt::<(), ()>::new((), None),
+			TestXt::new((), Some((1, ()))),
+			TestXt::new((), Some((2, ()))),
+		];
+
+		let stats = ProposalStats::new(5, &extrinsics);
+		assert_eq!(2, stats.included);
+		assert_eq!(3, stats.dropped());
+
+		// More transactions may become ready while proposing.
+		assert_eq!(0, ProposalStats::new(1, &extrinsics).dropped());
+	}
+}
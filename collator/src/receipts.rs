@@ -0,0 +1,474 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Signed receipts of the produced candidates.
+//!
+//! After a candidate was produced, the collator signs a [`CollationReceipt`] with its collator
+//! key. The receipts are stored in the auxiliary storage of the client and handed to a
+//! [`PublishReceipt`]. [`ReceiptGossip`] publishes them on a dedicated gossip topic, see
+//! [`gossip_protocol_name`]. Chains that reward or slash collators can use them as verifiable
+//! evidence of who produced which block.
+
+use crate::PHash;
+
+use polkadot_primitives::v1::{CollatorId, CollatorPair, CollatorSignature};
+use sc_client_api::AuxStore;
+use sc_network::{config::ProtocolId, PeerId};
+use sc_network_gossip::{GossipEngine, Network, ValidationResult, Validator, ValidatorContext};
+use sp_core::Pair;
+use sp_runtime::traits::{Block as BlockT, Hash as HashT, HashFor};
+use substrate_prometheus_endpoint::Registry;
+
+use codec::{Decode, Encode};
+use futures::{future, FutureExt};
+use log::warn;
+use parking_lot::Mutex;
+
+use std::{collections::VecDeque, marker::PhantomData, sync::Arc};
+
+/// The prefix of the keys of the receipts in the auxiliary storage.
+pub const RECEIPTS_KEY: &[u8] = b"cumulus_collation_receipts";
+
+/// The default number of receipts that are kept in the auxiliary storage.
+pub const DEFAULT_RECEIPTS_SIZE: usize = 256;
+
+/// The receipt of a produced candidate.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct CollationReceipt<Hash> {
+	/// The parachain block the candidate was built on.
+	pub para_parent: Hash,
+	/// The hash of the produced block.
+	pub block_hash: Hash,
+	/// The hash of the PoV of the candidate.
+	pub pov_hash: PHash,
+	/// The relay parent the candidate was produced on.
+	pub relay_parent: PHash,
+}
+
+impl<Hash: Encode> CollationReceipt<Hash> {
+	/// The message that is signed by the collator.
+	fn signing_payload(&self) -> Vec<u8> {
+		(b"cumulus-receipt", self).encode()
+	}
+
+	/// Sign the receipt with the collator `key`.
+	pub fn sign(self, key: &CollatorPair) -> SignedCollationReceipt<Hash> {
+		let signature = key.sign(&self.signing_payload());
+
+		SignedCollationReceipt {
+			receipt: self,
+			collator: key.public(),
+			signature,
+		}
+	}
+}
+
+/// A [`CollationReceipt`] signed by the collator that produced the candidate.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct SignedCollationReceipt<Hash> {
+	/// The signed receipt.
+	pub receipt: CollationReceipt<Hash>,
+	/// The collator that produced the candidate.
+	pub collator: CollatorId,
+	/// The signature of the `collator`.
+	pub signature: CollatorSignature,
+}
+
+impl<Hash: Encode> SignedCollationReceipt<Hash> {
+	/// Returns `true` if the receipt was signed by the `collator`.
+	pub fn verify(&self) -> bool {
+		CollatorPair::verify(
+			&self.signature,
+			&self.receipt.signing_payload(),
+			&self.collator,
+		)
+	}
+}
+
+/// Publishes the receipts of produced candidates, e.g. on a gossip topic.
+pub trait PublishReceipt<Hash>: Send + Sync {
+	/// Publish the `receipt` of a produced candidate.
+	fn publish(&self, receipt: &SignedCollationReceipt<Hash>);
+}
+
+impl<Hash, F> PublishReceipt<Hash> for F
+where
+	F: Fn(&SignedCollationReceipt<Hash>) + Send + Sync,
+{
+	fn publish(&self, receipt: &SignedCollationReceipt<Hash>) {
+		(self)(receipt)
+	}
+}
+
+/// The key of the receipt of the block `block_hash`.
+fn receipt_key<Hash: Encode>(block_hash: &Hash) -> Vec<u8> {
+	(RECEIPTS_KEY, b"receipt", block_hash).encode()
+}
+
+/// The key of the block hash of the `index`th receipt.
+fn index_key(index: u64) -> Vec<u8> {
+	(RECEIPTS_KEY, b"index", index).encode()
+}
+
+/// The key of the index of the next receipt.
+fn next_index_key() -> Vec<u8> {
+	(RECEIPTS_KEY, b"next").encode()
+}
+
+/// Read and write access to the stored receipts.
+trait ReceiptStore: Send + Sync {
+	fn read(&self, key: &[u8]) -> Option<Vec<u8>>;
+	fn write(&self, insert: &[(&[u8], &[u8])], delete: &[&[u8]]) -> sp_blockchain::Result<()>;
+}
+
+struct AuxReceiptStore<A>(Arc<A>);
+
+impl<A: AuxStore + Send + Sync> ReceiptStore for AuxReceiptStore<A> {
+	fn read(&self, key: &[u8]) -> Option<Vec<u8>> {
+		self.0.get_aux(key).ok().flatten()
+	}
+
+	fn write(&self, insert: &[(&[u8], &[u8])], delete: &[&[u8]]) -> sp_blockchain::Result<()> {
+		self.0.insert_aux(insert, delete)
+	}
+}
+
+/// The receipts of the last produced candidates.
+///
+/// Every receipt is stored under its own key, together with an index that is used to prune the
+/// oldest receipt. Clones share the same receipts.
+pub struct CollationReceipts<Hash> {
+	store: Arc<dyn ReceiptStore>,
+	size: usize,
+	/// The index of the next receipt.
+	next_index: Arc<Mutex<u64>>,
+	key: Option<Arc<CollatorPair>>,
+	publish: Option<Arc<dyn PublishReceipt<Hash>>>,
+	_marker: PhantomData<Hash>,
+}
+
+impl<Hash> Clone for CollationReceipts<Hash> {
+	fn clone(&self) -> Self {
+		Self {
+			store: self.store.clone(),
+			size: self.size,
+			next_index: self.next_index.clone(),
+			key: self.key.clone(),
+			publish: self.publish.clone(),
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<Hash: Encode + Decode + PartialEq> CollationReceipts<Hash> {
+	/// Keep the receipts of the last `size` candidates in the auxiliary storage of `aux`.
+	pub fn new<A: AuxStore + Send + Sync + 'static>(aux: Arc<A>, size: usize) -> Self {
+		let store = AuxReceiptStore(aux);
+		let next_index = store
+			.read(&next_index_key())
+			.and_then(|raw| u64::decode(&mut &raw[..]).ok())
+			.unwrap_or_default();
+
+		Self {
+			store: Arc::new(store),
+			size,
+			next_index: Arc::new(Mutex::new(next_index)),
+			key: None,
+			publish: None,
+			_marker: PhantomData,
+		}
+	}
+
+	/// Hand every new receipt to `publish`.
+	pub fn with_publisher(mut self, publish: Arc<dyn PublishReceipt<Hash>>) -> Self {
+		self.publish = Some(publish);
+		self
+	}
+
+	/// Sign the receipts with the collator `key`.
+	pub(crate) fn with_key(mut self, key: CollatorPair) -> Self {
+		self.key = Some(Arc::new(key));
+		self
+	}
+
+	/// Returns the stored receipts, oldest first.
+	pub fn receipts(&self) -> Vec<SignedCollationReceipt<Hash>> {
+		let next_index = *self.next_index.lock();
+
+		(next_index.saturating_sub(self.size as u64)..next_index)
+			.filter_map(|index| self.store.read(&index_key(index)))
+			.filter_map(|raw| Hash::decode(&mut &raw[..]).ok())
+			.filter_map(|block_hash| self.receipt(&block_hash))
+			.collect()
+	}
+
+	/// Returns the stored receipt of the block `block_hash`.
+	pub fn receipt(&self, block_hash: &Hash) -> Option<SignedCollationReceipt<Hash>> {
+		self.store
+			.read(&receipt_key(block_hash))
+			.and_then(|raw| SignedCollationReceipt::decode(&mut &raw[..]).ok())
+	}
+
+	/// Sign, store and publish the `receipt` of a produced candidate.
+	///
+	/// Does nothing if no collator key is set.
+	pub(crate) fn issue(&self, receipt: CollationReceipt<Hash>) {
+		let signed = match self.key {
+			Some(ref key) => receipt.sign(key),
+			None => return,
+		};
+
+		{
+			let mut next_index = self.next_index.lock();
+			let index = *next_index;
+
+			let receipt_key = receipt_key(&signed.receipt.block_hash);
+			let encoded = signed.encode();
+			let index_key = index_key(index);
+			let block_hash = signed.receipt.block_hash.encode();
+			let next_index_key = next_index_key();
+			let encoded_next_index = (index + 1).encode();
+			let insert = [
+				(&receipt_key[..], &encoded[..]),
+				(&index_key[..], &block_hash[..]),
+				(&next_index_key[..], &encoded_next_index[..]),
+			];
+
+			// Prune the receipt that falls out of the window.
+			let mut pruned = Vec::new();
+			if let Some(pruned_index) = index.checked_sub(self.size as u64) {
+				let pruned_index_key = index_key(pruned_index);
+				let pruned_hash = self
+					.store
+					.read(&pruned_index_key)
+					.and_then(|raw| Hash::decode(&mut &raw[..]).ok());
+				if let Some(pruned_hash) = pruned_hash {
+					pruned.push(receipt_key(&pruned_hash));
+				}
+				pruned.push(pruned_index_key);
+			}
+			let delete = pruned.iter().map(|key| &key[..]).collect::<Vec<_>>();
+
+			match self.store.write(&insert, &delete) {
+				Ok(()) => *next_index = index + 1,
+				Err(e) => warn!(
+					target: "cumulus-collator",
+					"Failed to write the collation receipt: {:?}",
+					e,
+				),
+			}
+		}
+
+		if let Some(ref publish) = self.publish {
+			publish.publish(&signed);
+		}
+	}
+}
+
+/// The name of the notifications protocol the receipts of the chain with the given
+/// `protocol_id` are gossiped on.
+///
+/// The protocol needs to be added to the `notifications_protocols` of the network
+/// configuration.
+pub fn gossip_protocol_name(protocol_id: &ProtocolId) -> String {
+	format!("/{}/cumulus/collation-receipts/1", protocol_id.as_ref())
+}
+
+/// The gossip topic of the receipts.
+pub fn receipts_topic<Block: BlockT>() -> Block::Hash {
+	<HashFor<Block> as HashT>::hash(b"cumulus-collation-receipts")
+}
+
+/// Only keeps receipts with a valid signature.
+///
+/// Remembers the block hashes of the last [`DEFAULT_RECEIPTS_SIZE`] receipts, older receipts
+/// expire and are not propagated anymore.
+struct ReceiptValidator<Block: BlockT> {
+	recent: Mutex<VecDeque<Block::Hash>>,
+}
+
+impl<Block: BlockT> Validator<Block> for ReceiptValidator<Block> {
+	fn validate(
+		&self,
+		_: &mut dyn ValidatorContext<Block>,
+		_: &PeerId,
+		data: &[u8],
+	) -> ValidationResult<Block::Hash> {
+		let receipt = match SignedCollationReceipt::<Block::Hash>::decode(&mut &data[..]) {
+			Ok(receipt) if receipt.verify() => receipt,
+			_ => return ValidationResult::Discard,
+		};
+
+		let mut recent = self.recent.lock();
+		if !recent.contains(&receipt.receipt.block_hash) {
+			recent.push_back(receipt.receipt.block_hash);
+			if recent.len() > DEFAULT_RECEIPTS_SIZE {
+				recent.pop_front();
+			}
+		}
+
+		ValidationResult::ProcessAndKeep(receipts_topic::<Block>())
+	}
+
+	fn message_expired<'a>(&'a self) -> Box<dyn FnMut(Block::Hash, &[u8]) -> bool + 'a> {
+		let recent = self.recent.lock();
+		Box::new(move |_, data| {
+			SignedCollationReceipt::<Block::Hash>::decode(&mut &data[..])
+				.map_or(true, |r| !recent.contains(&r.receipt.block_hash))
+		})
+	}
+}
+
+/// Gossips the receipts on the topic [`receipts_topic`].
+///
+/// Received receipts are validated and propagated to the other peers. Clones share the same
+/// gossip engine.
+pub struct ReceiptGossip<Block: BlockT> {
+	engine: Arc<Mutex<GossipEngine<Block>>>,
+}
+
+impl<Block: BlockT> Clone for ReceiptGossip<Block> {
+	fn clone(&self) -> Self {
+		Self {
+			engine: self.engine.clone(),
+		}
+	}
+}
+
+impl<Block: BlockT> ReceiptGossip<Block> {
+	/// Gossip the receipts over the `network` of the chain with the given `protocol_id`.
+	pub fn new<N>(network: N, protocol_id: &ProtocolId, registry: Option<&Registry>) -> Self
+	where
+		N: Network<Block> + Send + Clone + 'static,
+	{
+		let validator = Arc::new(ReceiptValidator {
+			recent: Default::default(),
+		});
+		let engine =
+			GossipEngine::new(network, gossip_protocol_name(protocol_id), validator, registry);
+
+		Self {
+			engine: Arc::new(Mutex::new(engine)),
+		}
+	}
+
+	/// Drives the gossip engine.
+	///
+	/// Must be spawned for the receipts to be sent and received.
+	pub async fn run(self) {
+		future::poll_fn(|cx| self.engine.lock().poll_unpin(cx)).await
+	}
+}
+
+impl<Block: BlockT> PublishReceipt<Block::Hash> for ReceiptGossip<Block> {
+	fn publish(&self, receipt: &SignedCollationReceipt<Block::Hash>) {
+		self.engine
+			.lock()
+			.gossip_message(receipts_topic::<Block>(), receipt.encode(), false);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_client::{DefaultTestClientBuilderExt, TestClientBuilder, TestClientBuilderExt};
+	use cumulus_test_runtime::Block;
+	use sp_core::H256;
+
+	fn receipt(byte: u8) -> CollationReceipt<H256> {
+		CollationReceipt {
+			para_parent: H256::repeat_byte(byte),
+			block_hash: H256::repeat_byte(byte + 1),
+			pov_hash: PHash::repeat_byte(byte + 2),
+			relay_parent: PHash::repeat_byte(byte + 3),
+		}
+	}
+
+	#[test]
+	fn stores_and_publishes_verifiable_receipts() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let published = Arc::new(Mutex::new(Vec::new()));
+
+		let receipts = CollationReceipts::<H256>::new(client.clone(), 2)
+			.with_publisher({
+				let published = published.clone();
+				Arc::new(move |r: &SignedCollationReceipt<H256>| published.lock().push(r.clone()))
+			})
+			.with_key(CollatorPair::generate().0);
+
+		receipts.issue(receipt(1));
+		receipts.issue(receipt(10));
+		receipts.issue(receipt(20));
+
+		// Reading the receipts from the database again.
+		let stored = CollationReceipts::<H256>::new(client, 2).receipts();
+
+		assert_eq!(2, stored.len());
+		assert_eq!(receipt(10), stored[0].receipt);
+		assert!(stored.iter().all(SignedCollationReceipt::verify));
+		assert_eq!(3, published.lock().len());
+		assert_eq!(
+			Some(receipt(20)),
+			receipts.receipt(&H256::repeat_byte(21)).map(|r| r.receipt),
+		);
+		// The oldest receipt was pruned.
+		assert!(receipts.receipt(&H256::repeat_byte(2)).is_none());
+
+		let mut forged = stored[1].clone();
+		forged.receipt.pov_hash = PHash::repeat_byte(0);
+		assert!(!forged.verify());
+	}
+
+	struct NoopContext;
+
+	impl ValidatorContext<Block> for NoopContext {
+		fn broadcast_topic(&mut self, _: H256, _: bool) {}
+		fn broadcast_message(&mut self, _: H256, _: Vec<u8>, _: bool) {}
+		fn send_message(&mut self, _: &PeerId, _: Vec<u8>) {}
+		fn send_topic(&mut self, _: &PeerId, _: H256, _: bool) {}
+	}
+
+	#[test]
+	fn gossip_only_keeps_signed_receipts() {
+		let validator = ReceiptValidator::<Block> {
+			recent: Default::default(),
+		};
+		let peer = PeerId::random();
+		let signed = receipt(1).sign(&CollatorPair::generate().0);
+
+		let mut forged = signed.clone();
+		forged.receipt.relay_parent = PHash::repeat_byte(0);
+		assert!(matches!(
+			validator.validate(&mut NoopContext, &peer, &forged.encode()),
+			ValidationResult::Discard,
+		));
+		assert!(matches!(
+			validator.validate(&mut NoopContext, &peer, &[1, 2, 3]),
+			ValidationResult::Discard,
+		));
+
+		match validator.validate(&mut NoopContext, &peer, &signed.encode()) {
+			ValidationResult::ProcessAndKeep(topic) => assert_eq!(receipts_topic::<Block>(), topic),
+			_ => panic!("A signed receipt is kept"),
+		}
+		assert!(!(validator.message_expired())(receipts_topic::<Block>(), &signed.encode()));
+
+		// Receipts of blocks that were not seen recently expire.
+		let unknown = receipt(10).sign(&CollatorPair::generate().0);
+		assert!((validator.message_expired())(receipts_topic::<Block>(), &unknown.encode()));
+	}
+}
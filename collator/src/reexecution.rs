@@ -0,0 +1,118 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Re-execute own blocks in WASM before importing them.
+//!
+//! Blocks produced by the collator are imported with the storage changes that were computed
+//! while building them, usually by the native runtime. The validators only execute the WASM
+//! runtime, so a divergence between the native and the WASM runtime is otherwise only noticed
+//! when the candidate fails validation. An [`ExecuteOwnBlock`] executes the block once more
+//! before it is imported, and the block is dropped if the execution fails.
+
+use sc_client_api::{CallExecutor, ExecutorProvider};
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+use sp_state_machine::ExecutionStrategy;
+
+use codec::Encode;
+
+use std::sync::Arc;
+
+/// Executes a freshly built block before it is imported.
+pub trait ExecuteOwnBlock<Block: BlockT>: Send + Sync {
+	/// Execute `block` on top of the state of `parent`.
+	///
+	/// Returns an error if the execution fails, e.g. because the computed state root does not
+	/// match the state root in the header of `block`.
+	fn execute_block(&self, parent: Block::Hash, block: &Block) -> Result<(), String>;
+}
+
+impl<Block, F> ExecuteOwnBlock<Block> for F
+where
+	Block: BlockT,
+	F: Fn(Block::Hash, &Block) -> Result<(), String> + Send + Sync,
+{
+	fn execute_block(&self, parent: Block::Hash, block: &Block) -> Result<(), String> {
+		(self)(parent, block)
+	}
+}
+
+/// Executes blocks with the WASM runtime that is stored in the state of the parent block.
+pub struct WasmReexecution<Client> {
+	client: Arc<Client>,
+}
+
+impl<Client> WasmReexecution<Client> {
+	/// Create a new instance that executes blocks with the executor of `client`.
+	pub fn new(client: Arc<Client>) -> Self {
+		Self { client }
+	}
+}
+
+impl<Block, Client> ExecuteOwnBlock<Block> for WasmReexecution<Client>
+where
+	Block: BlockT,
+	Client: ExecutorProvider<Block> + Send + Sync,
+{
+	fn execute_block(&self, parent: Block::Hash, block: &Block) -> Result<(), String> {
+		self.client
+			.executor()
+			.call(
+				&BlockId::Hash(parent),
+				"Core_execute_block",
+				&block.encode(),
+				ExecutionStrategy::AlwaysWasm,
+				None,
+			)
+			.map(drop)
+			.map_err(|e| format!("{:?}", e))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_client::{
+		generate_block_inherents, DefaultTestClientBuilderExt, TestClientBuilder,
+		TestClientBuilderExt,
+	};
+	use cumulus_test_runtime::Block;
+	use sc_block_builder::BlockBuilderProvider;
+	use sp_blockchain::HeaderBackend;
+	use sp_runtime::traits::Header as HeaderT;
+
+	#[test]
+	fn rejects_blocks_with_a_wrong_state_root() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let genesis = client.info().genesis_hash;
+
+		let mut builder = client
+			.new_block_at(&BlockId::Hash(genesis), Default::default(), false)
+			.expect("Initializes new block");
+		generate_block_inherents(&client, None)
+			.into_iter()
+			.for_each(|e| builder.push(e).expect("Pushes an inherent"));
+		let block = builder.build().expect("Creates block").block;
+
+		let reexecution = WasmReexecution::new(client);
+		assert!(reexecution.execute_block(genesis, &block).is_ok());
+
+		let (mut header, extrinsics) = block.deconstruct();
+		header.set_state_root(Default::default());
+		let block = Block::new(header, extrinsics);
+		assert!(reexecution.execute_block(genesis, &block).is_err());
+	}
+}
@@ -0,0 +1,116 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Check the runtime api versions of the relay chain.
+//!
+//! The collator reads the relay chain through the `ParachainHost` runtime api. A relay chain
+//! runtime upgrade can remove or change this api, after which every call fails. The versions
+//! are checked when the collator starts and again for a relay parent when reading its downward
+//! messages fails, so an incompatible relay chain is reported with the required versions
+//! instead of an opaque runtime api error for every candidate.
+//!
+//! Only the relay parent a candidate is built on matters. The check at startup, on the relay
+//! chain's best block, therefore only warns.
+
+use crate::{CollatorError, PHash};
+
+use sp_api::{ApiExt, ProvideRuntimeApi};
+use sp_runtime::generic::BlockId;
+
+use polkadot_primitives::v1::{Block as PBlock, ParachainHost};
+
+use std::{cell::Cell, fmt};
+
+/// The minimum version of the `ParachainHost` runtime api the collator supports.
+pub const MIN_PARACHAIN_HOST_VERSION: u32 = 1;
+
+/// The runtime api versions of a relay chain block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayChainApiVersions {
+	/// The version of the `ParachainHost` runtime api, `None` if it is not provided.
+	pub parachain_host: Option<u32>,
+}
+
+impl RelayChainApiVersions {
+	/// Detect the runtime api versions of the relay chain block `at`.
+	pub fn detect<P>(polkadot_client: &P, at: PHash) -> Result<Self, CollatorError>
+	where
+		P: ProvideRuntimeApi<PBlock>,
+		P::Api: ParachainHost<PBlock>,
+	{
+		let version = Cell::new(None);
+
+		polkadot_client
+			.runtime_api()
+			.has_api_with::<dyn ParachainHost<PBlock, Error = sp_blockchain::Error>, _>(
+				&BlockId::Hash(at),
+				|v| {
+					version.set(Some(v));
+					true
+				},
+			)
+			.map_err(|e| CollatorError::RelayApi(format!("{:?}", e)))?;
+
+		Ok(Self {
+			parachain_host: version.get(),
+		})
+	}
+
+	/// Returns `true` if the downward messages can be read with `dmq_contents`.
+	pub fn supports_dmq_contents(&self) -> bool {
+		self.parachain_host
+			.map_or(false, |v| v >= MIN_PARACHAIN_HOST_VERSION)
+	}
+
+	/// Check that all runtime apis required by the collator are provided.
+	pub fn check(self) -> Result<(), CollatorError> {
+		if self.supports_dmq_contents() {
+			Ok(())
+		} else {
+			Err(CollatorError::IncompatibleRelayChain(self))
+		}
+	}
+}
+
+impl fmt::Display for RelayChainApiVersions {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.parachain_host {
+			Some(v) => write!(f, "`ParachainHost` version {}", v),
+			None => write!(f, "no `ParachainHost`"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn requires_the_minimum_parachain_host_version() {
+		let versions = |parachain_host| RelayChainApiVersions { parachain_host };
+
+		assert!(versions(Some(MIN_PARACHAIN_HOST_VERSION)).check().is_ok());
+		assert!(versions(Some(MIN_PARACHAIN_HOST_VERSION + 1)).check().is_ok());
+		assert!(matches!(
+			versions(None).check(),
+			Err(CollatorError::IncompatibleRelayChain(_)),
+		));
+		assert!(matches!(
+			versions(Some(MIN_PARACHAIN_HOST_VERSION - 1)).check(),
+			Err(CollatorError::IncompatibleRelayChain(_)),
+		));
+	}
+}
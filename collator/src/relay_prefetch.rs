@@ -0,0 +1,221 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Prefetch the relay chain data of the next candidate when the relay chain is slow.
+//!
+//! The inherent data of a candidate is read from the relay chain with runtime api calls. With a
+//! local relay chain node these are fast, but with a remote node, e.g. over a WAN, they can take
+//! a significant part of the slot, and proposing starts late. The [`RelayApiLatency`] measures
+//! the downward messages calls that are not answered by the [`RelayChainCache`]. While they are
+//! slow, [`prefetch_relay_data`] reads the validation data and the downward messages of every new
+//! best relay block as soon as it is imported, into the [`RelayChainCache`], before the overseer
+//! requests a candidate for it.
+//!
+//! The collator reads both through the same cache: the validation data when it resolves the
+//! parent of the candidate, and the downward messages when it creates the inherent data. Only
+//! the calls that miss the cache are measured, so a relay chain that becomes fast again is
+//! noticed by the prefetching itself and it stops.
+
+use crate::{Metrics, PHash};
+
+use cumulus_consensus::RelayChainCache;
+
+use sc_client_api::BlockchainEvents;
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+
+use polkadot_primitives::v1::{
+	Block as PBlock, Id as ParaId, OccupiedCoreAssumption, ParachainHost,
+};
+
+use futures::StreamExt;
+use log::debug;
+use parking_lot::Mutex;
+
+use std::{
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+/// The default latency of the relay chain runtime api calls above which data is prefetched.
+pub const DEFAULT_SLOW_THRESHOLD: Duration = Duration::from_millis(250);
+
+/// The weight of a new measurement in the average latency, as `1 / SMOOTHING`.
+const SMOOTHING: u32 = 4;
+
+/// The average latency of the relay chain runtime api calls made for a candidate.
+///
+/// Clones share the same measurements.
+#[derive(Clone)]
+pub struct RelayApiLatency {
+	threshold: Duration,
+	average: Arc<Mutex<Option<Duration>>>,
+}
+
+impl Default for RelayApiLatency {
+	fn default() -> Self {
+		Self::new(DEFAULT_SLOW_THRESHOLD)
+	}
+}
+
+impl RelayApiLatency {
+	/// Consider the relay chain slow when the average latency is above `threshold`.
+	pub fn new(threshold: Duration) -> Self {
+		Self {
+			threshold,
+			average: Default::default(),
+		}
+	}
+
+	/// Note that reading the relay chain data of a candidate took `latency`.
+	pub fn note(&self, latency: Duration) {
+		let mut average = self.average.lock();
+		*average = Some(match *average {
+			Some(average) => (average * (SMOOTHING - 1) + latency) / SMOOTHING,
+			None => latency,
+		});
+	}
+
+	/// The average latency, `None` if nothing was measured yet.
+	pub fn average(&self) -> Option<Duration> {
+		*self.average.lock()
+	}
+
+	/// Returns `true` if the average latency is above the threshold.
+	pub fn is_slow(&self) -> bool {
+		self.average().map_or(false, |average| average > self.threshold)
+	}
+}
+
+/// Runs the relay chain call `f` and notes its duration in the `latency` and the `metrics`.
+///
+/// Nothing is noted if the result of `f` is `cached`, as the call does not reach the relay chain.
+pub(crate) fn measure<T>(
+	cached: bool,
+	latency: &RelayApiLatency,
+	metrics: &Metrics,
+	f: impl FnOnce() -> T,
+) -> T {
+	let started = Instant::now();
+	let res = f();
+
+	if !cached {
+		let elapsed = started.elapsed();
+		latency.note(elapsed);
+		metrics.report_relay_api_latency(elapsed);
+	}
+
+	res
+}
+
+/// Prefetch the relay chain data of `para_id` at every new best relay block into the `cache`,
+/// while the `latency` is slow.
+pub(crate) async fn prefetch_relay_data<PClient>(
+	polkadot_client: Arc<PClient>,
+	cache: RelayChainCache,
+	para_id: ParaId,
+	latency: RelayApiLatency,
+	metrics: Metrics,
+) where
+	PClient: ProvideRuntimeApi<PBlock>
+		+ HeaderBackend<PBlock>
+		+ BlockchainEvents<PBlock>
+		+ Send
+		+ Sync,
+	PClient::Api: ParachainHost<PBlock>,
+{
+	let mut imported_blocks = polkadot_client.import_notification_stream();
+
+	while let Some(notification) = imported_blocks.next().await {
+		if !notification.is_new_best || !latency.is_slow() {
+			continue;
+		}
+
+		prefetch(&*polkadot_client, &cache, para_id, notification.hash, &latency, &metrics);
+	}
+}
+
+fn prefetch<PClient>(
+	polkadot_client: &PClient,
+	cache: &RelayChainCache,
+	para_id: ParaId,
+	relay_block: PHash,
+	latency: &RelayApiLatency,
+	metrics: &Metrics,
+) where
+	PClient: ProvideRuntimeApi<PBlock> + HeaderBackend<PBlock>,
+	PClient::Api: ParachainHost<PBlock>,
+{
+	debug!(
+		target: "cumulus-collator",
+		"Prefetching the relay chain data at `{}`, the relay chain is slow.",
+		relay_block,
+	);
+
+	let validation_data = cache.persisted_validation_data(
+		polkadot_client,
+		relay_block,
+		para_id,
+		OccupiedCoreAssumption::Included,
+	);
+	let cached = cache.contains_dmq_contents(relay_block, para_id);
+	let downward_messages = measure(cached, latency, metrics, || {
+		cache.dmq_contents(polkadot_client, relay_block, para_id)
+	});
+
+	if let Err(e) = validation_data.and(downward_messages) {
+		debug!(
+			target: "cumulus-collator",
+			"Failed to prefetch the relay chain data at `{}`: {:?}",
+			relay_block,
+			e,
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn averages_the_latency() {
+		let latency = RelayApiLatency::new(Duration::from_millis(100));
+		assert!(!latency.is_slow());
+
+		latency.note(Duration::from_millis(40));
+		assert_eq!(Some(Duration::from_millis(40)), latency.average());
+
+		// A single slow call does not make the relay chain slow.
+		latency.note(Duration::from_millis(240));
+		assert_eq!(Some(Duration::from_millis(90)), latency.average());
+		assert!(!latency.is_slow());
+
+		latency.note(Duration::from_millis(210));
+		assert!(latency.is_slow());
+	}
+
+	#[test]
+	fn cached_calls_are_not_measured() {
+		let latency = RelayApiLatency::new(Duration::from_millis(100));
+		let metrics = Metrics::default();
+
+		assert_eq!(1, measure(true, &latency, &metrics, || 1));
+		assert_eq!(None, latency.average());
+
+		assert_eq!(2, measure(false, &latency, &metrics, || 2));
+		assert!(latency.average().is_some());
+	}
+}
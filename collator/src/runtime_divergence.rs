@@ -0,0 +1,219 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detect a divergence between the native and the WASM runtime in the background.
+//!
+//! Blocks are usually built with the native runtime, while the validators execute the WASM
+//! runtime. If the runtime logic was changed without bumping the `spec_version`, the native
+//! runtime of the node is still used and computes a different state than the WASM runtime, so
+//! every candidate is rejected by the relay chain. The [`RuntimeDivergenceDetector`] executes the
+//! `validate_block` of every produced block in the background, on the state given by the storage
+//! proof of the block, and compares the resulting state root with the one of the produced block.
+//! Unlike [`pre_validation`](crate::pre_validation), candidates are not delayed by the check.
+
+use crate::{
+	pre_validation::execute_validate_block, validation_code_check::ValidationCodeChecker, Metrics,
+};
+
+use cumulus_primitives::PersistedValidationData;
+use cumulus_runtime::ParachainBlockData;
+
+use sc_executor::WasmExecutor;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+
+use codec::Decode;
+use futures::{channel::mpsc, future::BoxFuture, FutureExt, StreamExt};
+use log::{debug, error, warn};
+
+/// The number of produced blocks that can wait for the check.
+///
+/// Further blocks are not checked until the queue has room again.
+pub const DIVERGENCE_QUEUE_SIZE: usize = 8;
+
+/// A produced block that waits for the check.
+struct DivergenceCheck<Block: BlockT> {
+	block_data: ParachainBlockData<Block>,
+	validation_code: Vec<u8>,
+	validation_data: PersistedValidationData,
+}
+
+/// Checks produced blocks for a divergence of the native and the WASM runtime.
+///
+/// The checks are executed by the future returned from [`new`](Self::new).
+pub struct RuntimeDivergenceDetector<Block: BlockT> {
+	sender: mpsc::Sender<DivergenceCheck<Block>>,
+}
+
+impl<Block: BlockT> Clone for RuntimeDivergenceDetector<Block> {
+	fn clone(&self) -> Self {
+		Self {
+			sender: self.sender.clone(),
+		}
+	}
+}
+
+impl<Block: BlockT> RuntimeDivergenceDetector<Block> {
+	/// Create a new detector that reports divergences to `metrics`.
+	///
+	/// The returned future executes the checks and should be spawned as a blocking task.
+	pub fn new(metrics: Metrics) -> (Self, BoxFuture<'static, ()>) {
+		let (sender, mut receiver) = mpsc::channel(DIVERGENCE_QUEUE_SIZE);
+		let executor = ValidationCodeChecker::default().executor();
+
+		let task = async move {
+			while let Some(check) = receiver.next().await {
+				let block_hash = check.block_data.header().hash();
+
+				match detect_divergence(
+					&executor,
+					&check.validation_code,
+					&check.block_data,
+					&check.validation_data,
+				) {
+					Ok(()) => debug!(
+						target: "cumulus-collator",
+						"Block `{:?}` has the same state root in the WASM runtime.",
+						block_hash,
+					),
+					Err(e) => {
+						error!(
+							target: "cumulus-collator",
+							"The native and the WASM runtime diverge for block `{:?}`: {} \
+							Candidates built with the native runtime will be rejected by the \
+							relay chain. Was the `spec_version` of the runtime bumped?",
+							block_hash,
+							e,
+						);
+						metrics.report_runtime_divergence();
+					}
+				}
+			}
+		};
+
+		(Self { sender }, task.boxed())
+	}
+
+	/// Queue the produced block for the check.
+	///
+	/// `validation_code` is the runtime of the parent block.
+	pub(crate) fn check(
+		&self,
+		block_data: ParachainBlockData<Block>,
+		validation_code: Vec<u8>,
+		validation_data: PersistedValidationData,
+	) {
+		let block_hash = block_data.header().hash();
+
+		if let Err(e) = self.sender.clone().try_send(DivergenceCheck {
+			block_data,
+			validation_code,
+			validation_data,
+		}) {
+			warn!(
+				target: "cumulus-collator",
+				"Not checking block `{:?}` for a runtime divergence: {}",
+				block_hash,
+				e,
+			);
+		}
+	}
+}
+
+/// Execute `block_data` with the WASM `validation_code` on the state of its storage proof.
+///
+/// Returns an error if the execution fails or if it results in another state root than the one
+/// of the block.
+pub fn detect_divergence<Block: BlockT>(
+	executor: &WasmExecutor,
+	validation_code: &[u8],
+	block_data: &ParachainBlockData<Block>,
+	validation_data: &PersistedValidationData,
+) -> Result<(), String> {
+	let result = execute_validate_block(executor, validation_code, block_data, validation_data)?;
+	let header = Block::Header::decode(&mut &result.head_data.0[..]).map_err(|e| {
+		format!("Failed to decode the header returned by `validate_block`: {:?}", e)
+	})?;
+
+	if header.state_root() != block_data.header().state_root() {
+		return Err(format!(
+			"The WASM runtime computed the state root `{:?}`, but the block has `{:?}`.",
+			header.state_root(),
+			block_data.header().state_root(),
+		));
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_primitives::ValidationData;
+	use cumulus_test_client::{
+		generate_block_inherents, DefaultTestClientBuilderExt, TestClientBuilder,
+		TestClientBuilderExt,
+	};
+	use cumulus_test_runtime::Block;
+	use sc_block_builder::BlockBuilderProvider;
+	use sp_blockchain::HeaderBackend;
+	use sp_runtime::generic::BlockId;
+
+	use codec::Encode;
+
+	#[test]
+	fn detects_a_wrong_state_root() {
+		let client = TestClientBuilder::new().build();
+		let genesis = client
+			.header(&BlockId::Number(0))
+			.expect("Reads the genesis header")
+			.expect("Genesis header exists");
+		let validation_data = PersistedValidationData {
+			block_number: 1,
+			parent_head: genesis.encode().into(),
+			..Default::default()
+		};
+
+		let mut builder = client
+			.new_block_at(&BlockId::Number(0), Default::default(), true)
+			.expect("Initializes new block");
+		generate_block_inherents(
+			&client,
+			Some(ValidationData {
+				persisted: validation_data.clone(),
+				..Default::default()
+			}),
+		)
+		.into_iter()
+		.for_each(|e| builder.push(e).expect("Pushes an inherent"));
+		let (block, _, proof) = builder.build().expect("Creates block").into_inner();
+		let (header, extrinsics) = block.deconstruct();
+		let proof = proof.expect("Proof is recorded");
+
+		let code = cumulus_test_runtime::WASM_BINARY
+			.expect("You need to build the WASM binaries to run the tests!");
+		let executor = ValidationCodeChecker::default().executor();
+
+		let block_data =
+			ParachainBlockData::<Block>::new(header.clone(), extrinsics.clone(), proof.clone());
+		assert!(detect_divergence(&executor, code, &block_data, &validation_data).is_ok());
+
+		let mut diverged = header;
+		diverged.set_state_root(Default::default());
+		let block_data = ParachainBlockData::<Block>::new(diverged, extrinsics, proof);
+		assert!(detect_divergence(&executor, code, &block_data, &validation_data).is_err());
+	}
+}
@@ -0,0 +1,144 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Remember why candidate production was skipped.
+//!
+//! Before a candidate is built, the collator checks that the parent block was imported with its
+//! state. If it was not, the slot is skipped and the reason is only visible in the logs.
+//! [`SkipReasons`] keeps the last reason per parent block in memory, so operators can ask the
+//! node, e.g. over RPC, why the last slots were skipped.
+
+use parking_lot::Mutex;
+
+use std::{
+	collections::VecDeque,
+	fmt,
+	sync::Arc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The number of parent blocks whose skip reason is remembered.
+const MAX_PARENTS: usize = 32;
+
+/// Why candidate production on a parent block was skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+	/// The parent block is still queued for import.
+	Queued,
+	/// The state of the parent block is already pruned.
+	Pruned,
+	/// The parent block is known to be bad.
+	KnownBad,
+	/// The parent block is unknown.
+	Unknown,
+	/// The status of the parent block could not be read.
+	Error(String),
+}
+
+impl fmt::Display for SkipReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Queued => write!(f, "queued"),
+			Self::Pruned => write!(f, "pruned"),
+			Self::KnownBad => write!(f, "known_bad"),
+			Self::Unknown => write!(f, "unknown"),
+			Self::Error(e) => write!(f, "error: {}", e),
+		}
+	}
+}
+
+/// The last skip on a parent block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedParent<Hash> {
+	/// The hash of the parent block.
+	pub parent: Hash,
+	/// Why the last candidate on the parent was skipped.
+	pub reason: SkipReason,
+	/// How often candidate production on the parent was skipped.
+	pub skips: u32,
+	/// Milliseconds since the unix epoch of the last skip.
+	pub last_skip: u64,
+}
+
+/// Shared record of the last skip reasons, per parent block.
+///
+/// The collator updates it while running, clones of it can be handed out to report the reasons.
+#[derive(Clone)]
+pub struct SkipReasons<Hash>(Arc<Mutex<VecDeque<SkippedParent<Hash>>>>);
+
+impl<Hash> Default for SkipReasons<Hash> {
+	fn default() -> Self {
+		Self(Default::default())
+	}
+}
+
+impl<Hash: Clone + PartialEq> SkipReasons<Hash> {
+	/// Note that candidate production on `parent` was skipped for the given `reason`.
+	pub(crate) fn note(&self, parent: Hash, reason: SkipReason) {
+		let last_skip = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_millis() as u64)
+			.unwrap_or_default();
+		let mut parents = self.0.lock();
+
+		let skips = match parents.iter().position(|p| p.parent == parent) {
+			Some(index) => parents.remove(index).map_or(0, |p| p.skips),
+			None => 0,
+		};
+		if parents.len() >= MAX_PARENTS {
+			parents.pop_front();
+		}
+
+		parents.push_back(SkippedParent {
+			parent,
+			reason,
+			skips: skips.saturating_add(1),
+			last_skip,
+		});
+	}
+
+	/// Returns the parent blocks that were skipped most recently, the oldest skip first.
+	pub fn recent(&self) -> Vec<SkippedParent<Hash>> {
+		self.0.lock().iter().cloned().collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keeps_the_last_reason_per_parent() {
+		let reasons = SkipReasons::default();
+
+		reasons.note(1u32, SkipReason::Queued);
+		reasons.note(2, SkipReason::Unknown);
+		reasons.note(1, SkipReason::Pruned);
+
+		let recent = reasons.recent();
+		assert_eq!(2, recent.len());
+		assert_eq!(2, recent[0].parent);
+		assert_eq!(SkipReason::Unknown, recent[0].reason);
+		assert_eq!(1, recent[1].parent);
+		assert_eq!(SkipReason::Pruned, recent[1].reason);
+		assert_eq!(2, recent[1].skips);
+
+		for parent in 0..MAX_PARENTS as u32 + 10 {
+			reasons.note(parent + 10, SkipReason::KnownBad);
+		}
+		assert_eq!(MAX_PARENTS, reasons.recent().len());
+	}
+}
@@ -0,0 +1,80 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Status of a running collator, used for readiness checks.
+
+use parking_lot::Mutex;
+
+use std::{
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+#[derive(Default)]
+struct Inner {
+	registered: bool,
+	last_candidate: Option<Instant>,
+}
+
+/// Shared status of the collator.
+///
+/// The collator updates it while running, clones of it can be handed out to report the status.
+#[derive(Clone, Default)]
+pub struct CollatorStatus(Arc<Mutex<Inner>>);
+
+impl CollatorStatus {
+	/// Note that the collator was registered at the overseer.
+	pub(crate) fn note_registered(&self) {
+		self.0.lock().registered = true;
+	}
+
+	/// Note that a candidate was produced at `now`.
+	pub(crate) fn note_candidate(&self, now: Instant) {
+		self.0.lock().last_candidate = Some(now);
+	}
+
+	/// Is the collator registered at the overseer?
+	pub fn is_registered(&self) -> bool {
+		self.0.lock().registered
+	}
+
+	/// The time since the last candidate was produced.
+	///
+	/// Returns `None` if no candidate was produced yet.
+	pub fn last_candidate_age(&self) -> Option<Duration> {
+		self.0.lock().last_candidate.map(|c| c.elapsed())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn status_is_shared_between_clones() {
+		let status = CollatorStatus::default();
+		let reported = status.clone();
+
+		assert!(!reported.is_registered());
+		assert!(reported.last_candidate_age().is_none());
+
+		status.note_registered();
+		status.note_candidate(Instant::now());
+
+		assert!(reported.is_registered());
+		assert!(reported.last_candidate_age().is_some());
+	}
+}
@@ -0,0 +1,116 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Export the storage changes of the blocks produced by the collator.
+//!
+//! The collator executes every block it produces and imports it together with its storage
+//! changes. Indexers running next to the collator can [`subscribe`](StorageDiffs::subscribe) to
+//! these changes, instead of executing the blocks again.
+//!
+//! Blocks that are replaced by a block that only carries a runtime upgrade are imported without
+//! storage changes, so no [`StorageDiff`] is sent for them.
+
+use sp_state_machine::{ChildStorageCollection, StorageCollection};
+
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+
+use std::sync::Arc;
+
+/// The storage changes of a produced block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageDiff<Hash> {
+	/// The hash of the block.
+	pub block_hash: Hash,
+	/// The changed keys of the main storage with their new values, `None` if removed.
+	pub changes: StorageCollection,
+	/// The changed keys of the child storages, by the storage key of the child trie.
+	pub child_changes: ChildStorageCollection,
+}
+
+/// Sends the [`StorageDiff`] of every produced block to its subscribers.
+///
+/// Clones share the same subscribers.
+pub struct StorageDiffs<Hash> {
+	subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<StorageDiff<Hash>>>>>,
+}
+
+impl<Hash> Clone for StorageDiffs<Hash> {
+	fn clone(&self) -> Self {
+		Self {
+			subscribers: self.subscribers.clone(),
+		}
+	}
+}
+
+impl<Hash> Default for StorageDiffs<Hash> {
+	fn default() -> Self {
+		Self {
+			subscribers: Default::default(),
+		}
+	}
+}
+
+impl<Hash: Clone> StorageDiffs<Hash> {
+	/// Returns a stream of the storage changes of the blocks produced from now on.
+	pub fn subscribe(&self) -> mpsc::UnboundedReceiver<StorageDiff<Hash>> {
+		let (sender, receiver) = mpsc::unbounded();
+		self.subscribers.lock().push(sender);
+		receiver
+	}
+
+	/// Returns `true` if there is at least one subscriber.
+	pub fn has_subscribers(&self) -> bool {
+		let mut subscribers = self.subscribers.lock();
+		subscribers.retain(|s| !s.is_closed());
+		!subscribers.is_empty()
+	}
+
+	/// Send `diff` to all subscribers, dropping the subscribers that went away.
+	pub fn notify(&self, diff: StorageDiff<Hash>) {
+		self.subscribers
+			.lock()
+			.retain(|s| s.unbounded_send(diff.clone()).is_ok());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures::StreamExt;
+
+	#[test]
+	fn sends_diffs_to_active_subscribers() {
+		let diffs = StorageDiffs::<u64>::default();
+		assert!(!diffs.has_subscribers());
+
+		let mut first = diffs.subscribe();
+		let second = diffs.subscribe();
+		drop(second);
+		assert!(diffs.has_subscribers());
+
+		let diff = StorageDiff {
+			block_hash: 1,
+			changes: vec![(vec![1], Some(vec![2])), (vec![3], None)],
+			child_changes: Vec::new(),
+		};
+		diffs.notify(diff.clone());
+
+		assert_eq!(Some(diff), futures::executor::block_on(first.next()));
+		assert_eq!(1, diffs.subscribers.lock().len());
+	}
+}
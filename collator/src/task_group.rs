@@ -0,0 +1,186 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Group the tasks of the parachain stack and measure how long they are polled.
+//!
+//! A [`TaskGroup`] wraps a [`SpawnNamed`] and reports the poll duration of every task it spawns
+//! to the [`TaskMetrics`], labeled with the group and the task name. A future that blocks the
+//! executor can then be attributed to the part of Cumulus that spawned it.
+
+use sp_core::traits::SpawnNamed;
+
+use substrate_prometheus_endpoint::{
+	register, CounterVec, HistogramOpts, HistogramVec, Opts, PrometheusError, Registry, U64,
+};
+
+use futures::{
+	future::{poll_fn, BoxFuture},
+	FutureExt,
+};
+
+use std::time::Instant;
+
+/// The group of the tasks that produce candidates.
+pub const COLLATOR_TASKS: &str = "cumulus-collator";
+/// The group of the tasks that announce and serve blocks.
+pub const NETWORK_TASKS: &str = "cumulus-network";
+/// The group of the tasks that recover missing blocks.
+pub const RECOVERY_TASKS: &str = "cumulus-recovery";
+
+/// Metrics of the tasks spawned by [`TaskGroup`]s.
+///
+/// Does nothing if no prometheus registry was given. Register it once and share it between all
+/// task groups.
+#[derive(Clone, Default)]
+pub struct TaskMetrics(Option<TaskMetricsInner>);
+
+#[derive(Clone)]
+struct TaskMetricsInner {
+	poll_duration: HistogramVec,
+	spawned: CounterVec<U64>,
+}
+
+impl TaskMetrics {
+	/// Register the metrics at the given `registry`.
+	pub fn register(registry: Option<&Registry>) -> Result<Self, PrometheusError> {
+		let registry = match registry {
+			Some(registry) => registry,
+			None => return Ok(Self(None)),
+		};
+
+		Ok(Self(Some(TaskMetricsInner {
+			poll_duration: register(
+				HistogramVec::new(
+					HistogramOpts::new(
+						"cumulus_task_poll_duration",
+						"Duration in seconds of each poll of a Cumulus task, by group and task",
+					)
+					.buckets(vec![0.001, 0.004, 0.016, 0.064, 0.256, 1.024, 4.096, 16.384]),
+					&["group", "task"],
+				)?,
+				registry,
+			)?,
+			spawned: register(
+				CounterVec::new(
+					Opts::new(
+						"cumulus_tasks_spawned_total",
+						"Number of spawned Cumulus tasks, by group and task",
+					),
+					&["group", "task"],
+				)?,
+				registry,
+			)?,
+		})))
+	}
+}
+
+/// A [`SpawnNamed`] that spawns its tasks as part of a group.
+#[derive(Clone)]
+pub struct TaskGroup<S> {
+	spawner: S,
+	group: &'static str,
+	metrics: TaskMetrics,
+}
+
+impl<S> TaskGroup<S> {
+	/// Spawn the tasks of `group` with `spawner` and report them to `metrics`.
+	pub fn new(spawner: S, group: &'static str, metrics: TaskMetrics) -> Self {
+		Self {
+			spawner,
+			group,
+			metrics,
+		}
+	}
+
+	/// The name of the group.
+	pub fn group(&self) -> &'static str {
+		self.group
+	}
+
+	/// Measure every poll of `future`.
+	fn instrument(
+		&self,
+		name: &'static str,
+		mut future: BoxFuture<'static, ()>,
+	) -> BoxFuture<'static, ()> {
+		let metrics = match &self.metrics.0 {
+			Some(metrics) => metrics,
+			None => return future,
+		};
+
+		metrics.spawned.with_label_values(&[self.group, name]).inc();
+		let poll_duration = metrics.poll_duration.with_label_values(&[self.group, name]);
+
+		poll_fn(move |cx| {
+			let start = Instant::now();
+			let res = future.poll_unpin(cx);
+			poll_duration.observe(start.elapsed().as_secs_f64());
+			res
+		})
+		.boxed()
+	}
+}
+
+impl<S: SpawnNamed + Clone> SpawnNamed for TaskGroup<S> {
+	fn spawn(&self, name: &'static str, future: BoxFuture<'static, ()>) {
+		self.spawner.spawn(name, self.instrument(name, future))
+	}
+
+	fn spawn_blocking(&self, name: &'static str, future: BoxFuture<'static, ()>) {
+		self.spawner.spawn_blocking(name, self.instrument(name, future))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sp_core::testing::TaskExecutor;
+
+	use std::sync::mpsc;
+
+	#[test]
+	fn reports_polls_of_spawned_tasks() {
+		let registry = Registry::new();
+		let metrics = TaskMetrics::register(Some(&registry)).expect("Registers the metrics");
+		let group = TaskGroup::new(TaskExecutor::new(), COLLATOR_TASKS, metrics);
+
+		let (tx, rx) = mpsc::channel();
+		group.spawn(
+			"test-task",
+			async move {
+				tx.send(()).expect("Receiver is alive");
+			}
+			.boxed(),
+		);
+		rx.recv().expect("Task was executed");
+
+		let families = registry.gather();
+		let spawned = families
+			.iter()
+			.find(|f| f.get_name() == "cumulus_tasks_spawned_total")
+			.expect("Spawn counter is registered");
+		assert_eq!(1.0, spawned.get_metric()[0].get_counter().get_value());
+
+		let polls = families
+			.iter()
+			.find(|f| f.get_name() == "cumulus_task_poll_duration")
+			.expect("Poll duration is registered");
+		let labels = polls.get_metric()[0].get_label();
+		assert!(labels.iter().any(|l| l.get_value() == COLLATOR_TASKS));
+		assert!(labels.iter().any(|l| l.get_value() == "test-task"));
+	}
+}
@@ -0,0 +1,177 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Dry-run a runtime upgrade against blocks that were already produced.
+//!
+//! Before enacting a new runtime on-chain, it is useful to know that the `validate_block`
+//! exported by the new validation code still accepts the blocks that were built with the old
+//! runtime. [`dry_run_upgrade`] rebuilds the proof-of-validity for the last `n` blocks of the
+//! local chain and feeds each of them through the `validate_block` of the given code.
+
+use cumulus_primitives::{versioned::decode_validation_data, well_known_keys};
+use cumulus_runtime::ParachainBlockData;
+
+use sc_client_api::{Backend as BackendT, BlockBackend, StorageProvider};
+use sc_executor::WasmExecutor;
+use sp_api::{ApiExt, Core, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_core::storage::StorageKey;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, Header as HeaderT, NumberFor, Zero},
+};
+
+use codec::Encode;
+
+use log::{debug, warn};
+
+use crate::{pre_validation::execute_validate_block, validation_code_check::ValidationCodeChecker};
+
+/// A block that was rejected by the validation code under test.
+#[derive(Debug)]
+pub struct Divergence<Block: BlockT> {
+	/// The hash of the rejected block.
+	pub hash: Block::Hash,
+	/// The number of the rejected block.
+	pub number: NumberFor<Block>,
+	/// Why the block was rejected.
+	pub reason: String,
+}
+
+/// Re-execute the last `count` blocks of the local chain through the `validate_block` of the given
+/// `validation_code`.
+///
+/// Returns every block that was rejected or for that the new validation code returned a different
+/// head data than the one we have stored locally. An empty list means that the new validation code
+/// accepts all checked blocks.
+pub fn dry_run_upgrade<Block, Client, Backend>(
+	client: &Client,
+	validation_code: &[u8],
+	count: u32,
+) -> Result<Vec<Divergence<Block>>, String>
+where
+	Block: BlockT,
+	Backend: BackendT<Block>,
+	Client: ProvideRuntimeApi<Block>
+		+ HeaderBackend<Block>
+		+ BlockBackend<Block>
+		+ StorageProvider<Block, Backend>,
+	Client::Api: Core<Block> + ApiExt<Block, StateBackend = Backend::State>,
+{
+	let executor = ValidationCodeChecker::default().executor();
+
+	let mut divergences = Vec::new();
+	let mut hash = client.info().best_hash;
+
+	for _ in 0..count {
+		let header = client
+			.header(BlockId::Hash(hash))
+			.map_err(|e| format!("Failed to get header of `{}`: {:?}", hash, e))?
+			.ok_or_else(|| format!("Header of `{}` not found", hash))?;
+
+		// The genesis block is never validated by the relay chain.
+		if header.number().is_zero() {
+			break;
+		}
+
+		let number = *header.number();
+		let parent_hash = *header.parent_hash();
+
+		debug!(
+			target: "cumulus-collator",
+			"Dry-run `validate_block` for block #{} (`{}`).",
+			number,
+			hash,
+		);
+
+		if let Err(reason) = dry_run_block(client, &executor, validation_code, header) {
+			warn!(
+				target: "cumulus-collator",
+				"New validation code rejects block #{} (`{}`): {}",
+				number,
+				hash,
+				reason,
+			);
+
+			divergences.push(Divergence {
+				hash,
+				number,
+				reason,
+			});
+		}
+
+		hash = parent_hash;
+	}
+
+	Ok(divergences)
+}
+
+/// Rebuild the PoV of the block with the given `header` and validate it with `validation_code`.
+fn dry_run_block<Block, Client, Backend>(
+	client: &Client,
+	executor: &WasmExecutor,
+	validation_code: &[u8],
+	header: Block::Header,
+) -> Result<(), String>
+where
+	Block: BlockT,
+	Backend: BackendT<Block>,
+	Client: ProvideRuntimeApi<Block>
+		+ HeaderBackend<Block>
+		+ BlockBackend<Block>
+		+ StorageProvider<Block, Backend>,
+	Client::Api: Core<Block> + ApiExt<Block, StateBackend = Backend::State>,
+{
+	let block_id = BlockId::Hash(header.hash());
+	let parent_id = BlockId::Hash(*header.parent_hash());
+
+	let extrinsics = client
+		.block_body(&block_id)
+		.map_err(|e| format!("Failed to get block body: {:?}", e))?
+		.ok_or_else(|| "Block body not found".to_string())?;
+
+	let validation_data = client
+		.storage(&block_id, &StorageKey(well_known_keys::VALIDATION_DATA.to_vec()))
+		.map_err(|e| format!("Failed to read the validation data: {:?}", e))?
+		.ok_or_else(|| "Validation data not found in the block state".to_string())
+		.and_then(|d| decode_validation_data(&d.0).map_err(|e| e.to_string()))?;
+
+	// Re-execute the block to record the storage proof.
+	let mut runtime_api = client.runtime_api();
+	runtime_api.record_proof();
+	runtime_api
+		.execute_block(&parent_id, Block::new(header.clone(), extrinsics.clone()))
+		.map_err(|e| format!("Failed to re-execute the block: {:?}", e))?;
+	let storage_proof = runtime_api
+		.extract_proof()
+		.ok_or_else(|| "Failed to extract the storage proof".to_string())?;
+
+	let expected_head = header.encode();
+	let block_data = ParachainBlockData::<Block>::new(header, extrinsics, storage_proof);
+
+	let result = execute_validate_block(
+		executor,
+		validation_code,
+		&block_data,
+		&validation_data.persisted,
+	)?;
+
+	if result.head_data.0 != expected_head {
+		return Err("Head data returned by `validate_block` does not match the local header".into());
+	}
+
+	Ok(())
+}
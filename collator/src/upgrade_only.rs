@@ -0,0 +1,165 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Build a block that only carries a runtime upgrade.
+//!
+//! The code of a runtime upgrade is submitted to the relay chain together with the PoV of the
+//! block that scheduled it. If the code and the PoV together exceed the maximum PoV size, the
+//! candidate can never be backed and every block that includes the upgrade fails the same way.
+//! In this case the collator replaces the proposed block with one that only contains the
+//! inherents and the extrinsic carrying the new code, built by an [`UpgradeOnlyBuilder`]. The
+//! dropped transactions stay in the pool and are included in the next block.
+
+use sc_block_builder::{BlockBuilderApi, BlockBuilderProvider};
+use sc_client_api::backend::StateBackendFor;
+use sp_api::{ApiExt, ProvideRuntimeApi};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, Extrinsic as ExtrinsicT},
+};
+use sp_state_machine::StorageProof;
+
+use codec::Encode;
+use cumulus_primitives::well_known_keys;
+
+use std::{marker::PhantomData, sync::Arc};
+
+/// Builds a block with exactly the given extrinsics.
+pub trait UpgradeOnlyBuilder<Block: BlockT>: Send + Sync {
+	/// Build a block on top of `parent` that contains the given `extrinsics` in this order.
+	///
+	/// Returns the block and the storage proof of its execution.
+	fn build(
+		&self,
+		parent: Block::Hash,
+		extrinsics: Vec<Block::Extrinsic>,
+	) -> Result<(Block, StorageProof), String>;
+}
+
+/// An [`UpgradeOnlyBuilder`] that builds the block with the block builder of the client.
+pub struct ClientUpgradeOnlyBuilder<Client, Backend> {
+	client: Arc<Client>,
+	_marker: PhantomData<fn() -> Backend>,
+}
+
+impl<Client, Backend> ClientUpgradeOnlyBuilder<Client, Backend> {
+	/// Create a new instance that builds blocks with `client`.
+	pub fn new(client: Arc<Client>) -> Self {
+		Self {
+			client,
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<Block, Client, Backend> UpgradeOnlyBuilder<Block> for ClientUpgradeOnlyBuilder<Client, Backend>
+where
+	Block: BlockT,
+	Backend: sc_client_api::Backend<Block>,
+	Client: BlockBuilderProvider<Backend, Block, Client> + ProvideRuntimeApi<Block> + Send + Sync,
+	Client::Api: BlockBuilderApi<Block, Error = sp_blockchain::Error>
+		+ ApiExt<Block, StateBackend = StateBackendFor<Backend, Block>>,
+{
+	fn build(
+		&self,
+		parent: Block::Hash,
+		extrinsics: Vec<Block::Extrinsic>,
+	) -> Result<(Block, StorageProof), String> {
+		let mut builder = self
+			.client
+			.new_block_at(&BlockId::Hash(parent), Default::default(), true)
+			.map_err(|e| format!("{:?}", e))?;
+
+		for extrinsic in extrinsics {
+			builder.push(extrinsic).map_err(|e| format!("{:?}", e))?;
+		}
+
+		let (block, _, proof) = builder.build().map_err(|e| format!("{:?}", e))?.into_inner();
+		let proof = proof.ok_or_else(|| "The block builder did not record a proof".to_string())?;
+
+		Ok((block, proof))
+	}
+}
+
+/// Returns the new validation code that is set by the given storage changes.
+pub(crate) fn new_validation_code(changes: &[(Vec<u8>, Option<Vec<u8>>)]) -> Option<&[u8]> {
+	changes
+		.iter()
+		.rev()
+		.find(|(key, _)| key.as_slice() == well_known_keys::NEW_VALIDATION_CODE)
+		.and_then(|(_, value)| value.as_deref())
+}
+
+/// Returns the extrinsics of a block that only carries the upgrade to `code`.
+///
+/// These are the unsigned extrinsics, i.e. the inherents, and the extrinsics that contain the
+/// new `code`.
+pub(crate) fn upgrade_only_extrinsics<E: ExtrinsicT + Encode + Clone>(
+	extrinsics: &[E],
+	code: &[u8],
+) -> Vec<E> {
+	extrinsics
+		.iter()
+		.filter(|e| {
+			e.is_signed() != Some(true) || e.using_encoded(|encoded| contains(encoded, code))
+		})
+		.cloned()
+		.collect()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+	needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sp_runtime::testing::TestXt;
+
+	fn extrinsic(signed: bool, call: Vec<u8>) -> TestXt<Vec<u8>, ()> {
+		TestXt::new(call, if signed { Some((1, ())) } else { None })
+	}
+
+	#[test]
+	fn keeps_inherents_and_the_upgrade() {
+		let code = vec![0, 97, 115, 109, 1, 2, 3];
+		let mut upgrade = vec![4, 5];
+		upgrade.extend(&code);
+
+		let extrinsics = vec![
+			extrinsic(false, vec![1]),
+			extrinsic(true, vec![2]),
+			extrinsic(true, upgrade.clone()),
+			extrinsic(true, vec![3]),
+		];
+
+		let kept = upgrade_only_extrinsics(&extrinsics, &code);
+
+		assert_eq!(vec![extrinsic(false, vec![1]), extrinsic(true, upgrade)], kept);
+	}
+
+	#[test]
+	fn finds_the_new_validation_code() {
+		let changes = vec![
+			(b"other".to_vec(), Some(vec![1])),
+			(well_known_keys::NEW_VALIDATION_CODE.to_vec(), Some(vec![2, 3])),
+		];
+
+		assert_eq!(Some(&[2, 3][..]), new_validation_code(&changes));
+		assert!(new_validation_code(&changes[..1]).is_none());
+	}
+}
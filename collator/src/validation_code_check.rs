@@ -0,0 +1,210 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Check validation code before it is registered on the relay chain.
+//!
+//! Validators execute the `validate_block` of a parachain with a fixed set of host functions and
+//! executor settings. A runtime that imports a host function the validators don't provide can
+//! never be validated. The [`ValidationCodeChecker`] finds such problems up front, e.g. in CI,
+//! and provides an executor with the same pinned settings for running `validate_block` locally.
+
+use sc_executor::{
+	sp_wasm_interface::{Function, HostFunctions},
+	WasmExecutionMethod, WasmExecutor,
+};
+
+use parity_wasm::elements::{External, Internal, Module};
+
+use std::fmt;
+
+/// The number of heap pages the validation function is executed with.
+pub const DEFAULT_HEAP_PAGES: u64 = 1024;
+
+/// The name of the function every validation code needs to export.
+const VALIDATE_BLOCK: &str = "validate_block";
+
+/// A problem with validation code found by the [`ValidationCodeChecker`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationCodeIssue {
+	/// The code is not a valid wasm module.
+	InvalidWasm(String),
+	/// The code is bigger than the configured maximum.
+	TooLarge { size: usize, max: usize },
+	/// The code does not export the `validate_block` function.
+	MissingValidateBlock,
+	/// The code imports a host function that is not available to validators.
+	DisallowedHostFunction(String),
+}
+
+impl fmt::Display for ValidationCodeIssue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ValidationCodeIssue::InvalidWasm(e) => write!(f, "Invalid wasm: {}", e),
+			ValidationCodeIssue::TooLarge { size, max } => {
+				write!(f, "Code size of {} bytes exceeds the maximum of {} bytes", size, max)
+			}
+			ValidationCodeIssue::MissingValidateBlock => {
+				write!(f, "`{}` is not exported", VALIDATE_BLOCK)
+			}
+			ValidationCodeIssue::DisallowedHostFunction(name) => {
+				write!(f, "Imports host function `{}` that is not available to validators", name)
+			}
+		}
+	}
+}
+
+/// Checks validation code against the environment validators execute it in.
+///
+/// By default all host functions of [`sp_io::SubstrateHostFunctions`] are allowed, as these are
+/// provided by the relay chain validators.
+#[derive(Clone)]
+pub struct ValidationCodeChecker {
+	heap_pages: u64,
+	max_code_size: Option<usize>,
+	host_functions: Vec<&'static dyn Function>,
+}
+
+impl Default for ValidationCodeChecker {
+	fn default() -> Self {
+		Self::empty().allow_host_functions::<sp_io::SubstrateHostFunctions>()
+	}
+}
+
+impl ValidationCodeChecker {
+	/// Create a checker that allows no host functions at all.
+	pub fn empty() -> Self {
+		Self {
+			heap_pages: DEFAULT_HEAP_PAGES,
+			max_code_size: None,
+			host_functions: Vec::new(),
+		}
+	}
+
+	/// Set the number of heap pages used by the [`executor`](Self::executor).
+	pub fn heap_pages(mut self, heap_pages: u64) -> Self {
+		self.heap_pages = heap_pages;
+		self
+	}
+
+	/// Reject code that is bigger than `max` bytes.
+	pub fn max_code_size(mut self, max: usize) -> Self {
+		self.max_code_size = Some(max);
+		self
+	}
+
+	/// Allow all host functions of `H`.
+	///
+	/// The host functions are also provided by the [`executor`](Self::executor).
+	pub fn allow_host_functions<H: HostFunctions>(mut self) -> Self {
+		self.host_functions.extend(H::host_functions());
+		self
+	}
+
+	/// Check the given validation `code`.
+	///
+	/// Returns all found issues, an empty list means that the code passed all checks.
+	pub fn check(&self, code: &[u8]) -> Vec<ValidationCodeIssue> {
+		let mut issues = Vec::new();
+
+		if let Some(max) = self.max_code_size {
+			if code.len() > max {
+				issues.push(ValidationCodeIssue::TooLarge {
+					size: code.len(),
+					max,
+				});
+			}
+		}
+
+		let module = match parity_wasm::deserialize_buffer::<Module>(code) {
+			Ok(module) => module,
+			Err(e) => {
+				issues.push(ValidationCodeIssue::InvalidWasm(e.to_string()));
+				return issues;
+			}
+		};
+
+		let exports_validate_block = module.export_section().map_or(false, |s| {
+			s.entries().iter().any(|e| {
+				e.field() == VALIDATE_BLOCK && matches!(e.internal(), Internal::Function(_))
+			})
+		});
+		if !exports_validate_block {
+			issues.push(ValidationCodeIssue::MissingValidateBlock);
+		}
+
+		if let Some(imports) = module.import_section() {
+			issues.extend(
+				imports
+					.entries()
+					.iter()
+					.filter(|e| matches!(e.external(), External::Function(_)))
+					.filter(|e| !self.host_functions.iter().any(|f| f.name() == e.field()))
+					.map(|e| ValidationCodeIssue::DisallowedHostFunction(e.field().to_string())),
+			);
+		}
+
+		issues
+	}
+
+	/// Returns an executor with the settings validators use to execute `validate_block`.
+	pub fn executor(&self) -> WasmExecutor {
+		WasmExecutor::new(
+			WasmExecutionMethod::Interpreted,
+			Some(self.heap_pages),
+			self.host_functions.clone(),
+			1,
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_runtime_passes_checks() {
+		let code = cumulus_test_runtime::WASM_BINARY
+			.expect("You need to build the WASM binaries to run the tests!");
+
+		assert!(ValidationCodeChecker::default().check(code).is_empty());
+	}
+
+	#[test]
+	fn detects_disallowed_host_functions() {
+		let code = cumulus_test_runtime::WASM_BINARY
+			.expect("You need to build the WASM binaries to run the tests!");
+
+		let issues = ValidationCodeChecker::empty().check(code);
+
+		assert!(!issues.is_empty());
+		assert!(issues
+			.iter()
+			.all(|i| matches!(i, ValidationCodeIssue::DisallowedHostFunction(_))));
+	}
+
+	#[test]
+	fn detects_invalid_code() {
+		let issues = ValidationCodeChecker::default()
+			.max_code_size(2)
+			.check(&[1, 2, 3]);
+
+		assert_eq!(
+			issues[0],
+			ValidationCodeIssue::TooLarge { size: 3, max: 2 },
+		);
+		assert!(matches!(issues[1], ValidationCodeIssue::InvalidWasm(_)));
+	}
+}
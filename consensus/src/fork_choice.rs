@@ -0,0 +1,39 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+use sp_consensus::ForkChoiceStrategy;
+use sp_runtime::traits::Block as BlockT;
+
+/// Decides if a block that was built by this node becomes the new best block.
+///
+/// By default the best block is only changed when a block is included in the relay chain, see
+/// [`RelayChainForkChoice`]. Chains with their own local consensus, e.g. their own finality
+/// gadget, can make their own blocks the best block immediately. Blocks from other nodes always
+/// follow the inclusion on the relay chain.
+pub trait ParachainForkChoice<Block: BlockT>: Send + Sync {
+	/// Returns the fork choice strategy for the own block with the given `header`.
+	fn fork_choice(&self, header: &Block::Header) -> ForkChoiceStrategy;
+}
+
+/// Leaves the choice of the best block to the relay chain.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RelayChainForkChoice;
+
+impl<Block: BlockT> ParachainForkChoice<Block> for RelayChainForkChoice {
+	fn fork_choice(&self, _: &Block::Header) -> ForkChoiceStrategy {
+		ForkChoiceStrategy::Custom(false)
+	}
+}
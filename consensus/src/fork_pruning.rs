@@ -0,0 +1,208 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Report the forks that were displaced by a finalized parachain block.
+//!
+//! The parachain block finalized by the relay chain is followed by
+//! [`follow_polkadot`](crate::follow_polkadot). Every leaf that is not a descendant of the
+//! finalized block can never become the best block again. The client prunes such forks as part
+//! of the finalization: the state of their blocks is discarded when the finalized chain is
+//! canonicalized and their leaves are dropped once the finalized number passes them.
+//!
+//! The displaced forks, back to the last block they share with the finalized chain, are
+//! reported in the logs and the metrics.
+
+use sc_client_api::{Backend, BlockchainEvents};
+use sp_blockchain::{tree_route, Backend as BlockchainBackend, Result as ClientResult};
+use sp_runtime::traits::Block as BlockT;
+use substrate_prometheus_endpoint::{
+	register, Counter, Histogram, HistogramOpts, PrometheusError, Registry, U64,
+};
+
+use futures::{future, Future, StreamExt};
+use log::{debug, warn};
+
+use std::{collections::HashSet, sync::Arc};
+
+/// A fork that was displaced by the finalized block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplacedFork<Hash> {
+	/// The leaf of the fork.
+	pub leaf: Hash,
+	/// The last block the fork shares with the finalized chain.
+	pub common_ancestor: Hash,
+	/// The blocks of the fork, from the leaf down to the child of the common ancestor.
+	pub blocks: Vec<Hash>,
+}
+
+/// Returns the forks of `blockchain` that are displaced by the `finalized` block.
+pub fn displaced_forks<Block, B>(
+	blockchain: &B,
+	finalized: Block::Hash,
+) -> ClientResult<Vec<DisplacedFork<Block::Hash>>>
+where
+	Block: BlockT,
+	B: BlockchainBackend<Block>,
+{
+	let mut forks = Vec::new();
+
+	for leaf in blockchain.leaves()? {
+		let route = tree_route(blockchain, leaf, finalized)?;
+
+		// The finalized block is an ancestor of the leaf, or the leaf itself.
+		if route.common_block().hash == finalized {
+			continue;
+		}
+
+		forks.push(DisplacedFork {
+			leaf,
+			common_ancestor: route.common_block().hash,
+			blocks: route.retracted().iter().map(|b| b.hash).collect(),
+		});
+	}
+
+	Ok(forks)
+}
+
+/// The metrics of the displaced forks.
+#[derive(Clone, Default)]
+pub struct Metrics(Option<MetricsInner>);
+
+#[derive(Clone)]
+struct MetricsInner {
+	displaced_forks: Counter<U64>,
+	displaced_fork_length: Histogram,
+}
+
+impl Metrics {
+	/// Register the metrics at the given `registry`.
+	pub fn register(registry: Option<&Registry>) -> Result<Self, PrometheusError> {
+		let registry = match registry {
+			Some(registry) => registry,
+			None => return Ok(Self(None)),
+		};
+
+		Ok(Self(Some(MetricsInner {
+			displaced_forks: register(
+				Counter::new(
+					"cumulus_displaced_forks_total",
+					"Number of forks displaced by a parachain block finalized by the relay chain",
+				)?,
+				registry,
+			)?,
+			displaced_fork_length: register(
+				Histogram::with_opts(
+					HistogramOpts::new(
+						"cumulus_displaced_fork_length",
+						"Number of blocks of the displaced forks",
+					)
+					.buckets(vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0]),
+				)?,
+				registry,
+			)?,
+		})))
+	}
+
+	fn note_displaced(&self, length: usize) {
+		if let Some(ref inner) = self.0 {
+			inner.displaced_forks.inc();
+			inner.displaced_fork_length.observe(length as f64);
+		}
+	}
+}
+
+/// Reports the displaced forks of every finalized block.
+///
+/// Every displaced fork is only reported once, the first time it is seen.
+pub struct DisplacedForks<Block: BlockT, B> {
+	backend: Arc<B>,
+	metrics: Metrics,
+	handled: HashSet<Block::Hash>,
+}
+
+impl<Block, B> DisplacedForks<Block, B>
+where
+	Block: BlockT,
+	B: Backend<Block>,
+{
+	/// Create a new instance that reports the displaced forks of `backend`.
+	pub fn new(backend: Arc<B>, metrics: Metrics) -> Self {
+		Self {
+			backend,
+			metrics,
+			handled: HashSet::new(),
+		}
+	}
+
+	/// Report the forks displaced by the `finalized` block.
+	///
+	/// Returns the forks that were not reported before.
+	pub fn on_finalized(&mut self, finalized: Block::Hash) -> Vec<DisplacedFork<Block::Hash>> {
+		let forks = match displaced_forks(self.backend.blockchain(), finalized) {
+			Ok(forks) => forks,
+			Err(e) => {
+				warn!(
+					target: "cumulus-consensus",
+					"Failed to find the forks displaced by `{:?}`: {:?}", finalized, e,
+				);
+				return Vec::new();
+			}
+		};
+
+		// Forget about the forks that are gone, so the set does not grow forever.
+		self.handled.retain(|leaf| forks.iter().any(|f| f.leaf == *leaf));
+
+		let new_forks = forks
+			.into_iter()
+			.filter(|f| self.handled.insert(f.leaf))
+			.collect::<Vec<_>>();
+
+		for fork in &new_forks {
+			self.metrics.note_displaced(fork.blocks.len());
+
+			debug!(
+				target: "cumulus-consensus",
+				"Fork `{:?}` with {} blocks is displaced by the finalized block `{:?}`.",
+				fork.leaf,
+				fork.blocks.len(),
+				finalized,
+			);
+		}
+
+		new_forks
+	}
+}
+
+/// Report the forks displaced by every block that is finalized in `client`.
+///
+/// The parachain blocks are finalized by [`follow_polkadot`](crate::follow_polkadot), when the
+/// relay chain finalized them.
+pub fn report_displaced_forks<Block, Client, B>(
+	client: Arc<Client>,
+	mut displaced_forks: DisplacedForks<Block, B>,
+) -> impl Future<Output = ()> + Send
+where
+	Block: BlockT,
+	Client: BlockchainEvents<Block>,
+	B: Backend<Block>,
+{
+	client
+		.finality_notification_stream()
+		.for_each(move |notification| {
+			displaced_forks.on_finalized(notification.hash);
+			future::ready(())
+		})
+}
@@ -32,12 +32,32 @@ use sp_runtime::{
 };
 
 /// A verifier that just checks the inherents.
-struct Verifier<Client, Block> {
+///
+/// This is the verifier of the [`import_queue`], for importing blocks that are fetched outside
+/// of the sync with the same checks.
+pub struct Verifier<Client, Block> {
 	client: Arc<Client>,
 	inherent_data_providers: InherentDataProviders,
 	_marker: PhantomData<Block>,
 }
 
+impl<Client, Block> Verifier<Client, Block> {
+	/// Create a new verifier that checks the inherents with the given `inherent_data_providers`.
+	pub fn new(client: Arc<Client>, inherent_data_providers: InherentDataProviders) -> Self {
+		Self {
+			client,
+			inherent_data_providers,
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<Client, Block> Clone for Verifier<Client, Block> {
+	fn clone(&self) -> Self {
+		Self::new(self.client.clone(), self.inherent_data_providers.clone())
+	}
+}
+
 impl<Client, Block> VerifierT<Block> for Verifier<Client, Block>
 where
 	Block: BlockT,
@@ -115,11 +135,7 @@ where
 	Client: ProvideRuntimeApi<Block> + Send + Sync + 'static,
 	<Client as ProvideRuntimeApi<Block>>::Api: BlockBuilderApi<Block>,
 {
-	let verifier = Verifier {
-		client,
-		inherent_data_providers,
-		_marker: PhantomData,
-	};
+	let verifier = Verifier::new(client, inherent_data_providers);
 
 	Ok(BasicQueue::new(
 		verifier,
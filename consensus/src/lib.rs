@@ -15,7 +15,6 @@
 // along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
 
 use sc_client_api::{Backend, BlockBackend, Finalizer, UsageProvider};
-use sp_api::ProvideRuntimeApi;
 use sp_blockchain::{Error as ClientError, Result as ClientResult};
 use sp_consensus::{
 	BlockImport, BlockImportParams, BlockOrigin, BlockStatus, Error as ConsensusError,
@@ -26,9 +25,7 @@ use sp_runtime::{
 	traits::{Block as BlockT, Header as HeaderT},
 };
 
-use polkadot_primitives::v1::{
-	Block as PBlock, Hash as PHash, Id as ParaId, OccupiedCoreAssumption, ParachainHost,
-};
+use polkadot_primitives::v1::{Block as PBlock, Hash as PHash, Id as ParaId};
 
 use codec::Decode;
 use futures::{future, Future, FutureExt, Stream, StreamExt};
@@ -36,7 +33,18 @@ use log::{error, trace, warn};
 
 use std::{marker::PhantomData, sync::Arc};
 
+mod fork_choice;
+pub mod fork_pruning;
 pub mod import_queue;
+pub mod new_best;
+pub mod relay_chain_cache;
+#[cfg(test)]
+mod tests;
+
+pub use fork_choice::{ParachainForkChoice, RelayChainForkChoice};
+pub use fork_pruning::{report_displaced_forks, DisplacedForks};
+pub use new_best::NewBestObserver;
+pub use relay_chain_cache::{CachedPolkadotClient, RelayChainCache};
 
 /// Errors that can occur while following the polkadot relay-chain.
 #[derive(Debug)]
@@ -57,6 +65,8 @@ pub struct HeadUpdate {
 
 /// Helper for the Polkadot client. This is expected to be a lightweight handle
 /// like an `Arc`.
+///
+/// Implemented for the client of a Polkadot node by [`CachedPolkadotClient`].
 pub trait PolkadotClient: Clone + 'static {
 	/// The error type for interacting with the Polkadot client.
 	type Error: std::fmt::Debug + Send;
@@ -100,11 +110,25 @@ where
 }
 
 /// Spawns a future that follows the Polkadot relay chain for the given parachain.
+///
+/// The relay chain is authoritative for the parachain:
+///
+/// - Every new best parachain head of the relay chain becomes the best block, even if it is on
+///   another fork or lower than the current best block. New best blocks are announced without a
+///   justification. Heads that are already best are skipped.
+/// - Every finalized parachain head of the relay chain is finalized. Heads that conflict with
+///   the already finalized block or that are ancestors of it are ignored.
+///
+/// Heads that are not known locally or that can not be decoded are ignored, the block is set as
+/// best or finalized once the relay chain reports a head that is known.
+///
+/// The `new_best_observer` is notified after a head was set as best block.
 pub fn follow_polkadot<L, P, Block, B>(
 	para_id: ParaId,
 	local: Arc<L>,
 	polkadot: P,
-	announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	announce_block: Arc<dyn Fn(Block::Hash) + Send + Sync>,
+	new_best_observer: Option<Arc<dyn NewBestObserver<Block>>>,
 ) -> ClientResult<impl Future<Output = ()> + Send + Unpin>
 where
 	Block: BlockT,
@@ -148,7 +172,7 @@ where
 
 	Ok(future::select(
 		follow_finalized,
-		follow_new_best(para_id, local, polkadot, announce_block)?,
+		follow_new_best(para_id, local, polkadot, announce_block, new_best_observer)?,
 	)
 	.map(|_| ()))
 }
@@ -158,7 +182,8 @@ fn follow_new_best<L, P, Block, B>(
 	para_id: ParaId,
 	local: Arc<L>,
 	polkadot: P,
-	announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	announce_block: Arc<dyn Fn(Block::Hash) + Send + Sync>,
+	new_best_observer: Option<Arc<dyn NewBestObserver<Block>>>,
 ) -> ClientResult<impl Future<Output = ()> + Send + Unpin>
 where
 	Block: BlockT,
@@ -184,8 +209,9 @@ where
 		})
 		.for_each(move |h| {
 			let hash = h.hash();
+			let previous_best = local.usage_info().chain.best_hash;
 
-			if local.usage_info().chain.best_hash == hash {
+			if previous_best == hash {
 				trace!(
 					target: "cumulus-consensus",
 					"Skipping set new best block, because block `{}` is already the best.",
@@ -197,21 +223,24 @@ where
 					Ok(BlockStatus::InChainWithState) => {
 						// Make it the new best block
 						let mut block_import_params =
-							BlockImportParams::new(BlockOrigin::ConsensusBroadcast, h);
+							BlockImportParams::new(BlockOrigin::ConsensusBroadcast, h.clone());
 						block_import_params.fork_choice = Some(ForkChoiceStrategy::Custom(true));
 						block_import_params.import_existing = true;
 
-						if let Err(err) =
-							(&*local).import_block(block_import_params, Default::default())
-						{
-							warn!(
+						match (&*local).import_block(block_import_params, Default::default()) {
+							Ok(_) => {
+								if let Some(ref observer) = new_best_observer {
+									observer.new_best(previous_best, &h);
+								}
+							}
+							Err(err) => warn!(
 								target: "cumulus-consensus",
 								"Failed to set new best block `{}` with error: {:?}",
 								hash, err
-							);
+							),
 						}
 
-						(*announce_block)(hash, Vec::new());
+						(*announce_block)(hash);
 					}
 					Ok(BlockStatus::InChainPruned) => {
 						error!(
@@ -236,58 +265,6 @@ where
 		}))
 }
 
-impl<T> PolkadotClient for Arc<T>
-where
-	T: sc_client_api::BlockchainEvents<PBlock> + ProvideRuntimeApi<PBlock> + 'static + Send + Sync,
-	<T as ProvideRuntimeApi<PBlock>>::Api: ParachainHost<PBlock, Error = ClientError>,
-{
-	type Error = ClientError;
-
-	type HeadStream = Box<dyn Stream<Item = Vec<u8>> + Send + Unpin>;
-
-	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
-		let polkadot = self.clone();
-
-		let s = self.import_notification_stream().filter_map(move |n| {
-			future::ready(if n.is_new_best {
-				polkadot
-					.parachain_head_at(&BlockId::hash(n.hash), para_id)
-					.ok()
-					.and_then(|h| h)
-			} else {
-				None
-			})
-		});
-
-		Ok(Box::new(s))
-	}
-
-	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
-		let polkadot = self.clone();
-
-		let s = self.finality_notification_stream().filter_map(move |n| {
-			future::ready(
-				polkadot
-					.parachain_head_at(&BlockId::hash(n.hash), para_id)
-					.ok()
-					.and_then(|h| h),
-			)
-		});
-
-		Ok(Box::new(s))
-	}
-
-	fn parachain_head_at(
-		&self,
-		at: &BlockId<PBlock>,
-		para_id: ParaId,
-	) -> ClientResult<Option<Vec<u8>>> {
-		self.runtime_api()
-			.persisted_validation_data(at, para_id, OccupiedCoreAssumption::TimedOut)
-			.map(|s| s.map(|s| s.parent_head.0))
-	}
-}
-
 /// Select chain implementation for parachains.
 ///
 /// The actual behavior of the implementation depends on the select chain implementation used by
@@ -0,0 +1,47 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Observe the blocks that become the best block because the relay chain included them.
+//!
+//! With the default [`RelayChainForkChoice`](crate::RelayChainForkChoice) no block, not even
+//! one built locally, is imported as new best block. The best block only changes when
+//! [`follow_polkadot`](crate::follow_polkadot) sees a block included in the relay chain and
+//! makes the already imported block the best block. This is not reported through the import
+//! notifications of the client, so a [`NewBestObserver`] is notified instead, e.g. to update
+//! the transaction pool before the next block is built.
+//!
+//! A chain that opts in to a [`ParachainForkChoice`](crate::ParachainForkChoice) that makes
+//! own blocks the best block on import gets these reported through the import notifications.
+//! The observer is then only notified for blocks that become the best block later, e.g. blocks
+//! of another collator.
+
+use sp_runtime::traits::Block as BlockT;
+
+/// Notified when the relay chain made an already imported block the new best block.
+pub trait NewBestObserver<Block: BlockT>: Send + Sync {
+	/// The block `header` replaced `previous_best` as best block.
+	fn new_best(&self, previous_best: Block::Hash, header: &Block::Header);
+}
+
+impl<Block, F> NewBestObserver<Block> for F
+where
+	Block: BlockT,
+	F: Fn(Block::Hash, &Block::Header) + Send + Sync,
+{
+	fn new_best(&self, previous_best: Block::Hash, header: &Block::Header) {
+		(self)(previous_best, header)
+	}
+}
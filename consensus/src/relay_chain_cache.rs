@@ -0,0 +1,399 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A cache of relay chain runtime api results.
+//!
+//! The collator, the consensus follower and the block announce validator all query the same
+//! runtime apis of the same relay blocks. A [`RelayChainCache`] is shared between them, so every
+//! runtime api is only executed once per relay block.
+//!
+//! The results of a relay block are dropped once a block with a higher number is finalized.
+//! Results of blocks below the finalized block are rarely requested again and are recomputed
+//! if they are.
+
+use crate::PolkadotClient;
+
+use sc_client_api::BlockchainEvents;
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::{Error as ClientError, HeaderBackend, Result as ClientResult};
+use sp_runtime::generic::BlockId;
+
+use polkadot_primitives::v1::{
+	Block as PBlock, BlockNumber as PBlockNumber, CandidateEvent, Hash as PHash,
+	Id as ParaId, InboundDownwardMessage, OccupiedCoreAssumption, ParachainHost,
+	PersistedValidationData,
+};
+
+use futures::{future, Stream, StreamExt};
+use parking_lot::Mutex;
+
+use std::{collections::HashMap, sync::Arc};
+
+/// The maximum number of relay blocks to keep results for, in case finality stalls.
+const MAX_CACHED_BLOCKS: usize = 256;
+
+/// The cached results of one relay block.
+#[derive(Default)]
+struct CachedBlock {
+	number: PBlockNumber,
+	validation_data: HashMap<(ParaId, u8), Option<PersistedValidationData>>,
+	downward_messages: HashMap<ParaId, Vec<InboundDownwardMessage>>,
+	candidate_events: Option<Vec<CandidateEvent>>,
+}
+
+#[derive(Default)]
+struct Inner {
+	finalized: PBlockNumber,
+	blocks: HashMap<PHash, CachedBlock>,
+}
+
+impl Inner {
+	/// Drop the results of all blocks below the `finalized` block.
+	fn note_finalized(&mut self, finalized: PBlockNumber) {
+		if finalized > self.finalized {
+			self.finalized = finalized;
+			self.blocks.retain(|_, b| b.number >= finalized);
+		}
+	}
+
+	/// Returns the cached results of the block `hash` with the given `number`.
+	fn block(&mut self, hash: PHash, number: PBlockNumber) -> &mut CachedBlock {
+		if !self.blocks.contains_key(&hash) && self.blocks.len() >= MAX_CACHED_BLOCKS {
+			let lowest = self
+				.blocks
+				.iter()
+				.min_by_key(|(_, b)| b.number)
+				.map(|(h, _)| *h);
+			if let Some(lowest) = lowest {
+				self.blocks.remove(&lowest);
+			}
+		}
+
+		self.blocks.entry(hash).or_insert_with(|| CachedBlock {
+			number,
+			..Default::default()
+		})
+	}
+}
+
+/// A cache of relay chain runtime api results, see the [module docs](self).
+///
+/// The cache is a cheap handle, clones share the cached results.
+#[derive(Clone, Default)]
+pub struct RelayChainCache(Arc<Mutex<Inner>>);
+
+impl RelayChainCache {
+	/// Returns the result of `f` for the block `relay_block`.
+	///
+	/// `get` returns the cached result, `set` stores the result of `f`. Results are not cached if
+	/// the block is unknown or below the finalized block.
+	fn cached<P, T: Clone>(
+		&self,
+		polkadot_client: &P,
+		relay_block: PHash,
+		get: impl FnOnce(&CachedBlock) -> Option<T>,
+		set: impl FnOnce(&mut CachedBlock, T),
+		f: impl FnOnce() -> ClientResult<T>,
+	) -> ClientResult<T>
+	where
+		P: HeaderBackend<PBlock>,
+	{
+		let finalized = polkadot_client.info().finalized_number;
+
+		{
+			let mut inner = self.0.lock();
+			inner.note_finalized(finalized);
+
+			if let Some(res) = inner.blocks.get(&relay_block).and_then(get) {
+				return Ok(res);
+			}
+		}
+
+		let res = f()?;
+
+		match polkadot_client.number(relay_block)? {
+			Some(number) if number >= finalized => {
+				set(self.0.lock().block(relay_block, number), res.clone());
+			}
+			_ => {}
+		}
+
+		Ok(res)
+	}
+
+	/// The persisted validation data of `para_id` at `relay_block`.
+	pub fn persisted_validation_data<P>(
+		&self,
+		polkadot_client: &P,
+		relay_block: PHash,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>>
+	where
+		P: ProvideRuntimeApi<PBlock> + HeaderBackend<PBlock>,
+		P::Api: ParachainHost<PBlock>,
+	{
+		let key = (
+			para_id,
+			match assumption {
+				OccupiedCoreAssumption::Included => 0,
+				OccupiedCoreAssumption::TimedOut => 1,
+				OccupiedCoreAssumption::Free => 2,
+			},
+		);
+
+		self.cached(
+			polkadot_client,
+			relay_block,
+			|b| b.validation_data.get(&key).cloned(),
+			|b, data| {
+				b.validation_data.insert(key, data);
+			},
+			|| {
+				polkadot_client
+					.runtime_api()
+					.persisted_validation_data(&BlockId::Hash(relay_block), para_id, assumption)
+					.map_err(|e| ClientError::Msg(format!("{:?}", e)))
+			},
+		)
+	}
+
+	/// The contents of the downward message queue of `para_id` at `relay_block`.
+	pub fn dmq_contents<P>(
+		&self,
+		polkadot_client: &P,
+		relay_block: PHash,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>>
+	where
+		P: ProvideRuntimeApi<PBlock> + HeaderBackend<PBlock>,
+		P::Api: ParachainHost<PBlock>,
+	{
+		self.cached(
+			polkadot_client,
+			relay_block,
+			|b| b.downward_messages.get(&para_id).cloned(),
+			|b, messages| {
+				b.downward_messages.insert(para_id, messages);
+			},
+			|| {
+				polkadot_client
+					.runtime_api()
+					.dmq_contents_with_context(
+						&BlockId::Hash(relay_block),
+						sp_core::ExecutionContext::Importing,
+						para_id,
+					)
+					.map_err(|e| ClientError::Msg(format!("{:?}", e)))
+			},
+		)
+	}
+
+	/// Returns `true` if the downward messages of `para_id` at `relay_block` are cached.
+	///
+	/// A call to [`dmq_contents`](Self::dmq_contents) is then answered without calling into the
+	/// relay chain.
+	pub fn contains_dmq_contents(&self, relay_block: PHash, para_id: ParaId) -> bool {
+		self.0
+			.lock()
+			.blocks
+			.get(&relay_block)
+			.map_or(false, |b| b.downward_messages.contains_key(&para_id))
+	}
+
+	/// The candidate events of `relay_block`.
+	pub fn candidate_events<P>(
+		&self,
+		polkadot_client: &P,
+		relay_block: PHash,
+	) -> ClientResult<Vec<CandidateEvent>>
+	where
+		P: ProvideRuntimeApi<PBlock> + HeaderBackend<PBlock>,
+		P::Api: ParachainHost<PBlock>,
+	{
+		self.cached(
+			polkadot_client,
+			relay_block,
+			|b| b.candidate_events.clone(),
+			|b, events| b.candidate_events = Some(events),
+			|| {
+				polkadot_client
+					.runtime_api()
+					.candidate_events(&BlockId::Hash(relay_block))
+					.map_err(|e| ClientError::Msg(format!("{:?}", e)))
+			},
+		)
+	}
+
+	/// Returns a [`PolkadotClient`] for `polkadot_client` that reads the parachain heads through
+	/// this cache.
+	pub fn with_client<P>(self, polkadot_client: Arc<P>) -> CachedPolkadotClient<P> {
+		CachedPolkadotClient {
+			polkadot_client,
+			cache: self,
+		}
+	}
+}
+
+/// A [`PolkadotClient`] that reads the parachain heads through a [`RelayChainCache`].
+///
+/// A client that is not shared with other users of the cache can be converted with `into()`.
+pub struct CachedPolkadotClient<P> {
+	polkadot_client: Arc<P>,
+	cache: RelayChainCache,
+}
+
+impl<P> From<Arc<P>> for CachedPolkadotClient<P> {
+	fn from(polkadot_client: Arc<P>) -> Self {
+		RelayChainCache::default().with_client(polkadot_client)
+	}
+}
+
+impl<P> Clone for CachedPolkadotClient<P> {
+	fn clone(&self) -> Self {
+		Self {
+			polkadot_client: self.polkadot_client.clone(),
+			cache: self.cache.clone(),
+		}
+	}
+}
+
+impl<P> PolkadotClient for CachedPolkadotClient<P>
+where
+	P: BlockchainEvents<PBlock>
+		+ ProvideRuntimeApi<PBlock>
+		+ HeaderBackend<PBlock>
+		+ Send
+		+ Sync
+		+ 'static,
+	P::Api: ParachainHost<PBlock>,
+{
+	type Error = ClientError;
+
+	type HeadStream = Box<dyn Stream<Item = Vec<u8>> + Send + Unpin>;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		let polkadot = self.clone();
+
+		let s = self
+			.polkadot_client
+			.import_notification_stream()
+			.filter_map(move |n| {
+				future::ready(if n.is_new_best {
+					polkadot
+						.parachain_head_at(&BlockId::hash(n.hash), para_id)
+						.ok()
+						.and_then(|h| h)
+				} else {
+					None
+				})
+			});
+
+		Ok(Box::new(s))
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		let polkadot = self.clone();
+
+		let s = self
+			.polkadot_client
+			.finality_notification_stream()
+			.filter_map(move |n| {
+				future::ready(
+					polkadot
+						.parachain_head_at(&BlockId::hash(n.hash), para_id)
+						.ok()
+						.and_then(|h| h),
+				)
+			});
+
+		Ok(Box::new(s))
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		let hash = match at {
+			BlockId::Hash(hash) => *hash,
+			BlockId::Number(number) => self
+				.polkadot_client
+				.hash(*number)?
+				.ok_or_else(|| ClientError::UnknownBlock(number.to_string()))?,
+		};
+
+		self.cache
+			.persisted_validation_data(
+				&*self.polkadot_client,
+				hash,
+				para_id,
+				OccupiedCoreAssumption::TimedOut,
+			)
+			.map(|s| s.map(|s| s.parent_head.0))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hash(n: u8) -> PHash {
+		PHash::repeat_byte(n)
+	}
+
+	#[test]
+	fn finality_drops_older_blocks() {
+		let mut inner = Inner::default();
+		inner.block(hash(1), 1).candidate_events = Some(Vec::new());
+		inner.block(hash(2), 2).candidate_events = Some(Vec::new());
+		inner.block(hash(3), 3).candidate_events = Some(Vec::new());
+
+		inner.note_finalized(2);
+
+		assert!(!inner.blocks.contains_key(&hash(1)));
+		assert!(inner.blocks.contains_key(&hash(2)));
+		assert!(inner.blocks.contains_key(&hash(3)));
+
+		// Finality never goes back.
+		inner.note_finalized(1);
+		assert_eq!(2, inner.finalized);
+	}
+
+	#[test]
+	fn drops_lowest_block_when_full() {
+		let mut inner = Inner::default();
+		for n in 0..MAX_CACHED_BLOCKS {
+			inner.block(PHash::from_low_u64_be(n as u64), n as PBlockNumber + 1);
+		}
+
+		inner.block(hash(0xff), 1_000);
+
+		assert_eq!(MAX_CACHED_BLOCKS, inner.blocks.len());
+		assert!(!inner.blocks.contains_key(&PHash::from_low_u64_be(0)));
+		assert!(inner.blocks.contains_key(&hash(0xff)));
+	}
+
+	#[test]
+	fn reports_cached_downward_messages() {
+		let cache = RelayChainCache::default();
+		cache.0.lock().block(hash(1), 1).downward_messages.insert(ParaId::from(100), Vec::new());
+
+		assert!(cache.contains_dmq_contents(hash(1), ParaId::from(100)));
+		assert!(!cache.contains_dmq_contents(hash(1), ParaId::from(200)));
+		assert!(!cache.contains_dmq_contents(hash(2), ParaId::from(100)));
+	}
+}
@@ -0,0 +1,372 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::*;
+
+use codec::Encode;
+use cumulus_test_client::{
+	generate_block_inherents,
+	runtime::{Block, Hash, Header},
+	Client, ClientBlockImportExt, DefaultTestClientBuilderExt, TestClientBuilder,
+	TestClientBuilderExt,
+};
+use futures::{
+	channel::mpsc,
+	task::{noop_waker, Context},
+};
+use parking_lot::Mutex;
+use sc_block_builder::BlockBuilderProvider;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::generic::{Digest, DigestItem};
+use std::pin::Pin;
+
+/// A relay chain that yields the parachain heads it is told to.
+///
+/// The best and finalized heads are two independent streams, like on a real relay chain. The
+/// tests push one event at a time and then drive the follower until it is idle, which makes the
+/// order in which the events are processed deterministic.
+#[derive(Clone)]
+struct MockRelayChain {
+	best: Arc<Mutex<Option<mpsc::UnboundedReceiver<Vec<u8>>>>>,
+	finalized: Arc<Mutex<Option<mpsc::UnboundedReceiver<Vec<u8>>>>>,
+}
+
+/// The sending side of the [`MockRelayChain`].
+struct RelayChainScript {
+	best: mpsc::UnboundedSender<Vec<u8>>,
+	finalized: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl RelayChainScript {
+	/// The relay chain has a new best block that includes `head`.
+	fn new_best(&self, head: &Header) {
+		self.best.unbounded_send(head.encode()).unwrap();
+	}
+
+	/// The relay chain finalized a block that includes `head`.
+	fn finalized(&self, head: &Header) {
+		self.finalized.unbounded_send(head.encode()).unwrap();
+	}
+}
+
+impl MockRelayChain {
+	fn new() -> (Self, RelayChainScript) {
+		let (best_tx, best_rx) = mpsc::unbounded();
+		let (finalized_tx, finalized_rx) = mpsc::unbounded();
+
+		(
+			Self {
+				best: Arc::new(Mutex::new(Some(best_rx))),
+				finalized: Arc::new(Mutex::new(Some(finalized_rx))),
+			},
+			RelayChainScript {
+				best: best_tx,
+				finalized: finalized_tx,
+			},
+		)
+	}
+}
+
+impl PolkadotClient for MockRelayChain {
+	type Error = ClientError;
+
+	type HeadStream = mpsc::UnboundedReceiver<Vec<u8>>;
+
+	fn new_best_heads(&self, _: ParaId) -> ClientResult<Self::HeadStream> {
+		Ok(self.best.lock().take().expect("Only called once"))
+	}
+
+	fn finalized_heads(&self, _: ParaId) -> ClientResult<Self::HeadStream> {
+		Ok(self.finalized.lock().take().expect("Only called once"))
+	}
+
+	fn parachain_head_at(&self, _: &BlockId<PBlock>, _: ParaId) -> ClientResult<Option<Vec<u8>>> {
+		unimplemented!("Not required in tests")
+	}
+}
+
+/// Build and import a block on top of `parent`.
+///
+/// `fork` is put into the digest, to build different blocks on the same parent.
+fn build_and_import_block(client: &mut Client, parent: Hash, fork: u8) -> Header {
+	let mut builder = client
+		.new_block_at(
+			&BlockId::Hash(parent),
+			Digest {
+				logs: vec![DigestItem::Other(vec![fork])],
+			},
+			false,
+		)
+		.expect("Initializes new block");
+
+	generate_block_inherents(client, None)
+		.into_iter()
+		.for_each(|e| builder.push(e).expect("Pushes an inherent"));
+
+	let block = builder.build().expect("Creates block").block;
+	let header = block.header().clone();
+
+	client
+		.import(BlockOrigin::Own, block)
+		.expect("Imports the block");
+
+	header
+}
+
+/// The follower and everything that is required to drive it.
+struct Follower {
+	client: Arc<Client>,
+	relay_chain: RelayChainScript,
+	announced: Arc<Mutex<Vec<Hash>>>,
+	/// The previous and the new best block of every new best block set by the follower.
+	new_best: Arc<Mutex<Vec<(Hash, Hash)>>>,
+	follow: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl Follower {
+	fn new(client: Arc<Client>) -> Self {
+		let (polkadot, relay_chain) = MockRelayChain::new();
+		let announced = Arc::new(Mutex::new(Vec::new()));
+
+		let announce_block = {
+			let announced = announced.clone();
+			Arc::new(move |hash: Hash| announced.lock().push(hash))
+		};
+
+		let new_best = Arc::new(Mutex::new(Vec::new()));
+		let new_best_observer: Arc<dyn NewBestObserver<Block>> = {
+			let new_best = new_best.clone();
+			Arc::new(move |previous: Hash, header: &Header| {
+				new_best.lock().push((previous, header.hash()))
+			})
+		};
+
+		let follow = follow_polkadot(
+			100.into(),
+			client.clone(),
+			polkadot,
+			announce_block,
+			Some(new_best_observer),
+		)
+		.unwrap();
+
+		Self {
+			client,
+			relay_chain,
+			announced,
+			new_best,
+			follow: Box::pin(follow),
+		}
+	}
+
+	/// Process all pending relay chain events.
+	fn step(&mut self) {
+		let waker = noop_waker();
+		let mut cx = Context::from_waker(&waker);
+
+		assert!(self.follow.as_mut().poll(&mut cx).is_pending());
+	}
+
+	fn best(&self) -> Hash {
+		self.client.info().best_hash
+	}
+
+	fn finalized(&self) -> Hash {
+		self.client.info().finalized_hash
+	}
+}
+
+/// Build the following chain:
+///
+/// ```text
+/// genesis - a1 - a2
+///         \
+///          b1
+/// ```
+fn build_forks() -> (Arc<Client>, Header, Header, Header) {
+	let mut client = TestClientBuilder::new().build();
+	let genesis = client.info().genesis_hash;
+
+	let a1 = build_and_import_block(&mut client, genesis, 0);
+	let a2 = build_and_import_block(&mut client, a1.hash(), 0);
+	let b1 = build_and_import_block(&mut client, genesis, 1);
+
+	assert_eq!(a2.hash(), client.info().best_hash);
+
+	(Arc::new(client), a1, a2, b1)
+}
+
+#[test]
+fn follows_the_best_head_of_the_relay_chain_across_forks() {
+	let (client, a1, a2, b1) = build_forks();
+	let mut follower = Follower::new(client);
+
+	// The relay chain switches to the fork that includes `b1`. Its head becomes the best block,
+	// even though it is lower than the current best block.
+	follower.relay_chain.new_best(&b1);
+	follower.step();
+	assert_eq!(b1.hash(), follower.best());
+
+	// And back to `a1`.
+	follower.relay_chain.new_best(&a1);
+	follower.step();
+	assert_eq!(a1.hash(), follower.best());
+
+	// The relay chain includes `a2` on top.
+	follower.relay_chain.new_best(&a2);
+	follower.step();
+	assert_eq!(a2.hash(), follower.best());
+
+	// Every new best block is announced.
+	assert_eq!(
+		vec![b1.hash(), a1.hash(), a2.hash()],
+		*follower.announced.lock(),
+	);
+
+	// And reported with the best block it replaced.
+	assert_eq!(
+		vec![
+			(a2.hash(), b1.hash()),
+			(b1.hash(), a1.hash()),
+			(a1.hash(), a2.hash()),
+		],
+		*follower.new_best.lock(),
+	);
+}
+
+#[test]
+fn does_not_announce_a_head_that_is_already_best() {
+	let (client, _, a2, _) = build_forks();
+	let mut follower = Follower::new(client);
+
+	follower.relay_chain.new_best(&a2);
+	follower.step();
+
+	assert_eq!(a2.hash(), follower.best());
+	assert!(follower.announced.lock().is_empty());
+	assert!(follower.new_best.lock().is_empty());
+}
+
+#[test]
+fn ignores_unknown_heads() {
+	let (client, a1, a2, _) = build_forks();
+	let mut follower = Follower::new(client);
+
+	let mut unknown = a1.clone();
+	unknown.set_parent_hash(Hash::repeat_byte(1));
+
+	follower.relay_chain.new_best(&unknown);
+	follower.relay_chain.finalized(&unknown);
+	follower.step();
+
+	assert_eq!(a2.hash(), follower.best());
+	assert_eq!(follower.client.info().genesis_hash, follower.finalized());
+	assert!(follower.announced.lock().is_empty());
+}
+
+#[test]
+fn ignores_undecodable_heads() {
+	let (client, _, a2, _) = build_forks();
+	let mut follower = Follower::new(client);
+
+	follower.relay_chain.best.unbounded_send(vec![1, 2, 3]).unwrap();
+	follower
+		.relay_chain
+		.finalized
+		.unbounded_send(vec![1, 2, 3])
+		.unwrap();
+	follower.step();
+
+	assert_eq!(a2.hash(), follower.best());
+	assert_eq!(follower.client.info().genesis_hash, follower.finalized());
+}
+
+#[test]
+fn finalizes_the_finalized_head_of_the_relay_chain() {
+	let (client, a1, a2, b1) = build_forks();
+	let mut follower = Follower::new(client);
+
+	follower.relay_chain.finalized(&a1);
+	follower.step();
+	assert_eq!(a1.hash(), follower.finalized());
+
+	// A conflicting head can not be finalized anymore.
+	follower.relay_chain.finalized(&b1);
+	follower.step();
+	assert_eq!(a1.hash(), follower.finalized());
+
+	follower.relay_chain.finalized(&a2);
+	follower.step();
+	assert_eq!(a2.hash(), follower.finalized());
+
+	// Finality does not go backwards.
+	follower.relay_chain.finalized(&a1);
+	follower.step();
+	assert_eq!(a2.hash(), follower.finalized());
+}
+
+#[test]
+fn finalized_and_best_events_of_the_same_step_are_both_applied() {
+	let (client, a1, a2, _) = build_forks();
+	let mut follower = Follower::new(client);
+
+	follower.relay_chain.finalized(&a1);
+	follower.relay_chain.new_best(&a1);
+	follower.step();
+
+	assert_eq!(a1.hash(), follower.finalized());
+	assert_eq!(a1.hash(), follower.best());
+
+	follower.relay_chain.new_best(&a2);
+	follower.relay_chain.finalized(&a2);
+	follower.step();
+
+	assert_eq!(a2.hash(), follower.finalized());
+	assert_eq!(a2.hash(), follower.best());
+}
+
+#[test]
+fn reports_the_forks_displaced_by_the_finalized_block() {
+	let builder = TestClientBuilder::new();
+	let backend = builder.backend();
+	let mut client = builder.build();
+	let genesis = client.info().genesis_hash;
+
+	// genesis - a1 - a2
+	//         \    \
+	//          b1   c2 - c3
+	let a1 = build_and_import_block(&mut client, genesis, 0);
+	let a2 = build_and_import_block(&mut client, a1.hash(), 0);
+	let b1 = build_and_import_block(&mut client, genesis, 1);
+	let c2 = build_and_import_block(&mut client, a1.hash(), 2);
+	let c3 = build_and_import_block(&mut client, c2.hash(), 2);
+
+	let mut displaced_forks = DisplacedForks::new(backend, Default::default());
+
+	// Finalizing `a1` only displaces `b1`.
+	let displaced = displaced_forks.on_finalized(a1.hash());
+	assert_eq!(1, displaced.len());
+	assert_eq!(b1.hash(), displaced[0].leaf);
+	assert_eq!(genesis, displaced[0].common_ancestor);
+	assert_eq!(vec![b1.hash()], displaced[0].blocks);
+
+	// Finalizing `a2` displaces the fork of `c3`, `b1` was already reported.
+	let displaced = displaced_forks.on_finalized(a2.hash());
+	assert_eq!(1, displaced.len());
+	assert_eq!(c3.hash(), displaced[0].leaf);
+	assert_eq!(a1.hash(), displaced[0].common_ancestor);
+	assert_eq!(vec![c3.hash(), c2.hash()], displaced[0].blocks);
+}
@@ -0,0 +1,198 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Collator signatures for block announcements.
+//!
+//! A collator can sign the blocks it announces with an [`AnnounceSigner`]. The SCALE encoded
+//! signature is appended to the justification of the announcement, after the
+//! [`SignedFullStatement`](polkadot_node_primitives::SignedFullStatement). Nodes that don't
+//! check signatures ignore it.
+//!
+//! Nodes with an [`AnnounceVerifier`] accept blocks with a valid signature as their new best
+//! block, while unsigned announcements are still imported, but not treated as new best. An
+//! announcement with an invalid signature is rejected.
+//!
+//! The signature scheme and the set of registered collators are chain specific. This module
+//! provides an sr25519 implementation with keys from the keystore.
+
+use sp_core::{
+	crypto::{CryptoTypePublicPair, KeyTypeId},
+	sr25519, Pair as _,
+};
+use sp_keystore::{SyncCryptoStore, SyncCryptoStorePtr};
+use sp_runtime::traits::Block as BlockT;
+
+use codec::{Decode, Encode};
+use log::debug;
+
+use std::collections::BTreeSet;
+
+/// The key type of the keys that are used to sign block announcements.
+pub const ANNOUNCE_KEY_TYPE: KeyTypeId = KeyTypeId(*b"cuan");
+
+/// Signs the blocks announced by a collator.
+pub trait AnnounceSigner<Block: BlockT>: Send + Sync {
+	/// Sign the announcement of the block with the given `hash`.
+	///
+	/// Returns `None` if the block should be announced without a signature.
+	fn sign(&self, hash: Block::Hash) -> Option<Vec<u8>>;
+}
+
+/// Verifies the signatures of block announcements.
+pub trait AnnounceVerifier<Block: BlockT>: Send + Sync {
+	/// Returns `true` if `signature` is a valid signature of a registered collator for the block
+	/// with the given `hash`.
+	fn verify(&self, hash: Block::Hash, signature: &[u8]) -> bool;
+}
+
+/// The message that is signed for the block with the given `hash`.
+fn signing_payload<Hash: Encode>(hash: &Hash) -> Vec<u8> {
+	(b"cumulus-announce", hash).encode()
+}
+
+/// An sr25519 signature of a block announcement, together with the key of the signer.
+#[derive(Encode, Decode)]
+struct Sr25519AnnounceSignature {
+	signer: sr25519::Public,
+	signature: sr25519::Signature,
+}
+
+/// Signs block announcements with an sr25519 key of type [`ANNOUNCE_KEY_TYPE`] from the
+/// keystore.
+pub struct KeystoreAnnounceSigner {
+	keystore: SyncCryptoStorePtr,
+	public: sr25519::Public,
+}
+
+impl KeystoreAnnounceSigner {
+	/// Create a new signer that uses the first announce key in the `keystore`.
+	///
+	/// Returns `None` if there is no such key.
+	pub fn new(keystore: SyncCryptoStorePtr) -> Option<Self> {
+		let public = SyncCryptoStore::sr25519_public_keys(&*keystore, ANNOUNCE_KEY_TYPE)
+			.into_iter()
+			.next()?;
+
+		Some(Self { keystore, public })
+	}
+}
+
+impl<Block: BlockT> AnnounceSigner<Block> for KeystoreAnnounceSigner {
+	fn sign(&self, hash: Block::Hash) -> Option<Vec<u8>> {
+		let signature = SyncCryptoStore::sign_with(
+			&*self.keystore,
+			ANNOUNCE_KEY_TYPE,
+			&CryptoTypePublicPair::from(self.public),
+			&signing_payload(&hash),
+		)
+		.map_err(|e| {
+			debug!(
+				target: "cumulus-network",
+				"failed to sign the announcement of block {}: {:?}",
+				hash,
+				e,
+			)
+		})
+		.ok()?;
+
+		let signature = sr25519::Signature::decode(&mut &signature[..]).ok()?;
+
+		Some(
+			Sr25519AnnounceSignature {
+				signer: self.public,
+				signature,
+			}
+			.encode(),
+		)
+	}
+}
+
+/// Verifies sr25519 signatures of block announcements against a fixed set of collators.
+pub struct Sr25519AnnounceVerifier {
+	collators: BTreeSet<sr25519::Public>,
+}
+
+impl Sr25519AnnounceVerifier {
+	/// Accept signatures of the given `collators`.
+	pub fn new(collators: impl IntoIterator<Item = sr25519::Public>) -> Self {
+		Self {
+			collators: collators.into_iter().collect(),
+		}
+	}
+}
+
+impl<Block: BlockT> AnnounceVerifier<Block> for Sr25519AnnounceVerifier {
+	fn verify(&self, hash: Block::Hash, mut signature: &[u8]) -> bool {
+		let signature = match Sr25519AnnounceSignature::decode(&mut signature) {
+			Ok(signature) => signature,
+			Err(_) => return false,
+		};
+
+		self.collators.contains(&signature.signer)
+			&& sr25519::Pair::verify(
+				&signature.signature,
+				signing_payload(&hash),
+				&signature.signer,
+			)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use cumulus_test_runtime::{Block, Hash};
+	use sp_keyring::Sr25519Keyring;
+	use sp_keystore::testing::KeyStore;
+	use std::sync::Arc;
+
+	fn signer(keyring: Sr25519Keyring) -> KeystoreAnnounceSigner {
+		let keystore: SyncCryptoStorePtr = Arc::new(KeyStore::new());
+		SyncCryptoStore::sr25519_generate_new(
+			&*keystore,
+			ANNOUNCE_KEY_TYPE,
+			Some(&keyring.to_seed()),
+		)
+		.unwrap();
+
+		KeystoreAnnounceSigner::new(keystore).expect("Keystore contains an announce key")
+	}
+
+	#[test]
+	fn signer_requires_announce_key() {
+		let keystore: SyncCryptoStorePtr = Arc::new(KeyStore::new());
+		assert!(KeystoreAnnounceSigner::new(keystore).is_none());
+	}
+
+	#[test]
+	fn verifies_signatures_of_registered_collators() {
+		let verifier = Sr25519AnnounceVerifier::new(vec![Sr25519Keyring::Alice.public()]);
+		let hash = Hash::repeat_byte(1);
+
+		let alice = AnnounceSigner::<Block>::sign(&signer(Sr25519Keyring::Alice), hash).unwrap();
+		let bob = AnnounceSigner::<Block>::sign(&signer(Sr25519Keyring::Bob), hash).unwrap();
+
+		assert!(AnnounceVerifier::<Block>::verify(&verifier, hash, &alice));
+		// Not registered.
+		assert!(!AnnounceVerifier::<Block>::verify(&verifier, hash, &bob));
+		// Signature of another block.
+		assert!(!AnnounceVerifier::<Block>::verify(
+			&verifier,
+			Hash::repeat_byte(2),
+			&alice,
+		));
+		assert!(!AnnounceVerifier::<Block>::verify(&verifier, hash, &[1, 2, 3]));
+	}
+}
@@ -0,0 +1,428 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The typed data of block announcements.
+//!
+//! The data attached to a block announcement is gossiped as raw bytes. A collator creates a
+//! [`CollationAnnouncement`] instead, which is encoded with a version its peers understand.
+//!
+//! Version 1 is the SCALE encoded [`SignedFullStatement`], optionally followed by the collator
+//! signature, and is understood by every node. Later versions start with
+//! [`VERSIONED_ANNOUNCEMENT_TAG`], which can not start a statement, followed by a
+//! [`VersionedAnnouncement`] whose index is its version.
+//!
+//! Nodes tell their peers the latest version they understand over a notifications protocol, see
+//! [`version_protocol_name`] and [`PeerVersions`]. Peers that don't, e.g. nodes of an older
+//! release, are assumed to understand version 1. A block is announced to all peers with the
+//! same data, so it is encoded with the latest version that every connected peer understands.
+
+use sc_network::{config::ProtocolId, Event, NetworkService, PeerId};
+use sp_runtime::traits::Block as BlockT;
+
+use polkadot_node_primitives::SignedFullStatement;
+
+use codec::{Decode, Encode};
+use futures::{Future, StreamExt};
+use log::trace;
+use parking_lot::Mutex;
+
+use std::{
+	collections::{HashMap, HashSet},
+	sync::Arc,
+};
+
+/// The latest version of [`CollationAnnouncement`] this node understands.
+pub const ANNOUNCEMENT_VERSION: u32 = 2;
+
+/// The version of [`CollationAnnouncement`] that every node understands.
+pub const BASE_ANNOUNCEMENT_VERSION: u32 = 1;
+
+/// The first byte of an announcement above [`BASE_ANNOUNCEMENT_VERSION`].
+///
+/// A version 1 announcement starts with the index of a [`Statement`], which is never `0`.
+///
+/// [`Statement`]: polkadot_node_primitives::Statement
+pub const VERSIONED_ANNOUNCEMENT_TAG: u8 = 0;
+
+/// The name of the notifications protocol over which nodes exchange their announcement version.
+///
+/// The protocol needs to be added to the `notifications_protocols` of the network
+/// configuration.
+pub fn version_protocol_name(protocol_id: &ProtocolId) -> String {
+	format!("/{}/cumulus/announcement-version/1", protocol_id.as_ref())
+}
+
+/// An announcement above [`BASE_ANNOUNCEMENT_VERSION`], the index is the version.
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum VersionedAnnouncement {
+	/// The statement and the optional collator signature of the candidate.
+	#[codec(index = "2")]
+	V2 {
+		statement: SignedFullStatement,
+		signature: Option<Vec<u8>>,
+	},
+}
+
+/// The data attached to the announcement of a parachain block.
+#[derive(Clone, Debug)]
+pub enum CollationAnnouncement {
+	/// No data is attached.
+	///
+	/// Nodes at the tip of the chain only accept such an announcement for blocks below the tip.
+	Empty,
+	/// The [`Statement::Seconded`](polkadot_node_primitives::Statement::Seconded) of the candidate
+	/// of the block and an optional collator signature, see
+	/// [`announce_signing`](crate::announce_signing).
+	Seconded {
+		statement: SignedFullStatement,
+		signature: Option<Vec<u8>>,
+	},
+}
+
+impl CollationAnnouncement {
+	/// The raw data of the announcement, encoded with the given `version`.
+	///
+	/// Versions below [`BASE_ANNOUNCEMENT_VERSION`] are encoded like the base version, an
+	/// [`Empty`](Self::Empty) announcement never carries data.
+	pub fn to_data(&self, version: u32) -> Vec<u8> {
+		match self {
+			Self::Empty => Vec::new(),
+			Self::Seconded {
+				statement,
+				signature,
+			} if version <= BASE_ANNOUNCEMENT_VERSION => {
+				let mut data = statement.encode();
+				if let Some(signature) = signature {
+					signature.encode_to(&mut data);
+				}
+				data
+			}
+			Self::Seconded {
+				statement,
+				signature,
+			} => {
+				let mut data = vec![VERSIONED_ANNOUNCEMENT_TAG];
+				VersionedAnnouncement::V2 {
+					statement: statement.clone(),
+					signature: signature.clone(),
+				}
+				.encode_to(&mut data);
+				data
+			}
+		}
+	}
+
+	/// Decode the raw data of an announcement of any version this node understands.
+	pub fn from_data(mut data: &[u8]) -> Result<Self, codec::Error> {
+		match data.first() {
+			None => Ok(Self::Empty),
+			Some(&VERSIONED_ANNOUNCEMENT_TAG) => {
+				match VersionedAnnouncement::decode(&mut &data[1..])? {
+					VersionedAnnouncement::V2 {
+						statement,
+						signature,
+					} => Ok(Self::Seconded {
+						statement,
+						signature,
+					}),
+				}
+			}
+			Some(_) => {
+				let statement = SignedFullStatement::decode(&mut data)?;
+				let signature = if data.is_empty() {
+					None
+				} else {
+					Some(Vec::<u8>::decode(&mut data)?)
+				};
+
+				Ok(Self::Seconded {
+					statement,
+					signature,
+				})
+			}
+		}
+	}
+}
+
+/// Announces parachain blocks.
+pub trait AnnounceBlock<Block: BlockT>: Send + Sync {
+	/// Announce the block with the given `hash` and attach the `announcement`.
+	fn announce_block(&self, hash: Block::Hash, announcement: CollationAnnouncement);
+}
+
+impl<Block, F> AnnounceBlock<Block> for F
+where
+	Block: BlockT,
+	F: Fn(Block::Hash, CollationAnnouncement) + Send + Sync,
+{
+	fn announce_block(&self, hash: Block::Hash, announcement: CollationAnnouncement) {
+		(self)(hash, announcement)
+	}
+}
+
+#[derive(Default)]
+struct Peers {
+	connected: HashSet<PeerId>,
+	versions: HashMap<PeerId, u32>,
+}
+
+/// The announcement versions of the connected peers.
+#[derive(Clone, Default)]
+pub struct PeerVersions(Arc<Mutex<Peers>>);
+
+impl PeerVersions {
+	/// Track the announcement versions of the peers of `network` and send them ours.
+	///
+	/// The returned future needs to be spawned.
+	pub fn new<Block: BlockT>(
+		network: Arc<NetworkService<Block, Block::Hash>>,
+		protocol_id: &ProtocolId,
+	) -> (Self, impl Future<Output = ()> + Send + 'static) {
+		let versions = Self::default();
+		let protocol_name = version_protocol_name(protocol_id);
+
+		let track_peers = {
+			let versions = versions.clone();
+			network
+				.event_stream("cumulus-announcement-versions")
+				.for_each(move |event| {
+					match event {
+						Event::SyncConnected { remote } => versions.note_connected(remote),
+						Event::SyncDisconnected { remote } => versions.note_disconnected(&remote),
+						Event::NotificationStreamOpened {
+							remote, protocol, ..
+						} if protocol == protocol_name => {
+							network.write_notification(
+								remote,
+								protocol,
+								ANNOUNCEMENT_VERSION.encode(),
+							);
+						}
+						Event::NotificationStreamClosed { remote, protocol }
+							if protocol == protocol_name =>
+						{
+							versions.0.lock().versions.remove(&remote);
+						}
+						Event::NotificationsReceived { remote, messages } => {
+							let messages = messages.iter().filter(|(p, _)| *p == protocol_name);
+							for (_, message) in messages {
+								match u32::decode(&mut &message[..]) {
+									Ok(version) => versions.note_version(remote.clone(), version),
+									Err(e) => trace!(
+										target: "cumulus-network",
+										"Failed to decode the announcement version of {}: {:?}",
+										remote,
+										e,
+									),
+								}
+							}
+						}
+						_ => {}
+					}
+
+					futures::future::ready(())
+				})
+		};
+
+		(versions, track_peers)
+	}
+
+	/// The latest version that this node and every connected peer understand.
+	pub fn negotiated(&self) -> u32 {
+		let peers = self.0.lock();
+		peers
+			.connected
+			.iter()
+			.map(|peer| {
+				peers
+					.versions
+					.get(peer)
+					.copied()
+					.unwrap_or(BASE_ANNOUNCEMENT_VERSION)
+			})
+			.fold(ANNOUNCEMENT_VERSION, u32::min)
+	}
+
+	fn note_connected(&self, peer: PeerId) {
+		self.0.lock().connected.insert(peer);
+	}
+
+	fn note_disconnected(&self, peer: &PeerId) {
+		self.0.lock().connected.remove(peer);
+	}
+
+	fn note_version(&self, peer: PeerId, version: u32) {
+		self.0.lock().versions.insert(peer, version);
+	}
+}
+
+/// Announces blocks with the encoded announcement, e.g. with `NetworkService::announce_block`.
+///
+/// Without [`PeerVersions`], announcements are encoded with [`BASE_ANNOUNCEMENT_VERSION`].
+pub struct EncodedAnnouncement<F> {
+	announce: F,
+	peer_versions: Option<PeerVersions>,
+}
+
+impl<F> EncodedAnnouncement<F> {
+	/// Announce blocks with `announce`, which gossips the hash and the raw data.
+	pub fn new(announce: F) -> Self {
+		Self {
+			announce,
+			peer_versions: None,
+		}
+	}
+
+	/// Encode announcements with the latest version every connected peer understands.
+	pub fn with_peer_versions(mut self, peer_versions: PeerVersions) -> Self {
+		self.peer_versions = Some(peer_versions);
+		self
+	}
+}
+
+impl<Block, F> AnnounceBlock<Block> for EncodedAnnouncement<F>
+where
+	Block: BlockT,
+	F: Fn(Block::Hash, Vec<u8>) + Send + Sync,
+{
+	fn announce_block(&self, hash: Block::Hash, announcement: CollationAnnouncement) {
+		let version = self
+			.peer_versions
+			.as_ref()
+			.map_or(BASE_ANNOUNCEMENT_VERSION, PeerVersions::negotiated);
+
+		(self.announce)(hash, announcement.to_data(version))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use polkadot_node_primitives::Statement;
+	use polkadot_primitives::v1::{CommittedCandidateReceipt, SigningContext, ValidatorId};
+	use sp_keyring::Sr25519Keyring;
+	use sp_keystore::{testing::KeyStore, SyncCryptoStore, SyncCryptoStorePtr};
+	use sp_runtime::RuntimeAppPublic;
+
+	fn seconded(signature: Option<Vec<u8>>) -> CollationAnnouncement {
+		let keystore: SyncCryptoStorePtr = Arc::new(KeyStore::new());
+		let alice = SyncCryptoStore::sr25519_generate_new(
+			&*keystore,
+			ValidatorId::ID,
+			Some(&Sr25519Keyring::Alice.to_seed()),
+		)
+		.unwrap();
+		let statement = block_on(SignedFullStatement::sign(
+			&keystore,
+			Statement::Seconded(CommittedCandidateReceipt::default()),
+			&SigningContext {
+				parent_hash: Default::default(),
+				session_index: 0,
+			},
+			0,
+			&alice.into(),
+		))
+		.expect("Signs the statement");
+
+		CollationAnnouncement::Seconded {
+			statement,
+			signature,
+		}
+	}
+
+	fn assert_round_trip(announcement: CollationAnnouncement, version: u32) {
+		let data = announcement.to_data(version);
+		let decoded = CollationAnnouncement::from_data(&data).expect("Decodes the announcement");
+
+		match (announcement, decoded) {
+			(CollationAnnouncement::Empty, CollationAnnouncement::Empty) => {}
+			(
+				CollationAnnouncement::Seconded {
+					statement,
+					signature,
+				},
+				CollationAnnouncement::Seconded {
+					statement: decoded_statement,
+					signature: decoded_signature,
+				},
+			) => {
+				assert_eq!(statement.encode(), decoded_statement.encode());
+				assert_eq!(signature, decoded_signature);
+			}
+			(_, decoded) => panic!("Decoded a different announcement: {:?}", decoded),
+		}
+	}
+
+	#[test]
+	fn announcements_round_trip() {
+		for version in &[BASE_ANNOUNCEMENT_VERSION, ANNOUNCEMENT_VERSION] {
+			assert_round_trip(CollationAnnouncement::Empty, *version);
+			assert_round_trip(seconded(None), *version);
+			assert_round_trip(seconded(Some(vec![1, 2, 3])), *version);
+		}
+	}
+
+	#[test]
+	fn empty_announcement_has_no_data() {
+		assert!(CollationAnnouncement::Empty
+			.to_data(BASE_ANNOUNCEMENT_VERSION)
+			.is_empty());
+		assert!(CollationAnnouncement::Empty
+			.to_data(ANNOUNCEMENT_VERSION)
+			.is_empty());
+	}
+
+	#[test]
+	fn version_one_does_not_start_with_the_versioned_tag() {
+		let data = seconded(None).to_data(BASE_ANNOUNCEMENT_VERSION);
+		assert_ne!(VERSIONED_ANNOUNCEMENT_TAG, data[0]);
+
+		let data = seconded(None).to_data(ANNOUNCEMENT_VERSION);
+		assert_eq!(VERSIONED_ANNOUNCEMENT_TAG, data[0]);
+	}
+
+	#[test]
+	fn rejects_truncated_versioned_announcement() {
+		let data = seconded(Some(vec![1, 2, 3])).to_data(ANNOUNCEMENT_VERSION);
+
+		assert!(CollationAnnouncement::from_data(&data[..1]).is_err());
+		assert!(CollationAnnouncement::from_data(&data[..data.len() / 2]).is_err());
+		assert!(CollationAnnouncement::from_data(&data[..data.len() - 1]).is_err());
+	}
+
+	#[test]
+	fn negotiates_the_version_every_connected_peer_understands() {
+		let versions = PeerVersions::default();
+		assert_eq!(ANNOUNCEMENT_VERSION, versions.negotiated());
+
+		let (current, old) = (PeerId::random(), PeerId::random());
+		versions.note_version(current.clone(), ANNOUNCEMENT_VERSION);
+		versions.note_connected(current.clone());
+		assert_eq!(ANNOUNCEMENT_VERSION, versions.negotiated());
+
+		// Peers that don't tell their version only understand the base version.
+		versions.note_connected(old.clone());
+		assert_eq!(BASE_ANNOUNCEMENT_VERSION, versions.negotiated());
+
+		versions.note_disconnected(&old);
+		assert_eq!(ANNOUNCEMENT_VERSION, versions.negotiated());
+
+		// Peers of a later release are served with the version of this node.
+		versions.note_version(current, ANNOUNCEMENT_VERSION + 1);
+		assert_eq!(ANNOUNCEMENT_VERSION, versions.negotiated());
+	}
+}
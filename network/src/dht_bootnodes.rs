@@ -0,0 +1,314 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Discover parachain nodes through the DHT of the relay chain.
+//!
+//! The bootnodes of a chain spec get stale over the lifetime of a parachain, while every
+//! parachain node also runs a relay chain node with a large and long-lived Kademlia DHT.
+//! Parachain nodes with public addresses publish them in the relay chain DHT, under a key
+//! derived from the para id and the genesis hash of the parachain, see [`dht_key`]. Every node
+//! looks the key up and hands the found nodes to the discovery of the parachain network, next
+//! to the bootnodes of the chain spec.
+//!
+//! The records are signed with the network key of the publishing node, so a node can only
+//! publish its own addresses. A found node is only a candidate to connect to, the peer is
+//! treated like any other peer by the parachain network. Nodes that are not found again in the
+//! next lookup round are forgotten.
+
+use sc_network::{DhtEvent, Event, Multiaddr, NetworkService, PeerId};
+use sp_runtime::traits::Block as BlockT;
+
+use polkadot_primitives::v1::{Block as PBlock, Hash as PHash, Id as ParaId};
+
+use codec::{Decode, Encode};
+use futures::{future::FutureExt, select, Future, StreamExt};
+use futures_timer::Delay;
+use libp2p::{
+	identity::{Keypair, PublicKey},
+	kad::record::Key,
+	multiaddr::Protocol,
+};
+use log::{debug, warn};
+
+use std::{collections::HashMap, convert::TryFrom, sync::Arc, time::Duration};
+
+/// How often the addresses are published and looked up again.
+pub const REPUBLISH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The maximum number of found nodes that are kept.
+const MAX_DISCOVERED_NODES: usize = 32;
+
+/// The key in the relay chain DHT under which the nodes of a parachain publish their addresses.
+pub fn dht_key(para_id: ParaId, genesis_hash: &[u8]) -> Key {
+	Key::new(&sp_core::blake2_256(
+		&(b"cumulus-bootnodes", para_id, genesis_hash).encode(),
+	))
+}
+
+/// The addresses of a node, signed with its network key.
+#[derive(Encode, Decode)]
+struct SignedAddresses {
+	/// The protobuf encoded public network key of the node.
+	public_key: Vec<u8>,
+	/// The encoded addresses of the node.
+	addresses: Vec<Vec<u8>>,
+	/// The signature of the DHT key and the `addresses`.
+	signature: Vec<u8>,
+}
+
+/// The payload that is signed by the node publishing its `addresses` under the `key`.
+fn signing_payload(key: &Key, addresses: &[Vec<u8>]) -> Vec<u8> {
+	(key.to_vec(), addresses).encode()
+}
+
+/// Encode the `addresses` of the node with the given `node_key` as DHT record under the `key`.
+fn encode_addresses(
+	node_key: &Keypair,
+	key: &Key,
+	addresses: &[Multiaddr],
+) -> Result<Vec<u8>, String> {
+	let peer_id = node_key.public().into_peer_id();
+	let addresses = addresses
+		.iter()
+		.map(|a| a.clone().with(Protocol::P2p(peer_id.clone().into())).to_vec())
+		.collect::<Vec<_>>();
+	let signature = node_key
+		.sign(&signing_payload(key, &addresses))
+		.map_err(|e| format!("{:?}", e))?;
+
+	Ok(SignedAddresses {
+		public_key: node_key.public().into_protobuf_encoding(),
+		addresses,
+		signature,
+	}
+	.encode())
+}
+
+/// Decode the node and its addresses of a DHT record under the `key`.
+///
+/// Returns `None` for records that are not correctly signed and for the records of
+/// `local_peer_id`. Only addresses that contain the peer id of the signing node are returned.
+fn decode_addresses(
+	record: &[u8],
+	key: &Key,
+	local_peer_id: &PeerId,
+) -> Option<(PeerId, Vec<Multiaddr>)> {
+	let record = SignedAddresses::decode(&mut &record[..]).ok()?;
+	let public_key = PublicKey::from_protobuf_encoding(&record.public_key).ok()?;
+
+	if !public_key.verify(&signing_payload(key, &record.addresses), &record.signature) {
+		return None;
+	}
+
+	let peer_id = public_key.into_peer_id();
+	if peer_id == *local_peer_id {
+		return None;
+	}
+
+	let own = Protocol::P2p(peer_id.clone().into());
+	let addresses = record
+		.addresses
+		.into_iter()
+		.filter_map(|a| Multiaddr::try_from(a).ok())
+		.filter(|a| a.iter().any(|p| p == own))
+		.collect();
+
+	Some((peer_id, addresses))
+}
+
+/// The nodes found in the DHT, with the lookup round they were last found in.
+#[derive(Default)]
+struct Discovered {
+	round: u64,
+	nodes: HashMap<PeerId, (u64, Vec<Multiaddr>)>,
+}
+
+impl Discovered {
+	/// Start the next lookup round and forget the nodes that were not found in the last one.
+	fn next_round(&mut self) {
+		self.round += 1;
+
+		let round = self.round;
+		self.nodes.retain(|_, (found_in, _)| *found_in + 1 >= round);
+	}
+
+	/// Note the `addresses` of the node `peer_id`, up to [`MAX_DISCOVERED_NODES`] nodes.
+	///
+	/// Returns the addresses that were not known yet.
+	fn note(&mut self, peer_id: PeerId, addresses: Vec<Multiaddr>) -> Vec<Multiaddr> {
+		if !self.nodes.contains_key(&peer_id) && self.nodes.len() >= MAX_DISCOVERED_NODES {
+			return Vec::new();
+		}
+
+		let round = self.round;
+		let (found_in, known) = self
+			.nodes
+			.entry(peer_id)
+			.or_insert_with(|| (round, Vec::new()));
+		*found_in = round;
+
+		let new = addresses
+			.iter()
+			.filter(|a| !known.contains(a))
+			.cloned()
+			.collect();
+		*known = addresses;
+
+		new
+	}
+}
+
+/// Publish the `public_addresses` of the parachain node in the DHT of the relay chain and
+/// connect to the nodes found there.
+///
+/// The records are signed with the `node_key` of the parachain network. Nodes without public
+/// addresses only look up the other nodes. The returned future needs to be spawned.
+pub fn dht_bootnodes<Block: BlockT>(
+	relay_network: Arc<NetworkService<PBlock, PHash>>,
+	para_network: Arc<NetworkService<Block, Block::Hash>>,
+	node_key: Keypair,
+	para_id: ParaId,
+	genesis_hash: Block::Hash,
+	public_addresses: Vec<Multiaddr>,
+) -> impl Future<Output = ()> + Send + 'static {
+	let key = dht_key(para_id, genesis_hash.as_ref());
+	let local_peer_id = para_network.local_peer_id().clone();
+	let record = if public_addresses.is_empty() {
+		None
+	} else {
+		encode_addresses(&node_key, &key, &public_addresses)
+			.map_err(|e| {
+				warn!(
+					target: "cumulus-network",
+					"Failed to sign the addresses for the relay chain DHT: {}",
+					e,
+				)
+			})
+			.ok()
+	};
+
+	async move {
+		let mut events = relay_network.event_stream("cumulus-dht-bootnodes").fuse();
+		let mut discovered = Discovered::default();
+
+		loop {
+			if let Some(ref record) = record {
+				relay_network.put_value(key.clone(), record.clone());
+			}
+			relay_network.get_value(&key);
+			discovered.next_round();
+
+			let mut republish = Delay::new(REPUBLISH_INTERVAL).fuse();
+
+			loop {
+				select! {
+					event = events.next() => match event {
+						Some(Event::Dht(DhtEvent::ValueFound(values))) => {
+							let found = values
+								.iter()
+								.filter(|(k, _)| *k == key)
+								.filter_map(|(_, v)| decode_addresses(v, &key, &local_peer_id));
+
+							for (peer_id, addresses) in found {
+								for address in discovered.note(peer_id.clone(), addresses) {
+									debug!(
+										target: "cumulus-network",
+										"Found parachain node {} at {} in the relay chain DHT.",
+										peer_id,
+										address,
+									);
+									para_network.add_known_address(peer_id.clone(), address);
+								}
+							}
+						}
+						Some(Event::Dht(DhtEvent::ValuePutFailed(k))) if k == key => {
+							debug!(
+								target: "cumulus-network",
+								"Failed to publish the addresses in the relay chain DHT.",
+							);
+						}
+						Some(_) => {}
+						None => return,
+					},
+					_ = republish => break,
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn records_contain_the_signed_addresses_of_other_nodes() {
+		let (local, remote) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+		let (local_id, remote_id) = (local.public().into_peer_id(), remote.public().into_peer_id());
+		let key = dht_key(100.into(), &[1; 32]);
+		let address: Multiaddr = "/ip4/1.2.3.4/tcp/30333".parse().unwrap();
+
+		let record = encode_addresses(&remote, &key, &[address.clone()]).unwrap();
+		assert_eq!(
+			Some((
+				remote_id.clone(),
+				vec![address.clone().with(Protocol::P2p(remote_id.clone().into()))],
+			)),
+			decode_addresses(&record, &key, &local_id),
+		);
+
+		// The own addresses are skipped.
+		assert!(decode_addresses(&record, &key, &remote_id).is_none());
+		assert!(decode_addresses(b"garbage", &key, &local_id).is_none());
+
+		// Records are only valid under the key they were signed for.
+		assert!(decode_addresses(&record, &dht_key(200.into(), &[1; 32]), &local_id).is_none());
+
+		// Addresses can not be published in the name of another node.
+		let mut forged = SignedAddresses::decode(&mut &record[..]).unwrap();
+		forged.public_key = local.public().into_protobuf_encoding();
+		assert!(decode_addresses(&forged.encode(), &key, &remote_id).is_none());
+
+		assert_ne!(dht_key(100.into(), &[1; 32]), dht_key(200.into(), &[1; 32]));
+		assert_ne!(dht_key(100.into(), &[1; 32]), dht_key(100.into(), &[2; 32]));
+	}
+
+	#[test]
+	fn forgets_nodes_that_are_not_found_again() {
+		let (first, second) = (PeerId::random(), PeerId::random());
+		let address: Multiaddr = "/ip4/1.2.3.4/tcp/30333".parse().unwrap();
+		let mut discovered = Discovered::default();
+
+		discovered.next_round();
+		assert_eq!(vec![address.clone()], discovered.note(first.clone(), vec![address.clone()]));
+		assert!(discovered.note(first.clone(), vec![address.clone()]).is_empty());
+
+		discovered.next_round();
+		discovered.note(second.clone(), vec![address.clone()]);
+		assert!(discovered.nodes.contains_key(&first));
+
+		discovered.next_round();
+		assert!(!discovered.nodes.contains_key(&first));
+		assert!(discovered.nodes.contains_key(&second));
+
+		// At most `MAX_DISCOVERED_NODES` nodes are kept.
+		for _ in 0..MAX_DISCOVERED_NODES {
+			discovered.note(PeerId::random(), vec![address.clone()]);
+		}
+		assert_eq!(MAX_DISCOVERED_NODES, discovered.nodes.len());
+		assert!(discovered.note(first, vec![address]).is_empty());
+	}
+}
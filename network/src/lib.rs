@@ -20,6 +20,10 @@
 //! that use the relay chain provided consensus. See [`BlockAnnounceValidator`]
 //! and [`WaitToAnnounce`] for more information about this implementation.
 
+pub mod announce_signing;
+pub mod announcement;
+pub mod dht_bootnodes;
+pub mod recent_blocks;
 #[cfg(test)]
 mod tests;
 
@@ -44,15 +48,22 @@ use polkadot_primitives::v1::{
 };
 use polkadot_service::ClientHandle;
 
+use announce_signing::{AnnounceSigner, AnnounceVerifier};
+use announcement::{AnnounceBlock, CollationAnnouncement};
+use recent_blocks::RecentJustifications;
+
+use cumulus_consensus::RelayChainCache;
+
 use codec::{Decode, Encode};
 use futures::{
 	channel::{mpsc, oneshot},
-	future::{ready, FutureExt},
+	future::{pending, ready, FutureExt},
 	pin_mut, select, Future, StreamExt,
 };
-use log::{trace, warn};
+use futures_timer::Delay;
+use log::{debug, trace, warn};
 
-use std::{marker::PhantomData, pin::Pin, sync::Arc};
+use std::{marker::PhantomData, pin::Pin, sync::Arc, time::Duration};
 
 /// Parachain specific block announce validator.
 ///
@@ -73,22 +84,33 @@ use std::{marker::PhantomData, pin::Pin, sync::Arc};
 /// For each block announcement that is received, the generic block announcement validation
 /// will call this validator and provides the extra data that was attached to the announcement.
 /// We call this extra data `justification`.
-/// It is expected that the attached data is a SCALE encoded [`SignedFullStatement`]. The
-/// statement is checked to be a [`Statement::Seconded`] and that it is signed by an active
-/// parachain validator.
+/// It is expected that the attached data is a [`CollationAnnouncement::Seconded`] of any version
+/// this node understands, see [`announcement`]. The statement is checked to be a
+/// [`Statement::Seconded`] and that it is signed by an active parachain validator.
+///
+/// Announcements with an invalid justification are rejected with [`Validation::Failure`], which
+/// makes the sync report the sending peer to the peer-set manager. Peers that keep sending such
+/// announcements drop below the reputation threshold and are banned. Errors are only returned
+/// for failures that are not caused by the peer, e.g. an unknown relay parent.
 ///
 /// If no justification was provided we check if the block announcement is at the tip of the known
 /// chain. If it is at the tip, it is required to provide a justification or otherwise we reject
 /// it. However, if the announcement is for a block below the tip the announcement is accepted
 /// as it probably comes from a node that is currently syncing the chain.
-pub struct BlockAnnounceValidator<B, P> {
+///
+/// With an [`AnnounceVerifier`], the justification also needs to carry a valid collator signature
+/// for the block to become the new best block, see [`announce_signing`].
+pub struct BlockAnnounceValidator<B: BlockT, P> {
 	phantom: PhantomData<B>,
 	polkadot_client: Arc<P>,
 	para_id: ParaId,
 	polkadot_sync_oracle: Box<dyn SyncOracle + Send>,
+	justifications: Option<RecentJustifications<B::Hash>>,
+	verifier: Option<Arc<dyn AnnounceVerifier<B>>>,
+	relay_chain_cache: RelayChainCache,
 }
 
-impl<B, P> BlockAnnounceValidator<B, P> {
+impl<B: BlockT, P> BlockAnnounceValidator<B, P> {
 	pub fn new(
 		polkadot_client: Arc<P>,
 		para_id: ParaId,
@@ -99,8 +121,33 @@ impl<B, P> BlockAnnounceValidator<B, P> {
 			polkadot_client,
 			para_id,
 			polkadot_sync_oracle,
+			justifications: None,
+			verifier: None,
+			relay_chain_cache: Default::default(),
 		}
 	}
+
+	/// Remember the justifications of valid announcements in `justifications`.
+	///
+	/// They are served with the blocks to peers that request them with the
+	/// [`recent_blocks`] protocol.
+	pub fn with_justifications(mut self, justifications: RecentJustifications<B::Hash>) -> Self {
+		self.justifications = Some(justifications);
+		self
+	}
+
+	/// Check the collator signatures of announcements with the given `verifier`.
+	pub fn with_verifier(mut self, verifier: Arc<dyn AnnounceVerifier<B>>) -> Self {
+		self.verifier = Some(verifier);
+		self
+	}
+
+	/// Read the relay chain data through the given `cache`, shared with the collator and the
+	/// consensus follower.
+	pub fn with_relay_chain_cache(mut self, cache: RelayChainCache) -> Self {
+		self.relay_chain_cache = cache;
+		self
+	}
 }
 
 impl<B: BlockT, P> BlockAnnounceValidatorT<B> for BlockAnnounceValidator<B, P>
@@ -111,7 +158,7 @@ where
 	fn validate(
 		&mut self,
 		header: &B::Header,
-		mut data: &[u8],
+		data: &[u8],
 	) -> Pin<Box<dyn Future<Output = Result<Validation, Box<dyn std::error::Error + Send>>> + Send>>
 	{
 		if self.polkadot_sync_oracle.is_major_syncing() {
@@ -123,22 +170,22 @@ where
 
 		if data.is_empty() {
 			let polkadot_client = self.polkadot_client.clone();
+			let relay_chain_cache = self.relay_chain_cache.clone();
 			let header = header.clone();
 			let para_id = self.para_id;
 
 			return async move {
 				// Check if block is equal or higher than best (this requires a justification)
-				let runtime_api_block_id = BlockId::Hash(polkadot_info.best_hash);
 				let block_number = header.number();
 
-				let local_validation_data = polkadot_client
-					.runtime_api()
+				let local_validation_data = relay_chain_cache
 					.persisted_validation_data(
-						&runtime_api_block_id,
+						&*polkadot_client,
+						polkadot_info.best_hash,
 						para_id,
 						OccupiedCoreAssumption::TimedOut,
 					)
-					.map_err(|e| Box::new(ClientError::Msg(format!("{:?}", e))) as Box<_>)?
+					.map_err(|e| Box::new(e) as Box<_>)?
 					.ok_or_else(|| {
 						Box::new(ClientError::Msg(
 							"Could not find parachain head in relay chain".into(),
@@ -167,23 +214,25 @@ where
 			.boxed();
 		}
 
-		let signed_stmt = match SignedFullStatement::decode(&mut data) {
-			Ok(r) => r,
-			Err(_) => return ready(Err(Box::new(ClientError::BadJustification(
-				"cannot decode block announcement justification, must be a `SignedFullStatement`"
-					.to_string(),
-			)) as Box<_>))
-			.boxed(),
+		let (signed_stmt, signature) = match CollationAnnouncement::from_data(data) {
+			Ok(CollationAnnouncement::Seconded {
+				statement,
+				signature,
+			}) => (statement, signature),
+			_ => {
+				return reject_justification(
+					"cannot decode block announcement justification, must be a `CollationAnnouncement`",
+				)
+			}
 		};
 
 		// Check statement is a candidate statement.
 		let candidate_receipt = match signed_stmt.payload() {
 			Statement::Seconded(ref candidate_receipt) => candidate_receipt,
 			_ => {
-				return ready(Err(Box::new(ClientError::BadJustification(
-					"block announcement justification must be a `Statement::Seconded`".to_string(),
-				)) as Box<_>))
-				.boxed()
+				return reject_justification(
+					"block announcement justification must be a `Statement::Seconded`",
+				)
 			}
 		};
 
@@ -243,11 +292,9 @@ where
 		let signer = match authorities.get(validator_index as usize) {
 			Some(r) => r,
 			None => {
-				return ready(Err(Box::new(ClientError::BadJustification(
-					"block accouncement justification signer is a validator index out of bound"
-						.to_string(),
-				)) as Box<_>))
-				.boxed()
+				return reject_justification(
+					"block accouncement justification signer is a validator index out of bound",
+				)
 			}
 		};
 
@@ -256,33 +303,70 @@ where
 			.check_signature(&signing_context, &signer)
 			.is_err()
 		{
-			return ready(Err(Box::new(ClientError::BadJustification(
-				"block announced justification signature is invalid".to_string(),
-			)) as Box<_>))
-			.boxed();
+			return reject_justification("block announced justification signature is invalid");
 		}
 
 		// Check the header in the candidate_receipt match header given header.
 		if header.encode() != candidate_receipt.commitments.head_data.0 {
-			return ready(Err(Box::new(ClientError::BadJustification(
-				"block announced header does not match the one justified".to_string(),
-			)) as Box<_>))
-			.boxed();
+			return reject_justification("block announced header does not match the one justified");
 		}
 
-		ready(Ok(Validation::Success { is_new_best: true })).boxed()
+		// The collator signature follows the statement.
+		let is_new_best = match (&self.verifier, signature) {
+			(None, _) => true,
+			(Some(_), None) => false,
+			(Some(verifier), Some(signature)) if verifier.verify(header.hash(), &signature) => true,
+			(Some(_), Some(_)) => {
+				return reject_justification("block announcement collator signature is invalid")
+			}
+		};
+
+		if let Some(justifications) = &self.justifications {
+			justifications.note(header.hash(), signed_stmt.encode());
+		}
+
+		ready(Ok(Validation::Success { is_new_best })).boxed()
 	}
 }
 
+/// Reject a block announcement because its justification is invalid.
+///
+/// The sync reports the peer that sent the announcement for every [`Validation::Failure`].
+fn reject_justification(
+	reason: &str,
+) -> Pin<Box<dyn Future<Output = Result<Validation, Box<dyn std::error::Error + Send>>> + Send>> {
+	debug!(
+		target: "cumulus-network",
+		"rejecting block announcement: {}",
+		reason,
+	);
+
+	ready(Ok(Validation::Failure)).boxed()
+}
+
 /// Build a block announce validator instance.
 ///
 /// Returns a boxed [`BlockAnnounceValidator`].
+///
+/// The justifications of valid announcements are remembered in `justifications`. If a `verifier`
+/// is given, the collator signatures of announcements are checked.
 pub fn build_block_announce_validator<B: BlockT>(
 	polkadot_client: polkadot_service::Client,
 	para_id: ParaId,
 	polkadot_sync_oracle: Box<dyn SyncOracle + Send>,
+	justifications: RecentJustifications<B::Hash>,
+	verifier: Option<Arc<dyn AnnounceVerifier<B>>>,
+	relay_chain_cache: RelayChainCache,
 ) -> Box<dyn BlockAnnounceValidatorT<B> + Send> {
-	BlockAnnounceValidatorBuilder::new(polkadot_client, para_id, polkadot_sync_oracle).build()
+	BlockAnnounceValidatorBuilder::new(
+		polkadot_client,
+		para_id,
+		polkadot_sync_oracle,
+		justifications,
+		verifier,
+		relay_chain_cache,
+	)
+	.build()
 }
 
 /// Block announce validator builder.
@@ -291,11 +375,14 @@ pub fn build_block_announce_validator<B: BlockT>(
 /// a concrete Polkadot client instance, the builder takes a [`polkadot_service::Client`]
 /// that wraps this concrete instanace. By using [`polkadot_service::ExecuteWithClient`]
 /// the builder gets access to this concrete instance.
-struct BlockAnnounceValidatorBuilder<B> {
+struct BlockAnnounceValidatorBuilder<B: BlockT> {
 	phantom: PhantomData<B>,
 	polkadot_client: polkadot_service::Client,
 	para_id: ParaId,
 	polkadot_sync_oracle: Box<dyn SyncOracle + Send>,
+	justifications: RecentJustifications<B::Hash>,
+	verifier: Option<Arc<dyn AnnounceVerifier<B>>>,
+	relay_chain_cache: RelayChainCache,
 }
 
 impl<B: BlockT> BlockAnnounceValidatorBuilder<B> {
@@ -304,11 +391,17 @@ impl<B: BlockT> BlockAnnounceValidatorBuilder<B> {
 		polkadot_client: polkadot_service::Client,
 		para_id: ParaId,
 		polkadot_sync_oracle: Box<dyn SyncOracle + Send>,
+		justifications: RecentJustifications<B::Hash>,
+		verifier: Option<Arc<dyn AnnounceVerifier<B>>>,
+		relay_chain_cache: RelayChainCache,
 	) -> Self {
 		Self {
 			polkadot_client,
 			para_id,
 			polkadot_sync_oracle,
+			justifications,
+			verifier,
+			relay_chain_cache,
 			phantom: PhantomData,
 		}
 	}
@@ -331,11 +424,67 @@ impl<B: BlockT> polkadot_service::ExecuteWithClient for BlockAnnounceValidatorBu
 		Api: polkadot_service::RuntimeApiCollection<StateBackend = PBackend::State>,
 		PClient: polkadot_service::AbstractClient<PBlock, PBackend, Api = Api> + 'static,
 	{
-		Box::new(BlockAnnounceValidator::new(
-			client,
-			self.para_id,
-			self.polkadot_sync_oracle,
-		))
+		let validator = BlockAnnounceValidator::new(client, self.para_id, self.polkadot_sync_oracle)
+			.with_justifications(self.justifications)
+			.with_relay_chain_cache(self.relay_chain_cache);
+
+		Box::new(match self.verifier {
+			Some(verifier) => validator.with_verifier(verifier),
+			None => validator,
+		})
+	}
+}
+
+/// What [`WaitToAnnounce`] does with a block for that no [`Statement::Seconded`] was received
+/// before the timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnounceTimeoutAction {
+	/// Announce the block without a justification.
+	///
+	/// Nodes at the tip of the chain reject such an announcement and lower the reputation of
+	/// this node, but nodes that are behind can still sync the block. If the statement arrives
+	/// later, the block is announced again with the statement as justification.
+	Announce,
+	/// Stop waiting and never announce the block.
+	///
+	/// The default, as announcements without a justification are penalized by peers at the tip
+	/// of the chain.
+	Drop,
+}
+
+impl AnnounceTimeoutAction {
+	/// A short name of the action, used as metric label.
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Announce => "announce",
+			Self::Drop => "drop",
+		}
+	}
+}
+
+/// Policy of [`WaitToAnnounce`] for announcing a block.
+#[derive(Clone, Copy, Debug)]
+pub struct AnnouncePolicy {
+	/// How long to wait for the [`Statement::Seconded`] of the candidate.
+	///
+	/// `None` waits until the next block should be announced.
+	pub timeout: Option<Duration>,
+	/// What to do when the `timeout` is reached.
+	pub on_timeout: AnnounceTimeoutAction,
+	/// Announce the block again for every [`Statement::Valid`] of the candidate.
+	///
+	/// The block is re-announced with the [`Statement::Seconded`] as justification, so that peers
+	/// that missed the first announcement have another chance to get the block.
+	pub reannounce: bool,
+}
+
+impl Default for AnnouncePolicy {
+	fn default() -> Self {
+		Self {
+			timeout: None,
+			on_timeout: AnnounceTimeoutAction::Drop,
+			reannounce: false,
+		}
 	}
 }
 
@@ -343,19 +492,23 @@ impl<B: BlockT> polkadot_service::ExecuteWithClient for BlockAnnounceValidatorBu
 /// add this message as justification for the block announcement.
 ///
 /// This object will spawn a new task every time the method `wait_to_announce` is called and cancel
-/// the previous task running.
+/// the previous task running. How long to wait and whether the block is announced again is
+/// controlled by the [`AnnouncePolicy`].
 pub struct WaitToAnnounce<Block: BlockT> {
 	spawner: Arc<dyn SpawnNamed + Send + Sync>,
-	announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	announce_block: Arc<dyn AnnounceBlock<Block>>,
 	overseer_handler: OverseerHandler,
 	current_trigger: oneshot::Sender<()>,
+	policy: AnnouncePolicy,
+	on_timeout: Option<Arc<dyn Fn(AnnounceTimeoutAction) + Send + Sync>>,
+	signer: Option<Arc<dyn AnnounceSigner<Block>>>,
 }
 
 impl<Block: BlockT> WaitToAnnounce<Block> {
 	/// Create the `WaitToAnnounce` object
 	pub fn new(
 		spawner: Arc<dyn SpawnNamed + Send + Sync>,
-		announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+		announce_block: Arc<dyn AnnounceBlock<Block>>,
 		overseer_handler: OverseerHandler,
 	) -> WaitToAnnounce<Block> {
 		let (tx, _rx) = oneshot::channel();
@@ -365,15 +518,42 @@ impl<Block: BlockT> WaitToAnnounce<Block> {
 			announce_block,
 			overseer_handler,
 			current_trigger: tx,
+			policy: AnnouncePolicy::default(),
+			on_timeout: None,
+			signer: None,
 		}
 	}
 
+	/// Use the given `policy` for announcing blocks.
+	pub fn with_policy(mut self, policy: AnnouncePolicy) -> Self {
+		self.policy = policy;
+		self
+	}
+
+	/// Call `on_timeout` every time the timeout of the [`AnnouncePolicy`] is reached.
+	pub fn with_timeout_observer(
+		mut self,
+		on_timeout: Arc<dyn Fn(AnnounceTimeoutAction) + Send + Sync>,
+	) -> Self {
+		self.on_timeout = Some(on_timeout);
+		self
+	}
+
+	/// Append a collator signature from `signer` to the justification of announcements.
+	pub fn with_signer(mut self, signer: Arc<dyn AnnounceSigner<Block>>) -> Self {
+		self.signer = Some(signer);
+		self
+	}
+
 	/// Wait for a candidate message for the block, then announce the block. The candidate
 	/// message will be added as justification to the block announcement.
 	pub fn wait_to_announce(&mut self, block_hash: <Block as BlockT>::Hash, pov_hash: PHash) {
 		let (tx, rx) = oneshot::channel();
 		let announce_block = self.announce_block.clone();
 		let overseer_handler = self.overseer_handler.clone();
+		let policy = self.policy;
+		let on_timeout = self.on_timeout.clone();
+		let signer = self.signer.clone();
 
 		self.current_trigger = tx;
 
@@ -385,6 +565,9 @@ impl<Block: BlockT> WaitToAnnounce<Block> {
 					pov_hash,
 					announce_block,
 					overseer_handler,
+					policy,
+					on_timeout,
+					signer,
 				)
 				.fuse();
 				let t2 = rx.fuse();
@@ -419,10 +602,13 @@ impl<Block: BlockT> WaitToAnnounce<Block> {
 async fn wait_to_announce<Block: BlockT>(
 	block_hash: <Block as BlockT>::Hash,
 	pov_hash: PHash,
-	announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	announce_block: Arc<dyn AnnounceBlock<Block>>,
 	mut overseer_handler: OverseerHandler,
+	policy: AnnouncePolicy,
+	on_timeout: Option<Arc<dyn Fn(AnnounceTimeoutAction) + Send + Sync>>,
+	signer: Option<Arc<dyn AnnounceSigner<Block>>>,
 ) {
-	let (sender, mut receiver) = mpsc::channel(5);
+	let (sender, receiver) = mpsc::channel(5);
 	if overseer_handler
 		.send_msg(StatementDistributionMessage::RegisterStatementListener(
 			sender,
@@ -437,14 +623,103 @@ async fn wait_to_announce<Block: BlockT>(
 		return;
 	}
 
-	while let Some(statement) = receiver.next().await {
-		match &statement.payload() {
-			Statement::Seconded(c) if &c.descriptor.pov_hash == &pov_hash => {
-				announce_block(block_hash, statement.encode());
+	announce_on_statements(
+		receiver,
+		block_hash,
+		pov_hash,
+		announce_block,
+		policy,
+		on_timeout,
+		signer,
+	)
+	.await
+}
 
-				break;
-			}
-			_ => {}
+/// Announce the block `block_hash` once one of the `statements` seconds its candidate with
+/// `pov_hash`, following the `policy`.
+async fn announce_on_statements<Block: BlockT>(
+	mut statements: mpsc::Receiver<SignedFullStatement>,
+	block_hash: <Block as BlockT>::Hash,
+	pov_hash: PHash,
+	announce_block: Arc<dyn AnnounceBlock<Block>>,
+	policy: AnnouncePolicy,
+	on_timeout: Option<Arc<dyn Fn(AnnounceTimeoutAction) + Send + Sync>>,
+	signer: Option<Arc<dyn AnnounceSigner<Block>>>,
+) {
+	let timeout = match policy.timeout {
+		Some(timeout) => Delay::new(timeout).left_future(),
+		None => pending().right_future(),
+	}
+	.fuse();
+	pin_mut!(timeout);
+
+	// The candidate hash and the announcement, once the candidate was seconded.
+	let mut seconded = None;
+
+	loop {
+		select! {
+			statement = statements.next() => {
+				let statement = match statement {
+					Some(statement) => statement,
+					None => break,
+				};
+
+				match statement.payload() {
+					Statement::Seconded(c)
+						if seconded.is_none() && c.descriptor.pov_hash == pov_hash =>
+					{
+						let announcement = CollationAnnouncement::Seconded {
+							statement: statement.clone(),
+							signature: signer.as_ref().and_then(|s| s.sign(block_hash)),
+						};
+
+						announce_block.announce_block(block_hash, announcement.clone());
+
+						if !policy.reannounce {
+							break;
+						}
+
+						seconded = Some((c.hash(), announcement));
+					}
+					Statement::Valid(candidate_hash) => {
+						if let Some((seconded_hash, announcement)) = &seconded {
+							if candidate_hash == seconded_hash {
+								trace!(
+									target: "cumulus-network",
+									"re-announcing block {} after additional statement",
+									block_hash,
+								);
+
+								announce_block.announce_block(block_hash, announcement.clone());
+							}
+						}
+					}
+					_ => {}
+				}
+			},
+			_ = timeout => {
+				if seconded.is_some() {
+					continue;
+				}
+
+				debug!(
+					target: "cumulus-network",
+					"no seconded statement for block {} before the timeout, action: {}",
+					block_hash,
+					policy.on_timeout.name(),
+				);
+
+				if let Some(on_timeout) = &on_timeout {
+					on_timeout(policy.on_timeout);
+				}
+
+				match policy.on_timeout {
+					AnnounceTimeoutAction::Announce => {
+						announce_block.announce_block(block_hash, CollationAnnouncement::Empty)
+					}
+					AnnounceTimeoutAction::Drop => break,
+				}
+			},
 		}
 	}
 }
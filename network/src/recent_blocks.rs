@@ -0,0 +1,611 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Request/response protocol for fetching recent parachain blocks.
+//!
+//! The generic sync protocol is slow at closing a gap of a single block, e.g. when a collator
+//! misses the parent of the block it should build on. With this protocol a node asks its peers
+//! directly for a block by hash. The response contains the block and, if known, the
+//! justification the block was announced with, i.e. the [`SignedFullStatement`] that carries
+//! the candidate receipt.
+//!
+//! [`RecentBlocksHandler`] answers the requests of other nodes and [`RecentBlockFetcher`] sends
+//! requests to the connected peers.
+//!
+//! The name of the protocol contains the protocol id of the chain, see [`protocol_name`], so
+//! nodes of different chains that share bootnodes don't talk to each other.
+//!
+//! [`SignedFullStatement`]: polkadot_node_primitives::SignedFullStatement
+
+use sc_client_api::BlockBackend;
+use sc_network::{
+	config::{IncomingRequest, ProtocolId, RequestResponseConfig},
+	Event, NetworkService, PeerId,
+};
+use sp_consensus::{
+	import_queue::{import_single_block, IncomingBlock, Verifier},
+	BlockImport, BlockOrigin, Error as ConsensusError,
+};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, Header as HeaderT},
+};
+
+use codec::{Decode, Encode};
+use futures::{
+	channel::mpsc,
+	future::{self, Either},
+	pin_mut, Future, StreamExt,
+};
+use futures_timer::Delay;
+use log::{debug, trace};
+use parking_lot::Mutex;
+
+use std::{
+	collections::{HashSet, VecDeque},
+	sync::Arc,
+	time::Duration,
+};
+
+/// The name of the protocol for the chain with the given `protocol_id`.
+pub fn protocol_name(protocol_id: &ProtocolId) -> String {
+	format!("/{}/cumulus/recent-blocks/1", protocol_id.as_ref())
+}
+
+/// The maximum size of a request, a request only contains a block hash.
+const MAX_REQUEST_SIZE: u64 = 1024;
+
+/// The maximum size of a response.
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// How long to wait for the response of a peer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for any of the peers to return a requested block.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum number of peers that are asked for a block at the same time.
+const MAX_PARALLEL_REQUESTS: usize = 4;
+
+/// The number of incoming requests that are buffered before new requests are rejected.
+const INBOUND_QUEUE_SIZE: usize = 16;
+
+/// The number of justifications kept by [`RecentJustifications`].
+const MAX_JUSTIFICATIONS: usize = 64;
+
+/// Request a block by its hash.
+#[derive(Encode, Decode, Debug)]
+pub struct RecentBlockRequest<Hash> {
+	/// The hash of the requested block.
+	pub hash: Hash,
+}
+
+/// Response to a [`RecentBlockRequest`].
+#[derive(Encode, Decode, Debug)]
+pub struct RecentBlockResponse<Block> {
+	/// The requested block, `None` if the peer doesn't know it.
+	pub block: Option<Block>,
+	/// The justification the block was announced with, if known by the peer.
+	pub justification: Option<Vec<u8>>,
+}
+
+/// The justifications of the most recently announced blocks.
+pub struct RecentJustifications<Hash>(Arc<Mutex<VecDeque<(Hash, Vec<u8>)>>>);
+
+impl<Hash> Clone for RecentJustifications<Hash> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+impl<Hash> Default for RecentJustifications<Hash> {
+	fn default() -> Self {
+		Self(Default::default())
+	}
+}
+
+impl<Hash: PartialEq> RecentJustifications<Hash> {
+	/// Remember the `justification` of the block with the given `hash`.
+	pub fn note(&self, hash: Hash, justification: Vec<u8>) {
+		let mut justifications = self.0.lock();
+
+		if justifications.iter().any(|(h, _)| *h == hash) {
+			return;
+		}
+
+		if justifications.len() >= MAX_JUSTIFICATIONS {
+			justifications.pop_front();
+		}
+		justifications.push_back((hash, justification));
+	}
+
+	/// Returns the justification of the block with the given `hash`.
+	pub fn get(&self, hash: &Hash) -> Option<Vec<u8>> {
+		self.0
+			.lock()
+			.iter()
+			.find(|(h, _)| h == hash)
+			.map(|(_, j)| j.clone())
+	}
+}
+
+/// Answers the [`RecentBlockRequest`]s of other nodes.
+pub struct RecentBlocksHandler<Block: BlockT, Client> {
+	client: Arc<Client>,
+	justifications: RecentJustifications<Block::Hash>,
+	requests: mpsc::Receiver<IncomingRequest>,
+}
+
+impl<Block, Client> RecentBlocksHandler<Block, Client>
+where
+	Block: BlockT,
+	Client: BlockBackend<Block>,
+{
+	/// Create a new handler.
+	///
+	/// The returned [`RequestResponseConfig`] needs to be added to the
+	/// `request_response_protocols` of the network configuration.
+	pub fn new(
+		client: Arc<Client>,
+		justifications: RecentJustifications<Block::Hash>,
+		protocol_id: &ProtocolId,
+	) -> (Self, RequestResponseConfig) {
+		let (tx, rx) = mpsc::channel(INBOUND_QUEUE_SIZE);
+
+		let config = RequestResponseConfig {
+			name: protocol_name(protocol_id).into(),
+			max_request_size: MAX_REQUEST_SIZE,
+			max_response_size: MAX_RESPONSE_SIZE,
+			request_timeout: REQUEST_TIMEOUT,
+			inbound_queue: Some(tx),
+		};
+
+		(
+			Self {
+				client,
+				justifications,
+				requests: rx,
+			},
+			config,
+		)
+	}
+
+	/// Run the handler until the network shuts down.
+	pub async fn run(mut self) {
+		while let Some(request) = self.requests.next().await {
+			let response = self.handle_request(&request.payload, &request.peer);
+
+			if request.pending_response.send(response.encode()).is_err() {
+				debug!(
+					target: "cumulus-network",
+					"failed to send recent block response to {}",
+					request.peer,
+				);
+			}
+		}
+	}
+
+	fn handle_request(&self, mut payload: &[u8], peer: &PeerId) -> RecentBlockResponse<Block> {
+		let hash = match RecentBlockRequest::<Block::Hash>::decode(&mut payload) {
+			Ok(request) => request.hash,
+			Err(e) => {
+				debug!(
+					target: "cumulus-network",
+					"failed to decode recent block request from {}: {:?}",
+					peer,
+					e,
+				);
+
+				return RecentBlockResponse {
+					block: None,
+					justification: None,
+				};
+			}
+		};
+
+		trace!(
+			target: "cumulus-network",
+			"{} requested recent block {}",
+			peer,
+			hash,
+		);
+
+		let block = match self.client.block(&BlockId::Hash(hash)) {
+			Ok(block) => block.map(|b| b.block),
+			Err(e) => {
+				debug!(
+					target: "cumulus-network",
+					"failed to load recent block {}: {:?}",
+					hash,
+					e,
+				);
+
+				None
+			}
+		};
+
+		RecentBlockResponse {
+			justification: block.as_ref().and_then(|_| self.justifications.get(&hash)),
+			block,
+		}
+	}
+}
+
+/// Fetches recent blocks from the connected peers.
+pub struct RecentBlockFetcher<Block: BlockT> {
+	network: Arc<NetworkService<Block, Block::Hash>>,
+	peers: Arc<Mutex<HashSet<PeerId>>>,
+	protocol_name: String,
+}
+
+impl<Block: BlockT> Clone for RecentBlockFetcher<Block> {
+	fn clone(&self) -> Self {
+		Self {
+			network: self.network.clone(),
+			peers: self.peers.clone(),
+			protocol_name: self.protocol_name.clone(),
+		}
+	}
+}
+
+impl<Block: BlockT> RecentBlockFetcher<Block> {
+	/// Create a new fetcher.
+	///
+	/// The returned future keeps track of the connected peers and needs to be spawned.
+	pub fn new(
+		network: Arc<NetworkService<Block, Block::Hash>>,
+		protocol_id: &ProtocolId,
+	) -> (Self, impl Future<Output = ()> + Send + 'static) {
+		let peers = Arc::new(Mutex::new(HashSet::new()));
+
+		let track_peers = {
+			let peers = peers.clone();
+			network
+				.event_stream("cumulus-recent-blocks")
+				.for_each(move |event| {
+					match event {
+						Event::SyncConnected { remote } => {
+							peers.lock().insert(remote);
+						}
+						Event::SyncDisconnected { remote } => {
+							peers.lock().remove(&remote);
+						}
+						_ => {}
+					}
+
+					futures::future::ready(())
+				})
+		};
+
+		(
+			Self {
+				network,
+				peers,
+				protocol_name: protocol_name(protocol_id),
+			},
+			track_peers,
+		)
+	}
+
+	/// Fetch the block with the given `hash`.
+	///
+	/// Up to [`MAX_PARALLEL_REQUESTS`] of the connected peers are asked at the same time, until
+	/// one of them returns the block or [`FETCH_TIMEOUT`] is reached.
+	pub async fn fetch(&self, hash: Block::Hash) -> Option<RecentBlockResponse<Block>> {
+		let peers = self.peers.lock().iter().cloned().collect::<Vec<_>>();
+		let request = RecentBlockRequest { hash }.encode();
+
+		fetch_from_peers(peers, hash, FETCH_TIMEOUT, |peer| {
+			self.network
+				.request(peer, self.protocol_name.clone(), request.clone())
+		})
+		.await
+	}
+
+	/// Fetch the block with the given `hash` and import it with `block_import`.
+	///
+	/// The block is checked by the `verifier` of the import queue before it is imported, see
+	/// [`import_recent_block`]. Returns `true` if the block was imported.
+	pub async fn fetch_and_import<BI, V>(
+		&self,
+		hash: Block::Hash,
+		block_import: BI,
+		verifier: V,
+	) -> bool
+	where
+		BI: BlockImport<Block, Error = ConsensusError>,
+		V: Verifier<Block>,
+	{
+		match self.fetch(hash).await.and_then(|r| r.block) {
+			Some(block) => import_recent_block(block, block_import, verifier),
+			None => false,
+		}
+	}
+}
+
+/// Ask the `peers` for the block with the given `hash` with `request`, until one of them
+/// returns the block or the `timeout` is reached.
+async fn fetch_from_peers<Block, Request, E>(
+	peers: Vec<PeerId>,
+	hash: Block::Hash,
+	timeout: Duration,
+	request: impl Fn(PeerId) -> Request,
+) -> Option<RecentBlockResponse<Block>>
+where
+	Block: BlockT,
+	Request: Future<Output = Result<Vec<u8>, E>>,
+	E: std::fmt::Debug,
+{
+	let responses = futures::stream::iter(peers)
+		.map(|peer| {
+			let response = request(peer.clone());
+			async move { (peer, response.await) }
+		})
+		.buffer_unordered(MAX_PARALLEL_REQUESTS)
+		.filter_map(|(peer, response)| {
+			future::ready(decode_response::<Block, E>(&peer, hash, response))
+		});
+	pin_mut!(responses);
+
+	match future::select(responses.next(), Delay::new(timeout)).await {
+		Either::Left((response, _)) => response,
+		Either::Right(_) => {
+			debug!(
+				target: "cumulus-network",
+				"no peer returned recent block {} in time",
+				hash,
+			);
+
+			None
+		}
+	}
+}
+
+/// Decode the `response` of `peer`, returns `None` if it doesn't contain the block `hash`.
+fn decode_response<Block: BlockT, E: std::fmt::Debug>(
+	peer: &PeerId,
+	hash: Block::Hash,
+	response: Result<Vec<u8>, E>,
+) -> Option<RecentBlockResponse<Block>> {
+	let response = match response {
+		Ok(response) => response,
+		Err(e) => {
+			trace!(
+				target: "cumulus-network",
+				"recent block request to {} failed: {:?}",
+				peer,
+				e,
+			);
+
+			return None;
+		}
+	};
+
+	match RecentBlockResponse::<Block>::decode(&mut &response[..]) {
+		Ok(response) if response.block.as_ref().map(|b| b.header().hash()) == Some(hash) => {
+			Some(response)
+		}
+		Ok(_) => None,
+		Err(e) => {
+			debug!(
+				target: "cumulus-network",
+				"failed to decode recent block response from {}: {:?}",
+				peer,
+				e,
+			);
+
+			None
+		}
+	}
+}
+
+/// Import the fetched `block` with `block_import`, after it was checked by the `verifier`.
+///
+/// This takes the same path as a block of the import queue, the `verifier` decides about the
+/// fork choice. Returns `true` if the block was imported.
+pub fn import_recent_block<Block, BI, V>(
+	block: Block,
+	mut block_import: BI,
+	mut verifier: V,
+) -> bool
+where
+	Block: BlockT,
+	BI: BlockImport<Block, Error = ConsensusError>,
+	V: Verifier<Block>,
+{
+	let hash = block.header().hash();
+	let (header, extrinsics) = block.deconstruct();
+	let block = IncomingBlock {
+		hash,
+		header: Some(header),
+		body: Some(extrinsics),
+		justification: None,
+		origin: None,
+		allow_missing_state: false,
+		import_existing: false,
+	};
+
+	match import_single_block(
+		&mut block_import,
+		BlockOrigin::NetworkBroadcast,
+		block,
+		&mut verifier,
+	) {
+		Ok(_) => true,
+		Err(e) => {
+			debug!(
+				target: "cumulus-network",
+				"failed to import recent block {}: {:?}",
+				hash,
+				e,
+			);
+
+			false
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_client::{
+		generate_block_inherents, runtime::Block, Client, DefaultTestClientBuilderExt,
+		TestClientBuilder, TestClientBuilderExt,
+	};
+	use futures::{executor::block_on, future::BoxFuture, FutureExt};
+	use sc_block_builder::BlockBuilderProvider;
+	use sp_blockchain::HeaderBackend;
+	use sp_consensus::{import_queue::CacheKeyId, BlockImportParams, ForkChoiceStrategy};
+	use sp_runtime::Justification;
+
+	use std::collections::HashMap;
+
+	fn genesis_block(client: &Client) -> Block {
+		client
+			.block(&BlockId::Number(0))
+			.expect("Reads the genesis block")
+			.expect("Genesis block exists")
+			.block
+	}
+
+	#[test]
+	fn handler_serves_known_blocks_with_their_justification() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let genesis = client.info().genesis_hash;
+		let justifications = RecentJustifications::default();
+		justifications.note(genesis, vec![1, 2, 3]);
+
+		let (handler, _) =
+			RecentBlocksHandler::new(client.clone(), justifications, &ProtocolId::from("test"));
+		let request = |hash| {
+			handler.handle_request(&RecentBlockRequest { hash }.encode(), &PeerId::random())
+		};
+
+		let response = request(genesis);
+		assert_eq!(Some(genesis), response.block.map(|b| b.header().hash()));
+		assert_eq!(Some(vec![1, 2, 3]), response.justification);
+
+		let response = request(Default::default());
+		assert!(response.block.is_none());
+		assert!(response.justification.is_none());
+
+		let response = handler.handle_request(&[1], &PeerId::random());
+		assert!(response.block.is_none());
+	}
+
+	#[test]
+	fn fetch_asks_peers_concurrently() {
+		let client = TestClientBuilder::new().build();
+		let block = genesis_block(&client);
+		let hash = block.header().hash();
+
+		let valid = RecentBlockResponse {
+			block: Some(block),
+			justification: None,
+		}
+		.encode();
+		let unknown = RecentBlockResponse::<Block> {
+			block: None,
+			justification: None,
+		}
+		.encode();
+
+		// The first peers never respond, return no block or fail, only the last has the block.
+		let mut responses: HashMap<PeerId, BoxFuture<'static, Result<Vec<u8>, ()>>> =
+			HashMap::new();
+		responses.insert(PeerId::random(), future::pending().boxed());
+		responses.insert(PeerId::random(), future::ready(Ok(unknown)).boxed());
+		responses.insert(PeerId::random(), future::ready(Err(())).boxed());
+		let with_block = PeerId::random();
+		let mut peers = responses.keys().cloned().collect::<Vec<_>>();
+		peers.push(with_block.clone());
+		responses.insert(with_block, future::ready(Ok(valid)).boxed());
+		let responses = Mutex::new(responses);
+
+		let response = block_on(fetch_from_peers(
+			peers,
+			hash,
+			Duration::from_secs(60),
+			|peer| responses.lock().remove(&peer).expect("Every peer is asked once"),
+		))
+		.expect("Returns the block of the last peer");
+		assert_eq!(Some(hash), response.block.map(|b| b.header().hash()));
+	}
+
+	#[test]
+	fn fetch_gives_up_after_the_timeout() {
+		let response = block_on(fetch_from_peers::<Block, _, ()>(
+			vec![PeerId::random(), PeerId::random()],
+			Default::default(),
+			Duration::from_millis(10),
+			|_| future::pending(),
+		));
+		assert!(response.is_none());
+	}
+
+	/// Accepts or rejects every block, like the verifier of an import queue.
+	struct TestVerifier {
+		accept: bool,
+	}
+
+	impl Verifier<Block> for TestVerifier {
+		fn verify(
+			&mut self,
+			origin: BlockOrigin,
+			header: <Block as BlockT>::Header,
+			justification: Option<Justification>,
+			body: Option<Vec<<Block as BlockT>::Extrinsic>>,
+		) -> Result<(BlockImportParams<Block, ()>, Option<Vec<(CacheKeyId, Vec<u8>)>>), String> {
+			if !self.accept {
+				return Err("Rejected".into());
+			}
+
+			let mut params = BlockImportParams::new(origin, header);
+			params.body = body;
+			params.justification = justification;
+			params.fork_choice = Some(ForkChoiceStrategy::Custom(false));
+
+			Ok((params, None))
+		}
+	}
+
+	#[test]
+	fn imports_only_verified_blocks() {
+		let builder_client = TestClientBuilder::new().build();
+		let mut builder = builder_client
+			.new_block_at(&BlockId::Number(0), Default::default(), false)
+			.expect("Initializes new block");
+		generate_block_inherents(&builder_client, None)
+			.into_iter()
+			.for_each(|e| builder.push(e).expect("Pushes an inherent"));
+		let block = builder.build().expect("Creates block").block;
+		let hash = block.header().hash();
+
+		let client = TestClientBuilder::new().build();
+		assert!(!import_recent_block(
+			block.clone(),
+			&client,
+			TestVerifier { accept: false },
+		));
+		assert!(client.header(BlockId::Hash(hash)).expect("Reads the header").is_none());
+
+		assert!(import_recent_block(block, &client, TestVerifier { accept: true }));
+		assert!(client.header(BlockId::Hash(hash)).expect("Reads the header").is_some());
+		// The verifier decides about the fork choice.
+		assert_eq!(client.info().genesis_hash, client.info().best_hash);
+	}
+}
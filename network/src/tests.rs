@@ -154,14 +154,13 @@ fn invalid_if_no_data_exceeds_best_known_number() {
 fn check_statement_is_encoded_correctly() {
 	let mut validator = make_validator_and_api().0;
 	let header = default_header();
-	let res = block_on(validator.validate(&header, &[0x42]))
-		.err()
-		.expect("Should fail on invalid encoded statement");
+	let res = block_on(validator.validate(&header, &[0x42]));
 
-	assert!(matches!(
-		*res.downcast::<ClientError>().unwrap(),
-		ClientError::BadJustification(x) if x.contains("must be a `SignedFullStatement`")
-	));
+	assert_eq!(
+		res.unwrap(),
+		Validation::Failure,
+		"validation fails on invalid encoded statement",
+	);
 }
 
 #[test]
@@ -219,14 +218,13 @@ fn check_signer_is_legit_validator() {
 	let (signed_statement, header) = make_gossip_message_and_header(api, relay_parent, 1);
 	let data = signed_statement.encode();
 
-	let res = block_on(validator.validate(&header, &data))
-		.err()
-		.expect("Should fail on invalid validator");
+	let res = block_on(validator.validate(&header, &data));
 
-	assert!(matches!(
-		*res.downcast::<ClientError>().unwrap(),
-		ClientError::BadJustification(x) if x.contains("signer is a validator")
-	));
+	assert_eq!(
+		res.unwrap(),
+		Validation::Failure,
+		"validation fails on invalid validator",
+	);
 }
 
 #[test]
@@ -242,14 +240,13 @@ fn check_statement_is_correctly_signed() {
 	let last = data.len() - 1;
 	data[last] = data[last].wrapping_add(1);
 
-	let res = block_on(validator.validate(&header, &data))
-		.err()
-		.expect("Validation should fail if the statement is not signed correctly");
+	let res = block_on(validator.validate(&header, &data));
 
-	assert!(matches!(
-		*res.downcast::<ClientError>().unwrap(),
-		ClientError::BadJustification(x) if x.contains("signature is invalid")
-	));
+	assert_eq!(
+		res.unwrap(),
+		Validation::Failure,
+		"validation fails if the statement is not signed correctly",
+	);
 }
 
 #[test]
@@ -286,14 +283,13 @@ fn check_statement_seconded() {
 	.expect("Signs statement");
 	let data = signed_statement.encode();
 
-	let res = block_on(validator.validate(&header, &data))
-		.err()
-		.expect("validation should fail if not seconded statement");
+	let res = block_on(validator.validate(&header, &data));
 
-	assert!(matches!(
-		*res.downcast::<ClientError>().unwrap(),
-		ClientError::BadJustification(x) if x.contains("must be a `Statement::Seconded`")
-	));
+	assert_eq!(
+		res.unwrap(),
+		Validation::Failure,
+		"validation fails if not seconded statement",
+	);
 }
 
 #[test]
@@ -305,14 +301,195 @@ fn check_header_match_candidate_receipt_header() {
 	let data = signed_statement.encode();
 	header.number = 300;
 
-	let res = block_on(validator.validate(&header, &data))
-		.err()
-		.expect("validation should fail if the header in doesn't match");
+	let res = block_on(validator.validate(&header, &data));
 
-	assert!(matches!(
-		*res.downcast::<ClientError>().unwrap(),
-		ClientError::BadJustification(x) if x.contains("header does not match")
+	assert_eq!(
+		res.unwrap(),
+		Validation::Failure,
+		"validation fails if the header in doesn't match",
+	);
+}
+
+#[test]
+fn valid_statement_makes_block_new_best() {
+	let (mut validator, api) = make_validator_and_api();
+	let relay_parent = H256::from_low_u64_be(1);
+
+	let (signed_statement, header) = make_gossip_message_and_header(api, relay_parent, 0);
+	let mut data = signed_statement.encode();
+
+	let res = block_on(validator.validate(&header, &data));
+	assert_eq!(res.unwrap(), Validation::Success { is_new_best: true });
+
+	// Without a verifier, the collator signature is ignored.
+	vec![1u8, 2, 3].encode_to(&mut data);
+
+	let res = block_on(validator.validate(&header, &data));
+	assert_eq!(res.unwrap(), Validation::Success { is_new_best: true });
+}
+
+#[test]
+fn announcements_are_encoded_with_their_version() {
+	use announcement::{
+		EncodedAnnouncement, ANNOUNCEMENT_VERSION, BASE_ANNOUNCEMENT_VERSION,
+		VERSIONED_ANNOUNCEMENT_TAG,
+	};
+	use parking_lot::Mutex;
+
+	let (mut validator, api) = make_validator_and_api();
+	let relay_parent = H256::from_low_u64_be(1);
+
+	let (signed_statement, header) = make_gossip_message_and_header(api, relay_parent, 0);
+	let announcement = CollationAnnouncement::Seconded {
+		statement: signed_statement.clone(),
+		signature: Some(vec![1, 2, 3]),
+	};
+
+	// The base version is the statement followed by the signature, as nodes of older releases
+	// expect it.
+	let base = announcement.to_data(BASE_ANNOUNCEMENT_VERSION);
+	let mut expected = signed_statement.encode();
+	vec![1u8, 2, 3].encode_to(&mut expected);
+	assert_eq!(expected, base);
+
+	let latest = announcement.to_data(ANNOUNCEMENT_VERSION);
+	assert_eq!(Some(&VERSIONED_ANNOUNCEMENT_TAG), latest.first());
+
+	for data in vec![base, latest] {
+		match CollationAnnouncement::from_data(&data) {
+			Ok(CollationAnnouncement::Seconded {
+				statement,
+				signature,
+			}) => {
+				assert_eq!(signed_statement.encode(), statement.encode());
+				assert_eq!(Some(vec![1, 2, 3]), signature);
+			}
+			other => panic!("Unexpected announcement: {:?}", other),
+		}
+
+		let res = block_on(validator.validate(&header, &data));
+		assert_eq!(res.unwrap(), Validation::Success { is_new_best: true });
+	}
+
+	// Without the versions of the peers, the announcement is encoded with the base version.
+	let announced = Arc::new(Mutex::new(Vec::new()));
+	let announce = {
+		let announced = announced.clone();
+		EncodedAnnouncement::new(move |_: H256, data: Vec<u8>| announced.lock().push(data))
+	};
+	AnnounceBlock::<Block>::announce_block(&announce, header.hash(), announcement.clone());
+	assert_eq!(
+		vec![announcement.to_data(BASE_ANNOUNCEMENT_VERSION)],
+		*announced.lock(),
+	);
+}
+
+#[test]
+fn collator_signature_is_checked_by_verifier() {
+	use announce_signing::{
+		KeystoreAnnounceSigner, Sr25519AnnounceVerifier, ANNOUNCE_KEY_TYPE,
+	};
+
+	let (validator, api) = make_validator_and_api();
+	let mut validator = validator.with_verifier(Arc::new(Sr25519AnnounceVerifier::new(vec![
+		Sr25519Keyring::Alice.public(),
+	])));
+	let relay_parent = H256::from_low_u64_be(1);
+
+	let (signed_statement, header) = make_gossip_message_and_header(api, relay_parent, 0);
+
+	let signed_data = |keyring: Sr25519Keyring| {
+		let keystore: SyncCryptoStorePtr = Arc::new(KeyStore::new());
+		SyncCryptoStore::sr25519_generate_new(
+			&*keystore,
+			ANNOUNCE_KEY_TYPE,
+			Some(&keyring.to_seed()),
+		)
+		.unwrap();
+		let signer = KeystoreAnnounceSigner::new(keystore).unwrap();
+
+		let mut data = signed_statement.encode();
+		AnnounceSigner::<Block>::sign(&signer, header.hash())
+			.unwrap()
+			.encode_to(&mut data);
+		data
+	};
+
+	let res = block_on(validator.validate(&header, &signed_data(Sr25519Keyring::Alice)));
+	assert_eq!(
+		res.unwrap(),
+		Validation::Success { is_new_best: true },
+		"a block signed by a registered collator becomes the new best",
+	);
+
+	let res = block_on(validator.validate(&header, &signed_statement.encode()));
+	assert_eq!(
+		res.unwrap(),
+		Validation::Success { is_new_best: false },
+		"an unsigned block is accepted, but doesn't become the new best",
+	);
+
+	let res = block_on(validator.validate(&header, &signed_data(Sr25519Keyring::Bob)));
+	assert_eq!(
+		res.unwrap(),
+		Validation::Failure,
+		"validation fails if the block is signed by an unknown collator",
+	);
+}
+
+/// Wait for the timeout of the announcement of a block without receiving a statement.
+///
+/// Returns the announcements that were made.
+fn announcements_after_timeout(on_timeout: AnnounceTimeoutAction) -> Vec<CollationAnnouncement> {
+	use parking_lot::Mutex;
+
+	let announced = Arc::new(Mutex::new(Vec::new()));
+	let announce_block: Arc<dyn AnnounceBlock<Block>> = {
+		let announced = announced.clone();
+		Arc::new(move |_: H256, announcement: CollationAnnouncement| {
+			announced.lock().push(announcement)
+		})
+	};
+	let policy = AnnouncePolicy {
+		timeout: Some(Duration::from_millis(10)),
+		on_timeout,
+		reannounce: false,
+	};
+
+	// The statement listener stays registered past the timeout, then it is closed.
+	let (sender, statements) = mpsc::channel(1);
+	block_on(futures::future::join(
+		announce_on_statements::<Block>(
+			statements,
+			H256::repeat_byte(1),
+			PHash::repeat_byte(2),
+			announce_block,
+			policy,
+			None,
+			None,
+		),
+		async move {
+			Delay::new(Duration::from_millis(100)).await;
+			drop(sender);
+		},
 	));
+
+	let announced = announced.lock().clone();
+	announced
+}
+
+#[test]
+fn unseconded_blocks_are_dropped_by_default() {
+	assert_eq!(AnnounceTimeoutAction::Drop, AnnouncePolicy::default().on_timeout);
+	assert!(announcements_after_timeout(AnnounceTimeoutAction::Drop).is_empty());
+}
+
+#[test]
+fn unseconded_blocks_are_announced_without_data() {
+	let announced = announcements_after_timeout(AnnounceTimeoutAction::Announce);
+
+	assert_eq!(1, announced.len());
+	assert!(matches!(announced[0], CollationAnnouncement::Empty));
 }
 
 #[derive(Default)]
@@ -30,19 +30,27 @@
 //! Users must ensure that they register this pallet as an inherent provider.
 
 use cumulus_primitives::{
-	inherents::VALIDATION_DATA_IDENTIFIER as INHERENT_IDENTIFIER,
-	well_known_keys::{NEW_VALIDATION_CODE, VALIDATION_DATA},
-	OnValidationData, ValidationData,
+	inherents::{
+		ParaIdType, PARA_ID_IDENTIFIER, VALIDATION_DATA_IDENTIFIER as INHERENT_IDENTIFIER,
+	},
+	versioned::{IncompatibleEncoding, VersionedValidationData},
+	well_known_keys::{HRMP_WATERMARK, NEW_VALIDATION_CODE, TIMESTAMP_ANCHOR, VALIDATION_DATA},
+	CodeUpgradeError, CollationInfo, OnValidationData, ParaId, ParachainActivation,
+	PersistedValidationData, TimestampAnchor, ValidationData,
 };
 use frame_support::{
 	decl_error, decl_event, decl_module, decl_storage, ensure, storage,
+	traits::Get,
 	weights::{DispatchClass, Weight},
 };
 use frame_system::{ensure_none, ensure_root};
-use parachain::primitives::RelayChainBlockNumber;
+use parachain::primitives::{HeadData, RelayChainBlockNumber};
 use sp_core::storage::well_known_keys;
 use sp_inherents::{InherentData, InherentIdentifier, ProvideInherent};
-use sp_std::vec::Vec;
+use sp_runtime::traits::{BlockNumberProvider, UniqueSaturatedInto};
+use sp_std::{marker::PhantomData, vec::Vec};
+
+use codec::Encode;
 
 type System<T> = frame_system::Module<T>;
 
@@ -53,6 +61,14 @@ pub trait Trait: frame_system::Trait {
 
 	/// Something which can be notified when the validation data is set.
 	type OnValidationData: OnValidationData;
+
+	/// Tells if the validation data is required, use `()` for chains that started as parachain.
+	type Activation: ParachainActivation;
+
+	/// The id of this parachain.
+	///
+	/// Blocks are only built if the collator fetched the validation data for this id.
+	type SelfParaId: Get<ParaId>;
 }
 
 // This pallet's storage items.
@@ -68,6 +84,28 @@ decl_storage! {
 
 		/// Were the validation data set to notify the relay chain?
 		DidSetValidationCode: bool;
+
+		/// The [`PersistedValidationData`] that was set by the last block.
+		///
+		/// In contrast to [`Module::validation_data`], this is kept across blocks and can be
+		/// used to reason about the relay chain context before the inherent of the current block
+		/// was applied.
+		LastPersistedValidationData get(fn last_persisted_validation_data):
+			Option<PersistedValidationData>;
+
+		/// The relay chain blocks that sent the horizontal messages processed in this block.
+		///
+		/// Set with [`Module::note_processed_hrmp_message`].
+		ProcessedHrmpMessages: Vec<RelayChainBlockNumber>;
+
+		/// The first relay chain block that sent a horizontal message this block did not process.
+		///
+		/// Set with [`Module::note_unprocessed_hrmp_message`]. If it is not set, the ingress up to
+		/// the relay parent counts as processed.
+		FirstUnprocessedHrmpMessage: Option<RelayChainBlockNumber>;
+
+		/// The HRMP watermark of the last block.
+		LastHrmpWatermark get(fn last_hrmp_watermark): Option<RelayChainBlockNumber>;
 	}
 }
 
@@ -122,13 +160,31 @@ decl_module! {
 			}
 
 			storage::unhashed::put(VALIDATION_DATA, &vfp);
+			LastPersistedValidationData::put(&vfp.persisted);
 			DidUpdateValidationData::put(true);
 			<T::OnValidationData as OnValidationData>::on_validation_data(vfp);
 		}
 
 		fn on_finalize() {
-			assert!(DidUpdateValidationData::take(), "VFPs must be updated once per block");
+			let did_update = DidUpdateValidationData::take();
+			if T::Activation::is_active() {
+				assert!(did_update, "VFPs must be updated once per block");
+			}
 			DidSetValidationCode::take();
+
+			// Taken in every block, so the notes can not leak into the next block.
+			let processed = ProcessedHrmpMessages::take();
+			let first_unprocessed = FirstUnprocessedHrmpMessage::take();
+
+			if let Some(vfp) = Self::validation_data() {
+				let watermark = Self::compute_hrmp_watermark(
+					vfp.persisted.block_number,
+					processed,
+					first_unprocessed,
+				);
+				storage::unhashed::put(HRMP_WATERMARK, &watermark);
+				LastHrmpWatermark::put(watermark);
+			}
 		}
 
 		fn on_initialize(n: T::BlockNumber) -> Weight {
@@ -140,6 +196,7 @@ decl_module! {
 			}
 
 			storage::unhashed::kill(VALIDATION_DATA);
+			storage::unhashed::kill(HRMP_WATERMARK);
 
 			0
 		}
@@ -154,6 +211,100 @@ impl<T: Trait> Module<T> {
 		storage::unhashed::get(VALIDATION_DATA)
 	}
 
+	/// The HRMP watermark of the last block.
+	pub fn hrmp_watermark() -> Option<RelayChainBlockNumber> {
+		storage::unhashed::get(HRMP_WATERMARK)
+	}
+
+	/// The output of the last block, whose header is `header`.
+	///
+	/// Exposed to the collator through [`cumulus_primitives::CollectCollationInfo`].
+	pub fn collect_collation_info(header: &T::Header) -> CollationInfo {
+		let relay_block_number = Self::validation_data()
+			.map(|vfp| vfp.persisted.block_number)
+			.unwrap_or_default();
+
+		CollationInfo::from_storage(
+			storage::unhashed::get_raw,
+			HeadData(header.encode()),
+			relay_block_number,
+		)
+		.unwrap_or_else(|e| panic!("Failed to decode {}: {:?}", e.what, e.error))
+	}
+
+	/// Note that this block processed a horizontal message that was sent at the relay chain block
+	/// `sent_at`.
+	///
+	/// Only required from handlers of the HRMP ingress that also call
+	/// [`Module::note_unprocessed_hrmp_message`]. Fails if the message can not be processed by
+	/// this block, see [`Module::check_hrmp_message`].
+	pub fn note_processed_hrmp_message(sent_at: RelayChainBlockNumber) -> Result<(), Error<T>> {
+		Self::check_hrmp_message(sent_at)?;
+		ProcessedHrmpMessages::append(sent_at);
+		Ok(())
+	}
+
+	/// Note that this block did not process a horizontal message that was sent at the relay chain
+	/// block `sent_at`.
+	///
+	/// Handlers of the HRMP ingress that stop before the relay parent, e.g. because the block is
+	/// full, need to call this for the first message they skip. Otherwise the watermark claims
+	/// that the remaining messages were processed as well. Fails like
+	/// [`Module::note_processed_hrmp_message`].
+	pub fn note_unprocessed_hrmp_message(sent_at: RelayChainBlockNumber) -> Result<(), Error<T>> {
+		Self::check_hrmp_message(sent_at)?;
+		let first = FirstUnprocessedHrmpMessage::get().map_or(sent_at, |n| n.min(sent_at));
+		FirstUnprocessedHrmpMessage::put(first);
+		Ok(())
+	}
+
+	/// Check that a horizontal message that was sent at the relay chain block `sent_at` can be
+	/// processed by this block.
+	///
+	/// The message has to be sent after the watermark of the last block, which already covers
+	/// every message up to it, and at the latest at the relay parent.
+	fn check_hrmp_message(sent_at: RelayChainBlockNumber) -> Result<(), Error<T>> {
+		let relay_parent_number = Self::validation_data()
+			.ok_or(Error::<T>::ValidationDataNotAvailable)?
+			.persisted
+			.block_number;
+		ensure!(sent_at <= relay_parent_number, Error::<T>::HrmpMessageAfterRelayParent);
+
+		if let Some(last) = LastHrmpWatermark::get() {
+			ensure!(sent_at > last, Error::<T>::HrmpMessageBeforeWatermark);
+		}
+
+		Ok(())
+	}
+
+	/// Compute the HRMP watermark of this block, which is built on `relay_parent_number`.
+	///
+	/// The relay chain accepts either the relay parent or a block that is after the watermark of
+	/// the last block and that sent horizontal messages to this parachain. If all messages were
+	/// processed, this is the relay parent. Otherwise it is the last block before the first
+	/// unprocessed message that sent a processed message.
+	///
+	/// If no processed message advances the watermark, the watermark of the last block is kept,
+	/// or the relay parent is used for the first block.
+	fn compute_hrmp_watermark(
+		relay_parent_number: RelayChainBlockNumber,
+		processed: Vec<RelayChainBlockNumber>,
+		first_unprocessed: Option<RelayChainBlockNumber>,
+	) -> RelayChainBlockNumber {
+		let first_unprocessed = match first_unprocessed {
+			Some(first) if first <= relay_parent_number => first,
+			_ => return relay_parent_number,
+		};
+
+		let last = LastHrmpWatermark::get();
+		processed
+			.into_iter()
+			.filter(|n| *n < first_unprocessed && last.map_or(true, |last| *n > last))
+			.max()
+			.or(last)
+			.unwrap_or(relay_parent_number)
+	}
+
 	/// Put a new validation function into a particular location where polkadot
 	/// monitors for updates. Calling this function notifies polkadot that a new
 	/// upgrade has been scheduled.
@@ -180,23 +331,32 @@ impl<T: Trait> Module<T> {
 		Self::validation_data().map(|vfp| vfp.transient.max_code_size)
 	}
 
+	/// Check if new validation code of `code_size` bytes can be scheduled in this block.
+	///
+	/// Returns the relay chain block number at which the upgrade would be applied. Outside of
+	/// block execution, this checks against the validation data of the last block.
+	pub fn check_code_upgrade(code_size: u32) -> Result<RelayChainBlockNumber, CodeUpgradeError> {
+		if PendingValidationFunction::exists() {
+			return Err(CodeUpgradeError::OverlappingUpgrades);
+		}
+		let vfp = Self::validation_data().ok_or(CodeUpgradeError::ValidationDataNotAvailable)?;
+		if code_size > vfp.transient.max_code_size {
+			return Err(CodeUpgradeError::TooBig {
+				max_code_size: vfp.transient.max_code_size,
+			});
+		}
+		vfp.transient
+			.code_upgrade_allowed
+			.ok_or(CodeUpgradeError::ProhibitedByRelayChain)
+	}
+
 	/// The implementation of the runtime upgrade scheduling.
 	fn schedule_upgrade_impl(
 		validation_function: Vec<u8>,
 	) -> frame_support::dispatch::DispatchResult {
-		ensure!(
-			!PendingValidationFunction::exists(),
-			Error::<T>::OverlappingUpgrades
-		);
-		let vfp = Self::validation_data().ok_or(Error::<T>::ValidationDataNotAvailable)?;
-		ensure!(
-			validation_function.len() <= vfp.transient.max_code_size as usize,
-			Error::<T>::TooBig
-		);
-		let apply_block = vfp
-			.transient
-			.code_upgrade_allowed
-			.ok_or(Error::<T>::ProhibitedByPolkadot)?;
+		let apply_block =
+			Self::check_code_upgrade(validation_function.len().unique_saturated_into())
+				.map_err(Error::<T>::from)?;
 
 		// When a code upgrade is scheduled, it has to be applied in two
 		// places, synchronized: both polkadot and the individual parachain
@@ -213,17 +373,98 @@ impl<T: Trait> Module<T> {
 	}
 }
 
+/// Provides the relay chain block number the current parachain block is built on.
+///
+/// The relay chain block number keeps advancing when the parachain stalls, which makes it a
+/// better clock than the parachain block number for pallets like vesting or the scheduler.
+///
+/// Before the validation data of the current block was set, the relay chain block number of
+/// the previous block is returned.
+pub struct RelaychainBlockNumberProvider<T>(PhantomData<T>);
+
+impl<T: Trait> BlockNumberProvider for RelaychainBlockNumberProvider<T> {
+	type BlockNumber = RelayChainBlockNumber;
+
+	fn current_block_number() -> Self::BlockNumber {
+		Module::<T>::validation_data()
+			.map(|d| d.persisted.block_number)
+			.or_else(|| Module::<T>::last_persisted_validation_data().map(|d| d.block_number))
+			.unwrap_or_default()
+	}
+}
+
+/// Checks that the timestamp of the block is within the relay chain slot implied by the
+/// validation data.
+///
+/// The relay chain block number and the timestamp of every block are stored as
+/// [`TimestampAnchor`] and the timestamp of the next block needs to be within `max_drift` of the
+/// slot implied by the relay chain blocks that passed since then. This prevents collators from
+/// drifting the timestamp to manipulate time based logic.
+///
+/// This should be used as [`Trait::OnValidationData`]. As it reads the timestamp of the current
+/// block, `pallet_timestamp` needs to be placed before this pallet in `construct_runtime!`.
+pub struct CheckRelayTimestamp<T, RelayBlockTime, MaxDrift>(
+	PhantomData<(T, RelayBlockTime, MaxDrift)>,
+);
+
+impl<T, RelayBlockTime, MaxDrift> OnValidationData
+	for CheckRelayTimestamp<T, RelayBlockTime, MaxDrift>
+where
+	T: pallet_timestamp::Trait,
+	RelayBlockTime: Get<u64>,
+	MaxDrift: Get<u64>,
+{
+	fn on_validation_data(data: ValidationData) {
+		let timestamp: u64 = pallet_timestamp::Module::<T>::now().unique_saturated_into();
+		let relay_block_number = data.persisted.block_number;
+
+		if let Some(anchor) = storage::unhashed::get::<TimestampAnchor>(TIMESTAMP_ANCHOR) {
+			assert!(
+				anchor.check(relay_block_number, timestamp),
+				"Timestamp is not within the slot of the relay chain block",
+			);
+		}
+
+		storage::unhashed::put(
+			TIMESTAMP_ANCHOR,
+			&TimestampAnchor {
+				relay_block_number,
+				timestamp,
+				relay_block_time: RelayBlockTime::get(),
+				max_drift: MaxDrift::get(),
+			},
+		);
+	}
+}
+
 impl<T: Trait> ProvideInherent for Module<T> {
 	type Call = Call<T>;
 	type Error = sp_inherents::MakeFatalError<()>;
 	const INHERENT_IDENTIFIER: InherentIdentifier = INHERENT_IDENTIFIER;
 
 	fn create_inherent(data: &InherentData) -> Option<Self::Call> {
-		let data: ValidationData = data
-			.get_data(&INHERENT_IDENTIFIER)
-			.ok()
-			.flatten()
-			.expect("validation function params are always injected into inherent data; qed");
+		if !T::Activation::is_active() {
+			return None;
+		}
+
+		// The para id is optional, collators that don't provide it are not checked.
+		if let Ok(Some(para_id)) = data.get_data::<ParaIdType>(&PARA_ID_IDENTIFIER) {
+			let self_para_id = T::SelfParaId::get();
+			if para_id != self_para_id {
+				panic!(
+					"The validation data was fetched for parachain {}, but the runtime is parachain {}. \
+					Is the node started with the wrong `--parachain-id`?",
+					u32::from(para_id),
+					u32::from(self_para_id),
+				);
+			}
+		}
+
+		let data = match data.get_data::<VersionedValidationData>(&INHERENT_IDENTIFIER) {
+			Ok(Some(data)) => data.0,
+			Ok(None) => panic!("validation function params are always injected into inherent data; qed"),
+			Err(_) => panic!("{}", IncompatibleEncoding::MESSAGE),
+		};
 
 		Some(Call::set_validation_data(data))
 	}
@@ -248,6 +489,21 @@ decl_error! {
 		TooBig,
 		/// The inherent which supplies the validation data did not run this block
 		ValidationDataNotAvailable,
+		/// The horizontal message was sent at or before the HRMP watermark of the last block
+		HrmpMessageBeforeWatermark,
+		/// The horizontal message was sent after the relay parent of this block
+		HrmpMessageAfterRelayParent,
+	}
+}
+
+impl<T: Trait> From<CodeUpgradeError> for Error<T> {
+	fn from(error: CodeUpgradeError) -> Self {
+		match error {
+			CodeUpgradeError::OverlappingUpgrades => Error::<T>::OverlappingUpgrades,
+			CodeUpgradeError::ProhibitedByRelayChain => Error::<T>::ProhibitedByPolkadot,
+			CodeUpgradeError::TooBig { .. } => Error::<T>::TooBig,
+			CodeUpgradeError::ValidationDataNotAvailable => Error::<T>::ValidationDataNotAvailable,
+		}
 	}
 }
 
@@ -256,7 +512,6 @@ decl_error! {
 mod tests {
 	use super::*;
 
-	use codec::Encode;
 	use cumulus_primitives::{PersistedValidationData, TransientValidationData};
 	use frame_support::{
 		assert_ok,
@@ -336,9 +591,14 @@ mod tests {
 		type BaseCallFilter = ();
 		type SystemWeightInfo = ();
 	}
+	parameter_types! {
+		pub SelfParaId: ParaId = 100.into();
+	}
 	impl Trait for Test {
 		type Event = TestEvent;
 		type OnValidationData = ();
+		type Activation = ();
+		type SelfParaId = SelfParaId;
 	}
 
 	type ParachainUpgrade = Module<Test>;
@@ -644,6 +904,139 @@ mod tests {
 			);
 	}
 
+	#[test]
+	fn stores_last_persisted_validation_data() {
+		BlockTests::new()
+			.add(123, || {
+				assert_eq!(
+					ParachainUpgrade::last_persisted_validation_data().map(|d| d.block_number),
+					Some(123),
+				);
+			})
+			.add_with_post_test(
+				124,
+				|| {},
+				|| {
+					assert_eq!(
+						ParachainUpgrade::last_persisted_validation_data().map(|d| d.block_number),
+						Some(124),
+					);
+				},
+			);
+	}
+
+	#[test]
+	fn relay_chain_block_number_provider() {
+		BlockTests::new()
+			.add(123, || {
+				assert_eq!(
+					RelaychainBlockNumberProvider::<Test>::current_block_number(),
+					123,
+				);
+			})
+			.add_with_post_test(
+				200,
+				|| {},
+				|| {
+					// The validation data is killed when the next block is initialized.
+					storage::unhashed::kill(VALIDATION_DATA);
+					assert_eq!(
+						RelaychainBlockNumberProvider::<Test>::current_block_number(),
+						200,
+					);
+				},
+			);
+	}
+
+	#[test]
+	fn computes_hrmp_watermark() {
+		BlockTests::new()
+			.add_with_post_test(
+				123,
+				|| {
+					assert_ok!(ParachainUpgrade::note_processed_hrmp_message(90));
+					assert_ok!(ParachainUpgrade::note_processed_hrmp_message(100));
+					assert_ok!(ParachainUpgrade::note_unprocessed_hrmp_message(110));
+				},
+				|| assert_eq!(ParachainUpgrade::hrmp_watermark(), Some(100)),
+			)
+			.add_with_post_test(
+				124,
+				|| {
+					assert_ok!(ParachainUpgrade::note_processed_hrmp_message(110));
+					assert_ok!(ParachainUpgrade::note_processed_hrmp_message(115));
+					assert_ok!(ParachainUpgrade::note_unprocessed_hrmp_message(120));
+					// The earliest unprocessed message counts.
+					assert_ok!(ParachainUpgrade::note_unprocessed_hrmp_message(115));
+				},
+				// The watermark lands on the last block that sent a message before 115.
+				|| assert_eq!(ParachainUpgrade::hrmp_watermark(), Some(110)),
+			)
+			.add_with_post_test(
+				125,
+				|| assert_ok!(ParachainUpgrade::note_processed_hrmp_message(115)),
+				|| assert_eq!(ParachainUpgrade::hrmp_watermark(), Some(125)),
+			);
+	}
+
+	#[test]
+	fn keeps_the_hrmp_watermark_that_does_not_advance() {
+		BlockTests::new()
+			.add_with_post_test(
+				123,
+				// Nothing before the first unprocessed message, there is no last watermark yet.
+				|| assert_ok!(ParachainUpgrade::note_unprocessed_hrmp_message(100)),
+				|| assert_eq!(ParachainUpgrade::hrmp_watermark(), Some(123)),
+			)
+			.add_with_post_test(
+				124,
+				|| {
+					assert_ok!(ParachainUpgrade::note_processed_hrmp_message(124));
+					assert_ok!(ParachainUpgrade::note_unprocessed_hrmp_message(124));
+				},
+				// No processed message was sent before the first unprocessed one.
+				|| assert_eq!(ParachainUpgrade::hrmp_watermark(), Some(123)),
+			);
+	}
+
+	#[test]
+	fn rejects_hrmp_messages_the_block_can_not_process() {
+		BlockTests::new()
+			.add(123, || {
+				assert_ok!(ParachainUpgrade::note_processed_hrmp_message(100));
+				assert_ok!(ParachainUpgrade::note_unprocessed_hrmp_message(110));
+			})
+			.add(124, || {
+				assert!(matches!(
+					ParachainUpgrade::note_processed_hrmp_message(100),
+					Err(Error::<Test>::HrmpMessageBeforeWatermark),
+				));
+				assert!(matches!(
+					ParachainUpgrade::note_unprocessed_hrmp_message(90),
+					Err(Error::<Test>::HrmpMessageBeforeWatermark),
+				));
+				assert!(matches!(
+					ParachainUpgrade::note_processed_hrmp_message(125),
+					Err(Error::<Test>::HrmpMessageAfterRelayParent),
+				));
+			});
+	}
+
+	#[test]
+	fn clears_the_hrmp_notes_without_validation_data() {
+		new_test_ext().execute_with(|| {
+			DidUpdateValidationData::put(true);
+			ProcessedHrmpMessages::append(100);
+			FirstUnprocessedHrmpMessage::put(110);
+
+			ParachainUpgrade::on_finalize(1);
+
+			assert!(!ProcessedHrmpMessages::exists());
+			assert!(!FirstUnprocessedHrmpMessage::exists());
+			assert_eq!(ParachainUpgrade::hrmp_watermark(), None);
+		});
+	}
+
 	#[test]
 	fn checks_size() {
 		BlockTests::new()
@@ -663,6 +1056,37 @@ mod tests {
 					ParachainUpgrade::schedule_upgrade(RawOrigin::Root.into(), vec![0; 64]),
 					Err(Error::<Test>::TooBig.into()),
 				);
+				assert_eq!(
+					ParachainUpgrade::check_code_upgrade(64),
+					Err(CodeUpgradeError::TooBig { max_code_size: 32 }),
+				);
+				assert_eq!(ParachainUpgrade::check_code_upgrade(32), Ok(1123));
 			});
 	}
+
+	fn inherent_data_for_para(para_id: u32) -> InherentData {
+		let mut inherent_data = InherentData::default();
+		inherent_data
+			.put_data(INHERENT_IDENTIFIER, &ValidationData::default())
+			.expect("failed to put VFP inherent");
+		inherent_data
+			.put_data(PARA_ID_IDENTIFIER, &ParaId::from(para_id))
+			.expect("failed to put para id inherent");
+		inherent_data
+	}
+
+	#[test]
+	fn creates_the_inherent_for_the_own_para_id() {
+		new_test_ext().execute_with(|| {
+			assert!(ParachainUpgrade::create_inherent(&inherent_data_for_para(100)).is_some());
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "wrong `--parachain-id`")]
+	fn refuses_validation_data_of_another_para() {
+		new_test_ext().execute_with(|| {
+			ParachainUpgrade::create_inherent(&inherent_data_for_para(200));
+		});
+	}
 }
@@ -0,0 +1,164 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The output of a parachain block that is sent to the relay chain.
+//!
+//! The runtime stores its output under the [`well_known_keys`](crate::well_known_keys). Both
+//! `validate_block` and the collator read it from there after executing a block, with
+//! [`CollationInfo::from_storage`].
+
+use crate::{relay_chain, well_known_keys, GenericUpwardMessage, ParaId};
+
+use codec::{Decode, Encode};
+use polkadot_core_primitives::OutboundHrmpMessage;
+use polkadot_parachain::primitives::{HeadData, ValidationCode, ValidationResult};
+use sp_std::vec::Vec;
+
+/// The output of a parachain block.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct CollationInfo {
+	/// The upward messages sent by the block.
+	pub upward_messages: Vec<GenericUpwardMessage>,
+	/// The horizontal messages sent by the block.
+	pub horizontal_messages: Vec<OutboundHrmpMessage<ParaId>>,
+	/// The new validation code, if the block schedules an upgrade.
+	pub new_validation_code: Option<ValidationCode>,
+	/// The number of downward messages processed by the block.
+	pub processed_downward_messages: u32,
+	/// The HRMP watermark of the block.
+	pub hrmp_watermark: relay_chain::BlockNumber,
+	/// The head data of the block.
+	pub head_data: HeadData,
+}
+
+/// A value of the [`CollationInfo`] in the storage could not be decoded.
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct InvalidCollationInfo {
+	/// The name of the value that could not be decoded.
+	pub what: &'static str,
+	/// The decoding error.
+	pub error: codec::Error,
+}
+
+impl CollationInfo {
+	/// Read the output of the block with the given `head_data` from the storage, through `get`.
+	///
+	/// `get` needs to return the storage after the block was executed. `relay_block_number` is
+	/// the number of the relay parent, it is the HRMP watermark if the runtime did not compute
+	/// one.
+	pub fn from_storage(
+		get: impl Fn(&[u8]) -> Option<Vec<u8>>,
+		head_data: HeadData,
+		relay_block_number: relay_chain::BlockNumber,
+	) -> Result<Self, InvalidCollationInfo> {
+		fn decode<T: Decode>(
+			value: Option<Vec<u8>>,
+			what: &'static str,
+		) -> Result<Option<T>, InvalidCollationInfo> {
+			value
+				.map(|v| T::decode(&mut &v[..]))
+				.transpose()
+				.map_err(|error| InvalidCollationInfo { what, error })
+		}
+
+		let upward_messages = decode(get(well_known_keys::UPWARD_MESSAGES), "the upward messages")?
+			.unwrap_or_default();
+		let horizontal_messages = decode(
+			get(well_known_keys::HRMP_OUTBOUND_MESSAGES),
+			"the horizontal messages",
+		)?
+		.unwrap_or_default();
+		let processed_downward_messages = decode(
+			get(well_known_keys::PROCESSED_DOWNWARD_MESSAGES),
+			"the count of processed downward messages",
+		)?
+		.unwrap_or_default();
+		let hrmp_watermark = decode(get(well_known_keys::HRMP_WATERMARK), "the hrmp watermark")?
+			.unwrap_or(relay_block_number);
+
+		Ok(Self {
+			upward_messages,
+			horizontal_messages,
+			new_validation_code: get(well_known_keys::NEW_VALIDATION_CODE).map(ValidationCode),
+			processed_downward_messages,
+			hrmp_watermark,
+			head_data,
+		})
+	}
+}
+
+impl From<CollationInfo> for ValidationResult {
+	fn from(info: CollationInfo) -> Self {
+		ValidationResult {
+			head_data: info.head_data,
+			new_validation_code: info.new_validation_code,
+			upward_messages: info.upward_messages,
+			horizontal_messages: info.horizontal_messages,
+			processed_downward_messages: info.processed_downward_messages,
+			hrmp_watermark: info.hrmp_watermark,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::collections::HashMap;
+
+	#[test]
+	fn reads_the_output_from_the_storage() {
+		let horizontal_messages = vec![OutboundHrmpMessage {
+			recipient: ParaId::from(200),
+			data: vec![7],
+		}];
+		let storage: HashMap<&[u8], Vec<u8>> = vec![
+			(well_known_keys::UPWARD_MESSAGES, vec![vec![1u8]].encode()),
+			(well_known_keys::HRMP_OUTBOUND_MESSAGES, horizontal_messages.encode()),
+			(well_known_keys::PROCESSED_DOWNWARD_MESSAGES, 2u32.encode()),
+			(well_known_keys::NEW_VALIDATION_CODE, vec![3, 4]),
+		]
+		.into_iter()
+		.collect();
+		let get = |key: &[u8]| storage.get(key).cloned();
+
+		let info = CollationInfo::from_storage(get, HeadData(vec![5]), 6)
+			.expect("Reads the collation info");
+
+		assert_eq!(vec![vec![1u8]], info.upward_messages);
+		assert_eq!(horizontal_messages, info.horizontal_messages);
+		assert_eq!(2, info.processed_downward_messages);
+		assert_eq!(Some(ValidationCode(vec![3, 4])), info.new_validation_code);
+		// Falls back to the relay parent.
+		assert_eq!(6, info.hrmp_watermark);
+		assert_eq!(HeadData(vec![5]), info.head_data);
+
+		let invalid = |key: &[u8]| {
+			if key == well_known_keys::HRMP_WATERMARK {
+				Some(vec![1])
+			} else {
+				None
+			}
+		};
+		assert_eq!(
+			"the hrmp watermark",
+			CollationInfo::from_storage(invalid, HeadData(vec![5]), 6)
+				.expect_err("The watermark is invalid")
+				.what,
+		);
+	}
+}
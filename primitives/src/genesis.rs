@@ -16,7 +16,10 @@
 
 use codec::Encode;
 use sc_chain_spec::ChainSpec;
-use sp_runtime::traits::{Block as BlockT, Hash as HashT, Header as HeaderT, Zero};
+use sp_runtime::{
+	traits::{Block as BlockT, Hash as HashT, Header as HeaderT, Zero},
+	Storage,
+};
 
 /// Generate the genesis state for a given ChainSpec.
 pub fn generate_genesis_block<Block: BlockT>(
@@ -24,6 +27,11 @@ pub fn generate_genesis_block<Block: BlockT>(
 ) -> Result<Block, String> {
 	let storage = chain_spec.build_storage()?;
 
+	Ok(genesis_block_from_storage(&storage))
+}
+
+/// Generate the genesis block of a chain that starts with the given `storage`.
+pub fn genesis_block_from_storage<Block: BlockT>(storage: &Storage) -> Block {
 	let child_roots = storage.children_default.iter().map(|(sk, child_content)| {
 		let state_root = <<<Block as BlockT>::Header as HeaderT>::Hashing as HashT>::trie_root(
 			child_content.data.clone().into_iter().collect(),
@@ -37,7 +45,7 @@ pub fn generate_genesis_block<Block: BlockT>(
 	let extrinsics_root =
 		<<<Block as BlockT>::Header as HeaderT>::Hashing as HashT>::trie_root(Vec::new());
 
-	Ok(Block::new(
+	Block::new(
 		<<Block as BlockT>::Header as HeaderT>::new(
 			Zero::zero(),
 			extrinsics_root,
@@ -46,5 +54,5 @@ pub fn generate_genesis_block<Block: BlockT>(
 			Default::default(),
 		),
 		Default::default(),
-	))
+	)
 }
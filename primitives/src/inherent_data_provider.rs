@@ -0,0 +1,188 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Client side construction of the Cumulus inherent data.
+
+use crate::{
+	inherents::{
+		DownwardMessagesType, DOWNWARD_MESSAGES_IDENTIFIER, PARA_ID_IDENTIFIER,
+		VALIDATION_DATA_IDENTIFIER,
+	},
+	relay_chain::Hash as PHash,
+	ParaId, RelaySessionInfo, ValidationData,
+};
+use sp_inherents::{Error, InherentData};
+
+/// The relay chain data required to build the inherent data of a parachain block.
+pub trait RelayChainInterface: Send + Sync {
+	/// Returns the downward messages for the parachain at the given `relay_parent`.
+	fn downward_messages(&self, relay_parent: PHash) -> Result<DownwardMessagesType, String>;
+
+	/// Returns the session the parachain is in at the given `relay_parent`.
+	///
+	/// The session info is optional, by default no session info is provided. It is only used on
+	/// the client side and not passed to the runtime, as it can not be checked against the
+	/// relay chain during validation.
+	fn session_info(&self, _relay_parent: PHash) -> Result<Option<RelaySessionInfo>, String> {
+		Ok(None)
+	}
+
+	/// Returns the id of the parachain the relay chain data is read for.
+	///
+	/// By default the id is unknown.
+	fn para_id(&self) -> Option<ParaId> {
+		None
+	}
+
+	/// Returns the time validators have to execute a candidate at the given `relay_parent`.
+	///
+	/// By default no timeout is provided and the collator assumes the timeout of Polkadot.
+	fn execution_timeout(
+		&self,
+		_relay_parent: PHash,
+	) -> Result<Option<std::time::Duration>, String> {
+		Ok(None)
+	}
+
+	/// Returns the maximum size of a PoV in bytes the relay chain accepts at the given
+	/// `relay_parent`.
+	///
+	/// By default no size is provided and the collator assumes the default of Polkadot.
+	fn max_pov_size(&self, _relay_parent: PHash) -> Result<Option<u32>, String> {
+		Ok(None)
+	}
+}
+
+impl<F> RelayChainInterface for F
+where
+	F: Fn(PHash) -> Result<DownwardMessagesType, String> + Send + Sync,
+{
+	fn downward_messages(&self, relay_parent: PHash) -> Result<DownwardMessagesType, String> {
+		(self)(relay_parent)
+	}
+}
+
+/// Provides the inherent data that is required by the `parachain-upgrade` pallet.
+///
+/// This is created per block, as the data depends on the relay parent the block is built on. It
+/// can be used by every authorship path, e.g. the collator, manual seal or tests.
+#[derive(Clone, Debug)]
+pub struct ParachainInherentDataProvider {
+	validation_data: ValidationData,
+	downward_messages: DownwardMessagesType,
+	para_id: Option<ParaId>,
+}
+
+impl ParachainInherentDataProvider {
+	/// Create a new instance from the given data.
+	pub fn new(validation_data: ValidationData, downward_messages: DownwardMessagesType) -> Self {
+		Self {
+			validation_data,
+			downward_messages,
+			para_id: None,
+		}
+	}
+
+	/// Also provide the id of the parachain the `validation_data` was fetched for.
+	///
+	/// The runtime refuses to build a block if the id does not match its own id.
+	pub fn with_para_id(mut self, para_id: Option<ParaId>) -> Self {
+		self.para_id = para_id;
+		self
+	}
+
+	/// Create a new instance for a block that is built on `relay_parent`.
+	///
+	/// The downward messages and the para id are taken from the given `relay_chain`.
+	pub fn create_at(
+		relay_parent: PHash,
+		validation_data: ValidationData,
+		relay_chain: &dyn RelayChainInterface,
+	) -> Result<Self, String> {
+		Ok(Self::new(
+			validation_data,
+			relay_chain.downward_messages(relay_parent)?,
+		)
+		.with_para_id(relay_chain.para_id()))
+	}
+
+	/// Put the data into the given `inherent_data`.
+	pub fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> Result<(), Error> {
+		inherent_data.put_data(VALIDATION_DATA_IDENTIFIER, &self.validation_data)?;
+		inherent_data.put_data(DOWNWARD_MESSAGES_IDENTIFIER, &self.downward_messages)?;
+
+		if let Some(para_id) = &self.para_id {
+			inherent_data.put_data(PARA_ID_IDENTIFIER, para_id)?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn provides_validation_data_and_downward_messages() {
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.block_number = 10;
+
+		let relay_chain = |_: PHash| -> Result<DownwardMessagesType, String> { Ok(Vec::new()) };
+		let provider = ParachainInherentDataProvider::create_at(
+			Default::default(),
+			validation_data.clone(),
+			&relay_chain,
+		)
+		.expect("Creates the provider");
+
+		let mut inherent_data = InherentData::new();
+		provider
+			.provide_inherent_data(&mut inherent_data)
+			.expect("Provides the inherent data");
+
+		assert_eq!(
+			Some(validation_data),
+			inherent_data
+				.get_data::<ValidationData>(&VALIDATION_DATA_IDENTIFIER)
+				.unwrap(),
+		);
+		assert_eq!(
+			Some(DownwardMessagesType::new()),
+			inherent_data
+				.get_data(&DOWNWARD_MESSAGES_IDENTIFIER)
+				.unwrap(),
+		);
+		assert_eq!(
+			None,
+			inherent_data.get_data::<ParaId>(&PARA_ID_IDENTIFIER).unwrap(),
+		);
+	}
+
+	#[test]
+	fn provides_the_para_id_if_known() {
+		let mut inherent_data = InherentData::new();
+		ParachainInherentDataProvider::new(Default::default(), Vec::new())
+			.with_para_id(Some(100.into()))
+			.provide_inherent_data(&mut inherent_data)
+			.expect("Provides the inherent data");
+
+		assert_eq!(
+			Some(ParaId::from(100)),
+			inherent_data.get_data(&PARA_ID_IDENTIFIER).unwrap(),
+		);
+	}
+}
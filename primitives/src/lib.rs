@@ -27,11 +27,24 @@ pub use polkadot_core_primitives::InboundDownwardMessage;
 pub use polkadot_parachain::primitives::UpwardMessage as GenericUpwardMessage;
 pub use polkadot_parachain::primitives::{Id as ParaId, ValidationParams};
 pub use polkadot_primitives::v1::{
-	PersistedValidationData, TransientValidationData, ValidationData,
+	CoreIndex, GroupIndex, PersistedValidationData, SessionIndex, TransientValidationData,
+	ValidationData,
 };
 
+#[cfg(feature = "std")]
+pub use inherent_data_provider::{ParachainInherentDataProvider, RelayChainInterface};
+pub use collation_info::CollationInfo;
+pub use message_queue_chain::MessageQueueChain;
+pub use relay_chain_types::{PolkadotRelayChain, RelayChainTypes};
+
+pub mod collation_info;
 #[cfg(feature = "std")]
 pub mod genesis;
+#[cfg(feature = "std")]
+pub mod inherent_data_provider;
+pub mod message_queue_chain;
+pub mod relay_chain_types;
+pub mod versioned;
 pub mod xcmp;
 
 /// Identifiers and types related to Cumulus Inherents
@@ -48,6 +61,14 @@ pub mod inherents {
 	pub const VALIDATION_DATA_IDENTIFIER: InherentIdentifier = *b"valfunp0";
 	/// The type of the inherent.
 	pub type ValidationDataType = crate::ValidationData;
+
+	/// Inherent identifier for the id of the parachain the validation data was fetched for.
+	///
+	/// This data is optional. If it is provided, the `set_validation_data` inherent is only
+	/// created if the id matches the id of the runtime.
+	pub const PARA_ID_IDENTIFIER: InherentIdentifier = *b"cumparid";
+	/// The type of the para id inherent data.
+	pub type ParaIdType = crate::ParaId;
 }
 
 /// Well known keys for values in the storage.
@@ -57,6 +78,12 @@ pub mod well_known_keys {
 	/// The upward messages are stored as SCALE encoded `Vec<GenericUpwardMessage>`.
 	pub const UPWARD_MESSAGES: &'static [u8] = b":cumulus_upward_messages:";
 
+	/// The storage key for the horizontal messages sent by the block.
+	///
+	/// The messages are stored as SCALE encoded `Vec<OutboundHrmpMessage<ParaId>>`. Runtimes that
+	/// do not set it send no horizontal messages.
+	pub const HRMP_OUTBOUND_MESSAGES: &'static [u8] = b":cumulus_hrmp_outbound_messages:";
+
 	/// Current validation data.
 	pub const VALIDATION_DATA: &'static [u8] = b":cumulus_validation_data:";
 
@@ -67,6 +94,91 @@ pub mod well_known_keys {
 	///
 	/// The value is stored as SCALE encoded `u32`.
 	pub const PROCESSED_DOWNWARD_MESSAGES: &'static [u8] = b":cumulus_processed_downward_messages:";
+
+	/// The storage key for the HRMP watermark of the block.
+	///
+	/// The value is stored as SCALE encoded relay chain `BlockNumber`. Runtimes that do not set it
+	/// use the number of the relay parent as watermark.
+	pub const HRMP_WATERMARK: &'static [u8] = b":cumulus_hrmp_watermark:";
+
+	/// The storage key for the timestamp anchor of the last block.
+	///
+	/// The value is stored as SCALE encoded [`TimestampAnchor`](crate::TimestampAnchor). It is
+	/// only set by runtimes that check their timestamp against the relay chain.
+	pub const TIMESTAMP_ANCHOR: &'static [u8] = b":cumulus_timestamp_anchor:";
+}
+
+/// The relay chain block number and the timestamp of a parachain block.
+///
+/// Used to check that the timestamp of the next block is within the relay chain slot implied by
+/// the relay chain block it is built on.
+#[derive(codec::Encode, codec::Decode, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct TimestampAnchor {
+	/// The relay chain block number the parachain block was built on.
+	pub relay_block_number: relay_chain::BlockNumber,
+	/// The timestamp of the parachain block.
+	pub timestamp: u64,
+	/// The time between two relay chain blocks in milliseconds.
+	pub relay_block_time: u64,
+	/// The maximum allowed deviation from the relay chain slot in milliseconds.
+	pub max_drift: u64,
+}
+
+impl TimestampAnchor {
+	/// Returns `true` if `timestamp` is within the relay chain slot implied by
+	/// `relay_block_number`, relative to this anchor.
+	pub fn check(&self, relay_block_number: relay_chain::BlockNumber, timestamp: u64) -> bool {
+		let elapsed = relay_block_number.saturating_sub(self.relay_block_number) as u64;
+
+		let earliest = self
+			.timestamp
+			.saturating_add(elapsed.saturating_mul(self.relay_block_time))
+			.saturating_sub(self.max_drift);
+		let latest = self
+			.timestamp
+			.saturating_add(elapsed.saturating_add(1).saturating_mul(self.relay_block_time))
+			.saturating_add(self.max_drift);
+
+		earliest <= timestamp && timestamp <= latest
+	}
+}
+
+/// Why the validation code can not be upgraded, see [`CodeUpgradeApi`].
+#[derive(codec::Encode, codec::Decode, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum CodeUpgradeError {
+	/// Another upgrade is already pending.
+	OverlappingUpgrades,
+	/// The relay chain currently prohibits an upgrade of the validation code.
+	ProhibitedByRelayChain,
+	/// The code is larger than the maximum code size of the relay chain.
+	TooBig {
+		/// The maximum code size in bytes.
+		max_code_size: u32,
+	},
+	/// The validation data was not set by the block.
+	ValidationDataNotAvailable,
+}
+
+/// Returns `true` if a candidate with a PoV of `pov_size` bytes that upgrades the validation
+/// code to `code_size` bytes can not be backed.
+///
+/// The new validation code is sent to the relay chain along with the PoV.
+pub fn exceeds_max_pov_size(pov_size: usize, code_size: usize, max_pov_size: usize) -> bool {
+	pov_size.saturating_add(code_size) > max_pov_size
+}
+
+/// The relay chain session a parachain block is built in.
+#[derive(codec::Encode, codec::Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct RelaySessionInfo {
+	/// The index of the relay chain session at the relay parent.
+	pub session_index: SessionIndex,
+	/// The availability core the parachain is scheduled on at the relay parent, if any.
+	pub core: Option<CoreIndex>,
+	/// The validator group that is assigned to the `core`.
+	pub group: Option<GroupIndex>,
 }
 
 /// Something that should be called when a downward message is received.
@@ -76,6 +188,56 @@ pub trait DownwardMessageHandler {
 	fn handle_downward_message(msg: &InboundDownwardMessage);
 }
 
+sp_api::decl_runtime_apis! {
+	/// Runtime api to query the relay chain context of the parachain.
+	pub trait PersistedValidationDataApi {
+		/// Returns the [`PersistedValidationData`] that was set by the latest block.
+		fn persisted_validation_data() -> Option<PersistedValidationData>;
+	}
+
+	/// Runtime api of chains that migrate from a standalone chain to a parachain.
+	pub trait SoloToParaApi {
+		/// Returns the block number from that on blocks are built as parachain blocks.
+		///
+		/// Returns `None` if no activation is scheduled.
+		fn activation_block() -> Option<sp_runtime::traits::NumberFor<Block>>;
+	}
+
+	/// Runtime api to collect the information the relay chain requires for a candidate.
+	pub trait CollectCollationInfo {
+		/// Collect the output of the latest block, whose header is `header`.
+		///
+		/// Must be called on the state after the block was executed.
+		fn collect_collation_info(header: &Block::Header) -> CollationInfo;
+	}
+
+	/// Runtime api to check a runtime upgrade before it is proposed, e.g. by governance.
+	pub trait CodeUpgradeApi {
+		/// Check if new validation code of `code_size` bytes can be scheduled on top of this
+		/// block, under the constraints of the relay chain at its relay parent.
+		///
+		/// Returns the relay chain block number at which the upgrade would be applied.
+		fn check_code_upgrade(
+			code_size: u32,
+		) -> Result<relay_chain::BlockNumber, CodeUpgradeError>;
+	}
+}
+
+/// Tells if the chain is already running as a parachain.
+///
+/// Chains that migrate from a standalone chain only require the parachain inherents once they
+/// are activated as parachain. The implementation for `()` is always active.
+pub trait ParachainActivation {
+	/// Returns `true` if the current block is built as a parachain block.
+	fn is_active() -> bool;
+}
+
+impl ParachainActivation for () {
+	fn is_active() -> bool {
+		true
+	}
+}
+
 /// A trait which is called when the validation data is set.
 #[impl_trait_for_tuples::impl_for_tuples(30)]
 pub trait OnValidationData {
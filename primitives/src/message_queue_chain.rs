@@ -0,0 +1,126 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The message queue chain (MQC) of the relay chain.
+//!
+//! The relay chain commits to the downward and HRMP message queues of a parachain by hash-linking
+//! the messages: the head of the chain is the hash of the previous head, the relay block number
+//! the message was sent at and the hash of the SCALE encoded message payload. The validation data only
+//! contains the heads, the messages given to a parachain block can be checked against them by
+//! extending the chain with the messages.
+
+use crate::relay_chain::{BlockNumber, Hash, InboundDownwardMessage, InboundHrmpMessage};
+
+use codec::{Decode, Encode};
+use sp_runtime::traits::{BlakeTwo256, Hash as HashT};
+
+/// A message queue chain, identified by its head.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct MessageQueueChain(Hash);
+
+impl MessageQueueChain {
+	/// Continue the chain with the given `head`.
+	pub fn new(head: Hash) -> Self {
+		Self(head)
+	}
+
+	/// Extend the chain with a message with the given `payload` that was sent at `sent_at`.
+	pub fn extend(&mut self, sent_at: BlockNumber, payload: &[u8]) -> &mut Self {
+		// The relay chain hashes the encoded payload, including its length prefix.
+		let payload_hash = BlakeTwo256::hash_of(&payload);
+		self.0 = BlakeTwo256::hash_of(&(self.0, sent_at, payload_hash));
+		self
+	}
+
+	/// Extend the chain with a downward message.
+	pub fn extend_downward(&mut self, message: &InboundDownwardMessage) -> &mut Self {
+		self.extend(message.sent_at, &message.msg)
+	}
+
+	/// Extend the chain with a HRMP message.
+	pub fn extend_hrmp(&mut self, message: &InboundHrmpMessage) -> &mut Self {
+		self.extend(message.sent_at, &message.data)
+	}
+
+	/// Returns the head of the chain.
+	pub fn head(&self) -> Hash {
+		self.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use hex_literal::hex;
+
+	#[test]
+	fn head_commits_to_order_and_contents() {
+		let first = InboundDownwardMessage {
+			sent_at: 1,
+			msg: vec![1, 2, 3],
+		};
+		let second = InboundDownwardMessage {
+			sent_at: 2,
+			msg: vec![4, 5, 6],
+		};
+
+		let mut chain = MessageQueueChain::default();
+		chain.extend_downward(&first).extend_downward(&second);
+
+		// Continuing from an intermediate head gives the same result.
+		let mut continued = MessageQueueChain::default();
+		continued.extend_downward(&first);
+		let mut continued = MessageQueueChain::new(continued.head());
+		continued.extend_downward(&second);
+		assert_eq!(chain, continued);
+
+		let mut reordered = MessageQueueChain::default();
+		reordered.extend_downward(&second).extend_downward(&first);
+		assert_ne!(chain.head(), reordered.head());
+
+		let mut hrmp = MessageQueueChain::default();
+		hrmp.extend_hrmp(&InboundHrmpMessage {
+			sent_at: 1,
+			data: vec![1, 2, 3],
+		})
+		.extend_hrmp(&InboundHrmpMessage {
+			sent_at: 2,
+			data: vec![4, 5, 6],
+		});
+		assert_eq!(chain, hrmp);
+	}
+
+	#[test]
+	fn head_matches_the_relay_chain() {
+		// The head the relay chain computes for these messages in its `dmp` module tests.
+		let mut chain = MessageQueueChain::default();
+		chain
+			.extend_downward(&InboundDownwardMessage {
+				sent_at: 2,
+				msg: vec![1, 2, 3],
+			})
+			.extend_downward(&InboundDownwardMessage {
+				sent_at: 3,
+				msg: vec![4, 5, 6],
+			});
+
+		assert_eq!(
+			chain.head(),
+			hex!["88dc00db8cc9d22aa62b87807705831f164387dfa49f80a8600ed1cbe1704b6b"].into(),
+		);
+	}
+}
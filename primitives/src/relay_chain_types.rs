@@ -0,0 +1,43 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The primitive types of the relay chain.
+//!
+//! Client code should refer to the relay chain primitives through [`RelayChainTypes`] instead of
+//! naming a concrete primitives version. This way a different relay chain configuration only
+//! requires a different implementation of the trait.
+
+use codec::Codec;
+use sp_runtime::traits::{AtLeast32BitUnsigned, Header as HeaderT, MaybeDisplay, Member};
+
+/// The primitive types of a relay chain.
+pub trait RelayChainTypes: Send + Sync + 'static {
+	/// The hash of a relay chain block.
+	type Hash: Member + MaybeDisplay + Codec + Copy + Default + Ord + AsRef<[u8]>;
+	/// The number of a relay chain block.
+	type BlockNumber: Member + MaybeDisplay + Codec + Copy + AtLeast32BitUnsigned;
+	/// The header of a relay chain block.
+	type Header: HeaderT<Hash = Self::Hash, Number = Self::BlockNumber>;
+}
+
+/// The primitive types of Polkadot and the relay chains that are based on it.
+pub struct PolkadotRelayChain;
+
+impl RelayChainTypes for PolkadotRelayChain {
+	type Hash = polkadot_primitives::v1::Hash;
+	type BlockNumber = polkadot_primitives::v1::BlockNumber;
+	type Header = polkadot_primitives::v1::Header;
+}
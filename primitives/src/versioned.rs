@@ -0,0 +1,164 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of the validation data that tolerates older relay chain encodings.
+//!
+//! The relay chain may change the encoding of the validation data with a runtime upgrade. Instead
+//! of failing with a generic decoding error, the functions of this module also accept the
+//! previous encoding and report an [`IncompatibleEncoding`] error for unknown encodings.
+
+use crate::{relay_chain, PersistedValidationData, TransientValidationData, ValidationData};
+use codec::{Decode, Encode, Input};
+use polkadot_parachain::primitives::{HeadData, Id as ParaId};
+use sp_std::vec::Vec;
+
+/// The validation data could not be decoded with any known encoding.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct IncompatibleEncoding;
+
+impl IncompatibleEncoding {
+	/// A description of the error for operators.
+	pub const MESSAGE: &'static str = "Unknown encoding of the validation data. \
+		The relay chain runtime was probably upgraded, please update Cumulus.";
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for IncompatibleEncoding {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str(Self::MESSAGE)
+	}
+}
+
+/// [`PersistedValidationData`] as encoded by relay chains without the downward message queue.
+#[derive(Encode, Decode)]
+struct PersistedValidationDataV0 {
+	parent_head: HeadData,
+	block_number: relay_chain::BlockNumber,
+	hrmp_mqc_heads: Vec<(ParaId, relay_chain::Hash)>,
+}
+
+impl From<PersistedValidationDataV0> for PersistedValidationData {
+	fn from(data: PersistedValidationDataV0) -> Self {
+		Self {
+			parent_head: data.parent_head,
+			block_number: data.block_number,
+			hrmp_mqc_heads: data.hrmp_mqc_heads,
+			dmq_mqc_head: Default::default(),
+		}
+	}
+}
+
+/// Decode `T` from `data`, requiring that all bytes are consumed.
+fn decode_exact<T: Decode>(mut data: &[u8]) -> Option<T> {
+	let res = T::decode(&mut data).ok()?;
+	if data.is_empty() {
+		Some(res)
+	} else {
+		None
+	}
+}
+
+/// Decode [`PersistedValidationData`] in the current or the previous encoding.
+pub fn decode_persisted_validation_data(
+	data: &[u8],
+) -> Result<PersistedValidationData, IncompatibleEncoding> {
+	decode_exact::<PersistedValidationData>(data)
+		.or_else(|| decode_exact::<PersistedValidationDataV0>(data).map(Into::into))
+		.ok_or(IncompatibleEncoding)
+}
+
+/// Decode [`ValidationData`] with the persisted part in the current or the previous encoding.
+pub fn decode_validation_data(data: &[u8]) -> Result<ValidationData, IncompatibleEncoding> {
+	decode_exact::<ValidationData>(data)
+		.or_else(|| {
+			decode_exact::<(PersistedValidationDataV0, TransientValidationData)>(data).map(
+				|(persisted, transient)| ValidationData {
+					persisted: persisted.into(),
+					transient,
+				},
+			)
+		})
+		.ok_or(IncompatibleEncoding)
+}
+
+/// Wrapper around [`ValidationData`] that decodes using [`decode_validation_data`].
+///
+/// The wrapped value needs to be the only value in the input, as all remaining bytes are consumed
+/// while decoding. This is the case for inherent data and storage values.
+pub struct VersionedValidationData(pub ValidationData);
+
+impl Decode for VersionedValidationData {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+		let len = input
+			.remaining_len()?
+			.ok_or("Length of the versioned validation data is unknown")?;
+		let mut data = sp_std::vec![0; len];
+		input.read(&mut data)?;
+
+		decode_validation_data(&data)
+			.map(VersionedValidationData)
+			.map_err(|_| IncompatibleEncoding::MESSAGE.into())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn persisted_v0() -> PersistedValidationDataV0 {
+		PersistedValidationDataV0 {
+			parent_head: HeadData(vec![1, 2, 3]),
+			block_number: 10,
+			hrmp_mqc_heads: vec![(ParaId::from(1), Default::default())],
+		}
+	}
+
+	#[test]
+	fn decodes_current_encoding() {
+		let mut data = ValidationData::default();
+		data.persisted.block_number = 5;
+		data.persisted.dmq_mqc_head = relay_chain::Hash::repeat_byte(1);
+
+		assert_eq!(Ok(data.clone()), decode_validation_data(&data.encode()));
+		assert_eq!(
+			Ok(data.persisted.clone()),
+			decode_persisted_validation_data(&data.persisted.encode()),
+		);
+	}
+
+	#[test]
+	fn decodes_previous_encoding() {
+		let persisted = decode_persisted_validation_data(&persisted_v0().encode())
+			.expect("Decodes the previous encoding");
+		assert_eq!(10, persisted.block_number);
+		assert_eq!(relay_chain::Hash::default(), persisted.dmq_mqc_head);
+
+		let encoded = (persisted_v0(), TransientValidationData::default()).encode();
+		let data = VersionedValidationData::decode(&mut &encoded[..])
+			.expect("Decodes the previous encoding")
+			.0;
+		assert_eq!(persisted, data.persisted);
+	}
+
+	#[test]
+	fn rejects_unknown_encoding() {
+		assert_eq!(
+			Err(IncompatibleEncoding),
+			decode_persisted_validation_data(&[1, 2, 3]),
+		);
+	}
+}
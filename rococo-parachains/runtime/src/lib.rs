@@ -198,6 +198,8 @@ impl pallet_sudo::Trait for Runtime {
 impl cumulus_parachain_upgrade::Trait for Runtime {
 	type Event = Event;
 	type OnValidationData = ();
+	type Activation = ();
+	type SelfParaId = ParachainInfo;
 }
 
 impl parachain_info::Trait for Runtime {}
@@ -322,6 +324,31 @@ impl_runtime_apis! {
 			SessionKeys::generate(seed)
 		}
 	}
+
+	impl cumulus_primitives::PersistedValidationDataApi<Block> for Runtime {
+		fn persisted_validation_data() -> Option<cumulus_primitives::PersistedValidationData> {
+			ParachainUpgrade::last_persisted_validation_data()
+		}
+	}
+
+	impl cumulus_primitives::CollectCollationInfo<Block> for Runtime {
+		fn collect_collation_info(
+			header: &<Block as BlockT>::Header,
+		) -> cumulus_primitives::CollationInfo {
+			ParachainUpgrade::collect_collation_info(header)
+		}
+	}
+
+	impl cumulus_primitives::CodeUpgradeApi<Block> for Runtime {
+		fn check_code_upgrade(
+			code_size: u32,
+		) -> Result<
+			cumulus_primitives::relay_chain::BlockNumber,
+			cumulus_primitives::CodeUpgradeError,
+		> {
+			ParachainUpgrade::check_code_upgrade(code_size)
+		}
+	}
 }
 
 cumulus_runtime::register_validate_block!(Block, Executive);
@@ -14,7 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::path::PathBuf;
+use cumulus_collator::execution_budget::ExecutionBudget;
+use cumulus_network::{AnnouncePolicy, AnnounceTimeoutAction};
+use cumulus_service::{RecoveryConfig, RelayChainDatabase, RelayChainDatabaseBackend};
+use std::{path::PathBuf, time::Duration};
 
 use sc_cli;
 use structopt::StructOpt;
@@ -50,6 +53,26 @@ pub enum Subcommand {
 
 	/// Revert the chain to a previous state.
 	Revert(sc_cli::RevertCmd),
+
+	/// Check that a new runtime accepts the latest blocks of the local chain.
+	#[structopt(name = "dry-run-upgrade")]
+	DryRunUpgrade(DryRunUpgradeCommand),
+
+	/// Import a PoV that was exported with `--pov-export-dir`.
+	#[structopt(name = "import-pov")]
+	ImportPov(ImportPovCommand),
+
+	/// Check that validation code can be executed by the relay chain validators.
+	#[structopt(name = "check-validation-code")]
+	CheckValidationCode(CheckValidationCodeCommand),
+
+	/// Export the head, validation code and state required to register the parachain again.
+	#[structopt(name = "export-registration")]
+	ExportRegistration(ExportRegistrationCommand),
+
+	/// Measure the proof sizes of storage accesses for the weight configuration of the runtime.
+	#[structopt(name = "calibrate-pov")]
+	CalibratePov(CalibratePovCommand),
 }
 
 /// Command for exporting the genesis state of the parachain
@@ -88,14 +111,227 @@ pub struct ExportGenesisWasmCommand {
 	pub chain: Option<String>,
 }
 
+/// Command for running the `validate_block` of a new runtime against the latest blocks.
+#[derive(Debug, StructOpt)]
+pub struct DryRunUpgradeCommand {
+	/// Path to the wasm blob of the new runtime.
+	#[structopt(parse(from_os_str))]
+	pub wasm: PathBuf,
+
+	/// The number of blocks, starting at the best block, that should be checked.
+	#[structopt(long, default_value = "10")]
+	pub blocks: u32,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: sc_cli::SharedParams,
+}
+
+/// Command for importing an exported PoV into the local chain.
+#[derive(Debug, StructOpt)]
+pub struct ImportPovCommand {
+	/// Path to the exported PoV.
+	#[structopt(parse(from_os_str))]
+	pub path: PathBuf,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: sc_cli::SharedParams,
+}
+
+/// Command for checking validation code before registering it on the relay chain.
+#[derive(Debug, StructOpt)]
+pub struct CheckValidationCodeCommand {
+	/// Path to the wasm blob of the validation code.
+	#[structopt(parse(from_os_str))]
+	pub wasm: PathBuf,
+
+	/// Reject code that is bigger than the given number of bytes.
+	#[structopt(long)]
+	pub max_code_size: Option<usize>,
+}
+
+/// Command for exporting the data required to register the parachain again.
+#[derive(Debug, StructOpt)]
+pub struct ExportRegistrationCommand {
+	/// The directory the head, the validation code and the chain spec are written to.
+	#[structopt(parse(from_os_str))]
+	pub output: PathBuf,
+
+	/// Number or hash of the block to export. Defaults to the best block.
+	#[structopt(long)]
+	pub at: Option<sc_cli::BlockNumberOrHash>,
+
+	/// Export the head of a new chain that starts with the exported state as genesis state.
+	///
+	/// By default the head of the exported block is exported, to continue the chain at it.
+	#[structopt(long)]
+	pub genesis: bool,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: sc_cli::SharedParams,
+}
+
+/// Command for measuring the proof sizes of storage accesses.
+#[derive(Debug, StructOpt)]
+pub struct CalibratePovCommand {
+	/// Output file name or stdout if unspecified.
+	#[structopt(parse(from_os_str))]
+	pub output: Option<PathBuf>,
+
+	/// Number or hash of the block whose state is measured. Defaults to the best block.
+	#[structopt(long)]
+	pub at: Option<sc_cli::BlockNumberOrHash>,
+
+	/// The number of storage keys that are sampled.
+	#[structopt(long, default_value = "100")]
+	pub samples: usize,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: sc_cli::SharedParams,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct RunCmd {
 	#[structopt(flatten)]
 	pub base: sc_cli::RunCmd,
 
 	/// Id of the parachain this collator collates for.
+	///
+	/// Only used to build the built-in chain specs. A chain spec that contains a parachain id
+	/// has to contain the same id.
 	#[structopt(long)]
 	pub parachain_id: Option<u32>,
+
+	/// Write every produced PoV into the given directory.
+	#[structopt(long, parse(from_os_str))]
+	pub pov_export_dir: Option<PathBuf>,
+
+	/// Run every produced candidate through the local `validate_block` before submitting it.
+	#[structopt(long)]
+	pub pre_validate: bool,
+
+	/// Execute every produced block once more with the WASM runtime before importing it.
+	///
+	/// Catches a divergence between the native and the WASM runtime before the candidate is
+	/// rejected by the validators, at the cost of executing every block twice.
+	#[structopt(long)]
+	pub reexecute_own_blocks: bool,
+
+	/// Check every produced block for a divergence of the native and the WASM runtime in the
+	/// background and report it loudly, e.g. when the `spec_version` was not bumped.
+	#[structopt(long)]
+	pub detect_runtime_divergence: bool,
+
+	/// Seconds to wait for the candidate of a produced block to be seconded before dropping
+	/// the block.
+	///
+	/// By default, a block is only announced once its candidate was seconded.
+	#[structopt(long)]
+	pub announce_timeout: Option<u64>,
+
+	/// Announce blocks whose candidate was not seconded before the `--announce-timeout`
+	/// without justification, instead of dropping them.
+	///
+	/// Peers penalize announcements without justification at the tip of the chain.
+	#[structopt(long, requires = "announce-timeout")]
+	pub announce_unseconded_blocks: bool,
+
+	/// Announce a block again for every additional backing statement of its candidate.
+	#[structopt(long)]
+	pub reannounce: bool,
+
+	/// The PVF execution timeout of the relay chain in milliseconds.
+	///
+	/// The collator spends 10% of it on building a block. Defaults to 5000, the timeout of
+	/// Polkadot validators in release builds.
+	#[structopt(long, value_name = "MS")]
+	pub execution_timeout: Option<u64>,
+
+	/// Rebuild the chain from the candidates included by the relay chain.
+	///
+	/// The blocks of the included candidates are fetched from the peers, or from the
+	/// `--recovery-pov-dir`. Recovering blocks older than the relay chain state pruning requires
+	/// an archive relay chain node.
+	#[structopt(long)]
+	pub recover_from_relay_chain: bool,
+
+	/// A directory of exported PoVs to recover the blocks from.
+	#[structopt(long, parse(from_os_str), requires = "recover-from-relay-chain")]
+	pub recovery_pov_dir: Option<PathBuf>,
+
+	/// The database backend of the embedded relay chain node.
+	///
+	/// Overrides `--database` of the relay chain arguments.
+	#[structopt(
+		long,
+		value_name = "DB",
+		possible_values = &sc_cli::Database::variants(),
+		case_insensitive = true,
+	)]
+	pub relay_chain_database: Option<sc_cli::Database>,
+
+	/// The database cache size of the embedded relay chain node in MiB.
+	///
+	/// Overrides `--db-cache` of the relay chain arguments.
+	#[structopt(long, value_name = "MiB")]
+	pub relay_chain_db_cache: Option<usize>,
+
+	/// The state cache size of the embedded relay chain node in bytes.
+	///
+	/// Overrides `--state-cache-size` of the relay chain arguments.
+	#[structopt(long, value_name = "Bytes")]
+	pub relay_chain_state_cache_size: Option<usize>,
+}
+
+impl RunCmd {
+	/// The announce policy configured on the command line.
+	pub fn announce_policy(&self) -> AnnouncePolicy {
+		AnnouncePolicy {
+			timeout: self.announce_timeout.map(Duration::from_secs),
+			on_timeout: if self.announce_unseconded_blocks {
+				AnnounceTimeoutAction::Announce
+			} else {
+				AnnounceTimeoutAction::Drop
+			},
+			reannounce: self.reannounce,
+		}
+	}
+
+	/// The execution budget configured on the command line.
+	pub fn execution_budget(&self) -> ExecutionBudget {
+		match self.execution_timeout {
+			Some(timeout) => {
+				ExecutionBudget::default().with_execution_timeout(Duration::from_millis(timeout))
+			}
+			None => ExecutionBudget::default(),
+		}
+	}
+
+	/// The database settings of the embedded relay chain node configured on the command line.
+	pub fn relay_chain_database(&self) -> RelayChainDatabase {
+		RelayChainDatabase {
+			backend: self.relay_chain_database.as_ref().map(|db| match db {
+				sc_cli::Database::RocksDb => RelayChainDatabaseBackend::RocksDb,
+				sc_cli::Database::ParityDb => RelayChainDatabaseBackend::ParityDb,
+			}),
+			cache_size: self.relay_chain_db_cache,
+			state_cache_size: self.relay_chain_state_cache_size,
+		}
+	}
+
+	/// The disaster recovery configured on the command line.
+	pub fn recovery(&self) -> Option<RecoveryConfig> {
+		if self.recover_from_relay_chain {
+			Some(RecoveryConfig {
+				pov_dir: self.recovery_pov_dir.clone(),
+			})
+		} else {
+			None
+		}
+	}
 }
 
 impl std::ops::Deref for RunCmd {
@@ -16,10 +16,14 @@
 
 use crate::{
 	chain_spec,
-	cli::{Cli, RelayChainCli, Subcommand},
+	cli::{
+		CalibratePovCommand, Cli, DryRunUpgradeCommand, ExportRegistrationCommand, ImportPovCommand,
+		RelayChainCli, Subcommand,
+	},
 };
 use codec::Encode;
 use cumulus_primitives::{genesis::generate_genesis_block, ParaId};
+use cumulus_service::chain_spec::{resolve_para_id, with_isolated_protocol_id};
 use log::info;
 use parachain_runtime::Block;
 use polkadot_parachain::primitives::AccountIdConversion;
@@ -32,29 +36,29 @@ use sc_service::{
 	PartialComponents,
 };
 use sp_core::hexdisplay::HexDisplay;
-use sp_runtime::traits::Block as BlockT;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
 use std::{io::Write, net::SocketAddr};
 
 fn load_spec(
 	id: &str,
 	para_id: ParaId,
 ) -> std::result::Result<Box<dyn sc_service::ChainSpec>, String> {
-	match id {
-		"staging" => Ok(Box::new(chain_spec::staging_test_net(para_id))),
-		"tick" => Ok(Box::new(chain_spec::ChainSpec::from_json_bytes(
+	let chain_spec = match id {
+		"staging" => chain_spec::staging_test_net(para_id),
+		"tick" => chain_spec::ChainSpec::from_json_bytes(
 			&include_bytes!("../res/tick.json")[..],
-		)?)),
-		"trick" => Ok(Box::new(chain_spec::ChainSpec::from_json_bytes(
+		)?,
+		"trick" => chain_spec::ChainSpec::from_json_bytes(
 			&include_bytes!("../res/trick.json")[..],
-		)?)),
-		"track" => Ok(Box::new(chain_spec::ChainSpec::from_json_bytes(
+		)?,
+		"track" => chain_spec::ChainSpec::from_json_bytes(
 			&include_bytes!("../res/track.json")[..],
-		)?)),
-		"" => Ok(Box::new(chain_spec::get_chain_spec(para_id))),
-		path => Ok(Box::new(chain_spec::ChainSpec::from_json_file(
-			path.into(),
-		)?)),
-	}
+		)?,
+		"" => chain_spec::get_chain_spec(para_id),
+		path => chain_spec::ChainSpec::from_json_file(path.into())?,
+	};
+
+	Ok(Box::new(with_isolated_protocol_id::<Block, _>(chain_spec)?))
 }
 
 impl SubstrateCli for Cli {
@@ -216,6 +220,98 @@ pub fn run() -> Result<()> {
 				Ok((cmd.run(client, backend), task_manager))
 			})
 		}
+		Some(Subcommand::DryRunUpgrade(cmd)) => {
+			let runner = cli.create_runner(cmd)?;
+			runner.sync_run(|config| {
+				let PartialComponents { client, .. } = crate::service::new_partial(&config)?;
+				let validation_code = std::fs::read(&cmd.wasm)?;
+
+				let divergences = cumulus_collator::upgrade_dry_run::dry_run_upgrade(
+					&*client,
+					&validation_code,
+					cmd.blocks,
+				)?;
+
+				if divergences.is_empty() {
+					info!("New runtime accepts the last {} blocks.", cmd.blocks);
+					return Ok(());
+				}
+
+				for divergence in &divergences {
+					info!(
+						"Block #{} (`{}`) rejected: {}",
+						divergence.number, divergence.hash, divergence.reason,
+					);
+				}
+
+				Err(format!("New runtime rejects {} block(s)", divergences.len()).into())
+			})
+		}
+		Some(Subcommand::ImportPov(cmd)) => {
+			let runner = cli.create_runner(cmd)?;
+			runner.sync_run(|config| {
+				let PartialComponents { client, .. } = crate::service::new_partial(&config)?;
+
+				cumulus_collator::pov_export::import_pov(&*client, &cmd.path)?;
+
+				Ok(())
+			})
+		}
+		Some(Subcommand::ExportRegistration(cmd)) => {
+			let runner = cli.create_runner(cmd)?;
+			runner.sync_run(|config| {
+				let PartialComponents { client, .. } = crate::service::new_partial(&config)?;
+				let at = cmd.at.as_ref().map(|at| at.parse::<Block>()).transpose()?;
+
+				let snapshot = cumulus_service::RegistrationSnapshot::at(client, at)?;
+				let head_data = if cmd.genesis {
+					snapshot.genesis_head_data()
+				} else {
+					snapshot.head_data()
+				};
+				let validation_code = snapshot
+					.validation_code()
+					.ok_or("Validation code not found in the state")?
+					.to_vec();
+				let chain_spec = snapshot.into_genesis_chain_spec(config.chain_spec, true)?;
+
+				std::fs::create_dir_all(&cmd.output)?;
+				std::fs::write(
+					cmd.output.join("head"),
+					format!("0x{:?}", HexDisplay::from(&head_data.0)),
+				)?;
+				std::fs::write(
+					cmd.output.join("wasm"),
+					format!("0x{:?}", HexDisplay::from(&validation_code)),
+				)?;
+				std::fs::write(cmd.output.join("chain_spec.json"), chain_spec)?;
+
+				info!("Exported registration data to `{}`.", cmd.output.display());
+
+				Ok(())
+			})
+		}
+		Some(Subcommand::CalibratePov(cmd)) => {
+			let runner = cli.create_runner(cmd)?;
+			runner.sync_run(|config| {
+				let PartialComponents { client, .. } = crate::service::new_partial(&config)?;
+				let at = match cmd.at {
+					Some(ref at) => at.parse::<Block>()?,
+					None => BlockId::Hash(client.chain_info().best_hash),
+				};
+
+				let calibration =
+					cumulus_collator::pov_calibration::calibrate_pov_size(&*client, at, cmd.samples)?;
+				let constants = calibration.to_rust();
+
+				match cmd.output {
+					Some(ref output) => std::fs::write(output, constants)?,
+					None => print!("{}", constants),
+				}
+
+				Ok(())
+			})
+		}
 		Some(Subcommand::ExportGenesisState(params)) => {
 			sc_cli::init_logger("", sc_tracing::TracingReceiver::Log, None)?;
 
@@ -257,6 +353,28 @@ pub fn run() -> Result<()> {
 
 			Ok(())
 		}
+		Some(Subcommand::CheckValidationCode(params)) => {
+			sc_cli::init_logger("", sc_tracing::TracingReceiver::Log, None)?;
+
+			let code = std::fs::read(&params.wasm)?;
+
+			let mut checker = cumulus_collator::validation_code_check::ValidationCodeChecker::default();
+			if let Some(max) = params.max_code_size {
+				checker = checker.max_code_size(max);
+			}
+
+			let issues = checker.check(&code);
+			if issues.is_empty() {
+				info!("Validation code passed all checks.");
+				return Ok(());
+			}
+
+			for issue in &issues {
+				info!("{}", issue);
+			}
+
+			Err(format!("Validation code has {} issue(s)", issues.len()).into())
+		}
 		None => {
 			let runner = cli.create_runner(&*cli.run)?;
 
@@ -264,9 +382,9 @@ pub fn run() -> Result<()> {
 				// TODO
 				let key = sp_core::Pair::generate().0;
 
-				let extension = chain_spec::Extensions::try_get(&config.chain_spec);
-				let relay_chain_id = extension.map(|e| e.relay_chain.clone());
-				let para_id = extension.map(|e| e.para_id);
+				let extension = chain_spec::Extensions::try_get(&*config.chain_spec);
+				let relay_chain_id = extension.map(|e| e.relay_chain().to_string());
+				let para_id = resolve_para_id(extension, cli.run.parachain_id)?;
 
 				let polkadot_cli = RelayChainCli::new(
 					config.base_path.as_ref().map(|x| x.path().join("polkadot")),
@@ -276,7 +394,7 @@ pub fn run() -> Result<()> {
 						.chain(cli.relaychain_args.iter()),
 				);
 
-				let id = ParaId::from(cli.run.parachain_id.or(para_id).unwrap_or(100));
+				let id = para_id.unwrap_or_else(|| ParaId::from(100));
 
 				let parachain_account =
 					AccountIdConversion::<polkadot_primitives::v0::AccountId>::into_account(&id);
@@ -286,9 +404,15 @@ pub fn run() -> Result<()> {
 				let genesis_state = format!("0x{:?}", HexDisplay::from(&block.header().encode()));
 
 				let task_executor = config.task_executor.clone();
-				let polkadot_config =
+				let mut polkadot_config =
 					SubstrateCli::create_configuration(&polkadot_cli, &polkadot_cli, task_executor)
 						.map_err(|err| format!("Relay chain argument error: {}", err))?;
+
+				let relay_chain_database = cli.run.relay_chain_database();
+				if !relay_chain_database.is_default() {
+					relay_chain_database.apply(&mut polkadot_config);
+					info!("Relay chain database: {:?}", polkadot_config.database);
+				}
 				let collator = cli.run.base.validator || cli.collator;
 
 				info!("Parachain id: {:?}", id);
@@ -296,14 +420,51 @@ pub fn run() -> Result<()> {
 				info!("Parachain genesis state: {}", genesis_state);
 				info!("Is collating: {}", if collator { "yes" } else { "no" });
 
-				crate::service::start_node(config, key, polkadot_config, id, collator)
-					.await
-					.map(|r| r.0)
+				crate::service::start_node(
+					config,
+					key,
+					polkadot_config,
+					id,
+					collator,
+					cli.run.pov_export_dir.clone(),
+					cli.run.pre_validate,
+					cli.run.reexecute_own_blocks,
+					cli.run.detect_runtime_divergence,
+					cli.run.announce_policy(),
+					cli.run.execution_budget(),
+					cli.run.recovery(),
+				)
+				.await
+				.map(|r| r.0)
 			})
 		}
 	}
 }
 
+impl CliConfiguration for DryRunUpgradeCommand {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+}
+
+impl CliConfiguration for ImportPovCommand {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+}
+
+impl CliConfiguration for CalibratePovCommand {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+}
+
+impl CliConfiguration for ExportRegistrationCommand {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+}
+
 impl DefaultConfigurationValues for RelayChainCli {
 	fn p2p_listen_port() -> u16 {
 		30334
@@ -14,20 +14,48 @@
 // You should have received a copy of the GNU General Public License
 // along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
 
-use cumulus_network::build_block_announce_validator;
+use codec::Encode;
+use cumulus_collator::{
+	disaster_recovery::{BlockSource, ExportedPoVSource, RecoveryImport},
+	bad_block_repair::BadBlocks,
+	events::{CollatorEvent, CollatorEventHandler, DefaultEventHandler},
+	execution_budget::ExecutionBudget,
+	journal::CollationOutcome,
+	receipts::{self, ReceiptGossip},
+	reexecution::WasmReexecution,
+	task_group::{NETWORK_TASKS, RECOVERY_TASKS},
+	upgrade_only::ClientUpgradeOnlyBuilder,
+	AnnounceBlock, CollationReceipts, EncodedAnnouncement, PoVSizeLimit, TaskGroup, TaskMetrics,
+};
+use cumulus_consensus::{
+	fork_pruning, import_queue::Verifier, report_displaced_forks, DisplacedForks, RelayChainCache,
+};
+use cumulus_network::{
+	announce_signing::{AnnounceSigner, KeystoreAnnounceSigner, ANNOUNCE_KEY_TYPE},
+	announcement::{self, PeerVersions},
+	build_block_announce_validator,
+	dht_bootnodes::dht_bootnodes,
+	recent_blocks::{RecentBlockFetcher, RecentBlocksHandler, RecentJustifications},
+	AnnouncePolicy,
+};
 use cumulus_service::{
-	prepare_node_config, start_collator, start_full_node, StartCollatorParams, StartFullNodeParams,
+	chain_spec::relay_genesis_hash, current_max_pov_size, prepare_node_config,
+	spawn_disaster_recovery, start_collator, start_full_node, ByProofFootprint, CollatorConfig,
+	MaintainPoolOnNewBest, ParachainExtensions, PoVReserve, PrioritizedPool, RecoveryConfig,
+	StartCollatorParams, StartFullNodeParams,
 };
+use futures::FutureExt;
 use parachain_runtime::RuntimeApi;
 use polkadot_primitives::v0::CollatorPair;
 use rococo_parachain_primitives::Block;
 use sc_executor::native_executor_instance;
 pub use sc_executor::NativeExecutor;
 use sc_service::{Configuration, PartialComponents, Role, TFullBackend, TFullClient, TaskManager};
-use sp_core::Pair;
-use sp_runtime::traits::BlakeTwo256;
+use sp_core::{traits::SpawnNamed, Pair};
+use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
+use sp_transaction_pool::TransactionPool;
 use sp_trie::PrefixedMemoryDB;
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 // Native executor instance.
 native_executor_instance!(
@@ -49,7 +77,7 @@ pub fn new_partial(
 		(),
 		sp_consensus::import_queue::BasicQueue<Block, PrefixedMemoryDB<BlakeTwo256>>,
 		sc_transaction_pool::FullPool<Block, TFullClient<Block, RuntimeApi, Executor>>,
-		(),
+		BadBlocks<Block>,
 	>,
 	sc_service::Error,
 > {
@@ -68,9 +96,12 @@ pub fn new_partial(
 		client.clone(),
 	);
 
+	// Blocks that fail to import are remembered, so the collator can repair included blocks that
+	// failed to import, e.g. on a corrupted database.
+	let bad_blocks = BadBlocks::default();
 	let import_queue = cumulus_consensus::import_queue::import_queue(
 		client.clone(),
-		client.clone(),
+		bad_blocks.block_import(client.clone()),
 		inherent_data_providers.clone(),
 		&task_manager.spawn_handle(),
 		registry.clone(),
@@ -85,7 +116,7 @@ pub fn new_partial(
 		transaction_pool,
 		inherent_data_providers,
 		select_chain: (),
-		other: (),
+		other: bad_blocks,
 	};
 
 	Ok(params)
@@ -99,7 +130,14 @@ async fn start_node_impl<RB>(
 	collator_key: CollatorPair,
 	polkadot_config: Configuration,
 	id: polkadot_primitives::v0::Id,
-	validator: bool,
+	collator: bool,
+	pov_export_dir: Option<PathBuf>,
+	pre_validate: bool,
+	reexecute_own_blocks: bool,
+	detect_runtime_divergence: bool,
+	announce_policy: AnnouncePolicy,
+	execution_budget: ExecutionBudget,
+	recovery: Option<RecoveryConfig>,
 	rpc_ext_builder: RB,
 ) -> sc_service::error::Result<(TaskManager, Arc<TFullClient<Block, RuntimeApi, Executor>>)>
 where
@@ -113,11 +151,15 @@ where
 		return Err("Light client not supported!".into());
 	}
 
-	let parachain_config = prepare_node_config(parachain_config);
+	let mut parachain_config = prepare_node_config(parachain_config);
 
 	let polkadot_full_node =
 		cumulus_service::build_polkadot_full_node(polkadot_config, collator_key.public())?;
 
+	if let Some(extensions) = ParachainExtensions::try_get(&*parachain_config.chain_spec) {
+		extensions.check_relay_genesis(relay_genesis_hash(&polkadot_full_node))?;
+	}
+
 	let params = new_partial(&parachain_config)?;
 	params
 		.inherent_data_providers
@@ -126,13 +168,37 @@ where
 
 	let client = params.client.clone();
 	let backend = params.backend.clone();
+	let bad_blocks = params.other.clone();
+	let justifications = RecentJustifications::default();
+	let relay_chain_cache = RelayChainCache::default();
 	let block_announce_validator = build_block_announce_validator(
 		polkadot_full_node.client.clone(),
 		id,
 		Box::new(polkadot_full_node.network.clone()),
+		justifications.clone(),
+		None,
+		relay_chain_cache.clone(),
 	);
 
+	let protocol_id = parachain_config.protocol_id();
+	let (recent_blocks_handler, recent_blocks_config) =
+		RecentBlocksHandler::new(client.clone(), justifications.clone(), &protocol_id);
+	parachain_config
+		.network
+		.request_response_protocols
+		.push(recent_blocks_config);
+	parachain_config
+		.network
+		.notifications_protocols
+		.push(receipts::gossip_protocol_name(&protocol_id).into());
+	parachain_config
+		.network
+		.notifications_protocols
+		.push(announcement::version_protocol_name(&protocol_id).into());
+
 	let prometheus_registry = parachain_config.prometheus_registry().cloned();
+	let task_metrics = TaskMetrics::register(prometheus_registry.as_ref())
+		.map_err(|e| format!("Failed to register the task metrics: {:?}", e))?;
 	let transaction_pool = params.transaction_pool.clone();
 	let mut task_manager = params.task_manager;
 	let import_queue = params.import_queue;
@@ -149,8 +215,159 @@ where
 			finality_proof_provider: None,
 		})?;
 
+	let network_tasks = TaskGroup::new(
+		task_manager.spawn_handle(),
+		NETWORK_TASKS,
+		task_metrics.clone(),
+	);
+	let recovery_tasks = TaskGroup::new(
+		task_manager.spawn_handle(),
+		RECOVERY_TASKS,
+		task_metrics.clone(),
+	);
+
+	let (recent_block_fetcher, track_peers) = RecentBlockFetcher::new(network.clone(), &protocol_id);
+	network_tasks.spawn("cumulus-recent-blocks-handler", recent_blocks_handler.run().boxed());
+	network_tasks.spawn("cumulus-recent-blocks-peers", track_peers.boxed());
+	let (peer_versions, track_versions) = PeerVersions::new(network.clone(), &protocol_id);
+	network_tasks.spawn("cumulus-announcement-versions", track_versions.boxed());
+	// Every node propagates the receipts, collators also publish their own.
+	let receipt_gossip =
+		ReceiptGossip::new(network.clone(), &protocol_id, prometheus_registry.as_ref());
+	network_tasks.spawn("cumulus-receipt-gossip", receipt_gossip.clone().run().boxed());
+	let node_key = parachain_config
+		.network
+		.node_key
+		.clone()
+		.into_keypair()
+		.map_err(|e| format!("Failed to read the network key: {}", e))?;
+	network_tasks.spawn(
+		"cumulus-dht-bootnodes",
+		dht_bootnodes(
+			polkadot_full_node.network.clone(),
+			network.clone(),
+			node_key,
+			id,
+			client.chain_info().genesis_hash,
+			parachain_config.network.public_addresses.clone(),
+		)
+		.boxed(),
+	);
+
+	let fork_metrics = fork_pruning::Metrics::register(prometheus_registry.as_ref())
+		.map_err(|e| format!("Failed to register the displaced fork metrics: {:?}", e))?;
+	task_manager.spawn_handle().spawn(
+		"cumulus-displaced-forks",
+		report_displaced_forks(client.clone(), DisplacedForks::new(backend.clone(), fork_metrics))
+			.boxed(),
+	);
+
+	let maintain_pool = Arc::new(MaintainPoolOnNewBest::new(
+		client.clone(),
+		transaction_pool.clone(),
+		task_manager.spawn_handle(),
+	));
+
+	let collator_status = cumulus_collator::CollatorStatus::default();
+	let readiness = {
+		let relay_chain_network = polkadot_full_node.network.clone();
+		let network = network.clone();
+		let collator_status = collator_status.clone();
+		move || cumulus_rpc::Readiness {
+			relay_chain_synced: !relay_chain_network.is_major_syncing(),
+			parachain_synced: !network.is_major_syncing(),
+			collator_key_present: collator,
+			registered_with_overseer: collator_status.is_registered(),
+			last_candidate_age: collator_status.last_candidate_age().map(|a| a.as_secs()),
+		}
+	};
+
+	let inclusion_tracker = cumulus_collator::InclusionTracker::default();
+	let recent_inclusions = {
+		let inclusion_tracker = inclusion_tracker.clone();
+		move || {
+			inclusion_tracker
+				.recent()
+				.into_iter()
+				.map(|i| cumulus_rpc::Inclusion {
+					pov_hash: i.pov_hash,
+					produced_at: i.produced_at,
+					included_at: i.included_at,
+					latency: i.latency(),
+				})
+				.collect()
+		}
+	};
+
+	let journal = cumulus_collator::CollationJournal::new(
+		client.clone(),
+		cumulus_collator::journal::DEFAULT_JOURNAL_SIZE,
+	);
+	let journal_entries = {
+		let journal = journal.clone();
+		move || {
+			journal
+				.entries()
+				.into_iter()
+				.map(|e| cumulus_rpc::CollationJournalEntry {
+					timestamp: e.timestamp,
+					relay_parent: e.relay_parent,
+					para_parent: e.para_parent,
+					block_hash: e.block_hash,
+					pov_hash: e.pov_hash,
+					outcome: match e.outcome {
+						CollationOutcome::Produced => "produced".into(),
+						CollationOutcome::Skipped => "skipped".into(),
+						CollationOutcome::Failed(code) => code,
+					},
+				})
+				.collect()
+		}
+	};
+
+	let skip_reasons = cumulus_collator::SkipReasons::default();
+	let skipped_parents = {
+		let skip_reasons = skip_reasons.clone();
+		move || {
+			skip_reasons
+				.recent()
+				.into_iter()
+				.map(|s| cumulus_rpc::SkippedParent {
+					parent: s.parent,
+					reason: s.reason.to_string(),
+					skips: s.skips,
+					last_skip: s.last_skip,
+				})
+				.collect()
+		}
+	};
+
 	let rpc_client = client.clone();
-	let rpc_extensions_builder = Box::new(move |_, _| rpc_ext_builder(rpc_client.clone()));
+	let relay_client = polkadot_full_node.client.clone();
+	let rpc_extensions_builder = Box::new(move |_, _| {
+		let mut io = rpc_ext_builder(rpc_client.clone());
+		io.extend_with(cumulus_rpc::CodeUpgradeCheckApi::to_delegate(
+			cumulus_rpc::CodeUpgradeChecker::new(rpc_client.clone(), {
+				let relay_client = relay_client.clone();
+				move || current_max_pov_size(&relay_client)
+			}),
+		));
+		io.extend_with(cumulus_rpc::ReadinessApi::to_delegate(
+			cumulus_rpc::ReadinessHandler::new(readiness.clone()),
+		));
+		io.extend_with(cumulus_rpc::InclusionLatencyApi::to_delegate(
+			cumulus_rpc::InclusionLatencyHandler::new(recent_inclusions.clone()),
+		));
+		io.extend_with(cumulus_rpc::CollationJournalApi::to_delegate(
+			cumulus_rpc::CollationJournalHandler::new(journal_entries.clone()),
+		));
+		io.extend_with(cumulus_rpc::SkipReasonsApi::to_delegate(
+			cumulus_rpc::SkipReasonsHandler::new(skipped_parents.clone()),
+		));
+		io
+	});
+
+	let keystore = params.keystore_container.sync_keystore();
 
 	sc_service::spawn_tasks(sc_service::SpawnTasksParams {
 		on_demand: None,
@@ -161,27 +378,128 @@ where
 		task_manager: &mut task_manager,
 		telemetry_connection_sinks: Default::default(),
 		config: parachain_config,
-		keystore: params.keystore_container.sync_keystore(),
+		keystore: keystore.clone(),
 		backend: backend.clone(),
 		network: network.clone(),
 		network_status_sinks,
 		system_rpc_tx,
 	})?;
 
-	let announce_block = {
+	let announce_block: Arc<dyn AnnounceBlock<Block>> = {
 		let network = network.clone();
-		Arc::new(move |hash, data| network.announce_block(hash, data))
+		Arc::new(
+			EncodedAnnouncement::new(move |hash, data: Vec<u8>| {
+				if !data.is_empty() {
+					justifications.note(hash, data.clone());
+				}
+				network.announce_block(hash, data)
+			})
+			.with_peer_versions(peer_versions),
+		)
 	};
 
-	if validator {
+	if let Some(recovery) = recovery {
+		let mut sources: Vec<Arc<dyn BlockSource<Block>>> = Vec::new();
+		if let Some(pov_dir) = recovery.pov_dir {
+			sources.push(Arc::new(ExportedPoVSource::new(&pov_dir)?));
+		}
+		sources.push(Arc::new(recent_block_fetcher.clone()));
+
+		spawn_disaster_recovery(
+			id,
+			client.clone(),
+			&polkadot_full_node,
+			sources,
+			RecoveryImport::default(),
+			recovery_tasks.clone(),
+		);
+	}
+
+	if collator {
+		let parent_recovery = {
+			let network = network.clone();
+			let client = client.clone();
+			let bad_blocks = bad_blocks.clone();
+			let verifier = Verifier::new(client.clone(), params.inherent_data_providers.clone());
+			move |hash, number| {
+				// Ask the peers directly and let the sync fetch the block as fallback.
+				let fetcher = recent_block_fetcher.clone();
+				let block_import = bad_blocks.block_import(client.clone());
+				let verifier = verifier.clone();
+				recovery_tasks.spawn(
+					"cumulus-recent-block-recovery",
+					async move {
+						fetcher.fetch_and_import(hash, block_import, verifier).await;
+					}
+					.boxed(),
+				);
+				network.set_sync_fork_request(Vec::new(), hash, number)
+			}
+		};
+		let ready_transactions = {
+			let transaction_pool = transaction_pool.clone();
+			move || transaction_pool.status().ready
+		};
+		// Try the transactions that pay the most per byte of the PoV first, the footprint in the
+		// PoV is estimated from the encoded size of the transaction. Only the part of the PoV
+		// that is not reserved for the inherents and the messages is filled with transactions.
+		let pov_size_limit = PoVSizeLimit::default();
+		let transaction_priority = ByProofFootprint::new(|xt: &<Block as BlockT>::Extrinsic| {
+			xt.encoded_size() as u64
+		})
+		.budget(PoVReserve::default(), pov_size_limit.clone());
 		let proposer_factory = sc_basic_authorship::ProposerFactory::new(
 			task_manager.spawn_handle(),
 			client.clone(),
-			transaction_pool,
+			Arc::new(PrioritizedPool::new(transaction_pool, Arc::new(transaction_priority))),
 			prometheus_registry.as_ref(),
 		);
 		let spawner = task_manager.spawn_handle();
 
+		let event_handler = DefaultEventHandler::new(prometheus_registry.as_ref())
+			.map_err(|e| format!("Failed to register the collator event metrics: {:?}", e))?;
+		let announce_signer = KeystoreAnnounceSigner::new(keystore);
+		if announce_signer.is_none() {
+			CollatorEventHandler::<Block>::handle_event(
+				&event_handler,
+				&CollatorEvent::KeyMissing(ANNOUNCE_KEY_TYPE),
+			);
+		}
+
+		let mut config = CollatorConfig::default()
+			.prometheus_registry(prometheus_registry.clone())
+			.pov_export_dir(pov_export_dir)
+			.status(collator_status)
+			.pre_validate(pre_validate)
+			.detect_runtime_divergence(detect_runtime_divergence)
+			.ready_transactions(Arc::new(ready_transactions))
+			.parent_recovery(Arc::new(parent_recovery))
+			.announce_policy(announce_policy)
+			.execution_budget(execution_budget)
+			.inclusion_tracker(inclusion_tracker)
+			.pov_size_limit(pov_size_limit)
+			.announce_signer(
+				announce_signer.map(|s| Arc::new(s) as Arc<dyn AnnounceSigner<Block>>),
+			)
+			.event_handler(Arc::new(event_handler))
+			.native_version(parachain_runtime::VERSION)
+			.relay_chain_cache(relay_chain_cache)
+			.task_metrics(task_metrics)
+			.journal(journal)
+			.skip_reasons(skip_reasons)
+			.bad_block_repair(Arc::new(bad_blocks))
+			.receipts(
+				CollationReceipts::new(client.clone(), receipts::DEFAULT_RECEIPTS_SIZE)
+					.with_publisher(Arc::new(receipt_gossip)),
+			)
+			.new_best_observer(maintain_pool)
+			.upgrade_only_builder(Arc::new(
+				ClientUpgradeOnlyBuilder::<_, TFullBackend<Block>>::new(client.clone()),
+			));
+		if reexecute_own_blocks {
+			config = config.reexecute_own_blocks(Arc::new(WasmReexecution::new(client.clone())));
+		}
+
 		let params = StartCollatorParams {
 			para_id: id,
 			block_import: client.clone(),
@@ -195,6 +513,7 @@ where
 			polkadot_full_node,
 			spawner,
 			backend,
+			config,
 		};
 
 		start_collator(params).await?;
@@ -205,6 +524,8 @@ where
 			task_manager: &mut task_manager,
 			para_id: id,
 			polkadot_full_node,
+			relay_chain_cache,
+			new_best_observer: Some(maintain_pool),
 		};
 
 		start_full_node(params)?;
@@ -222,6 +543,13 @@ pub async fn start_node(
 	polkadot_config: Configuration,
 	id: polkadot_primitives::v0::Id,
 	validator: bool,
+	pov_export_dir: Option<PathBuf>,
+	pre_validate: bool,
+	reexecute_own_blocks: bool,
+	detect_runtime_divergence: bool,
+	announce_policy: AnnouncePolicy,
+	execution_budget: ExecutionBudget,
+	recovery: Option<RecoveryConfig>,
 ) -> sc_service::error::Result<(TaskManager, Arc<TFullClient<Block, RuntimeApi, Executor>>)> {
 	start_node_impl(
 		parachain_config,
@@ -229,7 +557,20 @@ pub async fn start_node(
 		polkadot_config,
 		id,
 		validator,
-		|_| Default::default(),
+		pov_export_dir,
+		pre_validate,
+		reexecute_own_blocks,
+		detect_runtime_divergence,
+		announce_policy,
+		execution_budget,
+		recovery,
+		|client| {
+			let mut io = jsonrpc_core::IoHandler::default();
+			io.extend_with(cumulus_rpc::ParachainApi::to_delegate(
+				cumulus_rpc::Parachain::new(client),
+			));
+			io
+		},
 	)
 	.await
 }
@@ -0,0 +1,37 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+use assert_cmd::cargo::cargo_bin;
+use cumulus_test_orchestrator::{Network, NetworkConfig};
+
+/// Requires a `polkadot` binary, set `POLKADOT_BINARY` to its path and run with `--ignored`.
+#[test]
+#[ignore]
+fn parachain_produces_blocks() {
+	let polkadot =
+		std::env::var("POLKADOT_BINARY").expect("`POLKADOT_BINARY` is set to a polkadot binary");
+
+	let mut network = Network::launch(
+		NetworkConfig::new(polkadot, cargo_bin("rococo-collator"))
+			.validators(2)
+			.collators(1),
+	)
+	.expect("Launches the network");
+
+	network
+		.assert_para_blocks(3, 30)
+		.expect("The parachain produces blocks");
+}
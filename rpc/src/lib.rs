@@ -0,0 +1,471 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cumulus specific RPC extensions.
+//!
+//! Exposes the relay chain context of the parachain, as recorded by the runtime, to external
+//! tools like indexers, the readiness of the node for health checks, the inclusion latency of
+//! the candidates produced by a collator, its journal of the last collations and why it skipped
+//! candidate production on recent parent blocks. Runtime upgrades can be checked before they are
+//! proposed.
+
+use codec::Encode;
+use cumulus_primitives::{
+	exceeds_max_pov_size, CodeUpgradeApi, CodeUpgradeError, PersistedValidationDataApi,
+};
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+use serde::{Deserialize, Serialize};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::Bytes;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+use std::{marker::PhantomData, sync::Arc};
+
+/// Error code returned when a runtime api call failed.
+const RUNTIME_ERROR: i64 = 1;
+
+/// Parachain specific RPC methods.
+#[rpc]
+pub trait ParachainApi<BlockHash> {
+	/// Returns the SCALE encoded `PersistedValidationData` that was set by the block with the
+	/// given hash or by the best block, if no hash is given.
+	#[rpc(name = "parachain_persistedValidationData")]
+	fn persisted_validation_data(&self, at: Option<BlockHash>) -> Result<Option<Bytes>>;
+}
+
+/// Implementation of [`ParachainApi`].
+pub struct Parachain<Client, Block> {
+	client: Arc<Client>,
+	_marker: PhantomData<Block>,
+}
+
+impl<Client, Block> Parachain<Client, Block> {
+	/// Create new instance of `Self`.
+	pub fn new(client: Arc<Client>) -> Self {
+		Self {
+			client,
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<Client, Block> ParachainApi<<Block as BlockT>::Hash> for Parachain<Client, Block>
+where
+	Block: BlockT,
+	Client: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+	Client::Api: PersistedValidationDataApi<Block>,
+{
+	fn persisted_validation_data(
+		&self,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Option<Bytes>> {
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		self.client
+			.runtime_api()
+			.persisted_validation_data(&at)
+			.map(|data| data.map(|d| d.encode().into()))
+			.map_err(|e| RpcError {
+				code: ErrorCode::ServerError(RUNTIME_ERROR),
+				message: "Unable to query the persisted validation data.".into(),
+				data: Some(format!("{:?}", e).into()),
+			})
+	}
+}
+
+/// The readiness of a parachain node.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+	/// Is the embedded relay chain node synced?
+	pub relay_chain_synced: bool,
+	/// Is the parachain node synced?
+	pub parachain_synced: bool,
+	/// Is the node running as collator, i.e. has a collator key?
+	pub collator_key_present: bool,
+	/// Is the collator registered at the overseer?
+	pub registered_with_overseer: bool,
+	/// Seconds since the last candidate was produced, `None` if none was produced yet.
+	pub last_candidate_age: Option<u64>,
+}
+
+impl Readiness {
+	/// Is the node ready?
+	///
+	/// A node is ready when both chains are synced and, if it is a collator, it is registered at
+	/// the overseer.
+	pub fn is_ready(&self) -> bool {
+		self.relay_chain_synced
+			&& self.parachain_synced
+			&& (!self.collator_key_present || self.registered_with_overseer)
+	}
+}
+
+/// RPC methods for health checks of a parachain node.
+#[rpc]
+pub trait ReadinessApi {
+	/// Returns the readiness of the node.
+	#[rpc(name = "parachain_readiness")]
+	fn readiness(&self) -> Result<Readiness>;
+}
+
+/// Implementation of [`ReadinessApi`].
+pub struct ReadinessHandler {
+	readiness: Arc<dyn Fn() -> Readiness + Send + Sync>,
+}
+
+impl ReadinessHandler {
+	/// Create new instance of `Self`.
+	///
+	/// `readiness` is called to determine the readiness on every request.
+	pub fn new(readiness: impl Fn() -> Readiness + Send + Sync + 'static) -> Self {
+		Self {
+			readiness: Arc::new(readiness),
+		}
+	}
+}
+
+impl ReadinessApi for ReadinessHandler {
+	fn readiness(&self) -> Result<Readiness> {
+		Ok((self.readiness)())
+	}
+}
+
+/// The inclusion of a candidate produced by the collator.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inclusion {
+	/// The hash of the PoV of the candidate.
+	pub pov_hash: sp_core::H256,
+	/// The number of the relay parent the candidate was produced on.
+	pub produced_at: u32,
+	/// The number of the relay block that included the candidate.
+	pub included_at: u32,
+	/// The number of relay blocks between production and inclusion.
+	pub latency: u32,
+}
+
+/// RPC methods for the inclusion latency of the candidates produced by a collator.
+#[rpc]
+pub trait InclusionLatencyApi {
+	/// Returns the most recent inclusions of candidates produced by this collator, oldest first.
+	#[rpc(name = "parachain_recentInclusions")]
+	fn recent_inclusions(&self) -> Result<Vec<Inclusion>>;
+}
+
+/// Implementation of [`InclusionLatencyApi`].
+pub struct InclusionLatencyHandler {
+	recent_inclusions: Arc<dyn Fn() -> Vec<Inclusion> + Send + Sync>,
+}
+
+impl InclusionLatencyHandler {
+	/// Create new instance of `Self`.
+	///
+	/// `recent_inclusions` is called to get the inclusions on every request.
+	pub fn new(recent_inclusions: impl Fn() -> Vec<Inclusion> + Send + Sync + 'static) -> Self {
+		Self {
+			recent_inclusions: Arc::new(recent_inclusions),
+		}
+	}
+}
+
+impl InclusionLatencyApi for InclusionLatencyHandler {
+	fn recent_inclusions(&self) -> Result<Vec<Inclusion>> {
+		Ok((self.recent_inclusions)())
+	}
+}
+
+/// A collation request in the collation journal of the collator.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollationJournalEntry {
+	/// Milliseconds since the unix epoch when the request was finished.
+	pub timestamp: u64,
+	/// The relay parent of the request.
+	pub relay_parent: sp_core::H256,
+	/// The parachain block the candidate was built on, if known.
+	pub para_parent: Option<sp_core::H256>,
+	/// The hash of the produced block.
+	pub block_hash: Option<sp_core::H256>,
+	/// The hash of the PoV of the produced candidate.
+	pub pov_hash: Option<sp_core::H256>,
+	/// Either `produced`, `skipped` or the code of the error that failed the request.
+	pub outcome: String,
+}
+
+/// RPC methods for the collation journal of a collator.
+#[rpc]
+pub trait CollationJournalApi {
+	/// Returns the last collation requests of this collator, oldest first.
+	///
+	/// The journal is persisted, so it includes the requests before the last restart of the node.
+	#[rpc(name = "parachain_collationJournal")]
+	fn collation_journal(&self) -> Result<Vec<CollationJournalEntry>>;
+}
+
+/// Implementation of [`CollationJournalApi`].
+pub struct CollationJournalHandler {
+	entries: Arc<dyn Fn() -> Vec<CollationJournalEntry> + Send + Sync>,
+}
+
+impl CollationJournalHandler {
+	/// Create new instance of `Self`.
+	///
+	/// `entries` is called to read the journal on every request.
+	pub fn new(entries: impl Fn() -> Vec<CollationJournalEntry> + Send + Sync + 'static) -> Self {
+		Self {
+			entries: Arc::new(entries),
+		}
+	}
+}
+
+impl CollationJournalApi for CollationJournalHandler {
+	fn collation_journal(&self) -> Result<Vec<CollationJournalEntry>> {
+		Ok((self.entries)())
+	}
+}
+
+/// The last time a collator skipped candidate production on a parent block.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedParent {
+	/// The hash of the parent block.
+	pub parent: sp_core::H256,
+	/// Why the last candidate was skipped: `queued`, `pruned`, `known_bad`, `unknown` or the
+	/// error that occurred while reading the status of the parent.
+	pub reason: String,
+	/// How often candidate production on the parent was skipped.
+	pub skips: u32,
+	/// Milliseconds since the unix epoch of the last skip.
+	pub last_skip: u64,
+}
+
+/// RPC methods for the reasons a collator skipped candidate production.
+#[rpc]
+pub trait SkipReasonsApi {
+	/// Returns the parent blocks candidate production was skipped on most recently, oldest first.
+	#[rpc(name = "parachain_skippedParents")]
+	fn skipped_parents(&self) -> Result<Vec<SkippedParent>>;
+}
+
+/// Implementation of [`SkipReasonsApi`].
+pub struct SkipReasonsHandler {
+	skipped_parents: Arc<dyn Fn() -> Vec<SkippedParent> + Send + Sync>,
+}
+
+impl SkipReasonsHandler {
+	/// Create new instance of `Self`.
+	///
+	/// `skipped_parents` is called to get the skipped parents on every request.
+	pub fn new(skipped_parents: impl Fn() -> Vec<SkippedParent> + Send + Sync + 'static) -> Self {
+		Self {
+			skipped_parents: Arc::new(skipped_parents),
+		}
+	}
+}
+
+impl SkipReasonsApi for SkipReasonsHandler {
+	fn skipped_parents(&self) -> Result<Vec<SkippedParent>> {
+		Ok((self.skipped_parents)())
+	}
+}
+
+/// The size of the PoV of a block that carries a runtime upgrade, besides the code, in bytes.
+///
+/// Covers the inherents and the storage proof of the block that only carries the upgrade.
+pub const UPGRADE_BLOCK_OVERHEAD: usize = 64 * 1024;
+
+/// Returns the estimated size of the PoV of the block that carries new validation code of
+/// `code_size` bytes.
+///
+/// The code is part of the block, in the extrinsic that sets it. The collator checks the PoV
+/// together with the new code against the maximum PoV size, see [`exceeds_max_pov_size`].
+pub fn estimated_upgrade_pov_size(code_size: usize) -> usize {
+	code_size.saturating_add(UPGRADE_BLOCK_OVERHEAD)
+}
+
+/// The result of checking a runtime upgrade before it is proposed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeUpgradeCheck {
+	/// The size of the checked code in bytes.
+	pub code_size: u64,
+	/// The estimated size of the PoV of the block that carries the upgrade, in bytes.
+	pub estimated_pov_size: u64,
+	/// The current maximum PoV size of the relay chain, in bytes.
+	pub max_pov_size: u64,
+	/// The relay chain block number at which the upgrade would be applied, if it could be
+	/// scheduled now.
+	pub apply_block: Option<u32>,
+	/// Why the upgrade can not be included in a candidate: `exceeds_max_pov_size`,
+	/// `overlapping_upgrades`, `prohibited_by_relay_chain`, `too_big` or
+	/// `validation_data_not_available`.
+	pub problems: Vec<String>,
+}
+
+impl CodeUpgradeCheck {
+	/// Can the upgrade be included in a candidate?
+	pub fn is_ok(&self) -> bool {
+		self.problems.is_empty()
+	}
+}
+
+/// RPC methods to check a runtime upgrade before it is proposed, e.g. by governance.
+#[rpc]
+pub trait CodeUpgradeCheckApi<BlockHash> {
+	/// Check if the validation `code` can be included in a candidate on top of the block with
+	/// the given hash or the best block, if no hash is given.
+	///
+	/// Checks the maximum PoV size and the upgrade constraints of the relay chain, as recorded
+	/// by the runtime.
+	#[rpc(name = "parachain_checkCodeUpgrade")]
+	fn check_code_upgrade(&self, code: Bytes, at: Option<BlockHash>) -> Result<CodeUpgradeCheck>;
+}
+
+/// Implementation of [`CodeUpgradeCheckApi`].
+pub struct CodeUpgradeChecker<Client, Block> {
+	client: Arc<Client>,
+	max_pov_size: Arc<dyn Fn() -> usize + Send + Sync>,
+	_marker: PhantomData<Block>,
+}
+
+impl<Client, Block> CodeUpgradeChecker<Client, Block> {
+	/// Create new instance of `Self`.
+	///
+	/// `max_pov_size` is called to get the current maximum PoV size of the relay chain in bytes
+	/// on every request.
+	pub fn new(
+		client: Arc<Client>,
+		max_pov_size: impl Fn() -> usize + Send + Sync + 'static,
+	) -> Self {
+		Self {
+			client,
+			max_pov_size: Arc::new(max_pov_size),
+			_marker: PhantomData,
+		}
+	}
+}
+
+impl<Client, Block> CodeUpgradeCheckApi<<Block as BlockT>::Hash>
+	for CodeUpgradeChecker<Client, Block>
+where
+	Block: BlockT,
+	Client: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+	Client::Api: CodeUpgradeApi<Block>,
+{
+	fn check_code_upgrade(
+		&self,
+		code: Bytes,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<CodeUpgradeCheck> {
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let estimated_pov_size = estimated_upgrade_pov_size(code.len());
+		let max_pov_size = (self.max_pov_size)();
+
+		let scheduled = self
+			.client
+			.runtime_api()
+			.check_code_upgrade(&at, code.len() as u32)
+			.map_err(|e| RpcError {
+				code: ErrorCode::ServerError(RUNTIME_ERROR),
+				message: "Unable to check the code upgrade.".into(),
+				data: Some(format!("{:?}", e).into()),
+			})?;
+
+		let mut problems = Vec::new();
+		if exceeds_max_pov_size(estimated_pov_size, code.len(), max_pov_size) {
+			problems.push("exceeds_max_pov_size".into());
+		}
+		let apply_block = match scheduled {
+			Ok(apply_block) => Some(apply_block),
+			Err(e) => {
+				problems.push(
+					match e {
+						CodeUpgradeError::OverlappingUpgrades => "overlapping_upgrades",
+						CodeUpgradeError::ProhibitedByRelayChain => "prohibited_by_relay_chain",
+						CodeUpgradeError::TooBig { .. } => "too_big",
+						CodeUpgradeError::ValidationDataNotAvailable => {
+							"validation_data_not_available"
+						}
+					}
+					.into(),
+				);
+				None
+			}
+		};
+
+		Ok(CodeUpgradeCheck {
+			code_size: code.len() as u64,
+			estimated_pov_size: estimated_pov_size as u64,
+			max_pov_size: max_pov_size as u64,
+			apply_block,
+			problems,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_primitives::ValidationData;
+	use cumulus_test_client::{
+		BlockBuilderExt, ClientBlockImportExt, DefaultTestClientBuilderExt, TestClientBuilder,
+		TestClientBuilderExt,
+	};
+	use sc_block_builder::BlockBuilderProvider;
+	use sp_consensus::BlockOrigin;
+
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	#[test]
+	fn checks_the_upgrade_against_the_current_max_pov_size() {
+		let mut client = TestClientBuilder::new().build();
+		let mut validation_data = ValidationData::default();
+		validation_data.transient.max_code_size = 1024 * 1024;
+		validation_data.transient.code_upgrade_allowed = Some(20);
+
+		let block = client
+			.new_block(Default::default())
+			.unwrap()
+			.build_with_validation_data(&client, validation_data, Vec::new())
+			.expect("Builds the block")
+			.block;
+		client.import(BlockOrigin::Own, block).expect("Imports the block");
+
+		let max_pov_size = Arc::new(AtomicUsize::new(0));
+		let checker = CodeUpgradeChecker::new(Arc::new(client), {
+			let max_pov_size = max_pov_size.clone();
+			move || max_pov_size.load(Ordering::Relaxed)
+		});
+		let code = Bytes(vec![0; 1024]);
+		let pov_size = estimated_upgrade_pov_size(code.len());
+
+		// The PoV together with the code just fits.
+		max_pov_size.store(pov_size + code.len(), Ordering::Relaxed);
+		let check = checker.check_code_upgrade(code.clone(), None).unwrap();
+		assert!(check.is_ok());
+		assert_eq!(Some(20), check.apply_block);
+		assert_eq!(pov_size as u64, check.estimated_pov_size);
+
+		// The maximum PoV size of the relay chain is read on every request.
+		max_pov_size.store(pov_size + code.len() - 1, Ordering::Relaxed);
+		let check = checker.check_code_upgrade(code, None).unwrap();
+		assert_eq!(vec!["exceeds_max_pov_size".to_string()], check.problems);
+		assert_eq!((pov_size + 1023) as u64, check.max_pov_size);
+	}
+}
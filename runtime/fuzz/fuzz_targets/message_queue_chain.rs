@@ -0,0 +1,54 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+
+use cumulus_primitives::{
+	relay_chain::{InboundDownwardMessage, InboundHrmpMessage},
+	MessageQueueChain,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	// Every chunk is a message, its first byte the relay block it was sent at.
+	let messages = data
+		.chunks(16)
+		.map(|chunk| (chunk[0] as u32, chunk[1..].to_vec()))
+		.collect::<Vec<_>>();
+
+	let mut downward = MessageQueueChain::default();
+	let mut hrmp = MessageQueueChain::default();
+	for (sent_at, msg) in &messages {
+		let previous = downward.head();
+
+		downward.extend_downward(&InboundDownwardMessage {
+			sent_at: *sent_at,
+			msg: msg.clone(),
+		});
+		hrmp.extend_hrmp(&InboundHrmpMessage {
+			sent_at: *sent_at,
+			data: msg.clone(),
+		});
+
+		assert_ne!(previous, downward.head());
+		assert_eq!(
+			downward,
+			*MessageQueueChain::new(previous).extend(*sent_at, msg),
+		);
+	}
+
+	assert_eq!(downward, hrmp);
+});
@@ -0,0 +1,118 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Regression tests for the encoding of the PoV.
+//!
+//! Validators decode the [`ParachainBlockData`] of a candidate with the validation code that is
+//! registered on the relay chain. If the encoding changes by accident, the new collators produce
+//! PoVs the running chains can not validate anymore. The encoding is therefore compared byte for
+//! byte against the fixture in `fixtures/` of the current [`VERSION`].
+//!
+//! If a change of the encoding is intended, bump [`VERSION`] and run the tests with
+//! `CUMULUS_UPDATE_FIXTURES=1` to write the fixture of the new version. Existing fixtures are
+//! never overwritten, the fixtures of older versions are kept for reference.
+
+use crate::ParachainBlockData;
+
+use codec::{Decode, Encode};
+use sp_core::{bytes, H256};
+use sp_runtime::{
+	generic::{self, Digest, DigestItem},
+	traits::BlakeTwo256,
+	OpaqueExtrinsic,
+};
+use sp_trie::StorageProof;
+
+use std::path::PathBuf;
+
+type Header = generic::Header<u32, BlakeTwo256>;
+type Block = generic::Block<Header, OpaqueExtrinsic>;
+
+/// The version of the encoding of [`ParachainBlockData`].
+const VERSION: u32 = 1;
+
+/// Set this environment variable to write the fixture of a new [`VERSION`].
+const UPDATE_FIXTURES: &str = "CUMULUS_UPDATE_FIXTURES";
+
+fn fixture_path(version: u32) -> PathBuf {
+	PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+		.join("fixtures")
+		.join(format!("parachain_block_data_v{}.hex", version))
+}
+
+fn read_fixture(version: u32) -> Vec<u8> {
+	let path = fixture_path(version);
+	let hex = std::fs::read_to_string(&path)
+		.unwrap_or_else(|e| panic!("Failed to read the fixture `{}`: {}", path.display(), e));
+
+	bytes::from_hex(hex.trim()).expect("Fixtures are hex encoded")
+}
+
+fn canonical_header() -> Header {
+	Header {
+		parent_hash: H256::repeat_byte(1),
+		number: 2,
+		state_root: H256::repeat_byte(3),
+		extrinsics_root: H256::repeat_byte(4),
+		digest: Digest {
+			logs: vec![DigestItem::Other(vec![5, 6])],
+		},
+	}
+}
+
+fn canonical_extrinsics() -> Vec<OpaqueExtrinsic> {
+	vec![OpaqueExtrinsic::decode(&mut &[12, 1, 2, 3][..]).expect("Decodes the extrinsic")]
+}
+
+/// The block data the fixtures are generated from.
+fn canonical_block_data() -> ParachainBlockData<Block> {
+	ParachainBlockData::new(
+		canonical_header(),
+		canonical_extrinsics(),
+		StorageProof::new(vec![vec![7, 8, 9]]),
+	)
+}
+
+#[test]
+fn encoding_matches_the_fixture() {
+	let encoded = canonical_block_data().encode();
+	let path = fixture_path(VERSION);
+
+	if std::env::var_os(UPDATE_FIXTURES).is_some() && !path.exists() {
+		std::fs::write(&path, format!("{}\n", bytes::to_hex(&encoded, false)))
+			.expect("Writes the fixture");
+	}
+
+	assert!(
+		read_fixture(VERSION) == encoded,
+		"The encoding of `ParachainBlockData` does not match `{}`. Running chains can not \
+		validate PoVs with a different encoding. If the change is intended, bump `VERSION` \
+		and run the tests with `{}=1`.",
+		path.display(),
+		UPDATE_FIXTURES,
+	);
+}
+
+#[test]
+fn fixture_decodes_to_the_canonical_block_data() {
+	let fixture = read_fixture(VERSION);
+	let block_data = ParachainBlockData::<Block>::decode(&mut &fixture[..])
+		.expect("Decodes the fixture");
+
+	assert_eq!(&canonical_header(), block_data.header());
+	assert_eq!(&canonical_extrinsics()[..], block_data.extrinsics());
+	assert_eq!(fixture, block_data.encode());
+}
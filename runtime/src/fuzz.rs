@@ -0,0 +1,40 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Entry points for fuzzing the decoding of the data passed to `validate_block`.
+//!
+//! Validators run `validate_block` on PoVs supplied by collators, so the decode path needs to
+//! handle arbitrary bytes. The entry points return an error for invalid input and must never
+//! panic. The fuzz targets live in `fuzz/`, exported PoVs of a collator are a good corpus.
+
+use crate::{validate_block::witness::check_block, ParachainBlockData};
+
+use codec::Decode;
+use parachain::primitives::ValidationParams;
+use sp_runtime::traits::Block as BlockT;
+
+/// Decode `data` as [`ParachainBlockData`].
+pub fn decode_parachain_block_data<B: BlockT>(mut data: &[u8]) -> Result<(), codec::Error> {
+	ParachainBlockData::<B>::decode(&mut data).map(|_| ())
+}
+
+/// Decode `data` as [`ValidationParams`] and run the checks `validate_block` does before
+/// executing the block.
+pub fn check_validation_params<B: BlockT>(mut data: &[u8]) -> Result<(), &'static str> {
+	let params = ValidationParams::decode(&mut data).map_err(|_| "Invalid validation params")?;
+
+	check_block::<B>(&params).map(|_| ())
+}
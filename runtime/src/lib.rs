@@ -26,11 +26,15 @@ use sp_trie::StorageProof;
 #[doc(hidden)]
 pub use sp_std::slice;
 
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+#[cfg(test)]
+mod encoding_tests;
 #[macro_use]
 pub mod validate_block;
 
 /// The parachain block that is created on a collator and validated by a validator.
-#[derive(Encode, Decode)]
+#[derive(Clone, Encode, Decode)]
 pub struct ParachainBlockData<B: BlockT> {
 	/// The header of the parachain block.
 	header: <B as BlockT>::Header,
@@ -17,22 +17,15 @@
 //! The actual implementation of the validate block functionality.
 
 use frame_executive::ExecuteBlock;
-use sp_runtime::traits::{Block as BlockT, HashFor, NumberFor, Header as HeaderT};
+use sp_runtime::traits::{Block as BlockT, HashFor, NumberFor};
 
 use sp_std::{boxed::Box, vec::Vec};
 
-use hash_db::{HashDB, EMPTY_PREFIX};
-
-use parachain::primitives::{HeadData, ValidationCode, ValidationParams, ValidationResult};
+use parachain::primitives::{ValidationParams, ValidationResult};
 
 use codec::{Decode, Encode};
 
-use cumulus_primitives::{
-	well_known_keys::{
-		NEW_VALIDATION_CODE, PROCESSED_DOWNWARD_MESSAGES, UPWARD_MESSAGES, VALIDATION_DATA,
-	},
-	GenericUpwardMessage, ValidationData,
-};
+use cumulus_primitives::{well_known_keys::VALIDATION_DATA, CollationInfo, ValidationData};
 use sp_externalities::{set_and_run_with_externalities};
 use sp_externalities::{Externalities, ExtensionStore, Error, Extension};
 use sp_trie::MemoryDB;
@@ -66,25 +59,13 @@ impl Encode for EncodeOpaqueValue {
 /// Validate a given parachain block on a validator.
 #[doc(hidden)]
 pub fn validate_block<B: BlockT, E: ExecuteBlock<B>>(params: ValidationParams) -> ValidationResult {
-	let block_data = crate::ParachainBlockData::<B>::decode(&mut &params.block_data.0[..])
-		.expect("Invalid parachain block data");
-
-	let parent_head =
-		B::Header::decode(&mut &params.parent_head.0[..]).expect("Invalid parent head");
-
-	let head_data = HeadData(block_data.header.encode());
-
-	let block = B::new(block_data.header, block_data.extrinsics);
-	assert!(
-		parent_head.hash() == *block.header().parent_hash(),
-		"Invalid parent hash",
-	);
+	let super::witness::CheckedBlock {
+		block,
+		head_data,
+		db,
+		root,
+	} = super::witness::check_block::<B>(&params).unwrap_or_else(|e| panic!("{}", e));
 
-	let db = block_data.storage_proof.into_memory_db();
-	let root = parent_head.state_root().clone();
-	if !HashDB::<HashFor<B>, _>::contains(&db, &root, EMPTY_PREFIX) {
-		panic!("Witness data does not contain given storage root.");
-	}
 	let backend = sp_state_machine::TrieBackend::new(
 		db,
 		root,
@@ -140,40 +121,17 @@ pub fn validate_block<B: BlockT, E: ExecuteBlock<B>>(params: ValidationParams) -
 		E::execute_block(block);
 	});
 
-	// If in the course of block execution new validation code was set, insert
-	// its scheduled upgrade so we can validate that block number later.
-	let new_validation_code = overlay.storage(NEW_VALIDATION_CODE).flatten()
-		.map(|slice| slice.to_vec())
-		.map(ValidationCode);
-
-	// Extract potential upward messages from the storage.
-	let upward_messages = match overlay.storage(UPWARD_MESSAGES).flatten() {
-		Some(encoded) => Vec::<GenericUpwardMessage>::decode(&mut &encoded[..])
-			.expect("Upward messages vec is not correctly encoded in the storage!"),
-		None => Vec::new(),
-	};
-
-	let processed_downward_messages = overlay.storage(PROCESSED_DOWNWARD_MESSAGES)
-		.flatten()
-		.map(|v|
-			Decode::decode(&mut &v[..])
-				.expect("Processed downward message count is not correctly encoded in the storage")
-		)
-		.unwrap_or_default();
-
 	let validation_data: ValidationData = overlay.storage(VALIDATION_DATA).flatten()
 			.and_then(|v| Decode::decode(&mut &v[..]).ok())
 			.expect("`ValidationData` is required to be placed into the storage!");
 
-	ValidationResult {
+	match CollationInfo::from_storage(
+		|key| overlay.storage(key).flatten().map(|v| v.to_vec()),
 		head_data,
-		new_validation_code,
-		upward_messages,
-		processed_downward_messages,
-		//TODO!
-		horizontal_messages: Vec::new(),
-		//TODO!
-		hrmp_watermark: validation_data.persisted.block_number,
+		validation_data.persisted.block_number,
+	) {
+		Ok(info) => info.into(),
+		Err(e) => panic!("{} are not correctly encoded in the storage: {:?}", e.what, e.error),
 	}
 }
 
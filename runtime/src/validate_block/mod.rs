@@ -16,11 +16,17 @@
 
 //! A module that enables a runtime to work as parachain.
 
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_std::marker::PhantomData;
+
+pub use frame_executive::ExecuteBlock;
+
 #[cfg(not(feature = "std"))]
 #[doc(hidden)]
 pub mod implementation;
 #[cfg(test)]
 mod tests;
+pub(crate) mod witness;
 
 #[cfg(not(feature = "std"))]
 #[doc(hidden)]
@@ -31,7 +37,9 @@ pub use parachain;
 ///
 /// Does *nothing* when `std` feature is enabled.
 ///
-/// Expects as parameters the block and the block executor.
+/// Expects as parameters the block and the block executor. The block executor needs to
+/// implement [`ExecuteBlock`]. Usually this is `frame_executive::Executive`, but it can be any
+/// custom implementation, e.g. [`SealStrippingExecutor`] for runtimes whose blocks are sealed.
 ///
 /// # Example
 ///
@@ -83,3 +91,28 @@ macro_rules! register_validate_block_impl {
 macro_rules! register_validate_block_impl {
 	($block:ty, $block_executor:ty) => {};
 }
+
+/// A block executor that removes the seal digest items from the header before executing the
+/// block with `E`.
+///
+/// Consensus engines like Aura add a seal to the header after the block was built. The runtime
+/// doesn't know about the seal and would reject the header when re-executing the block, so it
+/// needs to be removed before. The head data returned by `validate_block` still contains the seal.
+pub struct SealStrippingExecutor<E>(PhantomData<E>);
+
+impl<B: BlockT, E: ExecuteBlock<B>> ExecuteBlock<B> for SealStrippingExecutor<E> {
+	fn execute_block(block: B) {
+		let (mut header, extrinsics) = block.deconstruct();
+
+		while header
+			.digest()
+			.logs()
+			.last()
+			.map_or(false, |d| d.as_seal().is_some())
+		{
+			header.digest_mut().pop();
+		}
+
+		E::execute_block(B::new(header, extrinsics))
+	}
+}
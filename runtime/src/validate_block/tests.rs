@@ -14,14 +14,16 @@
 // You should have received a copy of the GNU General Public License
 // along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::{ExecuteBlock, SealStrippingExecutor};
 use crate::ParachainBlockData;
 
 use cumulus_primitives::{PersistedValidationData, ValidationData};
 use cumulus_test_client::{
 	generate_block_inherents,
-	runtime::{Block, Hash, Header, UncheckedExtrinsic, WASM_BINARY},
-	transfer, Client, DefaultTestClientBuilderExt, LongestChain, TestClientBuilder,
-	TestClientBuilderExt,
+	generate_extrinsic,
+	runtime::{test_pallet, Block, Call, Hash, Header, UncheckedExtrinsic, WASM_BINARY},
+	transfer, Client, ClientBlockImportExt, DefaultTestClientBuilderExt, LongestChain,
+	TestClientBuilder, TestClientBuilderExt,
 };
 use parachain::primitives::{BlockData, HeadData, ValidationParams, ValidationResult};
 use sc_block_builder::BlockBuilderProvider;
@@ -29,17 +31,19 @@ use sc_executor::{
 	error::Result, sp_wasm_interface::HostFunctions, WasmExecutionMethod, WasmExecutor,
 };
 use sp_blockchain::HeaderBackend;
-use sp_consensus::SelectChain;
+use sp_consensus::{BlockOrigin, SelectChain};
 use sp_core::traits::CallInWasm;
 use sp_io::TestExternalities;
 use sp_keyring::AccountKeyring::*;
 use sp_runtime::{
-	generic::BlockId,
+	generic::{BlockId, DigestItem},
 	traits::{Block as BlockT, Header as HeaderT},
 };
 
 use codec::{Decode, Encode};
 
+use std::cell::RefCell;
+
 fn call_validate_block(
 	parent_head: Header,
 	block_data: ParachainBlockData<Block>,
@@ -158,6 +162,68 @@ fn validate_block_with_extra_extrinsics() {
 	assert_eq!(header, res_header);
 }
 
+#[test]
+fn validate_block_with_child_storage() {
+	let _ = env_logger::try_init();
+
+	let (client, longest_chain) = create_test_client();
+	let parent_head = longest_chain.best_chain().expect("Best block exists");
+	let extra_extrinsics = vec![generate_extrinsic(
+		&client,
+		Alice,
+		Call::TestPallet(test_pallet::Call::set_child_storage(b"key".to_vec(), b"value".to_vec())),
+	)];
+
+	let (block, witness_data) =
+		build_block_with_proof(&client, extra_extrinsics, parent_head.clone());
+	let (header, extrinsics) = block.deconstruct();
+
+	let block_data = ParachainBlockData::new(header.clone(), extrinsics, witness_data);
+
+	let res_header = call_validate_block(parent_head, block_data).expect("Calls `validate_block`");
+	assert_eq!(header, res_header);
+}
+
+#[test]
+fn validate_block_modifying_existing_child_storage() {
+	let _ = env_logger::try_init();
+
+	let (mut client, longest_chain) = create_test_client();
+	let parent_head = longest_chain.best_chain().expect("Best block exists");
+	let extra_extrinsics = vec![generate_extrinsic(
+		&client,
+		Alice,
+		Call::TestPallet(test_pallet::Call::set_child_storage(b"key".to_vec(), b"value".to_vec())),
+	)];
+
+	let (block, _) = build_block_with_proof(&client, extra_extrinsics, parent_head);
+	client.import(BlockOrigin::Own, block).expect("Imports the block");
+
+	// The second block needs the existing child trie nodes in its proof.
+	let parent_head = longest_chain.best_chain().expect("Best block exists");
+	let extra_extrinsics = vec![
+		generate_extrinsic(
+			&client,
+			Bob,
+			Call::TestPallet(test_pallet::Call::set_child_storage(b"key2".to_vec(), b"value2".to_vec())),
+		),
+		generate_extrinsic(
+			&client,
+			Charlie,
+			Call::TestPallet(test_pallet::Call::kill_child_storage()),
+		),
+	];
+
+	let (block, witness_data) =
+		build_block_with_proof(&client, extra_extrinsics, parent_head.clone());
+	let (header, extrinsics) = block.deconstruct();
+
+	let block_data = ParachainBlockData::new(header.clone(), extrinsics, witness_data);
+
+	let res_header = call_validate_block(parent_head, block_data).expect("Calls `validate_block`");
+	assert_eq!(header, res_header);
+}
+
 #[test]
 #[should_panic(expected = "Calls `validate_block`: Other(\"Trap: Trap { kind: Unreachable }\")")]
 fn validate_block_invalid_parent_hash() {
@@ -172,3 +238,73 @@ fn validate_block_invalid_parent_hash() {
 	let block_data = ParachainBlockData::new(header, extrinsics, witness_data);
 	call_validate_block(parent_head, block_data).expect("Calls `validate_block`");
 }
+
+#[test]
+fn check_block_returns_errors_for_invalid_input() {
+	let _ = env_logger::try_init();
+
+	let (client, longest_chain) = create_test_client();
+	let parent_head = longest_chain.best_chain().expect("Best block exists");
+	let (block, witness_data) = build_block_with_proof(&client, vec![], parent_head.clone());
+	let (header, extrinsics) = block.deconstruct();
+
+	let params = |block_data: Vec<u8>| ValidationParams {
+		block_data: BlockData(block_data),
+		parent_head: HeadData(parent_head.encode()),
+		relay_chain_height: 1,
+		hrmp_mqc_heads: Vec::new(),
+		dmq_mqc_head: Default::default(),
+	};
+
+	assert_eq!(
+		Some("Invalid parachain block data"),
+		super::witness::check_block::<Block>(&params(vec![1, 2, 3])).err(),
+	);
+
+	let mut invalid_header = header.clone();
+	invalid_header.set_parent_hash(Hash::from_low_u64_be(1));
+	let block_data =
+		ParachainBlockData::<Block>::new(invalid_header, extrinsics.clone(), witness_data.clone());
+	assert_eq!(
+		Some("Invalid parent hash"),
+		super::witness::check_block::<Block>(&params(block_data.encode())).err(),
+	);
+
+	let block_data = ParachainBlockData::<Block>::new(header, extrinsics, witness_data);
+	assert!(super::witness::check_block::<Block>(&params(block_data.encode())).is_ok());
+}
+
+#[test]
+fn seal_stripping_executor_removes_seals() {
+	thread_local! {
+		static EXECUTED: RefCell<Option<Header>> = RefCell::new(None);
+	}
+
+	struct RecordingExecutor;
+
+	impl ExecuteBlock<Block> for RecordingExecutor {
+		fn execute_block(block: Block) {
+			EXECUTED.with(|e| *e.borrow_mut() = Some(block.header().clone()));
+		}
+	}
+
+	let mut header = Header::new(
+		1,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	header
+		.digest_mut()
+		.push(DigestItem::PreRuntime(*b"test", vec![1]));
+	header.digest_mut().push(DigestItem::Seal(*b"test", vec![2]));
+
+	SealStrippingExecutor::<RecordingExecutor>::execute_block(Block::new(header, Vec::new()));
+
+	let executed = EXECUTED.with(|e| e.borrow_mut().take()).expect("Block was executed");
+	assert_eq!(
+		vec![DigestItem::PreRuntime(*b"test", vec![1])],
+		executed.digest().logs().to_vec(),
+	);
+}
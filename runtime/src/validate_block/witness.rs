@@ -0,0 +1,73 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding and checking of the attacker supplied data passed to `validate_block`.
+//!
+//! Everything in here needs to return an error for invalid input instead of panicking, as it is
+//! also used by the fuzzing entry points.
+
+use sp_runtime::traits::{Block as BlockT, HashFor, Header as HeaderT};
+
+use hash_db::{HashDB, EMPTY_PREFIX};
+
+use parachain::primitives::{HeadData, ValidationParams};
+
+use codec::{Decode, Encode};
+
+use sp_trie::MemoryDB;
+
+/// A parachain block that was decoded from the [`ValidationParams`] and that builds on the given
+/// parent head.
+pub struct CheckedBlock<B: BlockT> {
+	/// The block to execute.
+	pub block: B,
+	/// The head data of the block.
+	pub head_data: HeadData,
+	/// The witness data of the block.
+	pub db: MemoryDB<HashFor<B>>,
+	/// The state root of the parent block.
+	pub root: B::Hash,
+}
+
+/// Decode the block and the parent head in `params` and check that the witness data contains
+/// the state root of the parent.
+pub fn check_block<B: BlockT>(params: &ValidationParams) -> Result<CheckedBlock<B>, &'static str> {
+	let block_data = crate::ParachainBlockData::<B>::decode(&mut &params.block_data.0[..])
+		.map_err(|_| "Invalid parachain block data")?;
+
+	let parent_head =
+		B::Header::decode(&mut &params.parent_head.0[..]).map_err(|_| "Invalid parent head")?;
+
+	let head_data = HeadData(block_data.header.encode());
+
+	let block = B::new(block_data.header, block_data.extrinsics);
+	if parent_head.hash() != *block.header().parent_hash() {
+		return Err("Invalid parent hash");
+	}
+
+	let db = block_data.storage_proof.into_memory_db();
+	let root = *parent_head.state_root();
+	if !HashDB::<HashFor<B>, _>::contains(&db, &root, EMPTY_PREFIX) {
+		return Err("Witness data does not contain given storage root.");
+	}
+
+	Ok(CheckedBlock {
+		block,
+		head_data,
+		db,
+		root,
+	})
+}
@@ -0,0 +1,30 @@
+#!/usr/bin/env bash
+
+# Seed the corpus of the `parachain_block_data` fuzz target with PoVs that were exported by a
+# collator running with `--pov-export-dir`.
+
+usage() {
+    echo Usage:
+    echo "$0 <pov-export-dir>"
+    exit 1
+}
+
+pov_dir=$1
+
+[ -z "$pov_dir" ] && usage
+if ! [ -d "$pov_dir" ]; then
+    echo "Not a directory: $pov_dir"
+    exit 1
+fi
+
+corpus="$(dirname "$0")/../runtime/fuzz/corpus/parachain_block_data"
+mkdir -p "$corpus"
+
+count=0
+for pov in "$pov_dir"/*.pov; do
+    [ -e "$pov" ] || continue
+    cp "$pov" "$corpus/"
+    count=$((count + 1))
+done
+
+echo "Copied $count PoVs into $corpus"
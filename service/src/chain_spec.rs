@@ -0,0 +1,291 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The chain spec extension of a parachain.
+//!
+//! The id of the parachain and the relay chain it runs on are part of the chain spec, next to
+//! the genesis config of the runtime that contains the same id. The node reads them with
+//! [`ParachainExtensions::try_get`], instead of relying on a CLI flag that can silently
+//! disagree with the runtime. A `--parachain-id` is only accepted if it matches the chain spec,
+//! see [`resolve_para_id`].
+//!
+//! Chain specs without a protocol id share the default network protocol names with every other
+//! such chain. [`with_isolated_protocol_id`] derives the protocol id from the genesis hash and
+//! the fork id of the chain instead, so nodes of different chains on shared bootnodes don't
+//! connect to each other. Live chains keep the default protocol id unless they opt in with a
+//! fork id, as their running nodes only talk the default protocols.
+
+use crate::PFullNode;
+
+use cumulus_primitives::{genesis::genesis_block_from_storage, ParaId};
+use polkadot_primitives::v1::{Block as PBlock, Hash as PHash};
+use polkadot_service::{AbstractClient, ClientHandle, RuntimeApiCollection};
+use sc_chain_spec::{
+	ChainSpec, ChainSpecExtension, ChainSpecGroup, ChainType, GenericChainSpec, RuntimeGenesis,
+};
+use serde::{Deserialize, Serialize};
+use sp_blockchain::HeaderBackend;
+use sp_core::hexdisplay::HexDisplay;
+use sp_runtime::{
+	traits::{BlakeTwo256, Block as BlockT, Header as HeaderT},
+	BuildStorage,
+};
+
+use std::sync::Arc;
+
+/// Assumptions about the state of the relay chain the parachain was registered on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelayGenesis {
+	/// The genesis hash of the relay chain.
+	pub genesis_hash: PHash,
+}
+
+/// The chain spec extension of a parachain, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ChainSpecGroup, ChainSpecExtension)]
+#[serde(deny_unknown_fields)]
+pub struct ParachainExtensions {
+	/// The id of the relay chain chain spec, e.g. `rococo`.
+	pub relay_chain: String,
+	/// The id of the parachain.
+	pub para_id: u32,
+	/// The relay chain the parachain was registered on, checked when the node starts.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub relay_genesis: Option<RelayGenesis>,
+	/// Distinguishes the networks of chains that share the same genesis, e.g. after a fork.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub fork_id: Option<String>,
+}
+
+impl ParachainExtensions {
+	/// Create the extension of parachain `para_id` on the relay chain `relay_chain`.
+	pub fn new(relay_chain: impl Into<String>, para_id: ParaId) -> Self {
+		Self {
+			relay_chain: relay_chain.into(),
+			para_id: para_id.into(),
+			relay_genesis: None,
+			fork_id: None,
+		}
+	}
+
+	/// Try to get the extension from the given `chain_spec`.
+	pub fn try_get(chain_spec: &dyn ChainSpec) -> Option<&Self> {
+		sc_chain_spec::get_extension(chain_spec.extensions())
+	}
+
+	/// The id of the parachain.
+	pub fn para_id(&self) -> ParaId {
+		self.para_id.into()
+	}
+
+	/// The id of the relay chain chain spec.
+	pub fn relay_chain(&self) -> &str {
+		&self.relay_chain
+	}
+
+	/// The expected genesis hash of the relay chain, if any.
+	pub fn relay_genesis_hash(&self) -> Option<PHash> {
+		self.relay_genesis.as_ref().map(|g| g.genesis_hash)
+	}
+
+	/// Check that the relay chain with the given `genesis_hash` is the expected relay chain.
+	pub fn check_relay_genesis(&self, genesis_hash: PHash) -> Result<(), String> {
+		match self.relay_genesis_hash() {
+			Some(expected) if expected != genesis_hash => Err(format!(
+				"The chain spec expects relay chain `{}` with genesis `{}`, but the relay chain \
+				has genesis `{}`",
+				self.relay_chain, expected, genesis_hash,
+			)),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Returns the id of the parachain, given the `extensions` of the chain spec and the id passed
+/// on the CLI.
+///
+/// Fails if both are given and they disagree. Returns `None` if neither is given.
+pub fn resolve_para_id(
+	extensions: Option<&ParachainExtensions>,
+	cli_para_id: Option<u32>,
+) -> Result<Option<ParaId>, String> {
+	match (extensions, cli_para_id) {
+		(Some(e), Some(cli)) if e.para_id != cli => Err(format!(
+			"`--parachain-id {}` does not match the parachain id {} of the chain spec",
+			cli, e.para_id,
+		)),
+		(Some(e), _) => Ok(Some(e.para_id())),
+		(None, cli) => Ok(cli.map(Into::into)),
+	}
+}
+
+/// The number of leading bytes of the genesis hash in an isolated protocol id.
+const PROTOCOL_ID_GENESIS_BYTES: usize = 8;
+
+/// Returns the protocol id of the chain with the given `genesis_hash` and `fork_id`.
+pub fn isolated_protocol_id(genesis_hash: &[u8], fork_id: Option<&str>) -> String {
+	let genesis = &genesis_hash[..genesis_hash.len().min(PROTOCOL_ID_GENESIS_BYTES)];
+
+	match fork_id {
+		Some(fork_id) => format!("cumulus-{}-{}", HexDisplay::from(&genesis), fork_id),
+		None => format!("cumulus-{}", HexDisplay::from(&genesis)),
+	}
+}
+
+/// Set the protocol id of `chain_spec` to the [`isolated_protocol_id`] of its genesis block.
+///
+/// The protocol id of chain specs that declare one is kept. Chain specs of a
+/// [`ChainType::Live`] chain without a fork id also keep the default protocol id, so upgraded
+/// nodes still connect to the running nodes of the chain.
+pub fn with_isolated_protocol_id<Block, G>(
+	chain_spec: GenericChainSpec<G, ParachainExtensions>,
+) -> Result<GenericChainSpec<G, ParachainExtensions>, String>
+where
+	Block: BlockT,
+	G: RuntimeGenesis + 'static,
+{
+	let is_running_chain = matches!(chain_spec.chain_type(), ChainType::Live)
+		&& chain_spec.extensions().fork_id.is_none();
+	if chain_spec.protocol_id().is_some() || is_running_chain {
+		return Ok(chain_spec);
+	}
+
+	let genesis = genesis_block_from_storage::<Block>(&chain_spec.build_storage()?);
+	let protocol_id = isolated_protocol_id(
+		genesis.header().hash().as_ref(),
+		chain_spec.extensions().fork_id.as_deref(),
+	);
+
+	let mut json: serde_json::Value = serde_json::from_str(&chain_spec.as_json(false)?)
+		.map_err(|e| format!("Failed to encode the chain spec: {}", e))?;
+	json["protocolId"] = protocol_id.into();
+
+	GenericChainSpec::from_json_bytes(json.to_string().into_bytes())
+}
+
+/// Returns the genesis hash of the relay chain of the `polkadot_full_node`.
+pub fn relay_genesis_hash<PClient: ClientHandle>(polkadot_full_node: &PFullNode<PClient>) -> PHash {
+	polkadot_full_node.client.execute_with(GenesisHash)
+}
+
+struct GenesisHash;
+
+impl polkadot_service::ExecuteWithClient for GenesisHash {
+	type Output = PHash;
+
+	fn execute_with_client<PClient, Api, PBackend>(self, client: Arc<PClient>) -> Self::Output
+	where
+		<Api as sp_api::ApiExt<PBlock>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
+		PBackend: sc_client_api::Backend<PBlock>,
+		PBackend::State: sp_api::StateBackend<BlakeTwo256>,
+		Api: RuntimeApiCollection<StateBackend = PBackend::State>,
+		PClient: AbstractClient<PBlock, PBackend, Api = Api> + 'static,
+	{
+		client.info().genesis_hash
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn the_cli_para_id_has_to_match_the_chain_spec() {
+		let extensions = ParachainExtensions::new("rococo", 100.into());
+
+		assert_eq!(Ok(Some(100.into())), resolve_para_id(Some(&extensions), None));
+		assert_eq!(Ok(Some(100.into())), resolve_para_id(Some(&extensions), Some(100)));
+		assert!(resolve_para_id(Some(&extensions), Some(200)).is_err());
+		assert_eq!(Ok(Some(200.into())), resolve_para_id(None, Some(200)));
+		assert_eq!(Ok(None), resolve_para_id(None, None));
+	}
+
+	#[test]
+	fn checks_the_relay_genesis_if_it_is_known() {
+		let mut extensions = ParachainExtensions::new("rococo", 100.into());
+		assert!(extensions.check_relay_genesis(PHash::repeat_byte(1)).is_ok());
+
+		extensions.relay_genesis = Some(RelayGenesis {
+			genesis_hash: PHash::repeat_byte(1),
+		});
+		assert!(extensions.check_relay_genesis(PHash::repeat_byte(1)).is_ok());
+		assert!(extensions.check_relay_genesis(PHash::repeat_byte(2)).is_err());
+	}
+
+	#[test]
+	fn isolated_protocol_ids_differ_by_genesis_and_fork() {
+		let genesis = PHash::repeat_byte(0xab);
+
+		assert_eq!(
+			"cumulus-abababababababab",
+			isolated_protocol_id(genesis.as_ref(), None),
+		);
+		assert_eq!(
+			"cumulus-abababababababab-recovery",
+			isolated_protocol_id(genesis.as_ref(), Some("recovery")),
+		);
+		assert_ne!(
+			isolated_protocol_id(genesis.as_ref(), None),
+			isolated_protocol_id(PHash::repeat_byte(0xac).as_ref(), None),
+		);
+	}
+
+	#[test]
+	fn keeps_the_default_protocol_id_of_running_chains() {
+		use cumulus_test_runtime::{Block, GenesisConfig};
+
+		let protocol_id = |chain_type: &str, fork_id: Option<&str>| {
+			let json = serde_json::json!({
+				"name": "Test",
+				"id": "test",
+				"chainType": chain_type,
+				"bootNodes": [],
+				"telemetryEndpoints": null,
+				"protocolId": null,
+				"properties": null,
+				"relay_chain": "rococo",
+				"para_id": 100,
+				"fork_id": fork_id,
+				"genesis": { "raw": { "top": {}, "childrenDefault": {} } },
+			});
+			let chain_spec =
+				GenericChainSpec::<GenesisConfig, ParachainExtensions>::from_json_bytes(
+					json.to_string().into_bytes(),
+				)
+				.expect("Decodes the chain spec");
+
+			with_isolated_protocol_id::<Block, _>(chain_spec)
+				.expect("Sets the protocol id")
+				.protocol_id()
+				.map(ToString::to_string)
+		};
+
+		// E.g. the bundled tick, trick and track specs.
+		assert_eq!(None, protocol_id("Live", None));
+		assert!(protocol_id("Live", Some("v2")).unwrap().ends_with("-v2"));
+		assert!(protocol_id("Local", None).unwrap().starts_with("cumulus-"));
+		assert!(protocol_id("Development", None).unwrap().starts_with("cumulus-"));
+	}
+
+	#[test]
+	fn keeps_the_format_of_chain_specs_without_relay_genesis() {
+		let extensions = ParachainExtensions::new("rococo", 100.into());
+		let json = serde_json::to_string(&extensions).unwrap();
+
+		assert_eq!(r#"{"relay_chain":"rococo","para_id":100}"#, json);
+		assert_eq!(extensions, serde_json::from_str(&json).unwrap());
+	}
+}
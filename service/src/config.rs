@@ -0,0 +1,435 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The optional configuration of a collator.
+//!
+//! [`StartCollatorParams`](crate::StartCollatorParams) only contains the components a collator
+//! can not be started without. Everything else is set on a [`CollatorConfig`], which starts
+//! with sensible defaults. New options are added here, so existing node services keep
+//! compiling.
+
+use cumulus_collator::{
+	active_collator::ActiveCollator,
+	backing_connectivity::DEFAULT_MAX_UNSECONDED_RELAY_BLOCKS,
+	bad_block_repair::BadBlockRepair,
+	divergence_watchdog::DEFAULT_MAX_DIVERGENT_RELAY_BLOCKS,
+	downward_messages::{DmqBudget, RetrieveDmqContents},
+	events::CollatorEventHandler,
+	execution_budget::ExecutionBudget,
+	parent_recovery::ParentRecovery,
+	proof_recorder::ProofRecorderProvider,
+	proposal_stats::ReadyTransactions,
+	reexecution::ExecuteOwnBlock,
+	upgrade_only::UpgradeOnlyBuilder,
+	AnnouncePolicy, AnnounceSigner, CollationJournal, CollationReceipts, CollatorStatus,
+	InclusionTracker, PoVSizeLimit, RelayApiLatency, SkipReasons, StorageDiffs, TaskMetrics,
+};
+use cumulus_consensus::{
+	NewBestObserver, ParachainForkChoice, RelayChainCache, RelayChainForkChoice,
+};
+use sp_runtime::traits::Block as BlockT;
+use sp_version::RuntimeVersion;
+use substrate_prometheus_endpoint::Registry;
+
+use std::{fmt, path::PathBuf, sync::Arc};
+
+#[cfg(feature = "test-helpers")]
+use cumulus_collator::RelayChainDelay;
+
+/// The optional configuration of a collator, see the [module docs](self).
+pub struct CollatorConfig<Block: BlockT> {
+	pub(crate) allow_multiple_collations: bool,
+	pub(crate) prometheus_registry: Option<Registry>,
+	pub(crate) pov_export_dir: Option<PathBuf>,
+	pub(crate) status: CollatorStatus,
+	pub(crate) fork_choice: Arc<dyn ParachainForkChoice<Block>>,
+	pub(crate) pre_validate: bool,
+	pub(crate) ready_transactions: Option<Arc<dyn ReadyTransactions>>,
+	pub(crate) parent_recovery: Option<Arc<dyn ParentRecovery<Block>>>,
+	pub(crate) announce_policy: AnnouncePolicy,
+	pub(crate) proof_recorder: Option<Arc<dyn ProofRecorderProvider<Block>>>,
+	pub(crate) inclusion_tracker: InclusionTracker,
+	pub(crate) relay_api_latency: RelayApiLatency,
+	pub(crate) pov_size_limit: PoVSizeLimit,
+	pub(crate) announce_signer: Option<Arc<dyn AnnounceSigner<Block>>>,
+	pub(crate) native_version: Option<RuntimeVersion>,
+	pub(crate) relay_chain_cache: RelayChainCache,
+	pub(crate) task_metrics: TaskMetrics,
+	pub(crate) retrieve_dmq_contents: Option<Arc<dyn RetrieveDmqContents>>,
+	pub(crate) execution_budget: ExecutionBudget,
+	pub(crate) journal: Option<CollationJournal<Block::Hash>>,
+	pub(crate) upgrade_only_builder: Option<Arc<dyn UpgradeOnlyBuilder<Block>>>,
+	pub(crate) new_best_observer: Option<Arc<dyn NewBestObserver<Block>>>,
+	pub(crate) max_divergent_relay_blocks: u32,
+	pub(crate) max_unseconded_relay_blocks: u32,
+	pub(crate) storage_diffs: Option<StorageDiffs<Block::Hash>>,
+	#[cfg(feature = "test-helpers")]
+	pub(crate) relay_chain_delay: Option<RelayChainDelay>,
+	pub(crate) skip_reasons: Option<SkipReasons<Block::Hash>>,
+	pub(crate) bad_block_repair: Option<Arc<dyn BadBlockRepair<Block>>>,
+	pub(crate) receipts: Option<CollationReceipts<Block::Hash>>,
+	pub(crate) reexecute_own_blocks: Option<Arc<dyn ExecuteOwnBlock<Block>>>,
+	pub(crate) detect_runtime_divergence: bool,
+	pub(crate) event_handler: Option<Arc<dyn CollatorEventHandler<Block>>>,
+	pub(crate) active_collator: Option<Arc<dyn ActiveCollator<Block>>>,
+	pub(crate) dmq_budget: Option<DmqBudget>,
+}
+
+impl<Block: BlockT> Default for CollatorConfig<Block> {
+	fn default() -> Self {
+		Self {
+			allow_multiple_collations: false,
+			prometheus_registry: None,
+			pov_export_dir: None,
+			status: Default::default(),
+			fork_choice: Arc::new(RelayChainForkChoice),
+			pre_validate: false,
+			ready_transactions: None,
+			parent_recovery: None,
+			announce_policy: Default::default(),
+			proof_recorder: None,
+			inclusion_tracker: Default::default(),
+			relay_api_latency: Default::default(),
+			pov_size_limit: Default::default(),
+			announce_signer: None,
+			native_version: None,
+			relay_chain_cache: Default::default(),
+			task_metrics: Default::default(),
+			retrieve_dmq_contents: None,
+			execution_budget: Default::default(),
+			journal: None,
+			upgrade_only_builder: None,
+			new_best_observer: None,
+			max_divergent_relay_blocks: DEFAULT_MAX_DIVERGENT_RELAY_BLOCKS,
+			max_unseconded_relay_blocks: DEFAULT_MAX_UNSECONDED_RELAY_BLOCKS,
+			storage_diffs: None,
+			#[cfg(feature = "test-helpers")]
+			relay_chain_delay: None,
+			skip_reasons: None,
+			bad_block_repair: None,
+			receipts: None,
+			reexecute_own_blocks: None,
+			detect_runtime_divergence: false,
+			event_handler: None,
+			active_collator: None,
+			dmq_budget: None,
+		}
+	}
+}
+
+impl<Block: BlockT> CollatorConfig<Block> {
+	/// Allow producing more than one candidate for the same relay parent.
+	///
+	/// This should only be enabled if it is known that no other node collates with the same key.
+	pub fn allow_multiple_collations(mut self, allow: bool) -> Self {
+		self.allow_multiple_collations = allow;
+		self
+	}
+
+	/// Register the metrics of the collator at the given `registry`.
+	pub fn prometheus_registry(mut self, registry: Option<Registry>) -> Self {
+		self.prometheus_registry = registry;
+		self
+	}
+
+	/// Write every produced PoV into the given `dir`.
+	pub fn pov_export_dir(mut self, dir: Option<PathBuf>) -> Self {
+		self.pov_export_dir = dir;
+		self
+	}
+
+	/// Update the given `status` while the collator is running.
+	pub fn status(mut self, status: CollatorStatus) -> Self {
+		self.status = status;
+		self
+	}
+
+	/// Use the given `fork_choice` for own blocks, defaults to [`RelayChainForkChoice`].
+	pub fn fork_choice(mut self, fork_choice: Arc<dyn ParachainForkChoice<Block>>) -> Self {
+		self.fork_choice = fork_choice;
+		self
+	}
+
+	/// Run every candidate through the local `validate_block` before it is submitted.
+	pub fn pre_validate(mut self, pre_validate: bool) -> Self {
+		self.pre_validate = pre_validate;
+		self
+	}
+
+	/// Report how many of the `ready_transactions` were included in a candidate.
+	pub fn ready_transactions(mut self, ready_transactions: Arc<dyn ReadyTransactions>) -> Self {
+		self.ready_transactions = Some(ready_transactions);
+		self
+	}
+
+	/// Fetch unknown parent blocks with the given `parent_recovery`.
+	pub fn parent_recovery(mut self, parent_recovery: Arc<dyn ParentRecovery<Block>>) -> Self {
+		self.parent_recovery = Some(parent_recovery);
+		self
+	}
+
+	/// When and how often produced blocks are announced.
+	pub fn announce_policy(mut self, announce_policy: AnnouncePolicy) -> Self {
+		self.announce_policy = announce_policy;
+		self
+	}
+
+	/// Provide the storage proof of the candidates with the given `proof_recorder`.
+	pub fn proof_recorder(mut self, proof_recorder: Arc<dyn ProofRecorderProvider<Block>>) -> Self {
+		self.proof_recorder = Some(proof_recorder);
+		self
+	}
+
+	/// Note the inclusion of the produced candidates in the given `inclusion_tracker`.
+	pub fn inclusion_tracker(mut self, inclusion_tracker: InclusionTracker) -> Self {
+		self.inclusion_tracker = inclusion_tracker;
+		self
+	}
+
+	/// Measure the relay chain runtime api calls with the given `relay_api_latency`.
+	///
+	/// While they are slow, the relay chain data of the next candidate is prefetched.
+	pub fn relay_api_latency(mut self, relay_api_latency: RelayApiLatency) -> Self {
+		self.relay_api_latency = relay_api_latency;
+		self
+	}
+
+	/// Publish the maximum PoV size at the relay parent of every candidate in `pov_size_limit`.
+	///
+	/// The transaction pool of the proposer can read it to limit the transactions of a block.
+	pub fn pov_size_limit(mut self, pov_size_limit: PoVSizeLimit) -> Self {
+		self.pov_size_limit = pov_size_limit;
+		self
+	}
+
+	/// Sign the announcements of produced blocks with the given `signer`.
+	pub fn announce_signer(mut self, signer: Option<Arc<dyn AnnounceSigner<Block>>>) -> Self {
+		self.announce_signer = signer;
+		self
+	}
+
+	/// Refuse to start when the on-chain storage was migrated by a newer runtime than the
+	/// `native_version`.
+	pub fn native_version(mut self, native_version: RuntimeVersion) -> Self {
+		self.native_version = Some(native_version);
+		self
+	}
+
+	/// Share the given `relay_chain_cache` with the collator.
+	pub fn relay_chain_cache(mut self, relay_chain_cache: RelayChainCache) -> Self {
+		self.relay_chain_cache = relay_chain_cache;
+		self
+	}
+
+	/// Measure the tasks spawned by the collator with the given `task_metrics`.
+	pub fn task_metrics(mut self, task_metrics: TaskMetrics) -> Self {
+		self.task_metrics = task_metrics;
+		self
+	}
+
+	/// Retrieve the downward messages of the candidates with `retrieve`.
+	pub fn retrieve_dmq_contents(mut self, retrieve: Arc<dyn RetrieveDmqContents>) -> Self {
+		self.retrieve_dmq_contents = Some(retrieve);
+		self
+	}
+
+	/// Only provide the leading downward messages within `budget` to a candidate, instead of
+	/// failing the candidate when a spike of messages does not fit into it.
+	///
+	/// By default all downward messages are provided.
+	pub fn dmq_budget(mut self, budget: DmqBudget) -> Self {
+		self.dmq_budget = Some(budget);
+		self
+	}
+
+	/// Derive the maximum duration of a proposal with the given `execution_budget`.
+	pub fn execution_budget(mut self, execution_budget: ExecutionBudget) -> Self {
+		self.execution_budget = execution_budget;
+		self
+	}
+
+	/// Append every collation request to the given `journal`.
+	pub fn journal(mut self, journal: CollationJournal<Block::Hash>) -> Self {
+		self.journal = Some(journal);
+		self
+	}
+
+	/// Replace blocks whose runtime upgrade does not fit into the PoV with a block that only
+	/// carries the upgrade, built by `builder`.
+	pub fn upgrade_only_builder(mut self, builder: Arc<dyn UpgradeOnlyBuilder<Block>>) -> Self {
+		self.upgrade_only_builder = Some(builder);
+		self
+	}
+
+	/// Notify the `observer` when the relay chain made an already imported block the best block.
+	pub fn new_best_observer(mut self, observer: Arc<dyn NewBestObserver<Block>>) -> Self {
+		self.new_best_observer = Some(observer);
+		self
+	}
+
+	/// Halt candidate production when the local chain diverged from the relay chain for more
+	/// than `max` relay blocks.
+	pub fn max_divergent_relay_blocks(mut self, max: u32) -> Self {
+		self.max_divergent_relay_blocks = max;
+		self
+	}
+
+	/// Report the backing group as lost when produced candidates were not seconded for `max`
+	/// relay blocks.
+	pub fn max_unseconded_relay_blocks(mut self, max: u32) -> Self {
+		self.max_unseconded_relay_blocks = max;
+		self
+	}
+
+	/// Send the storage changes of every produced block to the subscribers of `storage_diffs`.
+	pub fn storage_diffs(mut self, storage_diffs: StorageDiffs<Block::Hash>) -> Self {
+		self.storage_diffs = Some(storage_diffs);
+		self
+	}
+
+	/// Add the given `delay` to every call to the relay chain.
+	///
+	/// This simulates a slow relay chain node and should only be used for testing.
+	#[cfg(feature = "test-helpers")]
+	pub fn relay_chain_delay(mut self, delay: RelayChainDelay) -> Self {
+		self.relay_chain_delay = Some(delay);
+		self
+	}
+
+	/// Note why candidate production on a parent block was skipped in `skip_reasons`.
+	pub fn skip_reasons(mut self, skip_reasons: SkipReasons<Block::Hash>) -> Self {
+		self.skip_reasons = Some(skip_reasons);
+		self
+	}
+
+	/// Clear the known bad mark of parent blocks that were included by the relay chain with
+	/// `repair` and fetch them again with the [`parent_recovery`](Self::parent_recovery).
+	pub fn bad_block_repair(mut self, repair: Arc<dyn BadBlockRepair<Block>>) -> Self {
+		self.bad_block_repair = Some(repair);
+		self
+	}
+
+	/// Sign the receipts of the produced candidates with the collator key and store them in
+	/// `receipts`.
+	pub fn receipts(mut self, receipts: CollationReceipts<Block::Hash>) -> Self {
+		self.receipts = Some(receipts);
+		self
+	}
+
+	/// Execute every produced block once more with `executor` before importing it, e.g. with a
+	/// [`WasmReexecution`](cumulus_collator::reexecution::WasmReexecution) to catch a divergence
+	/// between the native and the WASM runtime before the validators do.
+	///
+	/// By default, produced blocks are imported with the storage changes computed while building
+	/// them.
+	pub fn reexecute_own_blocks(mut self, executor: Arc<dyn ExecuteOwnBlock<Block>>) -> Self {
+		self.reexecute_own_blocks = Some(executor);
+		self
+	}
+
+	/// Check every produced block for a divergence of the native and the WASM runtime in the
+	/// background, e.g. because the `spec_version` was not bumped.
+	///
+	/// Disabled by default.
+	pub fn detect_runtime_divergence(mut self, detect_runtime_divergence: bool) -> Self {
+		self.detect_runtime_divergence = detect_runtime_divergence;
+		self
+	}
+
+	/// Report the events of the collator to `handler`, e.g. to alert the operator.
+	///
+	/// By default the events are logged and counted in the metrics.
+	pub fn event_handler(mut self, handler: Arc<dyn CollatorEventHandler<Block>>) -> Self {
+		self.event_handler = Some(handler);
+		self
+	}
+
+	/// Only collate while the collator key is designated by `active_collator`, for hot-standby
+	/// setups.
+	///
+	/// By default the collator always collates with its key.
+	pub fn active_collator(mut self, active_collator: Arc<dyn ActiveCollator<Block>>) -> Self {
+		self.active_collator = Some(active_collator);
+		self
+	}
+}
+
+impl<Block: BlockT> fmt::Debug for CollatorConfig<Block> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut debug = f.debug_struct("CollatorConfig");
+		debug
+			.field("allow_multiple_collations", &self.allow_multiple_collations)
+			.field("prometheus_registry", &self.prometheus_registry.is_some())
+			.field("pov_export_dir", &self.pov_export_dir)
+			.field("pre_validate", &self.pre_validate)
+			.field("ready_transactions", &self.ready_transactions.is_some())
+			.field("parent_recovery", &self.parent_recovery.is_some())
+			.field("announce_policy", &self.announce_policy)
+			.field("proof_recorder", &self.proof_recorder.is_some())
+			.field("announce_signer", &self.announce_signer.is_some())
+			.field("relay_api_latency", &self.relay_api_latency.average())
+			.field("pov_size_limit", &self.pov_size_limit.get())
+			.field("native_version", &self.native_version)
+			.field(
+				"retrieve_dmq_contents",
+				&self.retrieve_dmq_contents.is_some(),
+			)
+			.field("execution_budget", &self.execution_budget)
+			.field("journal", &self.journal.is_some())
+			.field("upgrade_only_builder", &self.upgrade_only_builder.is_some())
+			.field("new_best_observer", &self.new_best_observer.is_some())
+			.field(
+				"max_divergent_relay_blocks",
+				&self.max_divergent_relay_blocks,
+			)
+			.field(
+				"max_unseconded_relay_blocks",
+				&self.max_unseconded_relay_blocks,
+			)
+			.field("storage_diffs", &self.storage_diffs.is_some())
+			.field("skip_reasons", &self.skip_reasons.is_some())
+			.field("bad_block_repair", &self.bad_block_repair.is_some())
+			.field("receipts", &self.receipts.is_some())
+			.field("reexecute_own_blocks", &self.reexecute_own_blocks.is_some())
+			.field("detect_runtime_divergence", &self.detect_runtime_divergence)
+			.field("event_handler", &self.event_handler.is_some())
+			.field("active_collator", &self.active_collator.is_some())
+			.field("dmq_budget", &self.dmq_budget);
+		#[cfg(feature = "test-helpers")]
+		debug.field("relay_chain_delay", &self.relay_chain_delay);
+		debug.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	type Block = sp_runtime::testing::Block<sp_runtime::testing::ExtrinsicWrapper<()>>;
+
+	#[test]
+	fn setters_override_the_defaults() {
+		let config = CollatorConfig::<Block>::default()
+			.allow_multiple_collations(true)
+			.pre_validate(true)
+			.pov_export_dir(Some("/tmp/povs".into()));
+
+		assert!(config.allow_multiple_collations);
+		assert!(config.pre_validate);
+		assert_eq!(Some(PathBuf::from("/tmp/povs")), config.pov_export_dir);
+		assert!(config.proof_recorder.is_none());
+	}
+}
@@ -0,0 +1,152 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rebuild the local chain from the candidates included by the relay chain.
+//!
+//! See [`cumulus_collator::disaster_recovery`] for the details.
+
+use crate::PFullNode;
+
+use cumulus_collator::disaster_recovery::{
+	included_candidates, recover_chain, BlockSource, RecoveryImport,
+};
+use cumulus_primitives::ParaId;
+use futures::FutureExt;
+use log::{error, info};
+use polkadot_primitives::v1::{Block as PBlock, Hash as PHash};
+use polkadot_service::{AbstractClient, ClientHandle, RuntimeApiCollection};
+use sc_network::NetworkService;
+use sp_blockchain::HeaderBackend;
+use sp_consensus::{BlockImport, Error as ConsensusError};
+use sp_core::traits::SpawnNamed;
+use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+/// How often to check if the relay chain finished its major sync.
+const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(6);
+
+/// Configuration of the disaster recovery.
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryConfig {
+	/// A directory of exported PoVs to take the blocks from, before asking the network.
+	pub pov_dir: Option<PathBuf>,
+}
+
+/// Spawn a task that rebuilds the local chain from the relay chain.
+///
+/// Once the relay chain finished its major sync, all candidates of `para_id` included up to the
+/// finalized relay block and after the local best block are collected. Their blocks are fetched
+/// from the `sources` and imported in order, as described by `import`.
+///
+/// The task is spawned with the given `spawner`, e.g. a [`TaskGroup`](cumulus_collator::TaskGroup)
+/// of the [`RECOVERY_TASKS`](cumulus_collator::task_group::RECOVERY_TASKS).
+pub fn spawn_disaster_recovery<Block, Client, PClient, Spawner>(
+	para_id: ParaId,
+	client: Arc<Client>,
+	polkadot_full_node: &PFullNode<PClient>,
+	sources: Vec<Arc<dyn BlockSource<Block>>>,
+	import: RecoveryImport<Block>,
+	spawner: Spawner,
+) where
+	Block: BlockT,
+	Client: HeaderBackend<Block> + Send + Sync + 'static,
+	for<'a> &'a Client: BlockImport<Block, Error = ConsensusError>,
+	PClient: ClientHandle,
+	Spawner: SpawnNamed,
+{
+	polkadot_full_node.client.execute_with(SpawnDisasterRecovery {
+		para_id,
+		client,
+		relay_chain_network: polkadot_full_node.network.clone(),
+		sources,
+		import,
+		spawner,
+	})
+}
+
+struct SpawnDisasterRecovery<Block: BlockT, Client, Spawner> {
+	para_id: ParaId,
+	client: Arc<Client>,
+	relay_chain_network: Arc<NetworkService<PBlock, PHash>>,
+	sources: Vec<Arc<dyn BlockSource<Block>>>,
+	import: RecoveryImport<Block>,
+	spawner: Spawner,
+}
+
+impl<Block, Client, Spawner> polkadot_service::ExecuteWithClient
+	for SpawnDisasterRecovery<Block, Client, Spawner>
+where
+	Block: BlockT,
+	Client: HeaderBackend<Block> + Send + Sync + 'static,
+	for<'b> &'b Client: BlockImport<Block, Error = ConsensusError>,
+	Spawner: SpawnNamed,
+{
+	type Output = ();
+
+	fn execute_with_client<PClient, Api, PBackend>(self, polkadot_client: Arc<PClient>)
+	where
+		<Api as sp_api::ApiExt<PBlock>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
+		PBackend: sc_client_api::Backend<PBlock>,
+		PBackend::State: sp_api::StateBackend<BlakeTwo256>,
+		Api: RuntimeApiCollection<StateBackend = PBackend::State>,
+		PClient: AbstractClient<PBlock, PBackend, Api = Api> + 'static,
+	{
+		let Self {
+			para_id,
+			client,
+			relay_chain_network,
+			sources,
+			import,
+			spawner,
+		} = self;
+
+		let recovery = async move {
+			while relay_chain_network.is_major_syncing() {
+				futures_timer::Delay::new(SYNC_CHECK_INTERVAL).await;
+			}
+
+			let relay_head = polkadot_client.info().finalized_hash;
+			let local_best = client.info().best_number;
+
+			info!(
+				target: "cumulus-service",
+				"Recovering the chain after block #{} from relay block `{}`.",
+				local_best,
+				relay_head,
+			);
+
+			let candidates = match included_candidates::<Block, _>(
+				&*polkadot_client,
+				para_id,
+				relay_head,
+				local_best,
+			) {
+				Ok(candidates) => candidates,
+				Err(e) => {
+					error!(target: "cumulus-service", "Disaster recovery failed: {}", e);
+					return;
+				}
+			};
+
+			if let Err(e) = recover_chain(&*client, candidates, &sources, &import).await {
+				error!(target: "cumulus-service", "Disaster recovery failed: {}", e);
+			}
+		};
+
+		spawner.spawn("cumulus-disaster-recovery", recovery.boxed());
+	}
+}
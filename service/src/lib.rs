@@ -18,24 +18,55 @@
 //!
 //! Provides functions for starting a collator node or a normal full node.
 
-use cumulus_primitives::ParaId;
+use cumulus_collator::{
+	pov_size_limit::{relay_max_pov_size, DEFAULT_MAX_POV_SIZE},
+	AnnounceBlock, CollationAnnouncement,
+};
+use cumulus_consensus::{NewBestObserver, RelayChainCache};
+use cumulus_primitives::{CollectCollationInfo, ParaId};
 use futures::{Future, FutureExt};
+use log::warn;
 use polkadot_overseer::OverseerHandler;
 use polkadot_primitives::v1::{Block as PBlock, CollatorId, CollatorPair};
 use polkadot_service::{AbstractClient, Client as PClient, ClientHandle, RuntimeApiCollection};
 use sc_client_api::{Backend as BackendT, BlockBackend, Finalizer, UsageProvider};
 use sc_service::{error::Result as ServiceResult, Configuration, Role, TaskManager};
+use sp_api::ProvideRuntimeApi;
 use sp_blockchain::HeaderBackend;
 use sp_consensus::{BlockImport, Environment, Error as ConsensusError, Proposer};
 use sp_core::traits::SpawnNamed;
 use sp_inherents::InherentDataProviders;
 use sp_runtime::traits::{BlakeTwo256, Block as BlockT};
-use std::{marker::PhantomData, sync::Arc};
+use std::{fmt, marker::PhantomData, sync::Arc};
+
+pub mod chain_spec;
+pub mod config;
+pub mod disaster_recovery;
+pub mod pool_maintenance;
+pub mod pruning;
+pub mod registration;
+pub mod relay_chain_db;
+pub mod solo_to_para;
+pub mod transaction_priority;
+
+pub use chain_spec::ParachainExtensions;
+pub use config::CollatorConfig;
+pub use disaster_recovery::{spawn_disaster_recovery, RecoveryConfig};
+pub use pool_maintenance::MaintainPoolOnNewBest;
+pub use pruning::PruningPolicy;
+pub use registration::RegistrationSnapshot;
+pub use relay_chain_db::{RelayChainDatabase, RelayChainDatabaseBackend};
+pub use transaction_priority::{
+	ByProofFootprint, PoVReserve, PrioritizedPool, TransactionPriority,
+};
 
 /// Polkadot full node handles.
 type PFullNode<C> = polkadot_service::NewFull<C>;
 
 /// Parameters given to [`start_collator`].
+///
+/// Only contains the components that are required to start a collator, the optional settings
+/// are part of the [`CollatorConfig`].
 pub struct StartCollatorParams<'a, Block: BlockT, PF, BI, BS, Client, Backend, Spawner, PClient> {
 	pub proposer_factory: PF,
 	pub inherent_data_providers: InherentDataProviders,
@@ -43,12 +74,25 @@ pub struct StartCollatorParams<'a, Block: BlockT, PF, BI, BS, Client, Backend, S
 	pub block_import: BI,
 	pub block_status: Arc<BS>,
 	pub client: Arc<Client>,
-	pub announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	pub announce_block: Arc<dyn AnnounceBlock<Block>>,
 	pub spawner: Spawner,
 	pub para_id: ParaId,
 	pub collator_key: CollatorPair,
 	pub polkadot_full_node: PFullNode<PClient>,
 	pub task_manager: &'a mut TaskManager,
+	pub config: CollatorConfig<Block>,
+}
+
+impl<'a, Block: BlockT, PF, BI, BS, Client, Backend, Spawner, PClient> fmt::Debug
+	for StartCollatorParams<'a, Block, PF, BI, BS, Client, Backend, Spawner, PClient>
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("StartCollatorParams")
+			.field("para_id", &self.para_id)
+			.field("collator_key", &"<redacted>")
+			.field("config", &self.config)
+			.finish()
+	}
 }
 
 /// Start a collator node for a parachain.
@@ -70,6 +114,7 @@ pub async fn start_collator<'a, Block, PF, BI, BS, Client, Backend, Spawner, PCl
 		collator_key,
 		polkadot_full_node,
 		task_manager,
+		config,
 	}: StartCollatorParams<'a, Block, PF, BI, BS, Client, Backend, Spawner, PClient>,
 ) -> sc_service::error::Result<()>
 where
@@ -86,10 +131,12 @@ where
 	Client: Finalizer<Block, Backend>
 		+ UsageProvider<Block>
 		+ HeaderBackend<Block>
+		+ ProvideRuntimeApi<Block>
 		+ Send
 		+ Sync
 		+ BlockBackend<Block>
 		+ 'static,
+	Client::Api: CollectCollationInfo<Block>,
 	for<'b> &'b Client: BlockImport<Block>,
 	Backend: BackendT<Block> + 'static,
 	Spawner: SpawnNamed + Clone + Send + Sync + 'static,
@@ -111,6 +158,7 @@ where
 			collator_key,
 			block_import,
 			block_status,
+			config,
 		})
 		.await?;
 
@@ -126,11 +174,12 @@ struct StartCollator<Block: BlockT, Client, Backend, PF, BI, BS, Spawner> {
 	block_import: BI,
 	block_status: Arc<BS>,
 	client: Arc<Client>,
-	announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	announce_block: Arc<dyn AnnounceBlock<Block>>,
 	overseer_handler: OverseerHandler,
 	spawner: Spawner,
 	para_id: ParaId,
 	collator_key: CollatorPair,
+	config: CollatorConfig<Block>,
 }
 
 impl<Block, Client, Backend, PF, BI, BS, Spawner> polkadot_service::ExecuteWithClient
@@ -149,10 +198,12 @@ where
 	Client: Finalizer<Block, Backend>
 		+ UsageProvider<Block>
 		+ HeaderBackend<Block>
+		+ ProvideRuntimeApi<Block>
 		+ Send
 		+ Sync
 		+ BlockBackend<Block>
 		+ 'static,
+	Client::Api: CollectCollationInfo<Block>,
 	for<'b> &'b Client: BlockImport<Block>,
 	Backend: BackendT<Block> + 'static,
 	Spawner: SpawnNamed + Clone + Send + Sync + 'static,
@@ -167,6 +218,8 @@ where
 		Api: RuntimeApiCollection<StateBackend = PBackend::State>,
 		PClient: AbstractClient<PBlock, PBackend, Api = Api> + 'static,
 	{
+		let config = self.config;
+
 		async move {
 			cumulus_collator::start_collator(cumulus_collator::StartCollatorParams {
 				proposer_factory: self.proposer_factory,
@@ -181,9 +234,44 @@ where
 				para_id: self.para_id,
 				key: self.collator_key,
 				polkadot_client: client,
+				allow_multiple_collations: config.allow_multiple_collations,
+				prometheus_registry: config.prometheus_registry,
+				pov_export_dir: config.pov_export_dir,
+				status: config.status,
+				fork_choice: config.fork_choice,
+				pre_validate: config.pre_validate,
+				ready_transactions: config.ready_transactions,
+				parent_recovery: config.parent_recovery,
+				announce_policy: config.announce_policy,
+				proof_recorder: config.proof_recorder,
+				inclusion_tracker: config.inclusion_tracker,
+				relay_api_latency: config.relay_api_latency,
+				pov_size_limit: config.pov_size_limit,
+				announce_signer: config.announce_signer,
+				native_version: config.native_version,
+				relay_chain_cache: config.relay_chain_cache,
+				task_metrics: config.task_metrics,
+				retrieve_dmq_contents: config.retrieve_dmq_contents,
+				execution_budget: config.execution_budget,
+				journal: config.journal,
+				upgrade_only_builder: config.upgrade_only_builder,
+				new_best_observer: config.new_best_observer,
+				max_divergent_relay_blocks: config.max_divergent_relay_blocks,
+				max_unseconded_relay_blocks: config.max_unseconded_relay_blocks,
+				storage_diffs: config.storage_diffs,
+				#[cfg(feature = "test-helpers")]
+				relay_chain_delay: config.relay_chain_delay,
+				skip_reasons: config.skip_reasons,
+				bad_block_repair: config.bad_block_repair,
+				receipts: config.receipts,
+				reexecute_own_blocks: config.reexecute_own_blocks,
+				detect_runtime_divergence: config.detect_runtime_divergence,
+				event_handler: config.event_handler,
+				active_collator: config.active_collator,
+				dmq_budget: config.dmq_budget,
 			})
 			.await
-			.map_err(Into::into)
+			.map_err(|e| sc_service::Error::Other(e.to_string()))
 		}
 		.boxed()
 	}
@@ -195,7 +283,9 @@ pub struct StartFullNodeParams<'a, Block: BlockT, Client, PClient> {
 	pub client: Arc<Client>,
 	pub polkadot_full_node: PFullNode<PClient>,
 	pub task_manager: &'a mut TaskManager,
-	pub announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	pub announce_block: Arc<dyn AnnounceBlock<Block>>,
+	pub relay_chain_cache: RelayChainCache,
+	pub new_best_observer: Option<Arc<dyn NewBestObserver<Block>>>,
 }
 
 /// Start a full node for a parachain.
@@ -209,6 +299,8 @@ pub fn start_full_node<Block, Client, Backend, PClient>(
 		task_manager,
 		polkadot_full_node,
 		para_id,
+		relay_chain_cache,
+		new_best_observer,
 	}: StartFullNodeParams<Block, Client, PClient>,
 ) -> sc_service::error::Result<()>
 where
@@ -228,6 +320,8 @@ where
 		para_id,
 		client,
 		task_manager,
+		relay_chain_cache,
+		new_best_observer,
 		_phantom: PhantomData,
 	})?;
 
@@ -238,9 +332,11 @@ where
 
 struct StartFullNode<'a, Block: BlockT, Client, Backend> {
 	para_id: ParaId,
-	announce_block: Arc<dyn Fn(Block::Hash, Vec<u8>) + Send + Sync>,
+	announce_block: Arc<dyn AnnounceBlock<Block>>,
 	client: Arc<Client>,
 	task_manager: &'a mut TaskManager,
+	relay_chain_cache: RelayChainCache,
+	new_best_observer: Option<Arc<dyn NewBestObserver<Block>>>,
 	_phantom: PhantomData<Backend>,
 }
 
@@ -270,8 +366,14 @@ where
 		let future = cumulus_consensus::follow_polkadot(
 			self.para_id,
 			self.client,
-			client,
-			self.announce_block,
+			self.relay_chain_cache.with_client(client),
+			{
+				let announce_block = self.announce_block;
+				Arc::new(move |hash: Block::Hash| {
+					announce_block.announce_block(hash, CollationAnnouncement::Empty)
+				})
+			},
+			self.new_best_observer,
 		)?;
 		self.task_manager
 			.spawn_essential_handle()
@@ -281,13 +383,66 @@ where
 	}
 }
 
+struct RelayMaxPoVSize;
+
+impl polkadot_service::ExecuteWithClient for RelayMaxPoVSize {
+	type Output = usize;
+
+	fn execute_with_client<PClient, Api, PBackend>(self, client: Arc<PClient>) -> Self::Output
+	where
+		<Api as sp_api::ApiExt<PBlock>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
+		PBackend: sc_client_api::Backend<PBlock>,
+		PBackend::State: sp_api::StateBackend<BlakeTwo256>,
+		Api: RuntimeApiCollection<StateBackend = PBackend::State>,
+		PClient: AbstractClient<PBlock, PBackend, Api = Api> + 'static,
+	{
+		let best_hash = client.info().best_hash;
+
+		relay_max_pov_size(&*client, best_hash)
+			.unwrap_or_else(|e| {
+				warn!(
+					target: "cumulus-service",
+					"Failed to get the max PoV size of the relay chain, using the default: {}",
+					e,
+				);
+				None
+			})
+			.map_or(DEFAULT_MAX_POV_SIZE, |size| size as usize)
+	}
+}
+
+/// Returns the maximum PoV size at the best block of the relay chain, in bytes.
+///
+/// Falls back to [`DEFAULT_MAX_POV_SIZE`] if the relay chain does not provide it.
+pub fn current_max_pov_size(polkadot_client: &PClient) -> usize {
+	polkadot_client.execute_with(RelayMaxPoVSize)
+}
+
 /// Prepare the parachain's node condifugration
 ///
 /// This function will disable the default announcement of Substrate for the parachain in favor
-/// of the one of Cumulus.
-pub fn prepare_node_config(mut parachain_config: Configuration) -> Configuration {
+/// of the one of Cumulus. The state pruning is adjusted to the default [`PruningPolicy`].
+pub fn prepare_node_config(parachain_config: Configuration) -> Configuration {
+	prepare_node_config_with_pruning(parachain_config, PruningPolicy::default())
+}
+
+/// Like [`prepare_node_config`], but with a custom [`PruningPolicy`].
+pub fn prepare_node_config_with_pruning(
+	mut parachain_config: Configuration,
+	pruning_policy: PruningPolicy,
+) -> Configuration {
 	parachain_config.announce_block = false;
 
+	let (pruning, changed) = pruning_policy.apply(parachain_config.pruning.clone());
+	if changed {
+		warn!(
+			target: "cumulus-service",
+			"State pruning is too aggressive for a parachain, using {:?} instead.",
+			pruning,
+		);
+	}
+	parachain_config.pruning = pruning;
+
 	parachain_config
 }
 
@@ -0,0 +1,89 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keep the transaction pool up to date when the relay chain changes the best block.
+//!
+//! The transaction pool is maintained for the best blocks reported by the import notifications
+//! of the client. When [`follow_polkadot`](cumulus_consensus::follow_polkadot) makes an already
+//! imported block the best block, the pool would still contain the extrinsics of the new best
+//! fork and the next collation could include them again. [`MaintainPoolOnNewBest`] maintains
+//! the pool against the new best block right away.
+
+use cumulus_consensus::NewBestObserver;
+use futures::FutureExt;
+use log::debug;
+use sp_blockchain::HeaderMetadata;
+use sp_core::traits::SpawnNamed;
+use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_transaction_pool::{ChainEvent, MaintainedTransactionPool};
+
+use std::sync::Arc;
+
+/// A [`NewBestObserver`] that maintains the transaction `pool` against the new best block.
+pub struct MaintainPoolOnNewBest<Client, Pool> {
+	client: Arc<Client>,
+	pool: Arc<Pool>,
+	spawner: Arc<dyn SpawnNamed + Send + Sync>,
+}
+
+impl<Client, Pool> MaintainPoolOnNewBest<Client, Pool> {
+	/// Create a new instance.
+	///
+	/// The maintenance of the `pool` is spawned with the given `spawner`.
+	pub fn new(
+		client: Arc<Client>,
+		pool: Arc<Pool>,
+		spawner: impl SpawnNamed + Send + Sync + 'static,
+	) -> Self {
+		Self {
+			client,
+			pool,
+			spawner: Arc::new(spawner),
+		}
+	}
+}
+
+impl<Block, Client, Pool> NewBestObserver<Block> for MaintainPoolOnNewBest<Client, Pool>
+where
+	Block: BlockT,
+	Client: HeaderMetadata<Block, Error = sp_blockchain::Error> + Send + Sync,
+	Pool: MaintainedTransactionPool<Block = Block>,
+{
+	fn new_best(&self, previous_best: Block::Hash, header: &Block::Header) {
+		let hash = header.hash();
+
+		// Without a tree route the pool only prunes the extrinsics of the new best block itself.
+		let tree_route = match sp_blockchain::tree_route(&*self.client, previous_best, hash) {
+			Ok(tree_route) => Some(Arc::new(tree_route)),
+			Err(e) => {
+				debug!(
+					target: "cumulus-service",
+					"Failed to compute the tree route from `{:?}` to `{:?}`: {:?}",
+					previous_best,
+					hash,
+					e,
+				);
+				None
+			}
+		};
+
+		let maintain = self
+			.pool
+			.maintain(ChainEvent::NewBestBlock { hash, tree_route });
+		self.spawner
+			.spawn("cumulus-maintain-transaction-pool", maintain.boxed());
+	}
+}
@@ -0,0 +1,88 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! State pruning of parachain nodes.
+//!
+//! Parachain blocks are only finalized by [`cumulus_consensus::follow_polkadot`], after the relay
+//! chain finalized the relay block that included them. As the state of blocks is only pruned
+//! after they are finalized, the state of blocks that are built or included, but not yet
+//! finalized on the relay chain, is always kept.
+//!
+//! However, the relay chain may still ask to build on a parent that is older than the latest
+//! finalized parachain block, e.g. when the relay parent lags behind the relay chain finality.
+//! The [`PruningPolicy`] makes sure that the state of enough finalized blocks is kept for this.
+
+use sc_service::config::PruningMode;
+
+/// The default number of finalized blocks whose state is kept.
+pub const DEFAULT_KEEP_FINALIZED_BLOCKS: u32 = 256;
+
+/// Pruning policy that retains the state of the latest finalized blocks.
+#[derive(Debug, Clone, Copy)]
+pub struct PruningPolicy {
+	keep_finalized: u32,
+}
+
+impl Default for PruningPolicy {
+	fn default() -> Self {
+		Self::new(DEFAULT_KEEP_FINALIZED_BLOCKS)
+	}
+}
+
+impl PruningPolicy {
+	/// Create a new policy that keeps the state of at least `keep_finalized` finalized blocks.
+	pub fn new(keep_finalized: u32) -> Self {
+		Self { keep_finalized }
+	}
+
+	/// Apply the policy to the given `pruning` mode.
+	///
+	/// Returns the mode to use and if it was changed. Archive modes are never changed.
+	pub fn apply(&self, pruning: PruningMode) -> (PruningMode, bool) {
+		match pruning {
+			PruningMode::Constrained(ref constraints)
+				if constraints
+					.max_blocks
+					.map_or(false, |max| max < self.keep_finalized) =>
+			{
+				(PruningMode::keep_blocks(self.keep_finalized), true)
+			}
+			pruning => (pruning, false),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn raises_too_aggressive_pruning() {
+		let policy = PruningPolicy::new(100);
+
+		let (mode, changed) = policy.apply(PruningMode::keep_blocks(10));
+		assert!(changed);
+		assert_eq!(PruningMode::keep_blocks(100), mode);
+
+		let (mode, changed) = policy.apply(PruningMode::keep_blocks(1000));
+		assert!(!changed);
+		assert_eq!(PruningMode::keep_blocks(1000), mode);
+
+		let (mode, changed) = policy.apply(PruningMode::ArchiveAll);
+		assert!(!changed);
+		assert_eq!(PruningMode::ArchiveAll, mode);
+	}
+}
@@ -0,0 +1,126 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Snapshots of a live parachain for registering it again on the relay chain.
+//!
+//! When the lease of a parachain ends, it needs to be registered again with a head and validation
+//! code. A [`RegistrationSnapshot`] of any block provides both. The chain can either continue at
+//! the snapshot block, or restart with the state of the snapshot as genesis state.
+
+use cumulus_primitives::genesis::genesis_block_from_storage;
+
+use sc_client_api::{Backend as BackendT, StorageProvider, UsageProvider};
+use sc_service::ChainSpec;
+use sp_blockchain::HeaderBackend;
+use sp_core::storage::well_known_keys;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT, Storage};
+
+use polkadot_primitives::v1::HeadData;
+
+use codec::Encode;
+
+use std::sync::Arc;
+
+/// The head and the complete state of a parachain at a given block.
+pub struct RegistrationSnapshot<Block: BlockT> {
+	/// The header of the block the snapshot was taken at.
+	pub header: Block::Header,
+	/// The state at the block.
+	pub storage: Storage,
+}
+
+impl<Block: BlockT> RegistrationSnapshot<Block> {
+	/// Take a snapshot at the given block, or at the best block if `at` is `None`.
+	pub fn at<Backend, Client>(
+		client: Arc<Client>,
+		at: Option<BlockId<Block>>,
+	) -> Result<Self, String>
+	where
+		Backend: BackendT<Block>,
+		Client: UsageProvider<Block> + StorageProvider<Block, Backend> + HeaderBackend<Block>,
+	{
+		let at = at.unwrap_or_else(|| BlockId::Hash(client.usage_info().chain.best_hash));
+
+		let header = client
+			.header(at)
+			.map_err(|e| format!("Failed to get the header of {}: {:?}", at, e))?
+			.ok_or_else(|| format!("Header of {} not found", at))?;
+
+		let storage = sc_service::chain_ops::export_raw_state(client, &at)
+			.map_err(|e| format!("Failed to export the state of {}: {:?}", at, e))?;
+
+		Ok(Self { header, storage })
+	}
+
+	/// The head data to continue the chain at the snapshot block.
+	pub fn head_data(&self) -> HeadData {
+		HeadData(self.header.encode())
+	}
+
+	/// The head data of a new chain that starts with the state of the snapshot as genesis state.
+	pub fn genesis_head_data(&self) -> HeadData {
+		HeadData(
+			genesis_block_from_storage::<Block>(&self.storage)
+				.header()
+				.encode(),
+		)
+	}
+
+	/// The validation code in the state of the snapshot.
+	pub fn validation_code(&self) -> Option<&[u8]> {
+		self.storage
+			.top
+			.get(well_known_keys::CODE)
+			.map(|code| &code[..])
+	}
+
+	/// Put the state of the snapshot as genesis state into the given `chain_spec`.
+	///
+	/// Returns the chain spec as json. The chain spec can be used to start the chain that is
+	/// registered with the [`genesis_head_data`](Self::genesis_head_data).
+	pub fn into_genesis_chain_spec(
+		self,
+		mut chain_spec: Box<dyn ChainSpec>,
+		raw: bool,
+	) -> Result<String, String> {
+		chain_spec.set_storage(self.storage);
+		chain_spec.as_json(raw)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_client::{DefaultTestClientBuilderExt, TestClientBuilder, TestClientBuilderExt};
+	use cumulus_test_runtime::{Block, WASM_BINARY};
+
+	#[test]
+	fn snapshot_provides_head_and_validation_code() {
+		let client = Arc::new(TestClientBuilder::new().build());
+		let genesis = client.header(&BlockId::Number(0)).unwrap().unwrap();
+
+		let snapshot = RegistrationSnapshot::<Block>::at(client, None).expect("Takes a snapshot");
+
+		assert_eq!(HeadData(genesis.encode()), snapshot.head_data());
+		// Restarting with the state of the genesis block yields the same genesis block.
+		assert_eq!(snapshot.head_data(), snapshot.genesis_head_data());
+		assert_eq!(
+			WASM_BINARY.expect("You need to build the WASM binaries to run the tests!"),
+			snapshot.validation_code().expect("The state contains the validation code"),
+		);
+	}
+}
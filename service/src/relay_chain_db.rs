@@ -0,0 +1,142 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Database of the embedded relay chain node.
+//!
+//! The relay chain node runs in the same process as the parachain node, but its database sees a
+//! very different load: it stores a much larger chain and state, while the parachain database
+//! is mostly written by the collator. [`RelayChainDatabase`] overrides the database backend and
+//! cache sizes of the relay chain configuration, so both can be tuned separately.
+
+use sc_service::{config::DatabaseConfig, Configuration};
+use std::path::{Path, PathBuf};
+
+/// The database cache size in MiB that is used when switching the relay chain to RocksDb.
+pub const DEFAULT_DATABASE_CACHE_SIZE: usize = 128;
+
+/// The database backend of the embedded relay chain node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayChainDatabaseBackend {
+	/// RocksDb, the default of Substrate nodes.
+	RocksDb,
+	/// ParityDb.
+	ParityDb,
+}
+
+/// Database settings of the embedded relay chain node.
+///
+/// Settings that are `None` are taken from the relay chain configuration as is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayChainDatabase {
+	/// The database backend.
+	pub backend: Option<RelayChainDatabaseBackend>,
+	/// The database cache size in MiB, only used by RocksDb.
+	pub cache_size: Option<usize>,
+	/// The state cache size in bytes.
+	pub state_cache_size: Option<usize>,
+}
+
+impl RelayChainDatabase {
+	/// Returns `true` if no setting is overridden.
+	pub fn is_default(&self) -> bool {
+		*self == Self::default()
+	}
+
+	/// Apply the settings to the configuration of the relay chain node.
+	pub fn apply(&self, config: &mut Configuration) {
+		if let Some(state_cache_size) = self.state_cache_size {
+			config.state_cache_size = state_cache_size;
+		}
+
+		config.database = self.database(config.database.clone());
+	}
+
+	/// Apply the settings to the given `database`.
+	///
+	/// The database stays in the same directory when the backend is changed. Custom databases
+	/// are never changed.
+	fn database(&self, database: DatabaseConfig) -> DatabaseConfig {
+		let (dir, backend, cache_size) = match database {
+			DatabaseConfig::RocksDb { path, cache_size } => {
+				(parent(&path), RelayChainDatabaseBackend::RocksDb, cache_size)
+			}
+			DatabaseConfig::ParityDb { path } => (
+				parent(&path),
+				RelayChainDatabaseBackend::ParityDb,
+				DEFAULT_DATABASE_CACHE_SIZE,
+			),
+			custom => return custom,
+		};
+
+		match self.backend.unwrap_or(backend) {
+			RelayChainDatabaseBackend::RocksDb => DatabaseConfig::RocksDb {
+				path: dir.join("db"),
+				cache_size: self.cache_size.unwrap_or(cache_size),
+			},
+			RelayChainDatabaseBackend::ParityDb => DatabaseConfig::ParityDb {
+				path: dir.join("paritydb"),
+			},
+		}
+	}
+}
+
+/// The directory a database at `path` was created in.
+fn parent(path: &Path) -> PathBuf {
+	path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rocks_db(cache_size: usize) -> DatabaseConfig {
+		DatabaseConfig::RocksDb {
+			path: PathBuf::from("/relay/chains/rococo/db"),
+			cache_size,
+		}
+	}
+
+	fn assert_database(expected: DatabaseConfig, found: DatabaseConfig) {
+		assert_eq!(format!("{:?}", expected), format!("{:?}", found));
+	}
+
+	#[test]
+	fn overrides_the_backend_and_cache() {
+		let settings = RelayChainDatabase {
+			cache_size: Some(1024),
+			..Default::default()
+		};
+		assert_database(rocks_db(1024), settings.database(rocks_db(128)));
+
+		let settings = RelayChainDatabase {
+			backend: Some(RelayChainDatabaseBackend::ParityDb),
+			..Default::default()
+		};
+		let parity_db = settings.database(rocks_db(128));
+		assert_database(
+			DatabaseConfig::ParityDb {
+				path: PathBuf::from("/relay/chains/rococo/paritydb"),
+			},
+			parity_db.clone(),
+		);
+
+		assert!(RelayChainDatabase::default().is_default());
+		assert_database(
+			parity_db.clone(),
+			RelayChainDatabase::default().database(parity_db),
+		);
+	}
+}
@@ -0,0 +1,104 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Client side helpers for migrating a standalone chain in place to a parachain.
+//!
+//! The runtime side is provided by the `cumulus-solo-to-para` pallet. A node of a migrating
+//! chain authors blocks with its local consensus until [`wait_for_activation`] resolves and then
+//! switches to [`start_collator`](crate::start_collator).
+
+use cumulus_primitives::SoloToParaApi;
+use futures::StreamExt;
+use polkadot_primitives::v1::HeadData;
+use sc_client_api::BlockchainEvents;
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor, One, Saturating},
+};
+
+use codec::Encode;
+
+use std::sync::Arc;
+
+/// Returns the activation block that is scheduled in the state of the block `at`.
+fn activation_block<Block, Client>(
+	client: &Client,
+	at: BlockId<Block>,
+) -> Result<Option<NumberFor<Block>>, String>
+where
+	Block: BlockT,
+	Client: ProvideRuntimeApi<Block>,
+	Client::Api: SoloToParaApi<Block>,
+{
+	client
+		.runtime_api()
+		.activation_block(&at)
+		.map_err(|e| format!("Failed to get the activation block at {}: {:?}", at, e))
+}
+
+/// Returns the head data the parachain needs to be registered with on the relay chain.
+///
+/// This is the header of the last block before the activation block, as the first parachain
+/// block is built on top of it. Returns `None` if no activation is scheduled at the best block or
+/// if the block before the activation block was not yet imported.
+pub fn registration_head<Block, Client>(client: &Client) -> Result<Option<HeadData>, String>
+where
+	Block: BlockT,
+	Client: ProvideRuntimeApi<Block> + HeaderBackend<Block>,
+	Client::Api: SoloToParaApi<Block>,
+{
+	let best = BlockId::Hash(client.info().best_hash);
+	let activation = match activation_block(client, best)? {
+		Some(activation) => activation,
+		None => return Ok(None),
+	};
+
+	let parent = activation.saturating_sub(One::one());
+	client
+		.header(BlockId::Number(parent))
+		.map(|header| header.map(|h| HeadData(h.encode())))
+		.map_err(|e| format!("Failed to get the header of block #{}: {:?}", parent, e))
+}
+
+/// Wait until the next block to build is the activation block.
+///
+/// Resolves with the number of the activation block. From then on the local consensus needs to
+/// stop authoring and the Cumulus collator needs to be started.
+pub async fn wait_for_activation<Block, Client>(
+	client: Arc<Client>,
+) -> Result<NumberFor<Block>, String>
+where
+	Block: BlockT,
+	Client: ProvideRuntimeApi<Block> + HeaderBackend<Block> + BlockchainEvents<Block>,
+	Client::Api: SoloToParaApi<Block>,
+{
+	let mut imported = client.import_notification_stream();
+
+	loop {
+		let info = client.info();
+		if let Some(activation) = activation_block(&*client, BlockId::Hash(info.best_hash))? {
+			if info.best_number + One::one() >= activation {
+				return Ok(activation);
+			}
+		}
+
+		if imported.next().await.is_none() {
+			return Err("Import notification stream ended before the activation".into());
+		}
+	}
+}
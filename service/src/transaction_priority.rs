@@ -0,0 +1,472 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reorder the ready transactions before they are put into a candidate.
+//!
+//! The proposer takes the ready transactions of the pool in the order of the pool, until the
+//! block is full. The PoV of a candidate is limited as well, and transactions that touch keys
+//! already read by the block add much less to the PoV than transactions touching new keys. A
+//! [`PrioritizedPool`] wraps the pool of the proposer and lets a [`TransactionPriority`] reorder
+//! or skip the ready transactions, e.g. by their fee priority per [estimated proof
+//! footprint](ByProofFootprint).
+//!
+//! Not the whole PoV is available to the transactions. The inherents, the upward and horizontal
+//! messages and a pending code upgrade need space as well, a [`PoVReserve`] describes this space
+//! and gives the budget left for the transactions. The maximum PoV size is the one of the relay
+//! parent of the block that is built, which the collator publishes in a [`PoVSizeLimit`].
+
+use cumulus_collator::PoVSizeLimit;
+
+use futures::{future::Future, FutureExt};
+use sp_runtime::{generic::BlockId, traits::NumberFor};
+use sp_transaction_pool::{
+	ImportNotificationStream, InPoolTransaction, PoolFuture, PoolStatus, TransactionFor,
+	TransactionPool, TransactionSource, TransactionStatusStreamFor, TxHash,
+};
+
+use std::{
+	cmp::Ordering,
+	collections::{BinaryHeap, HashMap, HashSet},
+	pin::Pin,
+	sync::Arc,
+};
+
+/// The default space reserved for the validation data and the other inherents, in bytes.
+pub const DEFAULT_VALIDATION_DATA_RESERVE: u64 = 16 * 1024;
+
+/// The default space reserved for the upward and horizontal messages, in bytes.
+pub const DEFAULT_MESSAGES_RESERVE: u64 = 64 * 1024;
+
+/// The part of the PoV that is not available to the transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoVReserve {
+	/// Reserved for the validation data and the other inherents.
+	pub validation_data: u64,
+	/// Reserved for the upward and horizontal messages sent by the block.
+	pub messages: u64,
+	/// Reserved for the new validation code of a pending upgrade.
+	pub code_upgrade: u64,
+}
+
+impl Default for PoVReserve {
+	fn default() -> Self {
+		Self {
+			validation_data: DEFAULT_VALIDATION_DATA_RESERVE,
+			messages: DEFAULT_MESSAGES_RESERVE,
+			code_upgrade: 0,
+		}
+	}
+}
+
+impl PoVReserve {
+	/// The space left for the transactions in a PoV of `max_pov_size` bytes.
+	pub fn budget(&self, max_pov_size: usize) -> u64 {
+		(max_pov_size as u64)
+			.saturating_sub(self.validation_data)
+			.saturating_sub(self.messages)
+			.saturating_sub(self.code_upgrade)
+	}
+}
+
+/// Decides in which order the ready transactions are tried by the proposer.
+pub trait TransactionPriority<Tx>: Send + Sync {
+	/// Returns the `ready` transactions in the order they should be tried.
+	///
+	/// Transactions that are left out are skipped for the next block. `ready` is in the order of
+	/// the pool, which puts a transaction after the transactions it depends on. Implementations
+	/// need to keep this, a transaction that is tried before its dependencies is invalid and
+	/// removed from the pool by the proposer.
+	fn prioritize(&self, ready: Vec<Arc<Tx>>) -> Vec<Arc<Tx>>;
+}
+
+impl<Tx, F> TransactionPriority<Tx> for F
+where
+	F: Fn(Vec<Arc<Tx>>) -> Vec<Arc<Tx>> + Send + Sync,
+{
+	fn prioritize(&self, ready: Vec<Arc<Tx>>) -> Vec<Arc<Tx>> {
+		(self)(ready)
+	}
+}
+
+/// Tries the transactions with the highest priority per byte of estimated proof footprint first.
+///
+/// The priority is the one the runtime gave the transaction, usually derived from its fee. The
+/// footprint of a transaction is estimated by a function, for example from its encoded size and
+/// the storage keys its call is known to touch. Of transactions with the same priority per byte,
+/// the one with the smaller footprint is tried first. Transactions above the maximum footprint,
+/// or that do not fit into the remaining budget, are skipped together with the transactions
+/// depending on them. The dependencies between the transactions are kept.
+pub struct ByProofFootprint<F> {
+	estimate: F,
+	max_footprint: Option<u64>,
+	budget: Option<(PoVReserve, PoVSizeLimit)>,
+}
+
+impl<F> ByProofFootprint<F> {
+	/// Order the transactions by their priority per footprint returned by `estimate`, in bytes.
+	pub fn new(estimate: F) -> Self {
+		Self {
+			estimate,
+			max_footprint: None,
+			budget: None,
+		}
+	}
+
+	/// Skip the transactions with an estimated footprint above `max_footprint`.
+	pub fn max_footprint(mut self, max_footprint: u64) -> Self {
+		self.max_footprint = Some(max_footprint);
+		self
+	}
+
+	/// Only try transactions while their summed up footprint fits into the PoV.
+	///
+	/// The budget is the [`PoVReserve::budget`] of the current `pov_size_limit`.
+	pub fn budget(mut self, reserve: PoVReserve, pov_size_limit: PoVSizeLimit) -> Self {
+		self.budget = Some((reserve, pov_size_limit));
+		self
+	}
+}
+
+/// A ready transaction whose dependencies were tried already.
+#[derive(PartialEq, Eq)]
+struct Candidate {
+	priority: u64,
+	footprint: u64,
+	index: usize,
+}
+
+impl Ord for Candidate {
+	fn cmp(&self, other: &Self) -> Ordering {
+		// Compares `priority / footprint` without rounding, a higher ratio is tried first.
+		let ratio = |c: &Self, o: &Self| c.priority as u128 * o.footprint.max(1) as u128;
+
+		ratio(self, other)
+			.cmp(&ratio(other, self))
+			.then_with(|| other.footprint.cmp(&self.footprint))
+			.then_with(|| other.index.cmp(&self.index))
+	}
+}
+
+impl PartialOrd for Candidate {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl<Tx, F> TransactionPriority<Tx> for ByProofFootprint<F>
+where
+	Tx: InPoolTransaction,
+	F: Fn(&Tx::Transaction) -> u64 + Send + Sync,
+{
+	fn prioritize(&self, ready: Vec<Arc<Tx>>) -> Vec<Arc<Tx>> {
+		let footprints = ready
+			.iter()
+			.map(|tx| (self.estimate)(tx.data()))
+			.collect::<Vec<_>>();
+
+		let provided_by_ready = ready
+			.iter()
+			.flat_map(|tx| tx.provides())
+			.collect::<HashSet<_>>();
+
+		// The tags every transaction is still waiting for, and the transactions waiting for a tag.
+		let mut unmet = vec![0usize; ready.len()];
+		let mut waiting = HashMap::<&[u8], Vec<usize>>::new();
+		for (index, tx) in ready.iter().enumerate() {
+			for tag in tx.requires().iter().filter(|t| provided_by_ready.contains(t)) {
+				unmet[index] += 1;
+				waiting.entry(&tag[..]).or_default().push(index);
+			}
+		}
+
+		let candidate = |index: usize| Candidate {
+			priority: *ready[index].priority(),
+			footprint: footprints[index],
+			index,
+		};
+		let mut next = (0..ready.len())
+			.filter(|index| unmet[*index] == 0)
+			.map(candidate)
+			.collect::<BinaryHeap<_>>();
+
+		let budget = self
+			.budget
+			.as_ref()
+			.map(|(reserve, pov_size_limit)| reserve.budget(pov_size_limit.get()));
+
+		let mut ordered = Vec::with_capacity(ready.len());
+		let mut used = 0u64;
+		while let Some(Candidate {
+			footprint, index, ..
+		}) = next.pop()
+		{
+			if self.max_footprint.map_or(false, |max| footprint > max) {
+				continue;
+			}
+
+			let total = used.saturating_add(footprint);
+			if budget.map_or(false, |budget| total > budget) {
+				continue;
+			}
+
+			used = total;
+			ordered.push(ready[index].clone());
+
+			for tag in ready[index].provides() {
+				for dependent in waiting.remove(&tag[..]).unwrap_or_default() {
+					unmet[dependent] -= 1;
+					if unmet[dependent] == 0 {
+						next.push(candidate(dependent));
+					}
+				}
+			}
+		}
+
+		ordered
+	}
+}
+
+/// A transaction pool whose ready transactions are reordered by a [`TransactionPriority`].
+///
+/// Everything else is forwarded to the wrapped pool.
+pub struct PrioritizedPool<Pool: TransactionPool> {
+	inner: Arc<Pool>,
+	priority: Arc<dyn TransactionPriority<Pool::InPoolTransaction>>,
+}
+
+impl<Pool: TransactionPool> PrioritizedPool<Pool> {
+	/// Reorder the ready transactions of `inner` with `priority`.
+	pub fn new(
+		inner: Arc<Pool>,
+		priority: Arc<dyn TransactionPriority<Pool::InPoolTransaction>>,
+	) -> Self {
+		Self { inner, priority }
+	}
+}
+
+impl<Pool> TransactionPool for PrioritizedPool<Pool>
+where
+	Pool: TransactionPool + 'static,
+	Pool::InPoolTransaction: Send + Sync + 'static,
+{
+	type Block = Pool::Block;
+	type Hash = Pool::Hash;
+	type InPoolTransaction = Pool::InPoolTransaction;
+	type Error = Pool::Error;
+
+	fn submit_at(
+		&self,
+		at: &BlockId<Self::Block>,
+		source: TransactionSource,
+		xts: Vec<TransactionFor<Self>>,
+	) -> PoolFuture<Vec<Result<TxHash<Self>, Self::Error>>, Self::Error> {
+		self.inner.submit_at(at, source, xts)
+	}
+
+	fn submit_one(
+		&self,
+		at: &BlockId<Self::Block>,
+		source: TransactionSource,
+		xt: TransactionFor<Self>,
+	) -> PoolFuture<TxHash<Self>, Self::Error> {
+		self.inner.submit_one(at, source, xt)
+	}
+
+	fn submit_and_watch(
+		&self,
+		at: &BlockId<Self::Block>,
+		source: TransactionSource,
+		xt: TransactionFor<Self>,
+	) -> PoolFuture<Box<TransactionStatusStreamFor<Self>>, Self::Error> {
+		self.inner.submit_and_watch(at, source, xt)
+	}
+
+	fn ready_at(
+		&self,
+		at: NumberFor<Self::Block>,
+	) -> Pin<
+		Box<
+			dyn Future<Output = Box<dyn Iterator<Item = Arc<Self::InPoolTransaction>> + Send>>
+				+ Send,
+		>,
+	> {
+		let priority = self.priority.clone();
+		self.inner
+			.ready_at(at)
+			.map(move |ready| {
+				Box::new(priority.prioritize(ready.collect()).into_iter())
+					as Box<dyn Iterator<Item = _> + Send>
+			})
+			.boxed()
+	}
+
+	fn ready(&self) -> Box<dyn Iterator<Item = Arc<Self::InPoolTransaction>>> {
+		Box::new(self.priority.prioritize(self.inner.ready().collect()).into_iter())
+	}
+
+	fn remove_invalid(&self, hashes: &[TxHash<Self>]) -> Vec<Arc<Self::InPoolTransaction>> {
+		self.inner.remove_invalid(hashes)
+	}
+
+	fn status(&self) -> PoolStatus {
+		self.inner.status()
+	}
+
+	fn import_notification_stream(&self) -> ImportNotificationStream<TxHash<Self>> {
+		self.inner.import_notification_stream()
+	}
+
+	fn on_broadcasted(&self, propagations: HashMap<TxHash<Self>, Vec<String>>) {
+		self.inner.on_broadcasted(propagations)
+	}
+
+	fn hash_of(&self, xt: &TransactionFor<Self>) -> TxHash<Self> {
+		self.inner.hash_of(xt)
+	}
+
+	fn ready_transaction(&self, hash: &TxHash<Self>) -> Option<Arc<Self::InPoolTransaction>> {
+		self.inner.ready_transaction(hash)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use sp_runtime::transaction_validity::{
+		TransactionLongevity, TransactionPriority as Priority, TransactionTag,
+	};
+
+	#[derive(Debug)]
+	struct Tx {
+		footprint: u64,
+		priority: Priority,
+		requires: Vec<TransactionTag>,
+		provides: Vec<TransactionTag>,
+	}
+
+	impl InPoolTransaction for Tx {
+		type Transaction = u64;
+		type Hash = u64;
+
+		fn data(&self) -> &u64 {
+			&self.footprint
+		}
+
+		fn hash(&self) -> &u64 {
+			&self.footprint
+		}
+
+		fn priority(&self) -> &Priority {
+			&self.priority
+		}
+
+		fn longevity(&self) -> &TransactionLongevity {
+			&0
+		}
+
+		fn requires(&self) -> &[TransactionTag] {
+			&self.requires
+		}
+
+		fn provides(&self) -> &[TransactionTag] {
+			&self.provides
+		}
+
+		fn is_propagable(&self) -> bool {
+			true
+		}
+	}
+
+	fn tx(footprint: u64, requires: &[u8], provides: &[u8]) -> Arc<Tx> {
+		tx_with_priority(footprint, 0, requires, provides)
+	}
+
+	fn tx_with_priority(
+		footprint: u64,
+		priority: Priority,
+		requires: &[u8],
+		provides: &[u8],
+	) -> Arc<Tx> {
+		Arc::new(Tx {
+			footprint,
+			priority,
+			requires: requires.iter().map(|t| vec![*t]).collect(),
+			provides: provides.iter().map(|t| vec![*t]).collect(),
+		})
+	}
+
+	fn footprints(ordered: Vec<Arc<Tx>>) -> Vec<u64> {
+		ordered.iter().map(|tx| tx.footprint).collect()
+	}
+
+	#[test]
+	fn smallest_footprint_first_after_dependencies() {
+		let priority = ByProofFootprint::new(|footprint: &u64| *footprint);
+
+		// `30` provides the tag `20` requires, `5` requires a tag that is not in the pool.
+		let ready = vec![
+			tx(30, &[], &[1]),
+			tx(20, &[1], &[2]),
+			tx(10, &[], &[3]),
+			tx(5, &[9], &[]),
+		];
+
+		assert_eq!(vec![5, 10, 30, 20], footprints(priority.prioritize(ready)));
+	}
+
+	#[test]
+	fn highest_priority_per_footprint_first() {
+		let priority = ByProofFootprint::new(|footprint: &u64| *footprint);
+
+		// Priorities per byte: 2, 3, 2 and 0.
+		let ready = vec![
+			tx_with_priority(10, 20, &[], &[]),
+			tx_with_priority(20, 60, &[], &[]),
+			tx_with_priority(5, 10, &[], &[]),
+			tx_with_priority(1, 0, &[], &[]),
+		];
+
+		assert_eq!(vec![20, 5, 10, 1], footprints(priority.prioritize(ready)));
+	}
+
+	#[test]
+	fn skips_dependents_of_skipped_transactions() {
+		let priority = ByProofFootprint::new(|footprint: &u64| *footprint).max_footprint(25);
+
+		let ready = vec![tx(30, &[], &[1]), tx(20, &[1], &[2]), tx(10, &[], &[3])];
+
+		assert_eq!(vec![10], footprints(priority.prioritize(ready)));
+	}
+
+	#[test]
+	fn stops_at_the_pov_budget() {
+		let reserve = PoVReserve {
+			validation_data: 40,
+			messages: 20,
+			code_upgrade: 0,
+		};
+		assert_eq!(40, reserve.budget(100));
+		assert_eq!(0, reserve.budget(50));
+
+		let priority = ByProofFootprint::new(|footprint: &u64| *footprint)
+			.budget(reserve, PoVSizeLimit::new(100));
+
+		let ready = vec![tx(30, &[], &[]), tx(20, &[], &[]), tx(15, &[], &[])];
+
+		assert_eq!(vec![15, 20], footprints(priority.prioritize(ready)));
+	}
+}
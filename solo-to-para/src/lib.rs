@@ -0,0 +1,247 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+//! Migrate a standalone chain in place to a parachain.
+//!
+//! The migration works in the following steps:
+//!
+//! 1. A runtime upgrade adds this pallet and the `parachain-upgrade` pallet, with this pallet as
+//!    its `Activation` (see `cumulus_parachain_upgrade::Trait`). The chain keeps running with its
+//!    local consensus, as the parachain inherents are not required yet.
+//! 2. Root schedules the activation block with [`Module::schedule_activation`].
+//! 3. The parachain is registered on the relay chain with the head of the block before the
+//!    activation block, see `cumulus_service::solo_to_para`.
+//! 4. Nodes stop authoring with the local consensus and start the Cumulus collator once the
+//!    activation block is reached. Starting with this block, every block requires the
+//!    parachain inherents.
+//!
+//! The runtime should expose the activation block with [`cumulus_primitives::SoloToParaApi`].
+
+use cumulus_primitives::ParachainActivation;
+use frame_support::{decl_error, decl_event, decl_module, decl_storage, ensure, weights::Weight};
+use frame_system::ensure_root;
+
+type System<T> = frame_system::Module<T>;
+
+/// The pallet's configuration trait.
+pub trait Trait: frame_system::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as SoloToPara {
+		/// The block from that on blocks are built as parachain blocks.
+		ActivationBlock get(fn activation_block): Option<T::BlockNumber>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		type Error = Error<T>;
+
+		fn deposit_event() = default;
+
+		/// Schedule the activation as parachain at the given block.
+		///
+		/// The block needs to be in the future, to give the relay chain registration and the
+		/// nodes time to prepare the switch.
+		#[weight = 0]
+		pub fn schedule_activation(origin, at: T::BlockNumber) {
+			ensure_root(origin)?;
+			ensure!(!Self::is_activated(), Error::<T>::AlreadyActivated);
+			ensure!(at > System::<T>::block_number(), Error::<T>::ActivationInThePast);
+
+			ActivationBlock::<T>::put(at);
+			Self::deposit_event(RawEvent::ActivationScheduled(at));
+		}
+
+		/// Cancel the scheduled activation.
+		#[weight = 0]
+		pub fn cancel_activation(origin) {
+			ensure_root(origin)?;
+			ensure!(!Self::is_activated(), Error::<T>::AlreadyActivated);
+
+			ActivationBlock::<T>::kill();
+			Self::deposit_event(RawEvent::ActivationCancelled);
+		}
+
+		fn on_initialize(n: T::BlockNumber) -> Weight {
+			if Self::activation_block() == Some(n) {
+				Self::deposit_event(RawEvent::Activated(n));
+			}
+
+			0
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+	/// Returns `true` if the current block is built as a parachain block.
+	pub fn is_activated() -> bool {
+		Self::activation_block().map_or(false, |at| at <= System::<T>::block_number())
+	}
+}
+
+impl<T: Trait> ParachainActivation for Module<T> {
+	fn is_active() -> bool {
+		Self::is_activated()
+	}
+}
+
+decl_event! {
+	pub enum Event<T> where BlockNumber = <T as frame_system::Trait>::BlockNumber {
+		/// The activation as parachain was scheduled at the given block.
+		ActivationScheduled(BlockNumber),
+		/// The scheduled activation was cancelled.
+		ActivationCancelled,
+		/// The chain runs as parachain, starting with the given block.
+		Activated(BlockNumber),
+	}
+}
+
+decl_error! {
+	pub enum Error for Module<T: Trait> {
+		/// The chain already runs as parachain.
+		AlreadyActivated,
+		/// The activation block needs to be in the future.
+		ActivationInThePast,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use frame_support::{
+		assert_noop, assert_ok, impl_outer_event, impl_outer_origin, parameter_types,
+		traits::OnInitialize,
+	};
+	use frame_system::RawOrigin;
+	use sp_core::H256;
+	use sp_runtime::{
+		testing::Header,
+		traits::{BlakeTwo256, IdentityLookup},
+		DispatchError, Perbill,
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test where system = frame_system {}
+	}
+
+	mod solo_to_para {
+		pub use crate::Event;
+	}
+
+	impl_outer_event! {
+		pub enum TestEvent for Test {
+			frame_system<T>,
+			solo_to_para<T>,
+		}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	parameter_types! {
+		pub const BlockHashCount: u64 = 250;
+		pub const MaximumBlockWeight: Weight = 1024;
+		pub const MaximumBlockLength: u32 = 2 * 1024;
+		pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+	}
+	impl frame_system::Trait for Test {
+		type Origin = Origin;
+		type Call = ();
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = TestEvent;
+		type BlockHashCount = BlockHashCount;
+		type MaximumBlockWeight = MaximumBlockWeight;
+		type MaximumExtrinsicWeight = MaximumBlockWeight;
+		type MaximumBlockLength = MaximumBlockLength;
+		type AvailableBlockRatio = AvailableBlockRatio;
+		type Version = ();
+		type PalletInfo = ();
+		type AccountData = ();
+		type OnNewAccount = ();
+		type OnKilledAccount = ();
+		type DbWeight = ();
+		type BlockExecutionWeight = ();
+		type ExtrinsicBaseWeight = ();
+		type BaseCallFilter = ();
+		type SystemWeightInfo = ();
+	}
+	impl Trait for Test {
+		type Event = TestEvent;
+	}
+
+	type SoloToPara = Module<Test>;
+
+	fn new_test_ext() -> sp_io::TestExternalities {
+		frame_system::GenesisConfig::default()
+			.build_storage::<Test>()
+			.unwrap()
+			.into()
+	}
+
+	#[test]
+	fn activates_at_scheduled_block() {
+		new_test_ext().execute_with(|| {
+			System::<Test>::set_block_number(1);
+			assert!(!SoloToPara::is_active());
+
+			assert_ok!(SoloToPara::schedule_activation(RawOrigin::Root.into(), 3));
+
+			System::<Test>::set_block_number(2);
+			assert!(!SoloToPara::is_active());
+
+			System::<Test>::set_block_number(3);
+			SoloToPara::on_initialize(3);
+			assert!(SoloToPara::is_active());
+
+			assert_noop!(
+				SoloToPara::cancel_activation(RawOrigin::Root.into()),
+				Error::<Test>::AlreadyActivated,
+			);
+		});
+	}
+
+	#[test]
+	fn rejects_invalid_schedules() {
+		new_test_ext().execute_with(|| {
+			System::<Test>::set_block_number(5);
+
+			assert_noop!(
+				SoloToPara::schedule_activation(RawOrigin::Root.into(), 5),
+				Error::<Test>::ActivationInThePast,
+			);
+			assert_noop!(
+				SoloToPara::schedule_activation(RawOrigin::Signed(1).into(), 10),
+				DispatchError::BadOrigin,
+			);
+
+			assert_ok!(SoloToPara::schedule_activation(RawOrigin::Root.into(), 10));
+			assert_ok!(SoloToPara::cancel_activation(RawOrigin::Root.into()));
+			assert_eq!(None, SoloToPara::activation_block());
+		});
+	}
+}
@@ -14,12 +14,14 @@
 // You should have received a copy of the GNU General Public License
 // along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::Client;
-use cumulus_primitives::{inherents::VALIDATION_DATA_IDENTIFIER, ValidationData};
-use cumulus_test_runtime::GetLastTimestamp;
+use crate::{Backend, Client};
+use cumulus_primitives::{
+	inherents::DownwardMessagesType, ParachainInherentDataProvider, ValidationData,
+};
+use cumulus_test_runtime::{Block, GetLastTimestamp};
 use polkadot_primitives::v1::BlockNumber as PBlockNumber;
-use sc_block_builder::BlockBuilderApi;
-use sp_api::ProvideRuntimeApi;
+use sc_block_builder::{BlockBuilder, BlockBuilderApi, BuiltBlock};
+use sp_api::{ProvideRuntimeApi, StateBackendFor};
 use sp_blockchain::HeaderBackend;
 use sp_core::ExecutionContext;
 use sp_runtime::generic::BlockId;
@@ -33,30 +35,109 @@ pub fn generate_block_inherents(
 	client: &Client,
 	validation_data: Option<ValidationData<PBlockNumber>>,
 ) -> Vec<cumulus_test_runtime::UncheckedExtrinsic> {
+	generate_block_inherents_with_messages(client, validation_data.unwrap_or_default(), Vec::new())
+		.expect("Get inherents failed")
+}
+
+/// Generate the inherents required by the test runtime from the given `validation_data` and
+/// `downward_messages`.
+///
+/// Unlike [`generate_block_inherents`], the data is passed as is, so it can be invalid, e.g. a
+/// malformed parent head or a maximum PoV size of zero. Returns the error of the runtime if it
+/// fails to create the inherents.
+pub fn generate_block_inherents_with_messages(
+	client: &Client,
+	validation_data: ValidationData<PBlockNumber>,
+	downward_messages: DownwardMessagesType,
+) -> Result<Vec<cumulus_test_runtime::UncheckedExtrinsic>, sp_blockchain::Error> {
 	let mut inherent_data = sp_inherents::InherentData::new();
 	let block_id = BlockId::Hash(client.info().best_hash);
-	let last_timestamp = client
-		.runtime_api()
-		.get_last_timestamp(&block_id)
-		.expect("Get last timestamp");
+	let last_timestamp = client.runtime_api().get_last_timestamp(&block_id)?;
 	let timestamp = last_timestamp + cumulus_test_runtime::MinimumPeriod::get();
 
+	let map_err = |e: sp_inherents::Error| {
+		sp_blockchain::Error::Msg(format!("Failed to put the inherent data: {:?}", e))
+	};
 	inherent_data
 		.put_data(sp_timestamp::INHERENT_IDENTIFIER, &timestamp)
-		.expect("Put timestamp failed");
-	inherent_data
-		.put_data(
-			VALIDATION_DATA_IDENTIFIER,
-			&validation_data.unwrap_or_default(),
-		)
-		.expect("Put validation function params failed");
-
-	client
-		.runtime_api()
-		.inherent_extrinsics_with_context(
-			&BlockId::number(0),
-			ExecutionContext::BlockConstruction,
-			inherent_data,
-		)
-		.expect("Get inherents failed")
+		.map_err(map_err)?;
+	ParachainInherentDataProvider::new(validation_data, downward_messages)
+		.provide_inherent_data(&mut inherent_data)
+		.map_err(map_err)?;
+
+	client.runtime_api().inherent_extrinsics_with_context(
+		&BlockId::number(0),
+		ExecutionContext::BlockConstruction,
+		inherent_data,
+	)
+}
+
+/// Extensions for the [`BlockBuilder`] of the test [`Client`].
+pub trait BlockBuilderExt {
+	/// Push the inherents for the given `validation_data` and `downward_messages` and build the
+	/// block.
+	///
+	/// The inherents are generated with [`generate_block_inherents_with_messages`], so the block
+	/// can carry arbitrary relay chain data. Returns the error of the runtime if it fails to
+	/// create or rejects the inherents.
+	fn build_with_validation_data(
+		self,
+		client: &Client,
+		validation_data: ValidationData<PBlockNumber>,
+		downward_messages: DownwardMessagesType,
+	) -> Result<BuiltBlock<Block, StateBackendFor<Client, Block>>, sp_blockchain::Error>;
+}
+
+impl<'a> BlockBuilderExt for BlockBuilder<'a, Block, Client, Backend> {
+	fn build_with_validation_data(
+		mut self,
+		client: &Client,
+		validation_data: ValidationData<PBlockNumber>,
+		downward_messages: DownwardMessagesType,
+	) -> Result<BuiltBlock<Block, StateBackendFor<Client, Block>>, sp_blockchain::Error> {
+		for inherent in
+			generate_block_inherents_with_messages(client, validation_data, downward_messages)?
+		{
+			self.push(inherent)?;
+		}
+
+		self.build()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{DefaultTestClientBuilderExt, TestClientBuilder, TestClientBuilderExt};
+	use sc_block_builder::BlockBuilderProvider;
+
+	#[test]
+	fn builds_a_block_with_the_validation_data() {
+		let client = TestClientBuilder::new().build();
+		let mut validation_data = ValidationData::default();
+		validation_data.persisted.block_number = 10;
+
+		let built = client
+			.new_block(Default::default())
+			.unwrap()
+			.build_with_validation_data(&client, validation_data, Vec::new())
+			.expect("Builds the block");
+
+		assert!(!built.block.extrinsics.is_empty());
+	}
+
+	#[test]
+	fn rejected_inherents_are_returned_as_error() {
+		let client = TestClientBuilder::new().build();
+		let mut builder = client.new_block(Default::default()).unwrap();
+
+		// The runtime only accepts the inherents once per block.
+		for inherent in generate_block_inherents(&client, None) {
+			builder.push(inherent).unwrap();
+		}
+
+		assert!(builder
+			.build_with_validation_data(&client, Default::default(), Vec::new())
+			.is_err());
+	}
 }
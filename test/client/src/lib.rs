@@ -21,10 +21,11 @@ mod block_builder;
 pub use block_builder::*;
 
 use codec::Encode;
+use cumulus_primitives::ParaId;
 pub use cumulus_test_runtime as runtime;
 use runtime::{
-	Balance, Block, BlockHashCount, Call, GenesisConfig, Runtime, Signature, SignedExtra,
-	SignedPayload, UncheckedExtrinsic, VERSION,
+	AccountId, Balance, BalancesConfig, Block, BlockHashCount, Call, GenesisConfig, Runtime,
+	Signature, SignedExtra, SignedPayload, UncheckedExtrinsic, VERSION,
 };
 use sc_service::client;
 use sp_blockchain::HeaderBackend;
@@ -65,10 +66,26 @@ pub type LongestChain = sc_consensus::LongestChain<Backend, Block>;
 /// Test client type with `LocalExecutor` and generic Backend.
 pub type Client = client::Client<Backend, Executor, Block, runtime::RuntimeApi>;
 
+/// The para id of the test client, if not set with [`TestClientBuilderExt::set_para_id`].
+pub const DEFAULT_PARA_ID: u32 = 100;
+
 /// Parameters of test-client builder with test-runtime.
-#[derive(Default)]
 pub struct GenesisParameters {
 	support_changes_trie: bool,
+	para_id: ParaId,
+	balances: Option<Vec<(AccountId, Balance)>>,
+	extra_storage: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Default for GenesisParameters {
+	fn default() -> Self {
+		Self {
+			support_changes_trie: false,
+			para_id: DEFAULT_PARA_ID.into(),
+			balances: None,
+			extra_storage: Default::default(),
+		}
+	}
 }
 
 impl substrate_test_client::GenesisInit for GenesisParameters {
@@ -78,7 +95,17 @@ impl substrate_test_client::GenesisInit for GenesisParameters {
 		} else {
 			None
 		};
-		let mut storage = genesis_config(changes_trie_config).build_storage().unwrap();
+		let mut genesis_config = genesis_config(self.para_id, changes_trie_config);
+		if let Some(ref balances) = self.balances {
+			genesis_config.pallet_balances = Some(BalancesConfig {
+				balances: balances.clone(),
+			});
+		}
+
+		let mut storage = genesis_config.build_storage().unwrap();
+		storage
+			.top
+			.extend(self.extra_storage.iter().map(|(k, v)| (k.clone(), v.clone())));
 
 		let child_roots = storage.children_default.iter().map(|(sk, child_content)| {
 			let state_root =
@@ -103,6 +130,19 @@ pub trait TestClientBuilderExt: Sized {
 	/// Enable or disable support for changes trie in genesis.
 	fn set_support_changes_trie(self, support_changes_trie: bool) -> Self;
 
+	/// Set the para id of the parachain in genesis, defaults to [`DEFAULT_PARA_ID`].
+	fn set_para_id(self, para_id: ParaId) -> Self;
+
+	/// Set the `balances` of the accounts in genesis.
+	///
+	/// Replaces the default balances, which endow the well known keyring accounts.
+	fn set_balances(self, balances: Vec<(AccountId, Balance)>) -> Self;
+
+	/// Set the `value` of the storage `key` in genesis.
+	///
+	/// Overrides the value written by the genesis config of the runtime.
+	fn set_genesis_storage(self, key: Vec<u8>, value: Vec<u8>) -> Self;
+
 	/// Build the test client.
 	fn build(self) -> Client {
 		self.build_with_longest_chain().0
@@ -118,6 +158,21 @@ impl TestClientBuilderExt for TestClientBuilder {
 		self
 	}
 
+	fn set_para_id(mut self, para_id: ParaId) -> Self {
+		self.genesis_init_mut().para_id = para_id;
+		self
+	}
+
+	fn set_balances(mut self, balances: Vec<(AccountId, Balance)>) -> Self {
+		self.genesis_init_mut().balances = Some(balances);
+		self
+	}
+
+	fn set_genesis_storage(mut self, key: Vec<u8>, value: Vec<u8>) -> Self {
+		self.genesis_init_mut().extra_storage.insert(key, value);
+		self
+	}
+
 	fn build_with_longest_chain(self) -> (Client, LongestChain) {
 		self.build_with_native_executor(None)
 	}
@@ -135,8 +190,11 @@ impl DefaultTestClientBuilderExt for TestClientBuilder {
 	}
 }
 
-fn genesis_config(changes_trie_config: Option<ChangesTrieConfiguration>) -> GenesisConfig {
-	cumulus_test_service::local_testnet_genesis(changes_trie_config)
+fn genesis_config(
+	para_id: ParaId,
+	changes_trie_config: Option<ChangesTrieConfiguration>,
+) -> GenesisConfig {
+	cumulus_test_service::local_testnet_genesis(para_id, changes_trie_config)
 }
 
 fn additional_storage_with_genesis(genesis_block: &Block) -> BTreeMap<Vec<u8>, Vec<u8>> {
@@ -150,11 +208,23 @@ pub fn generate_extrinsic(
 	client: &Client,
 	origin: sp_keyring::AccountKeyring,
 	function: Call,
+) -> UncheckedExtrinsic {
+	generate_extrinsic_with_nonce(client, origin, function, 0)
+}
+
+/// Generate an extrinsic with the given `nonce` from the provided function call, origin and
+/// [`Client`].
+///
+/// This allows to put more than one extrinsic of the same `origin` into a block.
+pub fn generate_extrinsic_with_nonce(
+	client: &Client,
+	origin: sp_keyring::AccountKeyring,
+	function: Call,
+	nonce: u32,
 ) -> UncheckedExtrinsic {
 	let current_block_hash = client.info().best_hash;
 	let current_block = client.info().best_number.saturated_into();
 	let genesis_block = client.hash(0).unwrap().unwrap();
-	let nonce = 0;
 	let period = BlockHashCount::get()
 		.checked_next_power_of_two()
 		.map(|c| c / 2)
@@ -201,3 +271,46 @@ pub fn transfer(
 
 	generate_extrinsic(client, origin, function)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_keyring::AccountKeyring;
+	use substrate_test_client::GenesisInit;
+
+	fn storage_key(module: &[u8], item: &[u8]) -> Vec<u8> {
+		[twox_128(module), twox_128(item)].concat()
+	}
+
+	#[test]
+	fn genesis_is_configured_by_the_builder() {
+		let mut builder = TestClientBuilder::new()
+			.set_para_id(200.into())
+			.set_balances(vec![(AccountKeyring::Alice.into(), 1_000)])
+			.set_genesis_storage(b"key".to_vec(), b"value".to_vec());
+
+		let storage = builder.genesis_init_mut().genesis_storage();
+
+		assert_eq!(
+			Some(&ParaId::from(200).encode()),
+			storage.top.get(&storage_key(b"ParachainUpgrade", b"ParachainId")),
+		);
+		assert_eq!(
+			Some(&1_000u128.encode()),
+			storage.top.get(&storage_key(b"Balances", b"TotalIssuance")),
+		);
+		assert_eq!(Some(&b"value".to_vec()), storage.top.get(&b"key"[..]));
+	}
+
+	#[test]
+	fn genesis_storage_overrides_the_genesis_config() {
+		let para_id_key = storage_key(b"ParachainUpgrade", b"ParachainId");
+		let mut builder = TestClientBuilder::new()
+			.set_para_id(200.into())
+			.set_genesis_storage(para_id_key.clone(), ParaId::from(300).encode());
+
+		let storage = builder.genesis_init_mut().genesis_storage();
+
+		assert_eq!(Some(&ParaId::from(300).encode()), storage.top.get(&para_id_key));
+	}
+}
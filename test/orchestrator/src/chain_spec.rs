@@ -0,0 +1,95 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Build the chain spec of the relay chain with the parachain registered at genesis.
+
+use crate::Error;
+
+use serde_json::{json, Value};
+
+/// The genesis config sections of the relay chain runtimes that contain the parachains.
+const PARAS_SECTIONS: &[&str] = &["parachainsParas", "paras"];
+
+/// Register the parachain `para_id` in the genesis config of the relay chain `spec`.
+///
+/// `spec` is a chain spec as written by `build-spec` without `--raw`. `genesis_head` and
+/// `validation_code` are hex encoded, as written by `export-genesis-state` and
+/// `export-genesis-wasm`.
+pub fn register_parachain(
+	spec: &mut Value,
+	para_id: u32,
+	genesis_head: &str,
+	validation_code: &str,
+) -> Result<(), Error> {
+	let runtime = spec
+		.pointer_mut("/genesis/runtime")
+		.ok_or_else(|| Error::ChainSpec("No genesis runtime config, is the spec raw?".into()))?;
+
+	let section = PARAS_SECTIONS
+		.iter()
+		.find(|s| runtime.get(**s).is_some())
+		.ok_or_else(|| {
+			Error::ChainSpec(format!(
+				"The relay chain has none of the genesis sections {:?}",
+				PARAS_SECTIONS,
+			))
+		})?;
+
+	let paras = runtime[*section]["paras"]
+		.as_array_mut()
+		.ok_or_else(|| Error::ChainSpec(format!("`{}.paras` is not a list", section)))?;
+
+	paras.push(json!([
+		para_id,
+		{
+			"genesis_head": genesis_head.trim(),
+			"validation_code": validation_code.trim(),
+			"parachain": true,
+		},
+	]));
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn registers_the_parachain_in_the_genesis_config() {
+		let mut spec = json!({
+			"genesis": {
+				"runtime": {
+					"parachainsParas": { "paras": [] },
+				},
+			},
+		});
+
+		register_parachain(&mut spec, 100, "0x01\n", "0x02").unwrap();
+
+		assert_eq!(
+			json!([[100, { "genesis_head": "0x01", "validation_code": "0x02", "parachain": true }]]),
+			spec["genesis"]["runtime"]["parachainsParas"]["paras"],
+		);
+	}
+
+	#[test]
+	fn rejects_raw_chain_specs() {
+		let mut spec = json!({ "genesis": { "raw": { "top": {} } } });
+
+		assert!(register_parachain(&mut spec, 100, "0x01", "0x02").is_err());
+	}
+}
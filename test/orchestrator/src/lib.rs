@@ -0,0 +1,472 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Launch a local network of relay chain validators and collators from integration tests.
+//!
+//! Every node runs as a separate process of a binary provided by the caller, e.g. a `polkadot`
+//! binary for the validators and the collator binary of the parachain under test. The
+//! parachain is registered in the genesis config of the relay chain, so it can produce blocks
+//! right from the start. A launched [`Network`] can then be checked for liveness properties,
+//! e.g. with [`Network::assert_para_blocks`].
+//!
+//! ```no_run
+//! use cumulus_test_orchestrator::{Network, NetworkConfig};
+//!
+//! let mut network = Network::launch(
+//! 	NetworkConfig::new("polkadot", "rococo-collator")
+//! 		.validators(2)
+//! 		.collators(1),
+//! )
+//! .unwrap();
+//!
+//! // The parachain produces at least 3 blocks in 30 relay chain blocks.
+//! network.assert_para_blocks(3, 30).unwrap();
+//! ```
+
+mod chain_spec;
+pub mod rpc;
+
+pub use chain_spec::register_parachain;
+
+use log::{debug, info};
+use tempfile::TempDir;
+
+use std::{
+	fmt, fs, io,
+	path::{Path, PathBuf},
+	process::{Child, Command, Stdio},
+	thread,
+	time::{Duration, Instant},
+};
+
+/// The names of the well known keys the validators are started with.
+const VALIDATOR_NAMES: &[&str] = &["alice", "bob", "charlie", "dave", "eve", "ferdie"];
+
+/// How long to wait for a node to answer RPC requests after it was started.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the nodes are polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Errors of the orchestrator.
+#[derive(Debug)]
+pub enum Error {
+	/// An io error.
+	Io(io::Error),
+	/// A command failed or a node exited.
+	Command(String),
+	/// The chain spec of the relay chain could not be built.
+	ChainSpec(String),
+	/// An RPC request failed.
+	Rpc(String),
+	/// A liveness property does not hold.
+	Liveness(String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "Io error: {}", e),
+			Error::Command(e) => write!(f, "Command failed: {}", e),
+			Error::ChainSpec(e) => write!(f, "Invalid chain spec: {}", e),
+			Error::Rpc(e) => write!(f, "Rpc error: {}", e),
+			Error::Liveness(e) => write!(f, "Liveness violated: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+/// The configuration of a [`Network`].
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+	relay_binary: PathBuf,
+	collator_binary: PathBuf,
+	relay_chain: String,
+	validators: usize,
+	collators: usize,
+	para_id: u32,
+	collator_args: Vec<String>,
+	base_port: u16,
+}
+
+impl NetworkConfig {
+	/// Run the validators with `relay_binary` and the collators with `collator_binary`.
+	///
+	/// Defaults to two validators of `rococo-local` and one collator of parachain `100`.
+	pub fn new(relay_binary: impl Into<PathBuf>, collator_binary: impl Into<PathBuf>) -> Self {
+		Self {
+			relay_binary: relay_binary.into(),
+			collator_binary: collator_binary.into(),
+			relay_chain: "rococo-local".into(),
+			validators: 2,
+			collators: 1,
+			para_id: 100,
+			collator_args: Vec::new(),
+			base_port: 31000,
+		}
+	}
+
+	/// The relay chain, as passed to `--chain` of the `relay_binary`.
+	pub fn relay_chain(mut self, relay_chain: impl Into<String>) -> Self {
+		self.relay_chain = relay_chain.into();
+		self
+	}
+
+	/// Start the given number of validators, at most one for every well known key.
+	pub fn validators(mut self, validators: usize) -> Self {
+		self.validators = validators;
+		self
+	}
+
+	/// Start the given number of collators.
+	pub fn collators(mut self, collators: usize) -> Self {
+		self.collators = collators;
+		self
+	}
+
+	/// The id the parachain is registered with.
+	pub fn para_id(mut self, para_id: u32) -> Self {
+		self.para_id = para_id;
+		self
+	}
+
+	/// Pass `arg` to every collator, before the relay chain arguments.
+	pub fn collator_arg(mut self, arg: impl Into<String>) -> Self {
+		self.collator_args.push(arg.into());
+		self
+	}
+
+	/// The first port that is assigned to the nodes, every node uses the following ports.
+	pub fn base_port(mut self, base_port: u16) -> Self {
+		self.base_port = base_port;
+		self
+	}
+}
+
+/// A running node of the network.
+#[derive(Debug)]
+pub struct Node {
+	/// The name of the node.
+	pub name: String,
+	/// The port of the RPC server of the node.
+	pub rpc_port: u16,
+	/// The file the output of the node is written to.
+	pub log: PathBuf,
+	process: Child,
+}
+
+impl Node {
+	/// Returns the number of the best block of the node.
+	pub fn best_number(&self) -> Result<u64, Error> {
+		rpc::best_number(self.rpc_port)
+	}
+
+	/// Returns an error if the process of the node exited.
+	fn check_running(&mut self) -> Result<(), Error> {
+		match self.process.try_wait().map_err(Error::Io)? {
+			Some(status) => Err(Error::Command(format!(
+				"Node `{}` exited with {}, see `{}`",
+				self.name,
+				status,
+				self.log.display(),
+			))),
+			None => Ok(()),
+		}
+	}
+
+	/// Wait until the node answers RPC requests.
+	fn wait_for_rpc(&mut self) -> Result<(), Error> {
+		let started = Instant::now();
+
+		loop {
+			self.check_running()?;
+
+			match self.best_number() {
+				Ok(_) => return Ok(()),
+				Err(e) if started.elapsed() > STARTUP_TIMEOUT => {
+					return Err(Error::Command(format!(
+						"Node `{}` did not start in {:?}: {}",
+						self.name, STARTUP_TIMEOUT, e,
+					)))
+				}
+				Err(_) => thread::sleep(POLL_INTERVAL),
+			}
+		}
+	}
+}
+
+impl Drop for Node {
+	fn drop(&mut self) {
+		let _ = self.process.kill();
+		let _ = self.process.wait();
+	}
+}
+
+/// A running network of relay chain validators and collators.
+///
+/// All nodes are killed when the network is dropped.
+pub struct Network {
+	/// The relay chain validators.
+	pub validators: Vec<Node>,
+	/// The collators of the parachain.
+	pub collators: Vec<Node>,
+	// Dropped last, so the nodes are killed before their directory is removed.
+	_dir: TempDir,
+}
+
+impl Network {
+	/// Launch the network described by `config` and wait until all nodes answer RPC requests.
+	pub fn launch(config: NetworkConfig) -> Result<Self, Error> {
+		if config.validators > VALIDATOR_NAMES.len() {
+			return Err(Error::Command(format!(
+				"At most {} validators are supported",
+				VALIDATOR_NAMES.len(),
+			)));
+		}
+
+		let dir = tempfile::tempdir().map_err(Error::Io)?;
+		let relay_spec = build_relay_spec(&config, dir.path())?;
+		let relay_spec = relay_spec.display().to_string();
+		let mut port = config.base_port;
+		let mut next_port = move || {
+			port += 1;
+			port - 1
+		};
+
+		let mut validators = Vec::new();
+		for name in &VALIDATOR_NAMES[..config.validators] {
+			let rpc_port = next_port();
+			let node_dir = dir.path().join(name);
+			let args = vec![
+				"--chain".into(),
+				relay_spec.clone(),
+				"--validator".into(),
+				format!("--{}", name),
+				"-d".into(),
+				node_dir.display().to_string(),
+				"--port".into(),
+				next_port().to_string(),
+				"--ws-port".into(),
+				next_port().to_string(),
+				"--rpc-port".into(),
+				rpc_port.to_string(),
+			];
+
+			validators.push(spawn(&config.relay_binary, name, &node_dir, rpc_port, args)?);
+		}
+
+		let mut collators = Vec::new();
+		for i in 0..config.collators {
+			let name = format!("collator-{}", i);
+			let rpc_port = next_port();
+			let node_dir = dir.path().join(&name);
+			let mut args = vec![
+				"--collator".into(),
+				"--parachain-id".into(),
+				config.para_id.to_string(),
+				"-d".into(),
+				node_dir.display().to_string(),
+				"--port".into(),
+				next_port().to_string(),
+				"--ws-port".into(),
+				next_port().to_string(),
+				"--rpc-port".into(),
+				rpc_port.to_string(),
+			];
+			args.extend(config.collator_args.iter().cloned());
+			args.extend(vec![
+				"--".into(),
+				"--chain".into(),
+				relay_spec.clone(),
+				"--port".into(),
+				next_port().to_string(),
+				"--ws-port".into(),
+				next_port().to_string(),
+				"--rpc-port".into(),
+				next_port().to_string(),
+			]);
+
+			collators.push(spawn(&config.collator_binary, &name, &node_dir, rpc_port, args)?);
+		}
+
+		let mut network = Self {
+			validators,
+			collators,
+			_dir: dir,
+		};
+
+		for node in network.nodes_mut() {
+			node.wait_for_rpc()?;
+		}
+
+		info!(
+			target: "cumulus-test-orchestrator",
+			"Launched {} validators and {} collators of parachain {}.",
+			network.validators.len(),
+			network.collators.len(),
+			config.para_id,
+		);
+
+		Ok(network)
+	}
+
+	/// Check that the parachain produces at least `para_blocks` blocks while the relay chain
+	/// produces `relay_blocks` blocks.
+	///
+	/// The blocks are counted from when this function is called. The best blocks are read from
+	/// the first validator and the first collator.
+	pub fn assert_para_blocks(&mut self, para_blocks: u64, relay_blocks: u64) -> Result<(), Error> {
+		let relay_start = self.relay_node()?.best_number()?;
+		let para_start = self.para_node()?.best_number()?;
+
+		loop {
+			for node in self.nodes_mut() {
+				node.check_running()?;
+			}
+
+			let relay = self.relay_node()?.best_number()?.saturating_sub(relay_start);
+			let para = self.para_node()?.best_number()?.saturating_sub(para_start);
+
+			debug!(
+				target: "cumulus-test-orchestrator",
+				"{} parachain blocks in {} relay chain blocks.", para, relay,
+			);
+
+			if para >= para_blocks {
+				return Ok(());
+			}
+
+			if relay >= relay_blocks {
+				return Err(Error::Liveness(format!(
+					"The parachain produced {} blocks in {} relay chain blocks, expected {}",
+					para, relay, para_blocks,
+				)));
+			}
+
+			thread::sleep(POLL_INTERVAL);
+		}
+	}
+
+	fn relay_node(&self) -> Result<&Node, Error> {
+		self.validators
+			.first()
+			.ok_or_else(|| Error::Command("The network has no validators".into()))
+	}
+
+	fn para_node(&self) -> Result<&Node, Error> {
+		self.collators
+			.first()
+			.ok_or_else(|| Error::Command("The network has no collators".into()))
+	}
+
+	fn nodes_mut(&mut self) -> impl Iterator<Item = &mut Node> {
+		self.validators.iter_mut().chain(self.collators.iter_mut())
+	}
+}
+
+/// Run `binary` with `args` and return its output.
+fn output(binary: &Path, args: &[&str]) -> Result<String, Error> {
+	let output = Command::new(binary)
+		.args(args)
+		.output()
+		.map_err(|e| Error::Command(format!("Failed to run `{}`: {}", binary.display(), e)))?;
+
+	if !output.status.success() {
+		return Err(Error::Command(format!(
+			"`{} {}` failed: {}",
+			binary.display(),
+			args.join(" "),
+			String::from_utf8_lossy(&output.stderr),
+		)));
+	}
+
+	String::from_utf8(output.stdout)
+		.map_err(|e| Error::Command(format!("`{}` wrote invalid utf8: {}", binary.display(), e)))
+}
+
+/// Build the raw chain spec of the relay chain with the parachain registered at genesis.
+///
+/// Returns the path of the chain spec in `dir`.
+fn build_relay_spec(config: &NetworkConfig, dir: &Path) -> Result<PathBuf, Error> {
+	let para_id = config.para_id.to_string();
+	let genesis_head = output(
+		&config.collator_binary,
+		&["export-genesis-state", "--parachain-id", &para_id],
+	)?;
+	let validation_code = output(&config.collator_binary, &["export-genesis-wasm"])?;
+
+	let spec = output(
+		&config.relay_binary,
+		&["build-spec", "--chain", &config.relay_chain, "--disable-default-bootnode"],
+	)?;
+	let mut spec = serde_json::from_str(&spec)
+		.map_err(|e| Error::ChainSpec(format!("Failed to parse the relay chain spec: {}", e)))?;
+	register_parachain(&mut spec, config.para_id, &genesis_head, &validation_code)?;
+
+	let plain_path = dir.join("relay-chain-plain.json");
+	fs::write(&plain_path, spec.to_string()).map_err(Error::Io)?;
+
+	let raw = output(
+		&config.relay_binary,
+		&[
+			"build-spec",
+			"--chain",
+			&plain_path.display().to_string(),
+			"--raw",
+			"--disable-default-bootnode",
+		],
+	)?;
+	let raw_path = dir.join("relay-chain.json");
+	fs::write(&raw_path, raw).map_err(Error::Io)?;
+
+	Ok(raw_path)
+}
+
+/// Start the node `name` by running `binary` with `args`.
+///
+/// The output of the node is written to a log file in `dir`.
+fn spawn(
+	binary: &Path,
+	name: &str,
+	dir: &Path,
+	rpc_port: u16,
+	args: Vec<String>,
+) -> Result<Node, Error> {
+	fs::create_dir_all(dir).map_err(Error::Io)?;
+	let log = dir.join("node.log");
+	let log_file = fs::File::create(&log).map_err(Error::Io)?;
+
+	debug!(
+		target: "cumulus-test-orchestrator",
+		"Starting `{}`: {} {}", name, binary.display(), args.join(" "),
+	);
+
+	let process = Command::new(binary)
+		.args(&args)
+		.stdout(log_file.try_clone().map_err(Error::Io)?)
+		.stderr(log_file)
+		.stdin(Stdio::null())
+		.spawn()
+		.map_err(|e| Error::Command(format!("Failed to start `{}`: {}", name, e)))?;
+
+	Ok(Node {
+		name: name.into(),
+		rpc_port,
+		log,
+		process,
+	})
+}
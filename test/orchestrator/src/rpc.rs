@@ -0,0 +1,100 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A minimal JSON-RPC client over HTTP, to query the nodes of the network.
+
+use crate::Error;
+
+use serde_json::{json, Value};
+
+use std::{
+	io::{Read, Write},
+	net::TcpStream,
+	time::Duration,
+};
+
+/// The timeout of a single request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Call `method` with `params` on the node listening for RPC requests on `port`.
+pub fn call(port: u16, method: &str, params: Value) -> Result<Value, Error> {
+	let body = json!({
+		"jsonrpc": "2.0",
+		"id": 1,
+		"method": method,
+		"params": params,
+	})
+	.to_string();
+
+	let mut stream = TcpStream::connect(("127.0.0.1", port)).map_err(Error::Io)?;
+	stream
+		.set_read_timeout(Some(REQUEST_TIMEOUT))
+		.map_err(Error::Io)?;
+
+	write!(
+		stream,
+		"POST / HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nContent-Type: application/json\r\n\
+		Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+		port,
+		body.len(),
+		body,
+	)
+	.map_err(Error::Io)?;
+
+	let mut response = String::new();
+	stream.read_to_string(&mut response).map_err(Error::Io)?;
+
+	let body = response
+		.splitn(2, "\r\n\r\n")
+		.nth(1)
+		.ok_or_else(|| Error::Rpc(format!("Invalid response to `{}`: {}", method, response)))?;
+	let mut response: Value = serde_json::from_str(body)
+		.map_err(|e| Error::Rpc(format!("Invalid response to `{}`: {}", method, e)))?;
+
+	if let Some(error) = response.get("error") {
+		return Err(Error::Rpc(format!("`{}` failed: {}", method, error)));
+	}
+
+	Ok(response["result"].take())
+}
+
+/// Returns the number of the best block of the node listening on `port`.
+pub fn best_number(port: u16) -> Result<u64, Error> {
+	let header = call(port, "chain_getHeader", json!([]))?;
+	let number = header["number"]
+		.as_str()
+		.ok_or_else(|| Error::Rpc(format!("Header without number: {}", header)))?;
+
+	parse_number(number)
+}
+
+/// Parse a hex encoded block number, as returned by the RPC.
+fn parse_number(number: &str) -> Result<u64, Error> {
+	u64::from_str_radix(number.trim_start_matches("0x"), 16)
+		.map_err(|e| Error::Rpc(format!("Invalid block number `{}`: {}", number, e)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_hex_block_numbers() {
+		assert_eq!(0, parse_number("0x0").unwrap());
+		assert_eq!(26, parse_number("0x1a").unwrap());
+		assert!(parse_number("0xzz").is_err());
+	}
+}
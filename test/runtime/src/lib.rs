@@ -22,6 +22,8 @@
 #[cfg(feature = "std")]
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
+pub mod test_pallet;
+
 use sp_api::{decl_runtime_apis, impl_runtime_apis};
 use sp_core::OpaqueMetadata;
 use sp_runtime::{
@@ -189,11 +191,13 @@ impl pallet_sudo::Trait for Runtime {
 impl cumulus_parachain_upgrade::Trait for Runtime {
 	type Event = Event;
 	type OnValidationData = ();
+	type Activation = ();
+	type SelfParaId = ParachainInfo;
 }
 
-parameter_types! {
-	pub storage ParachainId: cumulus_primitives::ParaId = 100.into();
-}
+impl test_pallet::Trait for Runtime {}
+
+impl parachain_info::Trait for Runtime {}
 
 construct_runtime! {
 	pub enum Runtime where
@@ -208,6 +212,8 @@ construct_runtime! {
 		RandomnessCollectiveFlip: pallet_randomness_collective_flip::{Module, Call, Storage},
 		ParachainUpgrade: cumulus_parachain_upgrade::{Module, Call, Storage, Inherent, Event},
 		TransactionPayment: pallet_transaction_payment::{Module, Storage},
+		TestPallet: test_pallet::{Module, Call},
+		ParachainInfo: parachain_info::{Module, Storage, Config},
 	}
 }
 
@@ -340,6 +346,31 @@ impl_runtime_apis! {
 		}
 	}
 
+	impl cumulus_primitives::PersistedValidationDataApi<Block> for Runtime {
+		fn persisted_validation_data() -> Option<cumulus_primitives::PersistedValidationData> {
+			ParachainUpgrade::last_persisted_validation_data()
+		}
+	}
+
+	impl cumulus_primitives::CollectCollationInfo<Block> for Runtime {
+		fn collect_collation_info(
+			header: &<Block as BlockT>::Header,
+		) -> cumulus_primitives::CollationInfo {
+			ParachainUpgrade::collect_collation_info(header)
+		}
+	}
+
+	impl cumulus_primitives::CodeUpgradeApi<Block> for Runtime {
+		fn check_code_upgrade(
+			code_size: u32,
+		) -> Result<
+			cumulus_primitives::relay_chain::BlockNumber,
+			cumulus_primitives::CodeUpgradeError,
+		> {
+			ParachainUpgrade::check_code_upgrade(code_size)
+		}
+	}
+
 	impl crate::GetLastTimestamp<Block> for Runtime {
 		fn get_last_timestamp() -> u64 {
 			<pallet_timestamp::Module<Self>>::now()
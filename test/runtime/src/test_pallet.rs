@@ -0,0 +1,52 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A pallet that is only used by tests to exercise features of the runtime.
+
+use frame_support::{decl_module, storage::child};
+use frame_system::ensure_signed;
+use sp_core::storage::ChildInfo;
+use sp_std::vec::Vec;
+
+/// The storage key of the child trie used by this pallet.
+pub const CHILD_TRIE_KEY: &[u8] = b"cumulus_test_child_trie";
+
+pub trait Trait: frame_system::Trait {}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		/// Write `value` at `key` into the child trie.
+		#[weight = 0]
+		fn set_child_storage(origin, key: Vec<u8>, value: Vec<u8>) {
+			ensure_signed(origin)?;
+
+			child::put_raw(&child_info(), &key, &value);
+		}
+
+		/// Remove the entire child trie.
+		#[weight = 0]
+		fn kill_child_storage(origin) {
+			ensure_signed(origin)?;
+
+			child::kill_storage(&child_info());
+		}
+	}
+}
+
+/// The child trie used by this pallet.
+pub fn child_info() -> ChildInfo {
+	ChildInfo::new_default(CHILD_TRIE_KEY)
+}
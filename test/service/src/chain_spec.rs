@@ -67,7 +67,7 @@ pub fn get_chain_spec(id: ParaId) -> ChainSpec {
 		"Local Testnet",
 		"local_testnet",
 		ChainType::Local,
-		move || local_testnet_genesis(None),
+		move || local_testnet_genesis(id, None),
 		vec![],
 		None,
 		None,
@@ -81,6 +81,7 @@ pub fn get_chain_spec(id: ParaId) -> ChainSpec {
 
 /// Local testnet genesis for testing.
 pub fn local_testnet_genesis(
+	id: ParaId,
 	changes_trie_config: Option<ChangesTrieConfiguration>,
 ) -> cumulus_test_runtime::GenesisConfig {
 	testnet_genesis(
@@ -99,6 +100,7 @@ pub fn local_testnet_genesis(
 			get_account_id_from_seed::<sr25519::Public>("Eve//stash"),
 			get_account_id_from_seed::<sr25519::Public>("Ferdie//stash"),
 		],
+		id,
 		changes_trie_config,
 	)
 }
@@ -106,6 +108,7 @@ pub fn local_testnet_genesis(
 fn testnet_genesis(
 	root_key: AccountId,
 	endowed_accounts: Vec<AccountId>,
+	id: ParaId,
 	changes_trie_config: Option<ChangesTrieConfiguration>,
 ) -> cumulus_test_runtime::GenesisConfig {
 	cumulus_test_runtime::GenesisConfig {
@@ -123,5 +126,6 @@ fn testnet_genesis(
 				.collect(),
 		}),
 		pallet_sudo: Some(cumulus_test_runtime::SudoConfig { key: root_key }),
+		parachain_info: Some(cumulus_test_runtime::ParachainInfoConfig { parachain_id: id }),
 	}
 }
@@ -25,10 +25,15 @@ pub use chain_spec::*;
 pub use genesis::*;
 
 use core::future::Future;
-use cumulus_network::BlockAnnounceValidator;
+use cumulus_collator::{
+	events::CollatorEvent, AnnounceBlock, EncodedAnnouncement, RelayChainDelay,
+};
+use cumulus_consensus::RelayChainCache;
+use cumulus_network::{recent_blocks::RecentJustifications, BlockAnnounceValidator};
 use cumulus_primitives::ParaId;
 use cumulus_service::{
-	prepare_node_config, start_collator, start_full_node, StartCollatorParams, StartFullNodeParams,
+	prepare_node_config, start_collator, start_full_node, CollatorConfig, StartCollatorParams,
+	StartFullNodeParams,
 };
 use cumulus_test_runtime::{NodeBlock as Block, RuntimeApi};
 use polkadot_primitives::v1::CollatorPair;
@@ -48,8 +53,9 @@ use sp_core::{Pair, H256};
 use sp_keyring::Sr25519Keyring;
 use sp_runtime::traits::BlakeTwo256;
 use sp_state_machine::BasicExternalities;
+use sp_transaction_pool::TransactionPool;
 use sp_trie::PrefixedMemoryDB;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use substrate_test_client::BlockchainEventsExt;
 
 // Native executor instance.
@@ -124,6 +130,9 @@ async fn start_node_impl<RB>(
 	polkadot_config: Configuration,
 	para_id: ParaId,
 	is_collator: bool,
+	relay_chain_delay: Option<RelayChainDelay>,
+	collator_events: CollatorEvents,
+	announce_justifications: RecentJustifications<H256>,
 	rpc_ext_builder: RB,
 ) -> sc_service::error::Result<(
 	TaskManager,
@@ -160,11 +169,14 @@ where
 
 	let client = params.client.clone();
 	let backend = params.backend.clone();
+	let relay_chain_cache = RelayChainCache::default();
 	let block_announce_validator = BlockAnnounceValidator::new(
 		polkadot_full_node.client.clone(),
 		para_id,
 		Box::new(polkadot_full_node.network.clone()),
-	);
+	)
+	.with_relay_chain_cache(relay_chain_cache.clone())
+	.with_justifications(announce_justifications);
 	let block_announce_validator_builder = move |_| Box::new(block_announce_validator) as Box<_>;
 
 	let prometheus_registry = parachain_config.prometheus_registry().cloned();
@@ -204,13 +216,21 @@ where
 		system_rpc_tx,
 	})?;
 
-	let announce_block = {
+	let announce_block: Arc<dyn AnnounceBlock<Block>> = {
 		let network = network.clone();
-		Arc::new(move |hash, data| network.announce_block(hash, data))
+		Arc::new(EncodedAnnouncement::new(move |hash, data| network.announce_block(hash, data)))
 	};
 
 	let polkadot_full_node = polkadot_full_node.with_client(polkadot_test_service::TestClient);
 	if is_collator {
+		let parent_recovery = {
+			let network = network.clone();
+			move |hash, number| network.set_sync_fork_request(Vec::new(), hash, number)
+		};
+		let ready_transactions = {
+			let transaction_pool = transaction_pool.clone();
+			move || transaction_pool.status().ready
+		};
 		let proposer_factory = sc_basic_authorship::ProposerFactory::new(
 			task_manager.spawn_handle(),
 			client.clone(),
@@ -218,6 +238,19 @@ where
 			prometheus_registry.as_ref(),
 		);
 
+		let mut config = CollatorConfig::default()
+			.prometheus_registry(prometheus_registry.clone())
+			.pre_validate(true)
+			.ready_transactions(Arc::new(ready_transactions))
+			.parent_recovery(Arc::new(parent_recovery))
+			.relay_chain_cache(relay_chain_cache)
+			.event_handler(Arc::new(move |event: &CollatorEvent<H256>| {
+				collator_events.lock().expect("Locks the events").push(event.clone())
+			}));
+		if let Some(relay_chain_delay) = relay_chain_delay {
+			config = config.relay_chain_delay(relay_chain_delay);
+		}
+
 		let params = StartCollatorParams {
 			proposer_factory,
 			inherent_data_providers: params.inherent_data_providers,
@@ -231,6 +264,7 @@ where
 			para_id,
 			collator_key,
 			polkadot_full_node,
+			config,
 		};
 
 		start_collator(params).await?;
@@ -241,6 +275,8 @@ where
 			task_manager: &mut task_manager,
 			para_id,
 			polkadot_full_node,
+			relay_chain_cache,
+			new_best_observer: None,
 		};
 
 		start_full_node(params)?;
@@ -264,8 +300,15 @@ pub struct CumulusTestNode {
 	pub addr: MultiaddrWithPeerId,
 	/// RPCHandlers to make RPC queries.
 	pub rpc_handlers: RpcHandlers,
+	/// The events of the collator, empty if the node is no collator.
+	pub collator_events: CollatorEvents,
+	/// The justifications of the block announcements the node validated successfully.
+	pub announce_justifications: RecentJustifications<H256>,
 }
 
+/// The events reported by a collator, in the order they were reported.
+pub type CollatorEvents = Arc<Mutex<Vec<CollatorEvent<H256>>>>;
+
 /// Run a Cumulus test node using the Cumulus test runtime. The node will be using an in-memory
 /// socket, therefore you need to provide boot nodes if you want it to be connected to other nodes.
 /// The `storage_update_func` can be used to make adjustements to the runtime before the node
@@ -280,7 +323,87 @@ pub async fn run_test_node(
 	para_id: ParaId,
 	is_collator: bool,
 ) -> CumulusTestNode {
-	let collator_key = CollatorPair::generate().0;
+	run_test_node_with_relay_chain_delay(
+		task_executor,
+		key,
+		parachain_storage_update_func,
+		polkadot_storage_update_func,
+		parachain_boot_nodes,
+		polkadot_boot_nodes,
+		para_id,
+		is_collator,
+		None,
+	)
+	.await
+}
+
+/// Run a Cumulus test node like [`run_test_node`], with the given `relay_chain_delay` added to
+/// every call of the collator to the relay chain.
+pub async fn run_test_node_with_relay_chain_delay(
+	task_executor: TaskExecutor,
+	key: Sr25519Keyring,
+	parachain_storage_update_func: impl Fn(),
+	polkadot_storage_update_func: impl Fn(),
+	parachain_boot_nodes: Vec<MultiaddrWithPeerId>,
+	polkadot_boot_nodes: Vec<MultiaddrWithPeerId>,
+	para_id: ParaId,
+	is_collator: bool,
+	relay_chain_delay: Option<RelayChainDelay>,
+) -> CumulusTestNode {
+	run_test_node_impl(
+		task_executor,
+		key,
+		parachain_storage_update_func,
+		polkadot_storage_update_func,
+		parachain_boot_nodes,
+		polkadot_boot_nodes,
+		para_id,
+		is_collator,
+		CollatorPair::generate().0,
+		relay_chain_delay,
+	)
+	.await
+}
+
+/// Run a Cumulus test collator like [`run_test_node`], that collates with the given
+/// `collator_key`.
+///
+/// This allows to run multiple collators that share the same key.
+pub async fn run_test_collator_with_key(
+	task_executor: TaskExecutor,
+	key: Sr25519Keyring,
+	parachain_boot_nodes: Vec<MultiaddrWithPeerId>,
+	polkadot_boot_nodes: Vec<MultiaddrWithPeerId>,
+	para_id: ParaId,
+	collator_key: CollatorPair,
+) -> CumulusTestNode {
+	run_test_node_impl(
+		task_executor,
+		key,
+		|| {},
+		|| {},
+		parachain_boot_nodes,
+		polkadot_boot_nodes,
+		para_id,
+		true,
+		collator_key,
+		None,
+	)
+	.await
+}
+
+async fn run_test_node_impl(
+	task_executor: TaskExecutor,
+	key: Sr25519Keyring,
+	parachain_storage_update_func: impl Fn(),
+	polkadot_storage_update_func: impl Fn(),
+	parachain_boot_nodes: Vec<MultiaddrWithPeerId>,
+	polkadot_boot_nodes: Vec<MultiaddrWithPeerId>,
+	para_id: ParaId,
+	is_collator: bool,
+	collator_key: CollatorPair,
+	relay_chain_delay: Option<RelayChainDelay>,
+) -> CumulusTestNode {
 	let parachain_config = node_config(
 		parachain_storage_update_func,
 		task_executor.clone(),
@@ -302,12 +425,17 @@ pub async fn run_test_node(
 		format!("{} (relay chain)", polkadot_config.network.node_name);
 
 	let multiaddr = parachain_config.network.listen_addresses[0].clone();
+	let collator_events = CollatorEvents::default();
+	let announce_justifications = RecentJustifications::default();
 	let (task_manager, client, network, rpc_handlers) = start_node_impl(
 		parachain_config,
 		collator_key,
 		polkadot_config,
 		para_id,
 		is_collator,
+		relay_chain_delay,
+		collator_events.clone(),
+		announce_justifications.clone(),
 		|_| Default::default(),
 	)
 	.await
@@ -322,6 +450,8 @@ pub async fn run_test_node(
 		network,
 		addr,
 		rpc_handlers,
+		collator_events,
+		announce_justifications,
 	}
 }
 
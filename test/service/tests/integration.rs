@@ -14,15 +14,54 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
-use cumulus_primitives::ParaId;
-use cumulus_test_service::initial_head_data;
+use codec::Decode;
+use cumulus_collator::events::CollatorEvent;
+use cumulus_primitives::{well_known_keys, ParaId, ValidationData};
+use cumulus_test_service::{initial_head_data, CumulusTestNode};
 use futures::join;
+use polkadot_primitives::v1::CollatorPair;
+use sc_client_api::StorageProvider;
 use sc_service::TaskExecutor;
+use sp_blockchain::HeaderBackend;
+use sp_core::{storage::StorageKey, Pair, H256};
+use sp_runtime::generic::BlockId;
+use std::collections::HashSet;
 use substrate_test_runtime_client::AccountKeyring::*;
 
+/// Returns the relay parents and blocks of the candidates `node` produced.
+fn produced_candidates(node: &CumulusTestNode) -> Vec<(H256, H256)> {
+	node.collator_events
+		.lock()
+		.expect("Locks the events")
+		.iter()
+		.filter_map(|event| match event {
+			CollatorEvent::CandidateProduced {
+				relay_parent,
+				block_hash,
+				..
+			} => Some((*relay_parent, *block_hash)),
+			_ => None,
+		})
+		.collect()
+}
+
+/// Returns the relay parent number the block `hash` of `node` was built on.
+fn relay_parent_number(node: &CumulusTestNode, hash: H256) -> u32 {
+	let validation_data = node
+		.client
+		.storage(&BlockId::Hash(hash), &StorageKey(well_known_keys::VALIDATION_DATA.to_vec()))
+		.expect("Reads the validation data")
+		.expect("Every block stores its validation data");
+
+	ValidationData::decode(&mut &validation_data.0[..])
+		.expect("Decodes the validation data")
+		.persisted
+		.block_number
+}
+
 #[substrate_test_utils::test]
 async fn test_collating_and_non_collator_mode_catching_up(task_executor: TaskExecutor) {
-	sc_cli::init_logger("", Default::default(), None).expect("Sets up logger");
+	let _ = sc_cli::init_logger("", Default::default(), None);
 
 	let para_id = ParaId::from(100);
 
@@ -87,3 +126,234 @@ async fn test_collating_and_non_collator_mode_catching_up(task_executor: TaskExe
 		// dave.task_manager.clean_shutdown(),
 	);
 }
+
+#[substrate_test_utils::test]
+async fn test_two_parachains_on_the_same_relay_chain(task_executor: TaskExecutor) {
+	let _ = sc_cli::init_logger("", Default::default(), None);
+
+	let para_a = ParaId::from(100);
+	let para_b = ParaId::from(200);
+
+	// start alice
+	let alice = polkadot_test_service::run_validator_node(task_executor.clone(), Alice, || {}, vec![]);
+
+	// start bob
+	let bob = polkadot_test_service::run_validator_node(
+		task_executor.clone(),
+		Bob,
+		|| {},
+		vec![alice.addr.clone()],
+	);
+
+	// register both parachains
+	for para_id in &[para_a, para_b] {
+		alice
+			.register_parachain(
+				*para_id,
+				cumulus_test_runtime::WASM_BINARY
+					.expect("You need to build the WASM binary to run this test!")
+					.to_vec(),
+				initial_head_data(*para_id),
+			)
+			.await
+			.unwrap();
+	}
+
+	// run cumulus charlie (a collator of parachain a)
+	let charlie = cumulus_test_service::run_test_node(
+		task_executor.clone(),
+		Charlie,
+		|| {},
+		|| {},
+		vec![],
+		vec![alice.addr.clone(), bob.addr.clone()],
+		para_a,
+		true,
+	)
+	.await;
+
+	// run cumulus dave (a collator of parachain b)
+	let dave = cumulus_test_service::run_test_node(
+		task_executor.clone(),
+		Dave,
+		|| {},
+		|| {},
+		vec![],
+		vec![alice.addr.clone(), bob.addr.clone()],
+		para_b,
+		true,
+	)
+	.await;
+
+	// both parachains make progress on the shared relay chain
+	join!(charlie.wait_for_blocks(3), dave.wait_for_blocks(3));
+
+	join!(
+		alice.task_manager.clean_shutdown(),
+		bob.task_manager.clean_shutdown(),
+		charlie.task_manager.clean_shutdown(),
+		dave.task_manager.clean_shutdown(),
+	);
+}
+
+#[substrate_test_utils::test]
+async fn test_collators_converge_on_one_candidate_per_relay_parent(task_executor: TaskExecutor) {
+	let _ = sc_cli::init_logger("", Default::default(), None);
+
+	let para_id = ParaId::from(100);
+
+	// start alice
+	let alice = polkadot_test_service::run_validator_node(task_executor.clone(), Alice, || {}, vec![]);
+
+	// start bob
+	let bob = polkadot_test_service::run_validator_node(
+		task_executor.clone(),
+		Bob,
+		|| {},
+		vec![alice.addr.clone()],
+	);
+
+	// register parachain
+	alice
+		.register_parachain(
+			para_id,
+			cumulus_test_runtime::WASM_BINARY
+				.expect("You need to build the WASM binary to run this test!")
+				.to_vec(),
+			initial_head_data(para_id),
+		)
+		.await
+		.unwrap();
+
+	let relay_boot_nodes = vec![alice.addr.clone(), bob.addr.clone()];
+	let shared_key = CollatorPair::generate().0;
+
+	// run cumulus charlie and dave, two collators that share the same key
+	let charlie = cumulus_test_service::run_test_collator_with_key(
+		task_executor.clone(),
+		Charlie,
+		vec![],
+		relay_boot_nodes.clone(),
+		para_id,
+		shared_key.clone(),
+	)
+	.await;
+	let dave = cumulus_test_service::run_test_collator_with_key(
+		task_executor.clone(),
+		Dave,
+		vec![charlie.addr.clone()],
+		relay_boot_nodes.clone(),
+		para_id,
+		shared_key,
+	)
+	.await;
+
+	// run cumulus eve and ferdie, two collators with their own keys
+	let eve = cumulus_test_service::run_test_node(
+		task_executor.clone(),
+		Eve,
+		|| {},
+		|| {},
+		vec![charlie.addr.clone()],
+		relay_boot_nodes.clone(),
+		para_id,
+		true,
+	)
+	.await;
+	let ferdie = cumulus_test_service::run_test_node(
+		task_executor.clone(),
+		Ferdie,
+		|| {},
+		|| {},
+		vec![charlie.addr.clone()],
+		relay_boot_nodes,
+		para_id,
+		true,
+	)
+	.await;
+
+	join!(
+		charlie.wait_for_blocks(5),
+		dave.wait_for_blocks(5),
+		eve.wait_for_blocks(5),
+		ferdie.wait_for_blocks(5),
+	);
+
+	// The relay chain includes one candidate per relay parent, the blocks of the other
+	// candidates are discarded. Every collator finalizes the included blocks, so all of them
+	// have to agree on the finalized chain.
+	let nodes = [&charlie, &dave, &eve, &ferdie];
+	let finalized = nodes
+		.iter()
+		.map(|node| node.client.chain_info().finalized_number)
+		.min()
+		.expect("There are nodes");
+	assert!(finalized > 0, "No parachain block was finalized");
+
+	let finalized_hashes = nodes
+		.iter()
+		.map(|node| node.client.hash(finalized).expect("Reads the finalized block"))
+		.collect::<Vec<_>>();
+	assert!(
+		finalized_hashes.windows(2).all(|w| w[0] == w[1]),
+		"The collators finalized different blocks at #{}: {:?}",
+		finalized,
+		finalized_hashes,
+	);
+
+	// The duplicate production guard: no collator produces two candidates for the same relay
+	// parent, also not the two that share a key.
+	for node in &nodes {
+		let candidates = produced_candidates(node);
+		let relay_parents = candidates.iter().map(|(relay_parent, _)| relay_parent);
+		assert_eq!(
+			candidates.len(),
+			relay_parents.collect::<HashSet<_>>().len(),
+			"A collator produced more than one candidate for a relay parent: {:?}",
+			candidates,
+		);
+	}
+
+	// The relay chain includes at most one candidate per relay parent, so the relay parents of
+	// the finalized chain strictly increase.
+	let relay_parent_numbers = (1..=finalized)
+		.map(|number| {
+			let hash = charlie.client.hash(number).unwrap().expect("The block is finalized");
+			relay_parent_number(&charlie, hash)
+		})
+		.collect::<Vec<_>>();
+	assert!(
+		relay_parent_numbers.windows(2).all(|w| w[0] < w[1]),
+		"Two finalized blocks were built on the same relay parent: {:?}",
+		relay_parent_numbers,
+	);
+
+	// Announce validation: the finalized blocks were produced by one of the collators and at
+	// least one other collator accepted its seconded announcement.
+	for number in 1..=finalized {
+		let hash = charlie.client.hash(number).unwrap().expect("The block is finalized");
+		let producers = nodes
+			.iter()
+			.enumerate()
+			.filter(|(_, node)| produced_candidates(node).iter().any(|(_, block)| *block == hash))
+			.map(|(index, _)| index)
+			.collect::<Vec<_>>();
+		assert!(!producers.is_empty(), "No collator produced the finalized block {}", hash);
+
+		let validated = nodes
+			.iter()
+			.enumerate()
+			.filter(|(index, _)| !producers.contains(index))
+			.any(|(_, node)| node.announce_justifications.get(&hash).is_some());
+		assert!(validated, "No collator validated the announcement of block {}", hash);
+	}
+
+	join!(
+		alice.task_manager.clean_shutdown(),
+		bob.task_manager.clean_shutdown(),
+		charlie.task_manager.clean_shutdown(),
+		dave.task_manager.clean_shutdown(),
+		eve.task_manager.clean_shutdown(),
+		ferdie.task_manager.clean_shutdown(),
+	);
+}
@@ -0,0 +1,103 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Soak test of a collator that reads the relay chain through a slow connection.
+//!
+//! This takes several minutes, run it with `cargo test -p cumulus-test-service -- --ignored`.
+
+use cumulus_collator::RelayChainDelay;
+use cumulus_primitives::ParaId;
+use cumulus_test_service::initial_head_data;
+use futures::join;
+use sc_service::TaskExecutor;
+use std::time::Duration;
+use substrate_test_runtime_client::AccountKeyring::*;
+
+/// The number of parachain blocks the collator has to produce.
+const PARACHAIN_BLOCKS: u32 = 20;
+
+/// The minimum percentage of relay chain blocks that need to get a parachain block.
+const MIN_SLOT_PERCENT: u32 = 75;
+
+#[substrate_test_utils::test]
+#[ignore]
+async fn collator_keeps_up_with_a_slow_relay_chain(task_executor: TaskExecutor) {
+	sc_cli::init_logger("", Default::default(), None).expect("Sets up logger");
+
+	let para_id = ParaId::from(100);
+
+	// start alice
+	let alice = polkadot_test_service::run_validator_node(task_executor.clone(), Alice, || {}, vec![]);
+
+	// start bob
+	let bob = polkadot_test_service::run_validator_node(
+		task_executor.clone(),
+		Bob,
+		|| {},
+		vec![alice.addr.clone()],
+	);
+
+	// register parachain
+	alice
+		.register_parachain(
+			para_id,
+			cumulus_test_runtime::WASM_BINARY
+				.expect("You need to build the WASM binary to run this test!")
+				.to_vec(),
+			initial_head_data(para_id),
+		)
+		.await
+		.unwrap();
+
+	// run cumulus charlie (a parachain collator) behind a slow relay chain connection
+	let charlie = cumulus_test_service::run_test_node_with_relay_chain_delay(
+		task_executor.clone(),
+		Charlie,
+		|| {},
+		|| {},
+		vec![],
+		vec![alice.addr.clone(), bob.addr.clone()],
+		para_id,
+		true,
+		Some(RelayChainDelay::new(Duration::from_millis(500)).jitter(Duration::from_millis(100))),
+	)
+	.await;
+
+	// wait until the parachain is onboarded and produces blocks
+	charlie.wait_for_blocks(1).await;
+
+	let relay_start = alice.client.chain_info().best_number;
+	let para_start = charlie.client.chain_info().best_number;
+
+	charlie.wait_for_blocks(PARACHAIN_BLOCKS as usize).await;
+
+	let relay_blocks = alice.client.chain_info().best_number - relay_start;
+	let para_blocks = charlie.client.chain_info().best_number - para_start;
+
+	assert!(
+		para_blocks * 100 >= relay_blocks * MIN_SLOT_PERCENT,
+		"The collator produced {} blocks in {} relay chain blocks, expected at least {}%",
+		para_blocks,
+		relay_blocks,
+		MIN_SLOT_PERCENT,
+	);
+
+	join!(
+		alice.task_manager.clean_shutdown(),
+		bob.task_manager.clean_shutdown(),
+		charlie.task_manager.clean_shutdown(),
+	);
+}